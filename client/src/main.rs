@@ -9,9 +9,18 @@ use bevy_atmosphere::prelude::{AtmosphereCamera, AtmospherePlugin, AtmosphereSet
 use bevy_flycam::{FlyCam, PlayerPlugin};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 
+use client::ui::diagnostics::DiagnosticsOverlayPlugin;
+use client::world::cubes::pbr::{SurfaceMaterialDefinition, SurfaceMaterialMarker};
 use client::world::cubes::CubeRenderWorldPlugin;
+use common::datapack::watch::{DataPackWatchPlugin, ReloadRegistryPlugin};
+use common::palette::GlobalPalette;
+use common::resource::state::{BlockSchema, BlockSchemaMarker};
+use common::resource::{BlockData, ResourceKey};
 use mesher::b32::{build_mesh32, VoxelCubeOcclusionMatrix32};
 use mesher::meshing::quads_to_mesh;
+use protocol::chunks::ChunkAssembler;
+use protocol::client::ClientLoginPlugin;
+use protocol::transport::local::{ClientLocalConnection, LocalTransportError};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Component)]
 pub struct ClientGameState {}
@@ -32,7 +41,19 @@ fn main() {
         ))
         .add_plugins(AtmospherePlugin)
         .add_plugins(PlayerPlugin)
-        .add_plugins(CubeRenderWorldPlugin)
+        .add_plugins((CubeRenderWorldPlugin, DiagnosticsOverlayPlugin))
+        //connects to a server in the same process once a ClientLogin is inserted
+        .add_plugins(ClientLoginPlugin::<ClientLocalConnection, LocalTransportError>::default())
+        //the chunks streamed by the server, the default state of the palette is the same as on the server
+        .insert_resource(GlobalPalette::new(air()))
+        .insert_resource(ChunkAssembler::new(|state| *state != air()))
+        //with the datapack-watch feature the content is reloaded when the data packs change,
+        //the materials and meshes follow the changes
+        .add_plugins((
+            DataPackWatchPlugin::new("datapacks").watch(cfg!(feature = "datapack-watch")),
+            ReloadRegistryPlugin::<BlockSchema, BlockSchemaMarker>::new("blocks"),
+            ReloadRegistryPlugin::<SurfaceMaterialDefinition, SurfaceMaterialMarker>::new("materials"),
+        ))
         .add_plugins(WorldInspectorPlugin::new())
         .insert_resource(WireframeConfig {
             // The global wireframe config enables drawing of wireframes on every mesh,
@@ -57,6 +78,10 @@ fn main() {
         .run();
 }
 
+fn air() -> BlockData {
+    BlockData::new(ResourceKey::core("air").expect("air is a valid key"))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Component)]
 struct CoordsText;
 
@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+
+use common::diagnostics::WorldMemoryReport;
+
+/// toggles the overlay
+pub const OVERLAY_KEY: KeyCode = KeyCode::F3;
+
+/// shows the [WorldMemoryReport] in the top right corner, toggled with [OVERLAY_KEY]
+pub struct DiagnosticsOverlayPlugin;
+
+impl Plugin for DiagnosticsOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldMemoryReport>();
+        app.add_systems(Startup, spawn_overlay);
+        app.add_systems(Update, (toggle_overlay, update_overlay));
+    }
+}
+
+#[derive(Debug, Default, Component)]
+pub struct DiagnosticsOverlay;
+
+fn spawn_overlay(mut commands: Commands) {
+    let mut text = TextBundle::from_section("", TextStyle::default())
+        .with_text_justify(JustifyText::Right)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(4.0),
+            right: Val::Px(4.0),
+            ..default()
+        })
+        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.5));
+    text.visibility = Visibility::Hidden;
+    commands.spawn((DiagnosticsOverlay, text));
+}
+
+fn toggle_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut overlays: Query<&mut Visibility, With<DiagnosticsOverlay>>,
+) {
+    if !keys.just_pressed(OVERLAY_KEY) {
+        return;
+    }
+    for mut visibility in overlays.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn update_overlay(
+    report: Res<WorldMemoryReport>,
+    mut overlays: Query<&mut Text, With<DiagnosticsOverlay>>,
+) {
+    if !report.is_changed() {
+        return;
+    }
+    for mut text in overlays.iter_mut() {
+        text.sections[0].value = report.to_string();
+    }
+}
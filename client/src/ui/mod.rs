@@ -1,6 +1,7 @@
 use bevy::app::App;
 use bevy::prelude::*;
 
+pub mod diagnostics;
 mod main_menu;
 
 #[derive(Debug)]
@@ -1,12 +1,17 @@
 use std::sync::Mutex;
 
 use bevy::prelude::*;
+use bevy::render::mesh::Indices;
 use bevy::render::primitives::Aabb;
 use bevy::render::render_asset::RenderAssetUsages;
-use common::CHUNK_SIZE;
 use hashbrown::HashMap;
 use rayon::prelude::*;
 
+use common::diagnostics::SourceReport;
+use common::registry::events::{
+    RegistryEntryAdded, RegistryEntryChanged, RegistryEntryRemoved, RegistryEventsPlugin,
+};
+use common::resource::state::{BlockSchema, BlockSchemaMarker, BlockSchemas};
 use mesher::b32::{build_mesh32, VoxelCubeOcclusionMatrix32};
 use mesher::meshing::quads_to_mesh;
 
@@ -16,10 +21,15 @@ pub struct VoxelMeshPlugin;
 
 impl Plugin for VoxelMeshPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<BlockSchemas>();
+        if !app.is_plugin_added::<RegistryEventsPlugin<BlockSchema, BlockSchemaMarker>>() {
+            app.add_plugins(RegistryEventsPlugin::<BlockSchema, BlockSchemaMarker>::default());
+        }
         app.add_systems(
             Update,
             (
                 sync_static_neighbors.in_set(ChunkRenderStage::ComputeOccupied),
+                remesh_changed_blocks.in_set(ChunkRenderStage::ComputeOccupied),
                 build_meshes_system.in_set(ChunkRenderStage::ComputeMesh),
             ),
         );
@@ -28,6 +38,41 @@ impl Plugin for VoxelMeshPlugin {
 
 fn sync_static_neighbors() {}
 
+/// the meshes are built from the block types, so every chunk is meshed again when they changed
+/// (f.e. after the data packs were reloaded)
+fn remesh_changed_blocks(
+    mut added: EventReader<RegistryEntryAdded<BlockSchema, BlockSchemaMarker>>,
+    mut changed: EventReader<RegistryEntryChanged<BlockSchema, BlockSchemaMarker>>,
+    mut removed: EventReader<RegistryEntryRemoved<BlockSchema, BlockSchemaMarker>>,
+    mut stores: Query<&mut VoxelCubeStore>,
+) {
+    let any_change = added.read().count() + changed.read().count() + removed.read().count() > 0;
+    if any_change {
+        for mut store in stores.iter_mut() {
+            store.set_changed();
+        }
+    }
+}
+
+/// the cpu side memory of the mesh assets (vertex attributes and indices)
+pub fn mesh_memory(meshes: &Assets<Mesh>) -> SourceReport {
+    let mut report = SourceReport::default();
+    for (_, mesh) in meshes.iter() {
+        let attributes = mesh
+            .attributes()
+            .map(|(_, values)| values.get_bytes().len())
+            .sum::<usize>();
+        let indices = match mesh.indices() {
+            Some(Indices::U16(indices)) => indices.len() * 2,
+            Some(Indices::U32(indices)) => indices.len() * 4,
+            None => 0,
+        };
+        report.count += 1;
+        report.bytes += std::mem::size_of::<Mesh>() + attributes + indices;
+    }
+    report
+}
+
 #[allow(clippy::type_complexity)]
 fn build_meshes_system(
     commands: ParallelCommands,
@@ -49,9 +94,8 @@ fn build_meshes_system(
         .par_iter()
         .for_each(|(kube_entity, occlusion_matrix, textures, children)| {
             let outcome = build_mesh32(occlusion_matrix, |x, y, z, face| {
-                let index = x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE;
                 //TODO: per direction textures
-                textures.get(index).to_owned()
+                textures.get_xyz(x, y, z).to_owned()
             });
 
             let meshes = outcome
@@ -3,14 +3,19 @@ use std::hash::Hash;
 use std::ops::Deref;
 
 use bevy::prelude::{
-    App, Changed, Component, IntoSystemSetConfigs, Plugin, Query, Reflect, SystemSet, Transform,
-    Update, Vec3,
+    App, Assets, Changed, Component, IntoSystemSetConfigs, Mesh, Plugin, Query, Reflect, SystemSet,
+    Transform, Update, Vec3,
 };
 use uuid::Uuid;
 
+use common::compressible::LZ4;
+use common::diagnostics::{WorldDiagnosticsAppExt, WorldDiagnosticsPlugin};
+use common::palette::ChunkStorage;
 use common::storage::Storage;
 use common::{CHUNK_SIZE, CHUNK_VOLUME};
 
+use mesher::b32::VoxelCubeOcclusionMatrix32;
+
 pub mod mesh;
 pub mod pbr;
 
@@ -35,6 +40,14 @@ impl Plugin for CubeRenderWorldPlugin {
         );
         app.add_plugins((mesh::VoxelMeshPlugin, pbr::ChunkPbrPlugin));
         app.add_systems(Update, set_static_cubes_position_system);
+
+        if !app.is_plugin_added::<WorldDiagnosticsPlugin>() {
+            app.add_plugins(WorldDiagnosticsPlugin::default());
+        }
+        app.add_storage_source::<CHUNK_VOLUME, Option<SurfaceMaterial>>("cube_stores")
+            .add_memory_source::<VoxelCubeOcclusionMatrix32>("occlusion_matrices")
+            .add_compressed_source::<ChunkStorage, LZ4>("compressed_chunks")
+            .add_resource_source::<Assets<Mesh>>("meshes", mesh::mesh_memory);
     }
 }
 
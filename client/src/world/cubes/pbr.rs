@@ -2,8 +2,15 @@ use std::ops::Deref;
 
 use bevy::prelude::*;
 use hashbrown::HashMap;
+use serde::Deserialize;
 use uuid::Uuid;
 
+use common::registry::events::{
+    RegistryEntryAdded, RegistryEntryChanged, RegistryEntryRemoved, RegistryEventsPlugin,
+};
+use common::registry::Registry;
+use common::resource::ResourceKey;
+
 use crate::world::cubes::ChunkRenderStage;
 
 pub struct ChunkPbrPlugin;
@@ -11,22 +18,58 @@ pub struct ChunkPbrPlugin;
 impl Plugin for ChunkPbrPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<MaterialMapper>();
+        app.init_resource::<SurfaceMaterials>();
+        app.add_plugins(RegistryEventsPlugin::<
+            SurfaceMaterialDefinition,
+            SurfaceMaterialMarker,
+        >::default());
         app.add_systems(Startup, test_textures);
         app.add_systems(
             Update,
-            apply_surfaces.in_set(ChunkRenderStage::ApplyMaterial),
+            (
+                update_materials.before(ChunkRenderStage::ApplyMaterial),
+                apply_surfaces.in_set(ChunkRenderStage::ApplyMaterial),
+            ),
         );
     }
 }
 
+/// marker of the [SurfaceMaterials] registry
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct SurfaceMaterialMarker;
+
+/// the materials of the surfaces, loaded from the `materials` of the data packs
+pub type SurfaceMaterials = Registry<SurfaceMaterialDefinition, SurfaceMaterialMarker>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct SurfaceMaterialDefinition {
+    /// the id of the material used by the surfaces
+    pub uuid: Uuid,
+    /// the asset path of the base color texture
+    pub texture: String,
+}
+
 #[derive(Debug, Clone, Default, Resource)]
 pub struct MaterialMapper {
     pub materials: HashMap<Uuid, Handle<StandardMaterial>>,
+    /// the material id of every entry of [SurfaceMaterials]
+    pub keys: HashMap<ResourceKey, Uuid>,
 }
 
 impl MaterialMapper {
     pub fn clear(&mut self) {
         self.materials.clear();
+        self.keys.clear();
+    }
+}
+
+fn surface_material(texture: Handle<Image>) -> StandardMaterial {
+    StandardMaterial {
+        base_color: Color::WHITE,
+        base_color_texture: Some(texture),
+        metallic: 0.0,
+        reflectance: 0.05,
+        ..Default::default()
     }
 }
 
@@ -47,24 +90,66 @@ fn test_textures(
     mut material_mapper: ResMut<MaterialMapper>,
 ) {
     for (path, uuid) in TEST_TEXTURES.into_iter() {
-        let texture = texture_loader.load(path);
-        let texture = assets.add(StandardMaterial {
-            base_color: Color::WHITE,
-            base_color_texture: Some(texture),
-            metallic: 0.0,
-            reflectance: 0.05,
-            ..Default::default()
-        });
+        let texture = assets.add(surface_material(texture_loader.load(path)));
         material_mapper.materials.insert(uuid, texture);
     }
 }
 
+/// keeps the materials in sync with the [SurfaceMaterials] registry (f.e. after the data packs were reloaded),
+/// [apply_surfaces] gives the changed materials to the surfaces afterwards
+fn update_materials(
+    texture_loader: Res<AssetServer>,
+    mut assets: ResMut<Assets<StandardMaterial>>,
+    mut material_mapper: ResMut<MaterialMapper>,
+    definitions: Res<SurfaceMaterials>,
+    mut added: EventReader<RegistryEntryAdded<SurfaceMaterialDefinition, SurfaceMaterialMarker>>,
+    mut changed: EventReader<
+        RegistryEntryChanged<SurfaceMaterialDefinition, SurfaceMaterialMarker>,
+    >,
+    mut removed: EventReader<
+        RegistryEntryRemoved<SurfaceMaterialDefinition, SurfaceMaterialMarker>,
+    >,
+) {
+    for event in removed.read() {
+        if let Some(uuid) = material_mapper.keys.remove(&event.key) {
+            if let Some(material) = material_mapper.materials.remove(&uuid) {
+                assets.remove(&material);
+            }
+        }
+    }
+    let keys = added
+        .read()
+        .map(|event| &event.key)
+        .chain(changed.read().map(|event| &event.key));
+    for key in keys {
+        let Some(definition) = definitions.get_by_key(key) else {
+            continue;
+        };
+        //the uuid of the entry may have changed as well
+        if let Some(uuid) = material_mapper.keys.insert(key.clone(), definition.uuid) {
+            if let Some(material) = material_mapper.materials.remove(&uuid) {
+                assets.remove(&material);
+            }
+        }
+        let material = assets.add(surface_material(
+            texture_loader.load(definition.texture.clone()),
+        ));
+        material_mapper.materials.insert(definition.uuid, material);
+    }
+}
+
+/// applies the materials to new surfaces, when the materials were replaced (f.e. after the content was reloaded)
+/// all surfaces get them again
 fn apply_surfaces(
     commands: ParallelCommands,
     materials: Res<MaterialMapper>,
-    surfaces: Query<(Entity, &SurfaceMaterial), Added<SurfaceMaterial>>,
+    surfaces: Query<(Entity, Ref<SurfaceMaterial>)>,
 ) {
+    let reapply = materials.is_changed();
     surfaces.par_iter().for_each(|(entity, surface)| {
+        if !reapply && !surface.is_added() {
+            return;
+        }
         let material = materials.materials.get(surface.deref()).cloned();
         if let Some(material) = material {
             commands.command_scope(|mut commands| {
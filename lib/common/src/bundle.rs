@@ -4,6 +4,8 @@ use std::sync::Arc;
 
 use hashbrown::HashMap;
 
+pub mod reflect;
+
 pub type AnyComponent = dyn Any + Send + Sync;
 
 #[derive(Default, Clone)]
@@ -0,0 +1,383 @@
+//! Serialization and spawning of [Bundle]s through bevy's reflection, so bundles can be used as entity templates.
+//!
+//! Every component of a bundle has to be registered in the [TypeRegistry] (`app.register_type::<C>()`)
+//! with `#[reflect(Component)]`. The serialized form is a map of the type path to the reflected value,
+//! the same as the components of a bevy scene:
+//!
+//! ```ron
+//! {
+//!     "game::Health": (current: 10, max: 20),
+//! }
+//! ```
+
+use std::any::{Any, TypeId};
+use std::fmt::{Display, Formatter};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use bevy::ecs::world::EntityWorldMut;
+use bevy::prelude::{Entity, ReflectComponent, World};
+use bevy::ptr::Ptr;
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::{Reflect, ReflectFromPtr, ReflectFromReflect, TypeRegistry};
+use itertools::Itertools;
+use serde::de::{DeserializeSeed, Error as _, MapAccess, Visitor};
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::bundle::Bundle;
+
+impl Bundle {
+    /// the reflected form of the component with [type_id]
+    fn reflect<'a>(
+        &'a self,
+        type_id: TypeId,
+        registry: &TypeRegistry,
+    ) -> Result<&'a dyn Reflect, BundleError> {
+        let component = self
+            .components
+            .get(&type_id)
+            .ok_or(BundleError::NotRegistered(type_id))?;
+        let from_ptr = registry
+            .get_type_data::<ReflectFromPtr>(type_id)
+            .ok_or(BundleError::NotRegistered(type_id))?;
+        // SAFETY: the component is stored with the TypeId of its type and the ReflectFromPtr is the one of this type
+        Ok(unsafe {
+            let ptr = Ptr::new(NonNull::from(&**component).cast::<u8>());
+            from_ptr.as_reflect(ptr)
+        })
+    }
+
+    /// inserts a reflected component, [component] may be a dynamic value (f.e. the result of deserialization)
+    pub fn insert_reflect(
+        &mut self,
+        component: &dyn Reflect,
+        registry: &TypeRegistry,
+    ) -> Result<(), BundleError> {
+        let type_path = component
+            .get_represented_type_info()
+            .map(|info| info.type_path())
+            .unwrap_or_else(|| component.reflect_type_path());
+        let registration = registry
+            .get_with_type_path(type_path)
+            .ok_or_else(|| BundleError::UnknownType(type_path.to_string()))?;
+        let from_reflect = registration
+            .data::<ReflectFromReflect>()
+            .ok_or_else(|| BundleError::missing_type_data::<ReflectFromReflect>(type_path))?;
+        let component = from_reflect
+            .from_reflect(component)
+            .ok_or_else(|| BundleError::InvalidValue(type_path.to_string()))?;
+        let component: Box<dyn Any + Send + Sync> = component;
+        self.components
+            .insert(registration.type_id(), Arc::from(component));
+        Ok(())
+    }
+
+    /// inserts all components into [entity], on error no component is inserted
+    pub fn insert_into(
+        &self,
+        entity: &mut EntityWorldMut,
+        registry: &TypeRegistry,
+    ) -> Result<(), BundleError> {
+        let components = self
+            .components
+            .keys()
+            .map(|type_id| {
+                let registration = registry
+                    .get(*type_id)
+                    .ok_or(BundleError::NotRegistered(*type_id))?;
+                let reflect_component =
+                    registration.data::<ReflectComponent>().ok_or_else(|| {
+                        BundleError::missing_type_data::<ReflectComponent>(
+                            registration.type_info().type_path(),
+                        )
+                    })?;
+                Ok((reflect_component, self.reflect(*type_id, registry)?))
+            })
+            .collect::<Result<Vec<_>, BundleError>>()?;
+        for (reflect_component, component) in components {
+            reflect_component.insert(entity, component, registry);
+        }
+        Ok(())
+    }
+
+    /// copies every reflected component of [entity] (panics if it does not exist), components which are not registered are skipped
+    pub fn capture(
+        world: &World,
+        entity: Entity,
+        registry: &TypeRegistry,
+    ) -> Result<Self, BundleError> {
+        let entity = world.entity(entity);
+        let mut bundle = Self::new();
+        for component_id in entity.archetype().components() {
+            let Some(type_id) = world
+                .components()
+                .get_info(component_id)
+                .and_then(|info| info.type_id())
+            else {
+                continue;
+            };
+            let Some(component) = registry
+                .get_type_data::<ReflectComponent>(type_id)
+                .and_then(|reflect_component| reflect_component.reflect(entity))
+            else {
+                continue;
+            };
+            bundle.insert_reflect(component, registry)?;
+        }
+        Ok(bundle)
+    }
+
+    pub fn to_ron(&self, registry: &TypeRegistry) -> Result<String, BundleError> {
+        ron::ser::to_string_pretty(
+            &BundleSerializer::new(self, registry),
+            ron::ser::PrettyConfig::default(),
+        )
+        .map_err(BundleError::format)
+    }
+
+    pub fn from_ron(text: &str, registry: &TypeRegistry) -> Result<Self, BundleError> {
+        let mut deserializer = ron::Deserializer::from_str(text).map_err(BundleError::format)?;
+        BundleDeserializer::new(registry)
+            .deserialize(&mut deserializer)
+            .map_err(BundleError::format)
+    }
+
+    pub fn to_msgpack(&self, registry: &TypeRegistry) -> Result<Vec<u8>, BundleError> {
+        rmp_serde::to_vec(&BundleSerializer::new(self, registry)).map_err(BundleError::format)
+    }
+
+    pub fn from_msgpack(bytes: &[u8], registry: &TypeRegistry) -> Result<Self, BundleError> {
+        BundleDeserializer::new(registry)
+            .deserialize(&mut rmp_serde::Deserializer::new(bytes))
+            .map_err(BundleError::format)
+    }
+}
+
+/// serializes a [Bundle] as map of the type paths to the components, sorted by the type path
+pub struct BundleSerializer<'a> {
+    bundle: &'a Bundle,
+    registry: &'a TypeRegistry,
+}
+
+impl<'a> BundleSerializer<'a> {
+    pub fn new(bundle: &'a Bundle, registry: &'a TypeRegistry) -> Self {
+        Self { bundle, registry }
+    }
+}
+
+impl Serialize for BundleSerializer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let components = self
+            .bundle
+            .components
+            .keys()
+            .map(|type_id| self.bundle.reflect(*type_id, self.registry))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(S::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(components.len()))?;
+        for component in components
+            .into_iter()
+            .sorted_by_key(|component| component.reflect_type_path())
+        {
+            map.serialize_entry(
+                component.reflect_type_path(),
+                &TypedReflectSerializer::new(component, self.registry),
+            )?;
+        }
+        map.end()
+    }
+}
+
+/// the counterpart of [BundleSerializer]
+pub struct BundleDeserializer<'a> {
+    registry: &'a TypeRegistry,
+}
+
+impl<'a> BundleDeserializer<'a> {
+    pub fn new(registry: &'a TypeRegistry) -> Self {
+        Self { registry }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for BundleDeserializer<'_> {
+    type Value = Bundle;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BundleDeserializer<'_> {
+    type Value = Bundle;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a map of type paths to components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut bundle = Bundle::new();
+        while let Some(type_path) = map.next_key::<String>()? {
+            let registration = self
+                .registry
+                .get_with_type_path(&type_path)
+                .ok_or_else(|| A::Error::custom(BundleError::UnknownType(type_path.clone())))?;
+            let component =
+                map.next_value_seed(TypedReflectDeserializer::new(registration, self.registry))?;
+            bundle
+                .insert_reflect(&*component, self.registry)
+                .map_err(A::Error::custom)?;
+        }
+        Ok(bundle)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleError {
+    /// the type of the component is not registered in the [TypeRegistry]
+    NotRegistered(TypeId),
+    /// no type with this path is registered
+    UnknownType(String),
+    /// the type is registered but without the reflected trait, f.e. `#[reflect(Component)]` is missing
+    MissingTypeData {
+        type_path: String,
+        type_data: &'static str,
+    },
+    /// the reflected value can not be converted into its type
+    InvalidValue(String),
+    /// the serialized bundle is invalid
+    Format(String),
+}
+
+impl BundleError {
+    fn missing_type_data<D>(type_path: &str) -> Self {
+        Self::MissingTypeData {
+            type_path: type_path.to_string(),
+            type_data: std::any::type_name::<D>(),
+        }
+    }
+
+    fn format(error: impl Display) -> Self {
+        Self::Format(error.to_string())
+    }
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BundleError::NotRegistered(type_id) => {
+                write!(
+                    f,
+                    "the component {:?} is not registered for reflection",
+                    type_id
+                )
+            }
+            BundleError::UnknownType(type_path) => write!(f, "unknown type {}", type_path),
+            BundleError::MissingTypeData {
+                type_path,
+                type_data,
+            } => write!(f, "{} is not registered with {}", type_path, type_data),
+            BundleError::InvalidValue(type_path) => {
+                write!(f, "invalid value for {}", type_path)
+            }
+            BundleError::Format(message) => write!(f, "invalid bundle: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BundleError {}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{AppTypeRegistry, Component};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct Health {
+        current: u32,
+        max: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    enum Team {
+        Player(String),
+    }
+
+    #[derive(Component)]
+    struct NotReflected;
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Health>();
+        registry.register::<Team>();
+        registry
+    }
+
+    fn bundle() -> Bundle {
+        let mut bundle = Bundle::new();
+        bundle.insert(Health {
+            current: 10,
+            max: 20,
+        });
+        bundle.insert(Team::Player("red".to_string()));
+        bundle
+    }
+
+    #[test]
+    fn serialize() {
+        let registry = registry();
+        let ron = bundle().to_ron(&registry).unwrap();
+        let bundle = Bundle::from_ron(&ron, &registry).unwrap();
+        assert_eq!(bundle.get::<Team>(), Some(&Team::Player("red".to_string())));
+
+        let msgpack = bundle.to_msgpack(&registry).unwrap();
+        let bundle = Bundle::from_msgpack(&msgpack, &registry).unwrap();
+        assert_eq!(
+            bundle.get::<Health>(),
+            Some(&Health {
+                current: 10,
+                max: 20
+            })
+        );
+
+        let mut unregistered = Bundle::new();
+        unregistered.insert(NotReflected);
+        assert!(unregistered.to_ron(&registry).is_err());
+        assert!(Bundle::from_ron(r#"{"unknown::Type": ()}"#, &registry).is_err());
+    }
+
+    #[test]
+    fn spawn_and_capture() {
+        let mut world = World::new();
+        world.insert_resource(AppTypeRegistry::default());
+        {
+            let mut registry = world.resource::<AppTypeRegistry>().write();
+            registry.register::<Health>();
+            registry.register::<Team>();
+        }
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+
+        let mut entity = world.spawn(NotReflected);
+        bundle().insert_into(&mut entity, &registry).unwrap();
+        let entity = entity.id();
+        assert_eq!(
+            world.get::<Team>(entity),
+            Some(&Team::Player("red".to_string()))
+        );
+
+        let captured = Bundle::capture(&world, entity, &registry).unwrap();
+        assert!(!captured.contains::<NotReflected>());
+        assert_eq!(
+            captured.get::<Health>(),
+            Some(&Health {
+                current: 10,
+                max: 20
+            })
+        );
+        assert_eq!(captured.all().len(), 2);
+    }
+}
@@ -0,0 +1,433 @@
+//! Typed coordinates for the voxel world.
+//!
+//! All world space types use the same axis convention as [Direction::get_vector_values]
+//! (east = +x, up = +y, north = +z) and the same flat index order as the chunk [Storage](crate::storage::Storage)
+//! (`x + y * CHUNK_SIZE + z * CHUNK_SIZE^2`).
+//!
+//! - [BlockPos] is the absolute position of a block in a dimension
+//! - [ChunkPos] is the position of a chunk (one unit = [CHUNK_SIZE] blocks)
+//! - [RegionPos] is the position of a region (one unit = [REGION_SIZE] blocks)
+//! - [LocalPos] is the position of a block inside its chunk
+
+use std::fmt::{Display, Formatter};
+
+use crate::storage::{local_index, local_position};
+use crate::{Direction, CHUNK_SIZE, REGION_SIZE};
+
+/// identifies a dimension (world) on the server
+pub type DimensionId = u32;
+
+const CHUNK_SIZE_I64: i64 = CHUNK_SIZE as i64;
+const REGION_SIZE_I64: i64 = REGION_SIZE as i64;
+const CHUNKS_PER_REGION: i64 = (REGION_SIZE / CHUNK_SIZE) as i64;
+
+/// The absolute position of a block.
+/// The dimension is not part of the position since blocks are mostly handled in the scope of a single dimension.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Ord,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct BlockPos {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+/// The position of a chunk in a dimension.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Ord,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct ChunkPos {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+    pub dimension: DimensionId,
+}
+
+/// The position of a region (a cube of chunks) in a dimension.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Ord,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct RegionPos {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+    pub dimension: DimensionId,
+}
+
+/// The position of a block inside its chunk, all axes are in `0..CHUNK_SIZE`.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Ord,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct LocalPos {
+    x: u8,
+    y: u8,
+    z: u8,
+}
+
+impl BlockPos {
+    pub const ZERO: BlockPos = BlockPos::new(0, 0, 0);
+
+    pub const fn new(x: i64, y: i64, z: i64) -> Self {
+        Self { x, y, z }
+    }
+
+    /// the chunk containing this block
+    pub fn chunk(&self, dimension: DimensionId) -> ChunkPos {
+        ChunkPos::new(
+            self.x.div_euclid(CHUNK_SIZE_I64),
+            self.y.div_euclid(CHUNK_SIZE_I64),
+            self.z.div_euclid(CHUNK_SIZE_I64),
+            dimension,
+        )
+    }
+
+    /// the position of this block inside its chunk
+    pub fn local(&self) -> LocalPos {
+        LocalPos {
+            x: self.x.rem_euclid(CHUNK_SIZE_I64) as u8,
+            y: self.y.rem_euclid(CHUNK_SIZE_I64) as u8,
+            z: self.z.rem_euclid(CHUNK_SIZE_I64) as u8,
+        }
+    }
+
+    /// the region containing this block
+    pub fn region(&self, dimension: DimensionId) -> RegionPos {
+        RegionPos::new(
+            self.x.div_euclid(REGION_SIZE_I64),
+            self.y.div_euclid(REGION_SIZE_I64),
+            self.z.div_euclid(REGION_SIZE_I64),
+            dimension,
+        )
+    }
+
+    /// returns [None] if the position would overflow
+    pub fn checked_offset(&self, x: i64, y: i64, z: i64) -> Option<BlockPos> {
+        Some(BlockPos::new(
+            self.x.checked_add(x)?,
+            self.y.checked_add(y)?,
+            self.z.checked_add(z)?,
+        ))
+    }
+
+    /// the adjacent block in [direction]
+    /// returns [None] at the edge of the coordinate space
+    pub fn neighbour(&self, direction: Direction) -> Option<BlockPos> {
+        let (x, y, z) = direction.get_vector_values();
+        self.checked_offset(x as i64, y as i64, z as i64)
+    }
+
+    /// all existing adjacent blocks (see [Direction::ALL] for the order)
+    pub fn neighbours(&self) -> impl Iterator<Item = (Direction, BlockPos)> + '_ {
+        Direction::ALL
+            .into_iter()
+            .filter_map(|direction| Some((direction, self.neighbour(direction)?)))
+    }
+}
+
+impl ChunkPos {
+    pub const fn new(x: i64, y: i64, z: i64, dimension: DimensionId) -> Self {
+        Self { x, y, z, dimension }
+    }
+
+    /// the block at [LocalPos] zero of this chunk
+    /// returns [None] if the chunk lies outside the block coordinate space
+    pub fn min_block(&self) -> Option<BlockPos> {
+        Some(BlockPos::new(
+            self.x.checked_mul(CHUNK_SIZE_I64)?,
+            self.y.checked_mul(CHUNK_SIZE_I64)?,
+            self.z.checked_mul(CHUNK_SIZE_I64)?,
+        ))
+    }
+
+    /// the absolute position of [local] in this chunk
+    pub fn block(&self, local: LocalPos) -> Option<BlockPos> {
+        self.min_block()?
+            .checked_offset(local.x as i64, local.y as i64, local.z as i64)
+    }
+
+    /// the region containing this chunk
+    pub fn region(&self) -> RegionPos {
+        RegionPos::new(
+            self.x.div_euclid(CHUNKS_PER_REGION),
+            self.y.div_euclid(CHUNKS_PER_REGION),
+            self.z.div_euclid(CHUNKS_PER_REGION),
+            self.dimension,
+        )
+    }
+
+    /// the adjacent chunk in [direction]
+    /// returns [None] at the edge of the coordinate space
+    pub fn neighbour(&self, direction: Direction) -> Option<ChunkPos> {
+        let (x, y, z) = direction.get_vector_values();
+        Some(ChunkPos::new(
+            self.x.checked_add(x as i64)?,
+            self.y.checked_add(y as i64)?,
+            self.z.checked_add(z as i64)?,
+            self.dimension,
+        ))
+    }
+
+    /// all existing adjacent chunks (see [Direction::ALL] for the order)
+    pub fn neighbours(&self) -> impl Iterator<Item = (Direction, ChunkPos)> + '_ {
+        Direction::ALL
+            .into_iter()
+            .filter_map(|direction| Some((direction, self.neighbour(direction)?)))
+    }
+}
+
+impl RegionPos {
+    pub const fn new(x: i64, y: i64, z: i64, dimension: DimensionId) -> Self {
+        Self { x, y, z, dimension }
+    }
+
+    /// the chunk with the smallest coordinates in this region
+    pub fn min_chunk(&self) -> Option<ChunkPos> {
+        Some(ChunkPos::new(
+            self.x.checked_mul(CHUNKS_PER_REGION)?,
+            self.y.checked_mul(CHUNKS_PER_REGION)?,
+            self.z.checked_mul(CHUNKS_PER_REGION)?,
+            self.dimension,
+        ))
+    }
+
+    /// the block with the smallest coordinates in this region
+    pub fn min_block(&self) -> Option<BlockPos> {
+        Some(BlockPos::new(
+            self.x.checked_mul(REGION_SIZE_I64)?,
+            self.y.checked_mul(REGION_SIZE_I64)?,
+            self.z.checked_mul(REGION_SIZE_I64)?,
+        ))
+    }
+
+    /// the adjacent region in [direction]
+    /// returns [None] at the edge of the coordinate space
+    pub fn neighbour(&self, direction: Direction) -> Option<RegionPos> {
+        let (x, y, z) = direction.get_vector_values();
+        Some(RegionPos::new(
+            self.x.checked_add(x as i64)?,
+            self.y.checked_add(y as i64)?,
+            self.z.checked_add(z as i64)?,
+            self.dimension,
+        ))
+    }
+}
+
+impl LocalPos {
+    pub const MIN: LocalPos = LocalPos { x: 0, y: 0, z: 0 };
+    pub const MAX: LocalPos = LocalPos {
+        x: CHUNK_SIZE as u8 - 1,
+        y: CHUNK_SIZE as u8 - 1,
+        z: CHUNK_SIZE as u8 - 1,
+    };
+
+    /// returns [None] if any axis is outside of `0..CHUNK_SIZE`
+    pub fn new(x: usize, y: usize, z: usize) -> Option<Self> {
+        if x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE {
+            Some(Self {
+                x: x as u8,
+                y: y as u8,
+                z: z as u8,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// returns [None] if the index is outside of the chunk
+    pub fn from_index(index: usize) -> Option<Self> {
+        if index < CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            let (x, y, z) = local_position(index);
+            Self::new(x, y, z)
+        } else {
+            None
+        }
+    }
+
+    /// the index of this position in a chunk [Storage](crate::storage::Storage)
+    #[inline]
+    pub fn index(&self) -> usize {
+        local_index(self.x(), self.y(), self.z())
+    }
+
+    #[inline]
+    pub fn x(&self) -> usize {
+        self.x as usize
+    }
+    #[inline]
+    pub fn y(&self) -> usize {
+        self.y as usize
+    }
+    #[inline]
+    pub fn z(&self) -> usize {
+        self.z as usize
+    }
+
+    #[inline]
+    pub fn xyz(&self) -> (usize, usize, usize) {
+        (self.x(), self.y(), self.z())
+    }
+
+    /// the adjacent position in [direction]
+    /// returns [None] if the neighbour belongs to another chunk
+    pub fn neighbour(&self, direction: Direction) -> Option<LocalPos> {
+        let (x, y, z) = direction.get_vector_values();
+        Self::new(
+            (self.x as i32 + x).try_into().ok()?,
+            (self.y as i32 + y).try_into().ok()?,
+            (self.z as i32 + z).try_into().ok()?,
+        )
+    }
+
+    /// all adjacent positions inside the same chunk (see [Direction::ALL] for the order)
+    pub fn neighbours(&self) -> impl Iterator<Item = (Direction, LocalPos)> + '_ {
+        Direction::ALL
+            .into_iter()
+            .filter_map(|direction| Some((direction, self.neighbour(direction)?)))
+    }
+}
+
+impl TryFrom<(usize, usize, usize)> for LocalPos {
+    type Error = LocalPosOutOfBounds;
+
+    fn try_from((x, y, z): (usize, usize, usize)) -> Result<Self, Self::Error> {
+        LocalPos::new(x, y, z).ok_or(LocalPosOutOfBounds(x, y, z))
+    }
+}
+
+impl From<LocalPos> for (usize, usize, usize) {
+    fn from(value: LocalPos) -> Self {
+        value.xyz()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalPosOutOfBounds(pub usize, pub usize, pub usize);
+
+impl Display for LocalPosOutOfBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "local position ({}, {}, {}) is outside of the chunk (size {})",
+            self.0, self.1, self.2, CHUNK_SIZE
+        )
+    }
+}
+
+impl std::error::Error for LocalPosOutOfBounds {}
+
+impl Display for BlockPos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}, {}]", self.x, self.y, self.z)
+    }
+}
+
+impl Display for ChunkPos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:[{}, {}, {}]", self.dimension, self.x, self.y, self.z)
+    }
+}
+
+impl Display for RegionPos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:[{}, {}, {}]", self.dimension, self.x, self.y, self.z)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_to_chunk() {
+        let block = BlockPos::new(-1, 32, 65);
+        assert_eq!(block.chunk(0), ChunkPos::new(-1, 1, 2, 0));
+        assert_eq!(block.local(), LocalPos::new(31, 0, 1).unwrap());
+        let chunk = block.chunk(0);
+        assert_eq!(chunk.block(block.local()), Some(block));
+    }
+
+    #[test]
+    fn chunk_to_region() {
+        let chunk = ChunkPos::new(-1, 31, 32, 3);
+        assert_eq!(chunk.region(), RegionPos::new(-1, 0, 1, 3));
+        let block = chunk.min_block().unwrap();
+        assert_eq!(block.region(3), chunk.region());
+        assert_eq!(
+            RegionPos::new(1, 0, 0, 0).min_chunk(),
+            Some(ChunkPos::new(32, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(ChunkPos::new(i64::MAX, 0, 0, 0).min_block(), None);
+        assert_eq!(
+            BlockPos::new(i64::MAX, 0, 0).neighbour(Direction::East),
+            None
+        );
+        assert_eq!(LocalPos::new(CHUNK_SIZE, 0, 0), None);
+        assert!(LocalPos::try_from((0, CHUNK_SIZE, 0)).is_err());
+    }
+
+    #[test]
+    fn local_index_matches_storage() {
+        let local = LocalPos::new(1, 2, 3).unwrap();
+        assert_eq!(local.index(), local_index(1, 2, 3));
+        assert_eq!(LocalPos::from_index(local.index()), Some(local));
+    }
+
+    #[test]
+    fn neighbours() {
+        assert_eq!(BlockPos::ZERO.neighbours().count(), 6);
+        assert_eq!(
+            BlockPos::ZERO.neighbour(Direction::North),
+            Some(BlockPos::new(0, 0, 1))
+        );
+        assert_eq!(LocalPos::MIN.neighbours().count(), 3);
+        assert_eq!(LocalPos::MAX.neighbour(Direction::Up), None);
+        assert_eq!(
+            ChunkPos::new(0, 0, 0, 1).neighbour(Direction::Down),
+            Some(ChunkPos::new(0, -1, 0, 1))
+        );
+    }
+}
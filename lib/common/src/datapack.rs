@@ -0,0 +1,796 @@
+//! Data packs provide the content of the [Registry]s from files instead of hard coding it.
+//!
+//! A pack is a directory or a zip file containing files at `namespace/kind/path.json` or `namespace/kind/path.toml`.
+//! F.e. the file `core/blocks/stone/granite.toml` registers `core:stone/granite` in the registry loaded as `blocks`.
+//! Tags of a registry are placed at `namespace/tags/kind/path.json` (see [TagDefinition]).
+//!
+//! Packs are applied by ascending priority, so a pack with a higher priority overrides the entries of the lower ones.
+//! Loading does not stop at the first broken file, all errors are collected together with their file and line.
+//!
+//! While developing content the packs can be reloaded at runtime when their files change (see [watch]).
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::Resource;
+use hashbrown::HashSet;
+use itertools::Itertools;
+use serde::de::DeserializeOwned;
+
+use crate::registry::tag::{TagDefinition, TagError, TagValue};
+use crate::registry::Registry;
+use crate::resource::{ResourceKey, NAMESPACE_CORE};
+
+pub mod watch;
+
+/// the kind of the files containing tags
+pub const TAGS_KIND: &str = "tags";
+
+/// the maximum size of a file in a zip pack, larger files are rejected instead of being read into memory
+pub const MAX_ZIP_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileFormat {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Clone)]
+struct PackFile {
+    namespace: String,
+    kind: String,
+    /// the path inside the kind without the extension
+    path: String,
+    format: FileFormat,
+    /// the pack and the path inside of it, used for errors
+    origin: String,
+    contents: String,
+}
+
+impl PackFile {
+    /// splits `namespace/kind/path.extension`, other files are ignored.
+    /// the contents are read afterwards
+    fn new(pack: &str, relative_path: &str) -> Option<Self> {
+        let mut components = relative_path.splitn(3, '/');
+        let namespace = components.next()?;
+        let kind = components.next()?;
+        let path = components.next()?;
+        let (path, format) = if let Some(path) = path.strip_suffix(".json") {
+            (path, FileFormat::Json)
+        } else if let Some(path) = path.strip_suffix(".toml") {
+            (path, FileFormat::Toml)
+        } else {
+            return None;
+        };
+        Some(Self {
+            namespace: namespace.to_string(),
+            kind: kind.to_string(),
+            path: path.to_string(),
+            format,
+            origin: format!("{}/{}", pack, relative_path),
+            contents: String::new(),
+        })
+    }
+
+    fn error(&self, line: Option<usize>, message: impl Display) -> DataPackError {
+        DataPackError {
+            file: self.origin.clone(),
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    fn key(&self, path: &str) -> Result<ResourceKey, DataPackError> {
+        ResourceKey::new(&self.namespace, path).map_err(|error| self.error(None, error))
+    }
+
+    /// the first line mentioning [tag] as value of a tag file (`"#namespace:path"` or `"#path"` for core keys)
+    fn line_of_tag(&self, tag: &ResourceKey) -> Option<usize> {
+        let full = format!("\"#{}\"", tag);
+        let short = format!("\"#{}\"", tag.path());
+        let position = self.contents.lines().position(|line| {
+            line.contains(&full) || (tag.namespace() == NAMESPACE_CORE && line.contains(&short))
+        })?;
+        Some(position + 1)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self) -> Result<T, DataPackError> {
+        match self.format {
+            FileFormat::Json => serde_json::from_str(&self.contents)
+                .map_err(|error| self.error(Some(error.line()).filter(|line| *line > 0), error)),
+            FileFormat::Toml => toml::from_str(&self.contents).map_err(|error| {
+                let line = error
+                    .span()
+                    .map(|span| self.contents[..span.start].matches('\n').count() + 1);
+                self.error(line, error.message())
+            }),
+        }
+    }
+}
+
+/// a directory or zip file with content, the files are read when the pack is opened
+#[derive(Debug, Clone)]
+pub struct DataPack {
+    name: String,
+    priority: i32,
+    files: Vec<PackFile>,
+}
+
+impl DataPack {
+    /// opens a directory or a zip file
+    pub fn open(path: &Path, priority: i32) -> Result<Self, DataPackErrors> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+        let io_error = |error: &dyn Display| DataPackError {
+            file: path.display().to_string(),
+            line: None,
+            message: error.to_string(),
+        };
+
+        let mut files = Vec::new();
+        if path.is_dir() {
+            read_directory(&name, path, String::new(), &mut files)
+                .map_err(|error| io_error(&error))?;
+        } else {
+            read_zip(&name, path, &mut files).map_err(|error| io_error(&error))?;
+        }
+        Ok(Self {
+            name,
+            priority,
+            files,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+fn read_directory(
+    pack: &str,
+    directory: &Path,
+    relative_path: String,
+    files: &mut Vec<PackFile>,
+) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative_path = if relative_path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative_path, name)
+        };
+        if entry.file_type()?.is_dir() {
+            read_directory(pack, &entry.path(), relative_path, files)?;
+        } else if let Some(mut file) = PackFile::new(pack, &relative_path) {
+            file.contents = std::fs::read_to_string(entry.path())?;
+            files.push(file);
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(pack: &str, path: &Path, files: &mut Vec<PackFile>) -> zip::result::ZipResult<()> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let relative_path = entry.name().to_string();
+        if let Some(mut file) = PackFile::new(pack, &relative_path) {
+            //the size in the header is not trusted, at most one byte more than allowed is read
+            let read = (&mut entry)
+                .take(MAX_ZIP_FILE_SIZE + 1)
+                .read_to_string(&mut file.contents)?;
+            if read as u64 > MAX_ZIP_FILE_SIZE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{} exceeds the maximum size of {} bytes",
+                        relative_path, MAX_ZIP_FILE_SIZE
+                    ),
+                )
+                .into());
+            }
+            files.push(file);
+        }
+    }
+    files.sort_by(|a, b| a.origin.cmp(&b.origin));
+    Ok(())
+}
+
+/// all packs of the game (or a world), use [DataPacks::load_registry] to fill the registries with their content
+#[derive(Debug, Clone, Default, Resource)]
+pub struct DataPacks {
+    /// ordered by ascending priority
+    packs: Vec<DataPack>,
+}
+
+impl DataPacks {
+    pub fn add(&mut self, pack: DataPack) {
+        let index = self
+            .packs
+            .partition_point(|other| other.priority <= pack.priority);
+        self.packs.insert(index, pack);
+    }
+
+    /// opens every directory and zip file in [directory], their priority is the alphabetical order of their names
+    pub fn discover(directory: &Path) -> Result<Self, DataPackErrors> {
+        let mut paths = std::fs::read_dir(directory)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<PathBuf>, _>>()
+            })
+            .map_err(|error| DataPackError {
+                file: directory.display().to_string(),
+                line: None,
+                message: error.to_string(),
+            })?;
+        paths.retain(|path| path.is_dir() || path.extension().is_some_and(|ext| ext == "zip"));
+        paths.sort();
+
+        let mut packs = Self::default();
+        let mut errors = Vec::new();
+        for (priority, path) in paths.iter().enumerate() {
+            match DataPack::open(path, priority as i32) {
+                Ok(pack) => packs.add(pack),
+                Err(pack_errors) => errors.extend(pack_errors.0),
+            }
+        }
+        DataPackErrors::check(errors)?;
+        Ok(packs)
+    }
+
+    pub fn packs(&self) -> &[DataPack] {
+        &self.packs
+    }
+
+    fn files(&self) -> impl Iterator<Item = &'_ PackFile> + '_ {
+        self.packs.iter().flat_map(|pack| pack.files.iter())
+    }
+
+    /// the entries of all `namespace/[kind]/...` files ordered by ascending priority
+    fn read_entries<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        errors: &mut Vec<DataPackError>,
+    ) -> Vec<(&'_ PackFile, ResourceKey, T)> {
+        self.files()
+            .filter(|file| file.kind == kind)
+            .filter_map(|file| {
+                match file
+                    .key(&file.path)
+                    .and_then(|key| Ok((key, file.deserialize()?)))
+                {
+                    Ok((key, data)) => Some((file, key, data)),
+                    Err(error) => {
+                        errors.push(error);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// the keys of the `namespace/[kind]/...` files without reading their contents
+    fn keys(&self, kind: &str) -> HashSet<ResourceKey> {
+        self.files()
+            .filter(|file| file.kind == kind)
+            .filter_map(|file| file.key(&file.path).ok())
+            .collect()
+    }
+
+    /// the tags of all `namespace/tags/[kind]/...` files ordered by ascending priority
+    fn read_tags(
+        &self,
+        kind: &str,
+        errors: &mut Vec<DataPackError>,
+    ) -> Vec<(&'_ PackFile, ResourceKey, TagDefinition)> {
+        let tag_prefix = format!("{}/", kind);
+        self.files()
+            .filter(|file| file.kind == TAGS_KIND)
+            .filter_map(|file| {
+                let path = file.path.strip_prefix(&tag_prefix)?;
+                match file
+                    .key(path)
+                    .and_then(|tag| Ok((tag, file.deserialize::<TagDefinition>()?)))
+                {
+                    Ok((tag, definition)) => Some((file, tag, definition)),
+                    Err(error) => {
+                        errors.push(error);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// the tags of the `namespace/tags/[kind]/...` files without reading their contents
+    fn tag_keys(&self, kind: &str) -> HashSet<ResourceKey> {
+        let tag_prefix = format!("{}/", kind);
+        self.files()
+            .filter(|file| file.kind == TAGS_KIND)
+            .filter_map(|file| file.key(file.path.strip_prefix(&tag_prefix)?).ok())
+            .collect()
+    }
+
+    /// Registers the entries of all `namespace/[kind]/...` files and defines the tags of `namespace/tags/[kind]/...`.
+    /// The valid files are applied even if other files contain errors.
+    ///
+    /// returns the number of loaded entries
+    pub fn load_registry<T, M>(
+        &self,
+        kind: &str,
+        registry: &mut Registry<T, M>,
+    ) -> Result<usize, DataPackErrors>
+    where
+        T: DeserializeOwned + Clone + Hash + PartialEq,
+    {
+        let mut errors = Vec::new();
+        let entries = self.read_entries::<T>(kind, &mut errors);
+        let loaded = entries.len();
+        for (_, key, data) in entries {
+            registry.register(key, data);
+        }
+
+        let tags = self.read_tags(kind, &mut errors);
+        define_tags(kind, tags, registry, &mut errors);
+
+        DataPackErrors::check(errors)?;
+        Ok(loaded)
+    }
+
+    /// Applies the entries of these packs to a [registry] which was loaded from [previous] before.
+    /// Entries whose files are gone are unregistered, entries which are not loaded from packs are kept.
+    ///
+    /// A frozen registry keeps its ids, so only the data of existing entries is replaced,
+    /// added and removed entries are reported as errors and need a restart.
+    ///
+    /// The tags of [previous] are replaced by the ones of these packs, also in frozen registries.
+    ///
+    /// returns the number of loaded entries
+    pub fn reload_registry<T, M>(
+        &self,
+        previous: &DataPacks,
+        kind: &str,
+        registry: &mut Registry<T, M>,
+    ) -> Result<usize, DataPackErrors>
+    where
+        T: DeserializeOwned + Clone + Hash + PartialEq,
+    {
+        let mut errors = Vec::new();
+        let entries = self.read_entries::<T>(kind, &mut errors);
+        let mut keys = HashSet::with_capacity(entries.len());
+        let mut loaded = 0;
+        for (file, key, data) in entries {
+            keys.insert(key.clone());
+            if !registry.is_frozen() {
+                registry.register(key, data);
+            } else if registry.update(&key, data).is_none() {
+                errors.push(file.error(None, "new entries can only be added after a restart"));
+                continue;
+            }
+            loaded += 1;
+        }
+
+        let mut removed = previous
+            .keys(kind)
+            .into_iter()
+            .filter(|key| !keys.contains(key))
+            .collect::<Vec<_>>();
+        removed.sort_unstable();
+        for key in removed {
+            if registry.is_frozen() {
+                errors.push(DataPackError {
+                    file: format!("{} of {}", key, kind),
+                    line: None,
+                    message: "removed entries are only removed after a restart".to_string(),
+                });
+            } else {
+                registry.unregister_material(&key);
+            }
+        }
+
+        let previous_tags = previous.tag_keys(kind);
+        match registry.remove_tags(&previous_tags) {
+            Ok(()) => {
+                let tags = self.read_tags(kind, &mut errors);
+                define_tags(kind, tags, registry, &mut errors);
+            }
+            Err(error) => errors.push(DataPackError {
+                file: format!("tags of {}", kind),
+                line: None,
+                message: error.to_string(),
+            }),
+        }
+
+        DataPackErrors::check(errors)?;
+        Ok(loaded)
+    }
+}
+
+/// Defines the tags of all files, a tag which can not be resolved is skipped (with all its files)
+/// and the error is reported at the files referencing the broken tag, the other tags are still defined.
+fn define_tags<T, M>(
+    kind: &str,
+    mut tags: Vec<(&PackFile, ResourceKey, TagDefinition)>,
+    registry: &mut Registry<T, M>,
+    errors: &mut Vec<DataPackError>,
+) where
+    T: Clone + Hash + PartialEq,
+{
+    let mut skipped = HashSet::new();
+    while !tags.is_empty() {
+        let definitions = tags
+            .iter()
+            .map(|(_, tag, definition)| (tag.clone(), definition.clone()));
+        let error = match registry.define_tags(definitions) {
+            Ok(()) => return,
+            Err(error) => error,
+        };
+
+        let (broken, message) = match &error {
+            TagError::Cycle(tag) => (Some(tag.clone()), error.to_string()),
+            //the error belongs to the tag including the unknown one
+            TagError::UnknownTag(unknown) => {
+                let including = tags
+                    .iter()
+                    .find(|(_, _, definition)| {
+                        definition
+                            .values
+                            .contains(&TagValue::Tag(unknown.clone()))
+                    })
+                    .map(|(_, tag, _)| tag.clone());
+                let message = if skipped.contains(unknown) {
+                    format!("the tag #{} could not be loaded", unknown)
+                } else {
+                    error.to_string()
+                };
+                (including, message)
+            }
+        };
+        //only the definitions already in the registry are broken, so none of the files can be fixed
+        let Some(broken) = broken.filter(|broken| tags.iter().any(|(_, tag, _)| tag == broken))
+        else {
+            errors.push(DataPackError {
+                file: format!("tags of {}", kind),
+                line: None,
+                message,
+            });
+            return;
+        };
+
+        //the value of a definition of the broken tag causing the error
+        let reference = |definition: &TagDefinition| {
+            definition.values.iter().find_map(|value| match (value, &error) {
+                (TagValue::Tag(included), TagError::UnknownTag(unknown)) if included == unknown => {
+                    Some(included.clone())
+                }
+                (TagValue::Tag(included), TagError::Cycle(_))
+                    if includes(&tags, included, &broken, &mut HashSet::new()) =>
+                {
+                    Some(included.clone())
+                }
+                _ => None,
+            })
+        };
+        let references = tags
+            .iter()
+            .filter(|(_, tag, _)| *tag == broken)
+            .map(|(file, _, definition)| (*file, reference(definition)))
+            .collect::<Vec<_>>();
+        let any_reference = references.iter().any(|(_, reference)| reference.is_some());
+        for (file, reference) in references {
+            if reference.is_some() || !any_reference {
+                let line = reference.and_then(|reference| file.line_of_tag(&reference));
+                errors.push(file.error(line, &message));
+            }
+        }
+        tags.retain(|(_, tag, _)| *tag != broken);
+        skipped.insert(broken);
+    }
+}
+
+/// whether [tag] is [target] or includes it (directly or through other tags) in the definitions of [tags]
+fn includes(
+    tags: &[(&PackFile, ResourceKey, TagDefinition)],
+    tag: &ResourceKey,
+    target: &ResourceKey,
+    visited: &mut HashSet<ResourceKey>,
+) -> bool {
+    if tag == target {
+        return true;
+    }
+    if !visited.insert(tag.clone()) {
+        return false;
+    }
+    tags.iter()
+        .filter(|(_, other, _)| other == tag)
+        .flat_map(|(_, _, definition)| definition.values.iter())
+        .any(|value| match value {
+            TagValue::Tag(included) => includes(tags, included, target, visited),
+            TagValue::Key(_) => false,
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPackError {
+    /// the pack and the path inside of it
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Display for DataPackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl std::error::Error for DataPackError {}
+
+/// all errors which occurred while loading
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPackErrors(pub Vec<DataPackError>);
+
+impl DataPackErrors {
+    fn check(errors: Vec<DataPackError>) -> Result<(), Self> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Self(errors))
+        }
+    }
+}
+
+impl From<DataPackError> for DataPackErrors {
+    fn from(error: DataPackError) -> Self {
+        Self(vec![error])
+    }
+}
+
+impl Display for DataPackErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.iter().join("\n"))
+    }
+}
+
+impl std::error::Error for DataPackErrors {}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::registry::RegistryChange;
+    use crate::resource_key;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Hash, serde::Deserialize)]
+    struct Block {
+        hardness: u32,
+    }
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn load() {
+        let root = std::env::temp_dir().join(format!("datapacks-{}", uuid::Uuid::new_v4()));
+        let base = root.join("a_base");
+        write(&base, "core/blocks/stone.json", r#"{"hardness": 3}"#);
+        write(&base, "core/blocks/ores/iron.toml", "hardness = 5");
+        write(
+            &base,
+            "core/tags/blocks/ores.json",
+            r#"{"values": ["ores/iron"]}"#,
+        );
+        write(
+            &base,
+            "core/tags/blocks/mineable.json",
+            r##"{"values": ["stone", "#ores"]}"##,
+        );
+        write(&base, "core/items/stick.json", "{}");
+        write(&base, "README.md", "ignored");
+
+        let mut zip = zip::ZipWriter::new(File::create(root.join("b_override.zip")).unwrap());
+        zip.start_file("core/blocks/stone.toml", Default::default())
+            .unwrap();
+        zip.write_all(b"hardness = 4").unwrap();
+        zip.start_file("test/blocks/gravel.json", Default::default())
+            .unwrap();
+        zip.write_all(b"{\"hardness\": 1}").unwrap();
+        zip.finish().unwrap();
+
+        let packs = DataPacks::discover(&root).unwrap();
+        assert_eq!(
+            packs.packs().iter().map(DataPack::name).collect::<Vec<_>>(),
+            vec!["a_base", "b_override.zip"]
+        );
+        let mut registry = Registry::<Block>::default();
+        assert_eq!(packs.load_registry("blocks", &mut registry), Ok(4));
+        assert_eq!(
+            registry.get_by_key(&resource_key!("stone")).unwrap().data,
+            Block { hardness: 4 }
+        );
+        assert!(registry.get_by_key(&resource_key!("test:gravel")).is_some());
+        let iron = registry.id_of_key(&resource_key!("ores/iron")).unwrap();
+        assert!(registry.is_in_tag(iron, &resource_key!("mineable")));
+
+        write(
+            &base,
+            "core/blocks/broken.json",
+            "{\n  \"hardness\": \"hard\"\n}",
+        );
+        write(&base, "core/blocks/broken2.toml", "\n\nhardness = ");
+        write(&base, "core/blocks/Invalid.json", r#"{"hardness": 1}"#);
+        let packs = DataPacks::discover(&root).unwrap();
+        let errors = packs
+            .load_registry("blocks", &mut Registry::<Block>::default())
+            .unwrap_err();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let errors = errors
+            .0
+            .iter()
+            .map(|error| (error.file.as_str(), error.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("a_base/core/blocks/Invalid.json", None),
+                ("a_base/core/blocks/broken.json", Some(2)),
+                ("a_base/core/blocks/broken2.toml", Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_tags() {
+        let root = std::env::temp_dir().join(format!("datapacks-{}", uuid::Uuid::new_v4()));
+        let pack = root.join("pack");
+        write(&pack, "core/blocks/stone.json", r#"{"hardness": 3}"#);
+        write(&pack, "core/tags/blocks/stones.json", r#"{"values": ["stone"]}"#);
+        write(
+            &pack,
+            "core/tags/blocks/missing.json",
+            "{\n  \"values\": [\n    \"stone\",\n    \"#unknown\"\n  ]\n}",
+        );
+        write(&pack, "core/tags/blocks/a.json", r##"{"values": ["#b"]}"##);
+        write(&pack, "core/tags/blocks/b.json", r##"{"values": ["#core:a"]}"##);
+
+        let packs = DataPacks::discover(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        let mut registry = Registry::<Block>::default();
+        let errors = packs.load_registry("blocks", &mut registry).unwrap_err();
+
+        let errors = errors
+            .0
+            .iter()
+            .map(|error| (error.file.as_str(), error.line, error.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "pack/core/tags/blocks/a.json",
+                    Some(1),
+                    "the tag #core:a includes itself"
+                ),
+                (
+                    "pack/core/tags/blocks/b.json",
+                    Some(1),
+                    "the tag #core:a could not be loaded"
+                ),
+                (
+                    "pack/core/tags/blocks/missing.json",
+                    Some(4),
+                    "the tag #core:unknown is not defined"
+                ),
+            ]
+        );
+        //the valid tag is still defined
+        let stone = registry.id_of_key(&resource_key!("stone")).unwrap();
+        assert!(registry.is_in_tag(stone, &resource_key!("stones")));
+        assert_eq!(registry.tags().count(), 1);
+    }
+
+    #[test]
+    fn reload() {
+        let root = std::env::temp_dir().join(format!("datapacks-{}", uuid::Uuid::new_v4()));
+        let pack = root.join("pack");
+        write(&pack, "core/blocks/stone.json", r#"{"hardness": 3}"#);
+        write(&pack, "core/blocks/dirt.json", r#"{"hardness": 1}"#);
+        write(&pack, "core/tags/blocks/hard.json", r#"{"values": ["stone"]}"#);
+        write(&pack, "core/tags/blocks/soft.json", r#"{"values": ["dirt"]}"#);
+        let previous = DataPacks::discover(&root).unwrap();
+        let mut registry = Registry::<Block>::default();
+        registry.register(resource_key!("builtin"), Block { hardness: 0 });
+        previous.load_registry("blocks", &mut registry).unwrap();
+        let dirt = registry.id_of_key(&resource_key!("dirt")).unwrap();
+        let mut frozen = registry.clone();
+        frozen.freeze();
+
+        write(&pack, "core/blocks/stone.json", r#"{"hardness": 4}"#);
+        write(&pack, "core/blocks/gravel.json", r#"{"hardness": 2}"#);
+        std::fs::remove_file(pack.join("core/blocks/dirt.json")).unwrap();
+        write(&pack, "core/tags/blocks/hard.json", r#"{"values": ["gravel"]}"#);
+        std::fs::remove_file(pack.join("core/tags/blocks/soft.json")).unwrap();
+        let packs = DataPacks::discover(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        registry.track_changes();
+        registry.drain_changes().for_each(drop);
+        assert_eq!(
+            packs.reload_registry(&previous, "blocks", &mut registry),
+            Ok(2)
+        );
+        let stone = registry.id_of_key(&resource_key!("stone")).unwrap();
+        assert_eq!(
+            registry.drain_changes().collect::<Vec<_>>(),
+            vec![
+                RegistryChange::Added(resource_key!("gravel"), 4),
+                RegistryChange::Changed(resource_key!("stone"), stone),
+                RegistryChange::Removed(resource_key!("dirt"), dirt),
+            ]
+        );
+        assert!(registry.get_by_key(&resource_key!("builtin")).is_some());
+        let gravel = registry.id_of_key(&resource_key!("gravel")).unwrap();
+        assert_eq!(
+            registry.tag_ids(&resource_key!("hard")).collect::<Vec<_>>(),
+            vec![gravel]
+        );
+        assert_eq!(registry.tags().count(), 1);
+
+        let errors = packs
+            .reload_registry(&previous, "blocks", &mut frozen)
+            .unwrap_err();
+        assert_eq!(errors.0.len(), 2);
+        assert_eq!(
+            frozen.get_by_key(&resource_key!("stone")).unwrap().data,
+            Block { hardness: 4 }
+        );
+        assert!(frozen.get_by_key(&resource_key!("dirt")).is_some());
+        //the tags are reloaded although the new entry is missing
+        assert!(!frozen.is_in_tag(stone, &resource_key!("hard")));
+        assert_eq!(frozen.tags().count(), 1);
+    }
+
+    #[test]
+    fn zip_file_size() {
+        let root = std::env::temp_dir().join(format!("datapacks-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("large.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("core/blocks/large.json", Default::default())
+            .unwrap();
+        zip.write_all(&vec![b' '; MAX_ZIP_FILE_SIZE as usize + 1])
+            .unwrap();
+        zip.finish().unwrap();
+        let result = DataPack::open(&path, 0);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let errors = result.unwrap_err();
+        assert!(errors.0[0]
+            .message
+            .contains("core/blocks/large.json exceeds the maximum size"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Reloads the data packs at runtime when their files change, meant for developing content.
+//!
+//! The [DataPackWatchPlugin] reads the packs of a directory in the first update. When [watching](DataPackWatchPlugin::watch)
+//! it afterwards polls the modification times of the directory and sends [DataPacksReloaded] after the packs were read again. A [ReloadRegistryPlugin] per registry applies the
+//! new content (see [DataPacks::reload_registry]); combined with a [RegistryEventsPlugin](crate::registry::events::RegistryEventsPlugin)
+//! the dependent systems (f.e. the materials and meshes of the client) get the changed entries as events.
+
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use bevy::log::{info, warn};
+use bevy::prelude::{
+    App, Event, EventReader, EventWriter, IntoSystemConfigs, IntoSystemSetConfigs, Plugin,
+    PreUpdate, Res, ResMut, Resource, Time, Timer, TimerMode,
+};
+use serde::de::DeserializeOwned;
+
+use crate::datapack::DataPacks;
+use crate::registry::events::RegistrySet;
+use crate::registry::{NoMarker, Registry};
+
+/// sent after the packs were read again, the [DataPacks] resource contains the new packs
+#[derive(Debug, Event)]
+pub struct DataPacksReloaded {
+    /// the packs before the reload, needed to find removed entries. [None] for the first read of the packs
+    pub previous: Option<DataPacks>,
+}
+
+/// polls the modification times of all files in a directory
+#[derive(Debug, Resource)]
+pub struct DataPackWatcher {
+    directory: PathBuf,
+    /// [None] if the packs are only read once
+    timer: Option<Timer>,
+    /// every file with its modification time ordered by the path
+    files: Vec<(PathBuf, SystemTime)>,
+    /// whether the packs were read at least once
+    loaded: bool,
+}
+
+impl DataPackWatcher {
+    /// polls the files every [interval] after the first read, [None] only reads them once
+    pub fn new(directory: PathBuf, interval: Option<Duration>) -> Self {
+        Self {
+            directory,
+            timer: interval.map(|interval| Timer::new(interval, TimerMode::Repeating)),
+            files: Vec::new(),
+            loaded: false,
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// whether a file was added, removed or modified since the last call
+    pub fn poll(&mut self) -> bool {
+        let files = scan(&self.directory);
+        if files == self.files {
+            return false;
+        }
+        self.files = files;
+        true
+    }
+}
+
+/// unreadable files and directories are skipped, they are picked up again once they can be read
+fn scan(directory: &Path) -> Vec<(PathBuf, SystemTime)> {
+    fn visit(directory: &Path, files: &mut Vec<(PathBuf, SystemTime)>) {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                visit(&entry.path(), files);
+            } else if let Ok(modified) = metadata.modified() {
+                files.push((entry.path(), modified));
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    visit(directory, &mut files);
+    files.sort_unstable();
+    files
+}
+
+/// reads the packs of [DataPackWatchPlugin::directory] (see [DataPacks::discover]) and watches them if enabled
+pub struct DataPackWatchPlugin {
+    pub directory: PathBuf,
+    /// the interval of polling the files, [None] (the default) only reads the packs once
+    pub interval: Option<Duration>,
+}
+
+impl DataPackWatchPlugin {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            interval: None,
+        }
+    }
+
+    /// reloads the packs when their files change, meant for developing content
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.interval = watch.then_some(Duration::from_secs(1));
+        self
+    }
+}
+
+impl Plugin for DataPackWatchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DataPacks>()
+            .insert_resource(DataPackWatcher::new(self.directory.clone(), self.interval))
+            .add_event::<DataPacksReloaded>()
+            .configure_sets(PreUpdate, RegistrySet::Reload.before(RegistrySet::Events))
+            .add_systems(PreUpdate, reload_data_packs.in_set(RegistrySet::Reload));
+    }
+}
+
+fn reload_data_packs(
+    time: Res<Time>,
+    mut watcher: ResMut<DataPackWatcher>,
+    mut packs: ResMut<DataPacks>,
+    mut reloaded: EventWriter<DataPacksReloaded>,
+) {
+    let first = !watcher.loaded;
+    if !first {
+        let Some(timer) = watcher.timer.as_mut() else {
+            return;
+        };
+        if !timer.tick(time.delta()).just_finished() {
+            return;
+        }
+    }
+    //the first read also records the files for the later polls
+    if !watcher.poll() && !first {
+        return;
+    }
+    watcher.loaded = true;
+    match DataPacks::discover(watcher.directory()) {
+        Ok(new) => {
+            info!("read the data packs of {}", watcher.directory().display());
+            let previous = std::mem::replace(&mut *packs, new);
+            reloaded.send(DataPacksReloaded {
+                previous: (!first).then_some(previous),
+            });
+        }
+        //the old packs stay active until the errors are fixed
+        Err(errors) => warn!("failed to read the data packs:\n{}", errors),
+    }
+}
+
+/// loads the data packs into the [Registry] with the data [T] and the marker [M] and applies the reloaded ones
+pub struct ReloadRegistryPlugin<T, M = NoMarker> {
+    /// the kind of the files, see [DataPacks::load_registry]
+    pub kind: &'static str,
+    _marker: PhantomData<fn() -> (T, M)>,
+}
+
+impl<T, M> ReloadRegistryPlugin<T, M> {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, M> Plugin for ReloadRegistryPlugin<T, M>
+where
+    T: DeserializeOwned + Clone + Hash + PartialEq + Send + Sync + 'static,
+    M: Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        let kind = self.kind;
+        let reload_registry =
+            move |mut reloaded: EventReader<DataPacksReloaded>,
+                  packs: Res<DataPacks>,
+                  registry: Option<ResMut<Registry<T, M>>>| {
+                let Some(mut registry) = registry else {
+                    reloaded.clear();
+                    return;
+                };
+                for event in reloaded.read() {
+                    let result = match &event.previous {
+                        Some(previous) => packs.reload_registry(previous, kind, &mut registry),
+                        None => packs.load_registry(kind, &mut registry),
+                    };
+                    match result {
+                        Ok(loaded) => info!("loaded {} entries of {}", loaded, kind),
+                        Err(errors) => warn!("failed to load {}:\n{}", kind, errors),
+                    }
+                }
+            };
+        app.add_systems(
+            PreUpdate,
+            reload_registry
+                .in_set(RegistrySet::Reload)
+                .after(reload_data_packs),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resource_key;
+
+    #[test]
+    fn first_update_loads() {
+        let root = std::env::temp_dir().join(format!("datapacks-{}", uuid::Uuid::new_v4()));
+        let file = root.join("pack/core/blocks/stone.json");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, "3").unwrap();
+
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<Registry<u32>>()
+            .add_plugins((
+                DataPackWatchPlugin::new(&root),
+                ReloadRegistryPlugin::<u32>::new("blocks"),
+            ));
+        app.update();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let registry = app.world.resource::<Registry<u32>>();
+        assert_eq!(
+            registry.get_by_key(&resource_key!("stone")).map(|entry| entry.data),
+            Some(3)
+        );
+    }
+}
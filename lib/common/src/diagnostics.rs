@@ -0,0 +1,379 @@
+//! Memory and compression diagnostics of the world data.
+//!
+//! The [WorldDiagnosticsPlugin] measures the registered sources (chunk storages, compressed chunks, meshes, ...)
+//! every [WorldDiagnosticsPlugin::interval]. The results are available in two ways:
+//!  - the [WorldMemoryReport] resource with all details, its [Display] output is meant for consoles
+//!  - bevy [Diagnostics] at `world/memory/<source>` (bytes), `world/palette/<source>` (average palette size)
+//!    and `world/compression/<source>` (uncompressed / compressed), f.e. for debug overlays
+//!
+//! Sources are added with the methods of [WorldDiagnosticsAppExt].
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::Hash;
+use std::time::Duration;
+
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::{
+    App, Component, First, IntoSystemConfigs, Last, Plugin, Query, Res, ResMut, Resource, Time,
+    Timer, TimerMode,
+};
+use mesher::b32::VoxelCubeOcclusionMatrix32;
+
+use crate::compressible::Compressed;
+use crate::humanize::humanize_memory;
+use crate::storage::Storage;
+
+/// the estimated memory usage in bytes including the size of the value itself
+pub trait MemoryUsage {
+    fn memory_usage(&self) -> usize;
+}
+
+impl<const SIZE: usize, ITEM> MemoryUsage for Storage<SIZE, ITEM>
+where
+    ITEM: Debug + Clone + Ord + Eq + Hash + Send + Sync,
+{
+    fn memory_usage(&self) -> usize {
+        Storage::memory_usage(self)
+    }
+}
+
+impl<T: serde::Serialize, A> MemoryUsage for Compressed<T, A> {
+    fn memory_usage(&self) -> usize {
+        Compressed::memory_usage(self)
+    }
+}
+
+impl MemoryUsage for VoxelCubeOcclusionMatrix32 {
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+}
+
+/// the measurement of one source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceReport {
+    pub count: usize,
+    pub bytes: usize,
+    /// the number of storages by their palette size rounded up to the next power of two
+    pub palette_sizes: BTreeMap<usize, usize>,
+    /// the sum of the exact palette sizes
+    pub palette_entries: usize,
+    /// the uncompressed and compressed bytes of compressed sources
+    pub compression: Option<(usize, usize)>,
+}
+
+impl SourceReport {
+    pub fn add_palette(&mut self, size: usize) {
+        self.palette_entries += size;
+        *self
+            .palette_sizes
+            .entry(size.next_power_of_two())
+            .or_default() += 1;
+    }
+
+    pub fn add_compressed(&mut self, uncompressed: usize, compressed: usize) {
+        let (total_uncompressed, total_compressed) = self.compression.get_or_insert((0, 0));
+        *total_uncompressed += uncompressed;
+        *total_compressed += compressed;
+    }
+
+    /// uncompressed / compressed
+    pub fn compression_ratio(&self) -> Option<f64> {
+        self.compression
+            .filter(|(_, compressed)| *compressed > 0)
+            .map(|(uncompressed, compressed)| uncompressed as f64 / compressed as f64)
+    }
+
+    /// the average of the exact palette sizes
+    pub fn average_palette_size(&self) -> Option<f64> {
+        let storages = self.palette_sizes.values().sum::<usize>();
+        (storages > 0).then(|| self.palette_entries as f64 / storages as f64)
+    }
+}
+
+impl Display for SourceReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in {} entries",
+            humanize_memory(self.bytes),
+            self.count
+        )?;
+        if let Some(ratio) = self.compression_ratio() {
+            write!(f, ", compression ratio {:.2}", ratio)?;
+        }
+        if !self.palette_sizes.is_empty() {
+            write!(f, ", palette sizes")?;
+            for (size, count) in &self.palette_sizes {
+                write!(f, " <={}: {}", size, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the latest measurement of all sources
+#[derive(Debug, Clone, Default, Resource)]
+pub struct WorldMemoryReport {
+    sources: BTreeMap<&'static str, SourceReport>,
+}
+
+impl WorldMemoryReport {
+    pub fn get(&self, source: &str) -> Option<&SourceReport> {
+        self.sources.get(source)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &'_ SourceReport)> + '_ {
+        self.sources.iter().map(|(name, report)| (*name, report))
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.sources.values().map(|report| report.bytes).sum()
+    }
+}
+
+impl Display for WorldMemoryReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, report) in &self.sources {
+            writeln!(f, "{}: {}", name, report)?;
+        }
+        write!(f, "total: {}", humanize_memory(self.total_bytes()))
+    }
+}
+
+/// finishes when the sources should be measured again
+#[derive(Debug, Resource)]
+struct MeasureTimer(Timer);
+
+fn tick_measure_timer(time: Res<Time>, mut timer: ResMut<MeasureTimer>) {
+    timer.0.tick(time.delta());
+}
+
+fn measure_now(timer: Res<MeasureTimer>) -> bool {
+    timer.0.just_finished()
+}
+
+/// measures the sources added by [WorldDiagnosticsAppExt] periodically
+pub struct WorldDiagnosticsPlugin {
+    pub interval: Duration,
+}
+
+impl Default for WorldDiagnosticsPlugin {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Plugin for WorldDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldMemoryReport>()
+            .insert_resource(MeasureTimer(Timer::new(
+                self.interval,
+                TimerMode::Repeating,
+            )))
+            .add_systems(First, tick_measure_timer);
+    }
+}
+
+pub trait WorldDiagnosticsAppExt {
+    /// measures the memory of every component [C]
+    fn add_memory_source<C: Component + MemoryUsage>(&mut self, name: &'static str) -> &mut Self;
+
+    /// measures the memory and the palette sizes of every [Storage] component
+    fn add_storage_source<const SIZE: usize, ITEM>(&mut self, name: &'static str) -> &mut Self
+    where
+        ITEM: Debug + Clone + Ord + Eq + Hash + Send + Sync + 'static;
+
+    /// measures the memory and the compression ratio of every [Compressed] component
+    fn add_compressed_source<T, A>(&mut self, name: &'static str) -> &mut Self
+    where
+        T: serde::Serialize + Send + Sync + 'static,
+        A: Send + Sync + 'static;
+
+    /// measures a resource, f.e. the mesh assets
+    fn add_resource_source<R: Resource>(
+        &mut self,
+        name: &'static str,
+        measure: fn(&R) -> SourceReport,
+    ) -> &mut Self;
+}
+
+impl WorldDiagnosticsAppExt for App {
+    fn add_memory_source<C: Component + MemoryUsage>(&mut self, name: &'static str) -> &mut Self {
+        add_component_source::<C>(self, name, |report, component| {
+            report.bytes += component.memory_usage();
+        })
+    }
+
+    fn add_storage_source<const SIZE: usize, ITEM>(&mut self, name: &'static str) -> &mut Self
+    where
+        ITEM: Debug + Clone + Ord + Eq + Hash + Send + Sync + 'static,
+    {
+        add_component_source::<Storage<SIZE, ITEM>>(self, name, |report, storage| {
+            report.bytes += storage.memory_usage();
+            report.add_palette(storage.palette().len());
+        })
+    }
+
+    fn add_compressed_source<T, A>(&mut self, name: &'static str) -> &mut Self
+    where
+        T: serde::Serialize + Send + Sync + 'static,
+        A: Send + Sync + 'static,
+    {
+        add_component_source::<Compressed<T, A>>(self, name, |report, compressed| {
+            report.bytes += compressed.memory_usage();
+            report.add_compressed(compressed.len_data(), compressed.len_compressed());
+        })
+    }
+
+    fn add_resource_source<R: Resource>(
+        &mut self,
+        name: &'static str,
+        measure: fn(&R) -> SourceReport,
+    ) -> &mut Self {
+        let paths = SourcePaths::register(self, name);
+        self.add_systems(
+            Last,
+            (move |resource: Option<Res<R>>,
+                   mut report: ResMut<WorldMemoryReport>,
+                   mut diagnostics: Diagnostics| {
+                let source = resource
+                    .map(|resource| measure(&resource))
+                    .unwrap_or_default();
+                paths.record(source, &mut report, &mut diagnostics);
+            })
+            .run_if(measure_now),
+        )
+    }
+}
+
+fn add_component_source<'a, C: Component>(
+    app: &'a mut App,
+    name: &'static str,
+    measure: fn(&mut SourceReport, &C),
+) -> &'a mut App {
+    let paths = SourcePaths::register(app, name);
+    app.add_systems(
+        Last,
+        (move |components: Query<&C>,
+               mut report: ResMut<WorldMemoryReport>,
+               mut diagnostics: Diagnostics| {
+            let mut source = SourceReport::default();
+            for component in &components {
+                source.count += 1;
+                measure(&mut source, component);
+            }
+            paths.record(source, &mut report, &mut diagnostics);
+        })
+        .run_if(measure_now),
+    )
+}
+
+#[derive(Clone)]
+struct SourcePaths {
+    name: &'static str,
+    memory: DiagnosticPath,
+    palette: DiagnosticPath,
+    compression: DiagnosticPath,
+}
+
+impl SourcePaths {
+    fn register(app: &mut App, name: &'static str) -> Self {
+        let paths = Self {
+            name,
+            memory: DiagnosticPath::new(format!("world/memory/{}", name)),
+            palette: DiagnosticPath::new(format!("world/palette/{}", name)),
+            compression: DiagnosticPath::new(format!("world/compression/{}", name)),
+        };
+        app.register_diagnostic(Diagnostic::new(paths.memory.clone()).with_suffix(" B"))
+            .register_diagnostic(Diagnostic::new(paths.palette.clone()))
+            .register_diagnostic(Diagnostic::new(paths.compression.clone()));
+        paths
+    }
+
+    fn record(
+        &self,
+        source: SourceReport,
+        report: &mut WorldMemoryReport,
+        diagnostics: &mut Diagnostics,
+    ) {
+        diagnostics.add_measurement(&self.memory, || source.bytes as f64);
+        if let Some(palette) = source.average_palette_size() {
+            diagnostics.add_measurement(&self.palette, || palette);
+        }
+        if let Some(ratio) = source.compression_ratio() {
+            diagnostics.add_measurement(&self.compression, || ratio);
+        }
+        report.sources.insert(self.name, source);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::diagnostic::DiagnosticsStore;
+
+    use crate::compressible::{Compressible, LZ4};
+
+    use super::*;
+
+    type TestStorage = Storage<64, u32>;
+
+    #[derive(Resource)]
+    struct Meshes(Vec<usize>);
+
+    #[test]
+    fn measure() {
+        let mut app = App::new();
+        app.add_plugins(WorldDiagnosticsPlugin {
+            interval: Duration::ZERO,
+        })
+        .add_storage_source::<64, u32>("storages")
+        .add_compressed_source::<Vec<u32>, LZ4>("compressed")
+        .add_resource_source::<Meshes>("meshes", |meshes| SourceReport {
+            count: meshes.0.len(),
+            bytes: meshes.0.iter().sum(),
+            ..Default::default()
+        })
+        .insert_resource(Meshes(vec![100, 200]))
+        .init_resource::<Time>();
+
+        let mut mixed = TestStorage::empty();
+        mixed.set(0, 1);
+        mixed.set(1, 2);
+        app.world.spawn(TestStorage::empty());
+        app.world.spawn(mixed);
+        app.world.spawn(vec![0u32; 1024].compress_lz4());
+        app.update();
+
+        let report = app.world.resource::<WorldMemoryReport>();
+        let storages = report.get("storages").unwrap();
+        assert_eq!(storages.count, 2);
+        assert_eq!(storages.palette_sizes, BTreeMap::from([(1, 1), (4, 1)]));
+        assert!(
+            report
+                .get("compressed")
+                .unwrap()
+                .compression_ratio()
+                .unwrap()
+                > 10.0
+        );
+        assert_eq!(report.get("meshes").unwrap().bytes, 300);
+        assert!(report.total_bytes() > 300);
+        assert!(report.to_string().starts_with("compressed: "));
+
+        let diagnostics = app.world.resource::<DiagnosticsStore>();
+        let meshes = diagnostics
+            .get(&DiagnosticPath::const_new("world/memory/meshes"))
+            .and_then(|diagnostic| diagnostic.value());
+        assert_eq!(meshes, Some(300.0));
+        assert_eq!(
+            diagnostics
+                .get(&DiagnosticPath::const_new("world/palette/storages"))
+                .and_then(|diagnostic| diagnostic.value()),
+            Some(2.0)
+        );
+    }
+}
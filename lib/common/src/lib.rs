@@ -3,16 +3,24 @@ use std::marker::PhantomData;
 
 use bevy::prelude::*;
 use bevy::tasks::{block_on, poll_once, Task};
+use mesher::FaceDirection;
+
+use crate::storage::SliceAxis;
 
 pub mod bundle;
 pub mod compressible;
+pub mod coordinate;
+pub mod datapack;
+pub mod diagnostics;
 pub mod humanize;
 pub mod lzw;
 pub mod network;
+pub mod palette;
 pub mod protocol;
 pub mod registry;
 pub mod resource;
 pub mod storage;
+pub mod task;
 pub mod utils;
 
 pub const CHUNK_SIZE: usize = 32;
@@ -57,6 +65,7 @@ where
 }
 
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AxialRotation {
     Zero = 0,
     By90 = 1,
@@ -114,6 +123,7 @@ impl AxialRotation {
 }
 
 /// Indicates the face of a block, cubes or some other block-like object.
+/// The axes are east = +x, up = +y and north = +z, [FaceDirection] of the mesher converts losslessly into it.
 #[repr(u8)]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Component)]
 pub enum Direction {
@@ -127,6 +137,15 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::East,
+        Direction::West,
+        Direction::Up,
+        Direction::Down,
+        Direction::North,
+        Direction::South,
+    ];
+
     pub fn get_index(&self) -> u32 {
         match self {
             Direction::Up => DIRECTION_UP,
@@ -158,6 +177,13 @@ impl Direction {
             _ => panic!("there is no direction with index {}", index),
         }
     }
+    /// the direction of an unit vector along one axis
+    pub fn from_vector(x: i32, y: i32, z: i32) -> Option<Direction> {
+        Direction::ALL
+            .into_iter()
+            .find(|direction| direction.get_vector_values() == (x, y, z))
+    }
+
     pub fn is_negative(&self) -> bool {
         match self {
             Direction::East | Direction::Up | Direction::North => false,
@@ -176,6 +202,22 @@ impl Direction {
         }
     }
 
+    /// the axis of this direction, the sign is given by [Direction::is_negative]
+    pub fn axis(&self) -> SliceAxis {
+        match self {
+            Direction::East | Direction::West => SliceAxis::X,
+            Direction::Up | Direction::Down => SliceAxis::Y,
+            Direction::North | Direction::South => SliceAxis::Z,
+        }
+    }
+
+    /// Maps a position on a face of a cube to the index of the block (in [Storage](storage::Storage) order).
+    /// [index] is `u + v * dimension_size` where u and v are the two remaining axes in xyz order.
+    ///
+    /// The faces are not the ones of [Direction::get_vector_values]: east and west are the z = 0 and z = max faces,
+    /// south and north the x = 0 and x = max faces and down and up the y = 0 and y = max faces.
+    /// Opposite directions share the surface index, so `d.inner_perspective_faced_index(i)`
+    /// and `d.outer_perspective_faced_index(i)` only differ in the layer.
     pub fn inner_perspective_faced_index(&self, index: usize, dimension_size: usize) -> usize {
         assert!(
             index < dimension_size * dimension_size,
@@ -187,29 +229,51 @@ impl Direction {
         if dimension_size == 1 {
             return 0;
         }
-        let (surface_x, surface_y) = (index % dimension_size, index / dimension_size);
+        let (u, v) = (index % dimension_size, index / dimension_size);
+        let max = dimension_size - 1;
         let (x, y, z) = match self {
-            Direction::East => (surface_x, surface_y, 0),
-            Direction::West => (
-                dimension_size - 1 - surface_x,
-                surface_y,
-                dimension_size - 1,
-            ),
-            Direction::Up => (
-                surface_x,
-                dimension_size - 1,
-                dimension_size - 1 - surface_y,
-            ),
-            Direction::Down => (surface_x, 0, surface_y),
-            Direction::North => (surface_x, surface_y, dimension_size - 1),
-            Direction::South => (dimension_size - 1 - surface_x, surface_y, 0),
+            Direction::East => (u, v, 0),
+            Direction::West => (u, v, max),
+            Direction::Up => (u, max, v),
+            Direction::Down => (u, 0, v),
+            Direction::North => (max, u, v),
+            Direction::South => (0, u, v),
         };
         //result (cords back to index)
         x + y * dimension_size + z * dimension_size * dimension_size
     }
+
+    /// the block of the opposite face of a cube which has the same surface [index]
     #[inline]
-    pub fn outer_perspective_faced_index(&self, size: usize, index: usize) -> usize {
-        self.opposite().inner_perspective_faced_index(size, index)
+    pub fn outer_perspective_faced_index(&self, index: usize, dimension_size: usize) -> usize {
+        self.opposite()
+            .inner_perspective_faced_index(index, dimension_size)
+    }
+}
+
+impl From<Direction> for FaceDirection {
+    fn from(value: Direction) -> Self {
+        match value {
+            Direction::East => FaceDirection::XPos,
+            Direction::West => FaceDirection::XNeg,
+            Direction::Up => FaceDirection::YPos,
+            Direction::Down => FaceDirection::YNeg,
+            Direction::North => FaceDirection::ZPos,
+            Direction::South => FaceDirection::ZNeg,
+        }
+    }
+}
+
+impl From<FaceDirection> for Direction {
+    fn from(value: FaceDirection) -> Self {
+        match value {
+            FaceDirection::XPos => Direction::East,
+            FaceDirection::XNeg => Direction::West,
+            FaceDirection::YPos => Direction::Up,
+            FaceDirection::YNeg => Direction::Down,
+            FaceDirection::ZPos => Direction::North,
+            FaceDirection::ZNeg => Direction::South,
+        }
     }
 }
 
@@ -220,8 +284,14 @@ pub const DIRECTION_DOWN: u32 = 3;
 pub const DIRECTION_NORTH: u32 = 4;
 pub const DIRECTION_SOUTH: u32 = 5;
 
-//TODO store mirroring bits
-/// represents all possible rottations of a block in a single byte
+/// represents all possible rotations (and mirroring) of a block in a single byte
+///
+/// the layout is `0b00MRRDDD`:
+///  - `D` the [Direction] the local up (+y) axis of the block is turned to
+///  - `R` the [AxialRotation] around local up (clockwise when looking down on the block)
+///  - `M` the block is mirrored along its local x axis (applied before the rotation)
+///
+/// together they form the 48 orientations of a cube (24 rotations with and without mirroring)
 #[derive(
     Debug,
     Clone,
@@ -237,6 +307,13 @@ pub const DIRECTION_SOUTH: u32 = 5;
 )]
 pub struct Positioning(u8);
 
+/// a 3x3 matrix (row major) with only -1, 0 and 1 as values
+pub(crate) type OrientationMatrix = [[i8; 3]; 3];
+
+const IDENTITY_MATRIX: OrientationMatrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+const MIRROR_X_MATRIX: OrientationMatrix = [[-1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
 impl Default for Positioning {
     fn default() -> Self {
         Self::new(Direction::Up, AxialRotation::Zero)
@@ -244,16 +321,239 @@ impl Default for Positioning {
 }
 
 impl Positioning {
+    const MIRROR_BIT: u8 = 0b00100000;
+
+    pub const IDENTITY: Positioning = Positioning(DIRECTION_UP as u8);
+
     pub fn new(direction: Direction, axial_rotation: AxialRotation) -> Positioning {
         Positioning((direction.get_index() | (axial_rotation.to_index() << 3)) as u8)
     }
 
+    pub fn new_mirrored(direction: Direction, axial_rotation: AxialRotation) -> Positioning {
+        Positioning(Self::new(direction, axial_rotation).0 | Self::MIRROR_BIT)
+    }
+
     pub fn direction(&self) -> Direction {
         Direction::from_index((self.0 & 0b00000111) as u32)
     }
     pub fn get_axial_rotation(&self) -> AxialRotation {
         AxialRotation::from_index(((self.0 & 0b00011000) >> 3) as u32)
     }
+    pub fn is_mirrored(&self) -> bool {
+        self.0 & Self::MIRROR_BIT != 0
+    }
+
+    /// all 48 possible values
+    pub fn all() -> impl Iterator<Item = Positioning> {
+        Direction::ALL.into_iter().flat_map(|direction| {
+            (0..8).map(move |i| {
+                let rotation = AxialRotation::from_index(i % 4);
+                if i < 4 {
+                    Positioning::new(direction, rotation)
+                } else {
+                    Positioning::new_mirrored(direction, rotation)
+                }
+            })
+        })
+    }
+
+    /// the orientation as matrix which transforms local block coordinates (relative to the center) into world coordinates
+    pub(crate) fn matrix(&self) -> OrientationMatrix {
+        let direction = self.direction();
+        let quarter = quarter_turn(direction);
+        let mut matrix = base_matrix(direction);
+        for _ in 0..self.get_axial_rotation().to_index() {
+            matrix = multiply(&quarter, &matrix);
+        }
+        if self.is_mirrored() {
+            matrix = multiply(&matrix, &MIRROR_X_MATRIX);
+        }
+        matrix
+    }
+
+    /// the positioning of an orthogonal axis aligned matrix
+    fn from_matrix(matrix: &OrientationMatrix) -> Positioning {
+        let column = |matrix: &OrientationMatrix, column: usize| {
+            [matrix[0][column], matrix[1][column], matrix[2][column]]
+        };
+        //the local up axis is turned to the direction
+        let [x, y, z] = column(matrix, 1);
+        let direction = Direction::from_vector(x as i32, y as i32, z as i32)
+            .expect("every orthogonal axis aligned matrix is a positioning");
+        //a mirrored matrix flips the handedness
+        let handedness = cross(&column(matrix, 0), &column(matrix, 1));
+        let mirrored = dot(&handedness, &column(matrix, 2)) < 0;
+        let unmirrored = if mirrored {
+            multiply(matrix, &MIRROR_X_MATRIX)
+        } else {
+            *matrix
+        };
+        //count the quarter turns which move the local x axis of the base to the one of the matrix
+        let target = column(&unmirrored, 0);
+        let quarter = quarter_turn(direction);
+        let mut x_axis = column(&base_matrix(direction), 0);
+        let mut rotation = AxialRotation::Zero;
+        while x_axis != target {
+            x_axis = transform(&quarter, x_axis.map(i32::from)).map(|value| value as i8);
+            rotation = rotation.clockwise();
+        }
+        if mirrored {
+            Positioning::new_mirrored(direction, rotation)
+        } else {
+            Positioning::new(direction, rotation)
+        }
+    }
+
+    /// applies [self] first and [next] afterwards
+    pub fn then(&self, next: Positioning) -> Positioning {
+        Self::from_matrix(&multiply(&next.matrix(), &self.matrix()))
+    }
+
+    /// the positioning which reverts [self]
+    pub fn inverse(&self) -> Positioning {
+        let matrix = self.matrix();
+        //orthogonal matrices are inverted by transposing them
+        let mut transposed = [[0; 3]; 3];
+        for (row, values) in matrix.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                transposed[column][row] = *value;
+            }
+        }
+        Self::from_matrix(&transposed)
+    }
+
+    /// [self] followed by mirroring along the world [axis]
+    pub fn mirror(&self, axis: SliceAxis) -> Positioning {
+        let mut mirror = IDENTITY_MATRIX;
+        let axis = match axis {
+            SliceAxis::X => 0,
+            SliceAxis::Y => 1,
+            SliceAxis::Z => 2,
+        };
+        mirror[axis][axis] = -1;
+        Self::from_matrix(&multiply(&mirror, &self.matrix()))
+    }
+
+    /// the world direction of the local [direction] of the block
+    pub fn rotate_direction(&self, direction: Direction) -> Direction {
+        let (x, y, z) = direction.get_vector_values();
+        let [x, y, z] = transform(&self.matrix(), [x, y, z]);
+        Direction::from_vector(x, y, z).expect("rotating an unit vector results in an unit vector")
+    }
+
+    /// the dimensions of a box with [size] after transforming it
+    pub fn transform_size(&self, size: (usize, usize, usize)) -> (usize, usize, usize) {
+        transform_size(&self.matrix(), size)
+    }
+
+    /// transforms a position inside a box with the dimensions of [size]
+    /// the result is inside the box of [Positioning::transform_size]
+    pub fn transform_local(
+        &self,
+        position: (usize, usize, usize),
+        size: (usize, usize, usize),
+    ) -> (usize, usize, usize) {
+        assert!(
+            position.0 < size.0 && position.1 < size.1 && position.2 < size.2,
+            "position {:?} is outside of the box {:?}",
+            position,
+            size
+        );
+        let matrix = self.matrix();
+        transform_local(&matrix, position, size, transform_size(&matrix, size))
+    }
+}
+
+/// turns the local up axis to [direction]
+fn base_matrix(direction: Direction) -> OrientationMatrix {
+    match direction {
+        Direction::Up => IDENTITY_MATRIX,
+        Direction::Down => [[1, 0, 0], [0, -1, 0], [0, 0, -1]],
+        Direction::North => [[1, 0, 0], [0, 0, -1], [0, 1, 0]],
+        Direction::South => [[1, 0, 0], [0, 0, 1], [0, -1, 0]],
+        Direction::East => [[0, 1, 0], [-1, 0, 0], [0, 0, 1]],
+        Direction::West => [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+    }
+}
+
+/// clockwise quarter turn around [direction]: `up * up^T - [up]x`
+fn quarter_turn(direction: Direction) -> OrientationMatrix {
+    let (x, y, z) = direction.get_vector_values();
+    let up = [x as i8, y as i8, z as i8];
+    [
+        [up[0] * up[0], up[1] * up[0] + up[2], up[2] * up[0] - up[1]],
+        [up[0] * up[1] - up[2], up[1] * up[1], up[2] * up[1] + up[0]],
+        [up[0] * up[2] + up[1], up[1] * up[2] - up[0], up[2] * up[2]],
+    ]
+}
+
+/// see [Positioning::transform_size]
+pub(crate) fn transform_size(
+    matrix: &OrientationMatrix,
+    size: (usize, usize, usize),
+) -> (usize, usize, usize) {
+    let [x, y, z] = transform(matrix, [size.0 as i32, size.1 as i32, size.2 as i32]);
+    (
+        x.unsigned_abs() as usize,
+        y.unsigned_abs() as usize,
+        z.unsigned_abs() as usize,
+    )
+}
+
+/// see [Positioning::transform_local], [target_size] is the [transform_size] of [size]
+#[inline]
+pub(crate) fn transform_local(
+    matrix: &OrientationMatrix,
+    position: (usize, usize, usize),
+    size: (usize, usize, usize),
+    target_size: (usize, usize, usize),
+) -> (usize, usize, usize) {
+    //use doubled coordinates to rotate around the center without fractions
+    let centered = |position: usize, size: usize| 2 * position as i32 - (size as i32 - 1);
+    let [x, y, z] = transform(
+        matrix,
+        [
+            centered(position.0, size.0),
+            centered(position.1, size.1),
+            centered(position.2, size.2),
+        ],
+    );
+    let uncentered = |position: i32, size: usize| ((position + size as i32 - 1) / 2) as usize;
+    (
+        uncentered(x, target_size.0),
+        uncentered(y, target_size.1),
+        uncentered(z, target_size.2),
+    )
+}
+
+fn multiply(a: &OrientationMatrix, b: &OrientationMatrix) -> OrientationMatrix {
+    let mut result = [[0; 3]; 3];
+    for (row, values) in result.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    result
+}
+
+fn cross(a: &[i8; 3], b: &[i8; 3]) -> [i8; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: &[i8; 3], b: &[i8; 3]) -> i8 {
+    (0..3).map(|i| a[i] * b[i]).sum()
+}
+
+fn transform(matrix: &OrientationMatrix, vector: [i32; 3]) -> [i32; 3] {
+    let mut result = [0; 3];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = (0..3).map(|i| matrix[row][i] as i32 * vector[i]).sum();
+    }
+    result
 }
 
 pub trait WithFixedSizeExt<T> {
@@ -273,9 +573,12 @@ impl<T> WithFixedSizeExt<T> for Vec<T> {
 mod test_perspective_faced_index {
     use std::ops::Range;
 
+    use mesher::FaceDirection;
+
+    use crate::Direction;
+
     const DIMENSION_SIZE: usize = 4;
     const DIMENSION_FACE_SURFACE: usize = DIMENSION_SIZE * DIMENSION_SIZE;
-    const DIMENSION_VOLUME: usize = DIMENSION_SIZE * DIMENSION_SIZE * DIMENSION_SIZE;
     const INPUT_RANGE: Range<usize> = 0..DIMENSION_FACE_SURFACE;
 
     //@formatter:off
@@ -319,15 +622,14 @@ mod test_perspective_faced_index {
 
     #[rustfmt::skip]
     const EXPECTED_SOUTH: [usize; DIMENSION_FACE_SURFACE] = [
-        48, 49, 50, 51,
-        52, 53, 54, 55,
-        56, 57, 58, 59,
-        60, 61, 62, 63
+        3,  7,  11, 15,
+        19, 23, 27, 31,
+        35, 39, 43, 47,
+        51, 55, 59, 63
     ];
 
     //@formatter:on
 
-    /*
     #[test]
     fn test_east() {
         let face = Direction::East;
@@ -381,7 +683,127 @@ mod test_perspective_faced_index {
             .collect();
         assert_eq!(result, EXPECTED_SOUTH);
     }
-     */
+
+    #[test]
+    fn test_face_direction_conversion() {
+        for direction in Direction::ALL {
+            let face = FaceDirection::from(direction);
+            assert_eq!(Direction::from(face), direction);
+            assert_eq!(FaceDirection::from(direction.opposite()), face.opposite());
+            assert_eq!(face.is_negative(), direction.is_negative());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_positioning {
+    use crate::storage::SliceAxis;
+    use crate::{AxialRotation, Direction, Positioning};
+
+    #[test]
+    fn all_orientations_are_distinct() {
+        let all = Positioning::all().collect::<Vec<_>>();
+        assert_eq!(all.len(), 48);
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a.matrix(), b.matrix(), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn from_matrix() {
+        for positioning in Positioning::all() {
+            assert_eq!(Positioning::from_matrix(&positioning.matrix()), positioning);
+        }
+    }
+
+    #[test]
+    fn rotate_direction() {
+        let positioning = Positioning::new(Direction::North, AxialRotation::Zero);
+        assert_eq!(
+            positioning.rotate_direction(Direction::Up),
+            Direction::North
+        );
+        assert_eq!(
+            positioning.rotate_direction(Direction::East),
+            Direction::East
+        );
+
+        let positioning = Positioning::new(Direction::Up, AxialRotation::By90);
+        assert_eq!(positioning.rotate_direction(Direction::Up), Direction::Up);
+        assert_eq!(
+            positioning.rotate_direction(Direction::East),
+            Direction::North
+        );
+        assert_eq!(
+            positioning.rotate_direction(Direction::North),
+            Direction::West
+        );
+    }
+
+    #[test]
+    fn composition_and_inverse() {
+        let quarter = Positioning::new(Direction::Up, AxialRotation::By90);
+        let half = Positioning::new(Direction::Up, AxialRotation::By180);
+        assert_eq!(quarter.then(quarter), half);
+        assert_eq!(
+            quarter.then(quarter).then(quarter).then(quarter),
+            Positioning::IDENTITY
+        );
+        for positioning in Positioning::all() {
+            assert_eq!(
+                positioning.then(positioning.inverse()),
+                Positioning::IDENTITY
+            );
+            assert_eq!(
+                positioning.inverse().then(positioning),
+                Positioning::IDENTITY
+            );
+            for direction in Direction::ALL {
+                let rotated = positioning.rotate_direction(direction);
+                assert_eq!(positioning.inverse().rotate_direction(rotated), direction);
+            }
+        }
+    }
+
+    #[test]
+    fn mirroring() {
+        let mirrored = Positioning::IDENTITY.mirror(SliceAxis::X);
+        assert!(mirrored.is_mirrored());
+        assert_eq!(mirrored.rotate_direction(Direction::East), Direction::West);
+        assert_eq!(mirrored.rotate_direction(Direction::Up), Direction::Up);
+        assert_eq!(mirrored.then(mirrored), Positioning::IDENTITY);
+
+        let mirrored = Positioning::IDENTITY.mirror(SliceAxis::Y);
+        assert!(mirrored.is_mirrored());
+        assert_eq!(mirrored.rotate_direction(Direction::Up), Direction::Down);
+    }
+
+    #[test]
+    fn transform_local() {
+        let quarter = Positioning::new(Direction::Up, AxialRotation::By90);
+        let size = (3, 1, 2);
+        assert_eq!(quarter.transform_size(size), (2, 1, 3));
+        // x -> z and z -> -x
+        assert_eq!(quarter.transform_local((0, 0, 0), size), (1, 0, 0));
+        assert_eq!(quarter.transform_local((2, 0, 1), size), (0, 0, 2));
+        for positioning in Positioning::all() {
+            let size = (4, 2, 3);
+            let transformed_size = positioning.transform_size(size);
+            for x in 0..size.0 {
+                for y in 0..size.1 {
+                    for z in 0..size.2 {
+                        let transformed = positioning.transform_local((x, y, z), size);
+                        let back = positioning
+                            .inverse()
+                            .transform_local(transformed, transformed_size);
+                        assert_eq!(back, (x, y, z));
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Component)]
@@ -0,0 +1,331 @@
+//! The world scoped palette of block states.
+//!
+//! Every distinct [BlockData] (material + data) used in a world is interned once in the [GlobalPalette]
+//! and gets a compact [StateId]. Chunks store these ids ([ChunkStorage]) instead of owning a copy of every
+//! block state in their local palette.
+//!
+//! When a chunk leaves the process (network or disk) it is encoded into an [EncodedChunk] which either contains
+//! the block states themselves ([PaletteMode::Local]) or only the global ids ([PaletteMode::Global]).
+//! Global ids are only meaningful when both sides share the same [GlobalPalette], so the mode has to be negotiated.
+
+use std::fmt::{Display, Formatter};
+
+use bevy::prelude::Resource;
+use hashbrown::HashMap;
+
+use crate::registry::Registry;
+use crate::resource::{BlockData, ResourceKey};
+use crate::storage::{InvalidGridError, Storage};
+use crate::CHUNK_VOLUME;
+
+/// the id of a block state in the [GlobalPalette]
+pub type StateId = u32;
+
+/// a chunk which references its blocks by their [StateId]
+pub type ChunkStorage = Storage<CHUNK_VOLUME, StateId>;
+
+/// marker for the material [Registry] of the [GlobalPalette]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct BlockStates;
+
+/// Interns all block states of a world.
+///
+/// State ids are assigned in insertion order and never change or get reused while the palette exists.
+/// The first state (passed to [GlobalPalette::new]) gets [GlobalPalette::DEFAULT_STATE],
+/// which is the [Default] of [StateId], so an empty [ChunkStorage] is filled with it.
+#[derive(Debug, Clone, Resource)]
+pub struct GlobalPalette {
+    /// every known material, registered once with its first state
+    materials: Registry<(), BlockStates>,
+    /// the ids of all states of a material
+    material_states: HashMap<ResourceKey, Vec<StateId>>,
+    states: Vec<BlockData>,
+    ids: HashMap<BlockData, StateId>,
+}
+
+impl GlobalPalette {
+    pub const DEFAULT_STATE: StateId = 0;
+
+    pub fn new(default: BlockData) -> Self {
+        let mut palette = Self {
+            materials: Registry::default(),
+            material_states: HashMap::new(),
+            states: Vec::new(),
+            ids: HashMap::new(),
+        };
+        palette.intern(default);
+        palette
+    }
+
+    /// returns the id of [state] and assigns a new one if the state is not known yet
+    pub fn intern(&mut self, state: BlockData) -> StateId {
+        if let Some(id) = self.ids.get(&state) {
+            return *id;
+        }
+        let id = StateId::try_from(self.states.len()).expect("the global palette is full");
+
+        if let Some(material_states) = self.material_states.get_mut(&state.material) {
+            material_states.push(id);
+        } else {
+            self.materials.register(state.material.clone(), ());
+            self.material_states
+                .insert(state.material.clone(), vec![id]);
+        }
+
+        self.ids.insert(state.clone(), id);
+        self.states.push(state);
+        id
+    }
+
+    pub fn id_of(&self, state: &BlockData) -> Option<StateId> {
+        self.ids.get(state).copied()
+    }
+
+    pub fn get(&self, id: StateId) -> Option<&BlockData> {
+        self.states.get(id as usize)
+    }
+
+    /// all states of [material] in the order they were interned
+    pub fn states_of(&self, material: &ResourceKey) -> &[StateId] {
+        self.material_states
+            .get(material)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// the materials with at least one interned state
+    pub fn materials(&self) -> &Registry<(), BlockStates> {
+        &self.materials
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (StateId, &'_ BlockData)> + '_ {
+        self.states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| (id as StateId, state))
+    }
+
+    /// interns every block state of [storage]
+    pub fn intern_storage<const SIZE: usize>(
+        &mut self,
+        storage: &Storage<SIZE, BlockData>,
+    ) -> Storage<SIZE, StateId> {
+        storage.map_palette(|state| self.intern(state.clone()))
+    }
+
+    /// the inverse of [GlobalPalette::intern_storage]
+    pub fn resolve_storage<const SIZE: usize>(
+        &self,
+        storage: &Storage<SIZE, StateId>,
+    ) -> Result<Storage<SIZE, BlockData>, PaletteError> {
+        storage.try_map_palette(|id| {
+            self.get(*id)
+                .cloned()
+                .ok_or(PaletteError::UnknownState(*id))
+        })
+    }
+
+    /// encodes [storage] for the network or disk, usually a [ChunkStorage] but parts of chunks work the same way
+    pub fn encode<const SIZE: usize>(
+        &self,
+        storage: &Storage<SIZE, StateId>,
+        mode: PaletteMode,
+    ) -> EncodedChunk {
+        let palette = match mode {
+            PaletteMode::Local => EncodedPalette::Local(
+                storage
+                    .palette()
+                    .iter()
+                    .map(|id| {
+                        self.get(*id)
+                            .expect("the storage only contains interned states")
+                            .clone()
+                    })
+                    .collect(),
+            ),
+            PaletteMode::Global => EncodedPalette::Global(storage.palette().to_vec()),
+        };
+        EncodedChunk {
+            palette,
+            grid: storage.export_grid(),
+        }
+    }
+
+    /// decodes a chunk created by [GlobalPalette::encode].
+    /// block states of local palettes are interned, global ids must already be known
+    pub fn decode(&mut self, chunk: &EncodedChunk) -> Result<ChunkStorage, PaletteError> {
+        self.decode_storage(chunk)
+    }
+
+    /// like [GlobalPalette::decode] for storages of any size
+    pub fn decode_storage<const SIZE: usize>(
+        &mut self,
+        chunk: &EncodedChunk,
+    ) -> Result<Storage<SIZE, StateId>, PaletteError> {
+        match &chunk.palette {
+            EncodedPalette::Local(states) => {
+                let ids = states
+                    .iter()
+                    .map(|state| self.intern(state.clone()))
+                    .collect();
+                Ok(Storage::import_grid(ids, &chunk.grid)?)
+            }
+            EncodedPalette::Global(ids) => {
+                if let Some(id) = ids.iter().find(|id| self.get(**id).is_none()) {
+                    return Err(PaletteError::UnknownState(*id));
+                }
+                Ok(Storage::import_grid(ids.clone(), &chunk.grid)?)
+            }
+        }
+    }
+}
+
+/// How the palette of a chunk is sent over the network or written to disk.
+/// [PaletteMode::Global] is smaller but requires the receiver to know the [GlobalPalette] of the sender.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum PaletteMode {
+    /// the block states are sent with every chunk
+    #[default]
+    Local,
+    /// only the [StateId]s are sent
+    Global,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EncodedPalette {
+    #[serde(rename = "l")]
+    Local(Vec<BlockData>),
+    #[serde(rename = "g")]
+    Global(Vec<StateId>),
+}
+
+impl EncodedPalette {
+    pub fn mode(&self) -> PaletteMode {
+        match self {
+            EncodedPalette::Local(_) => PaletteMode::Local,
+            EncodedPalette::Global(_) => PaletteMode::Global,
+        }
+    }
+}
+
+/// a chunk as it is sent over the network or stored on disk
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EncodedChunk {
+    #[serde(rename = "p")]
+    pub palette: EncodedPalette,
+    /// see [Storage::export_grid]
+    #[serde(rename = "g")]
+    pub grid: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    UnknownState(StateId),
+    InvalidGrid,
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::UnknownState(id) => write!(f, "unknown block state id {}", id),
+            PaletteError::InvalidGrid => write!(f, "{}", InvalidGridError),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<InvalidGridError> for PaletteError {
+    fn from(_: InvalidGridError) -> Self {
+        PaletteError::InvalidGrid
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(name: &str) -> BlockData {
+        BlockData::new(ResourceKey::core(name).unwrap())
+    }
+
+    #[test]
+    fn intern() {
+        let mut palette = GlobalPalette::new(state("air"));
+        assert_eq!(
+            palette.id_of(&state("air")),
+            Some(GlobalPalette::DEFAULT_STATE)
+        );
+        let dirt = palette.intern(state("dirt"));
+        assert_eq!(dirt, 1);
+        assert_eq!(palette.intern(state("dirt")), dirt);
+        assert_eq!(palette.get(dirt), Some(&state("dirt")));
+        assert_eq!(palette.states_of(&state("dirt").material), &[dirt]);
+        assert!(palette.states_of(&state("stone").material).is_empty());
+        assert_eq!(palette.len(), 2);
+    }
+
+    #[test]
+    fn storage_roundtrip() {
+        let mut palette = GlobalPalette::new(state("air"));
+        palette.intern(state("stone"));
+
+        let mut blocks = Storage::<CHUNK_VOLUME, BlockData>::new(&vec![state("air"); CHUNK_VOLUME]);
+        blocks.set(3, state("dirt"));
+        blocks.set(7, state("stone"));
+
+        let interned = palette.intern_storage(&blocks);
+        assert_eq!(interned.palette(), &[0, 1, 2]);
+        assert_eq!(*interned.get(3), palette.id_of(&state("dirt")).unwrap());
+        assert_eq!(palette.resolve_storage(&interned).unwrap(), blocks);
+
+        let mut unknown = interned.clone();
+        unknown.set(0, 42);
+        assert_eq!(
+            palette.resolve_storage(&unknown),
+            Err(PaletteError::UnknownState(42))
+        );
+    }
+
+    #[test]
+    fn encode() {
+        let mut palette = GlobalPalette::new(state("air"));
+        let mut chunk = ChunkStorage::empty();
+        chunk.set(10, palette.intern(state("dirt")));
+
+        for mode in [PaletteMode::Local, PaletteMode::Global] {
+            let encoded = palette.encode(&chunk, mode);
+            assert_eq!(encoded.palette.mode(), mode);
+            assert_eq!(palette.decode(&encoded), Ok(chunk.clone()));
+        }
+
+        //a receiver with another palette can only decode local palettes
+        let mut other = GlobalPalette::new(state("air"));
+        other.intern(state("stone"));
+        let decoded = other
+            .decode(&palette.encode(&chunk, PaletteMode::Local))
+            .unwrap();
+        assert_eq!(other.get(*decoded.get(10)), Some(&state("dirt")));
+        assert_eq!(
+            other.decode(
+                &GlobalPalette::new(state("air"))
+                    .encode(&ChunkStorage::empty(), PaletteMode::Global)
+            ),
+            Ok(ChunkStorage::empty())
+        );
+        assert_eq!(
+            GlobalPalette::new(state("air")).decode(&palette.encode(&chunk, PaletteMode::Global)),
+            Err(PaletteError::UnknownState(1))
+        );
+    }
+}
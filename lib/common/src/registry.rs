@@ -1,13 +1,28 @@
-use std::hash::{Hash, Hasher};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, ErrorKind};
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::sync::Arc;
 
 use bevy::prelude::Resource;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
 use slab::Slab;
 
+use crate::registry::tag::{ResolvedTag, TagDefinition, TagError};
 use crate::resource::ResourceKey;
 
+pub mod events;
+pub mod persistence;
+pub mod tag;
+
+/// ids of a [RegistrySnapshot] may exceed its number of entries by at most this, so a snapshot cannot make
+/// the registry allocate arbitrarily much for its ids
+const MAX_ID_GAP: usize = 4096;
+
 /// simply the marker type used when no marker is needed/set in [Registry] for convenience
 #[derive(Debug, Default, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct NoMarker;
@@ -17,6 +32,10 @@ pub struct NoMarker;
 ///   - it centrally stores arbitrary data to a [ResourceKey] so it can be accessed later
 ///   - it creates an id for each resource key so an often used resource key can be stored more efficiently
 ///
+/// ids start at 1, so 0 can be used as "no entry" by the users of the ids.
+/// After startup the registry should be [frozen](Registry::freeze), afterwards only the ids can be changed
+/// by applying the [RegistrySnapshot] of the server (see [Registry::apply_snapshot]).
+///
 /// the [Registry] is basically copy on write (more at [Arc::make_mut]).
 /// So it is cheap to clone and can be easily shared between threads
 /// but editing the registry may be expensive and does will not be shared to clones made before the edit
@@ -25,9 +44,13 @@ pub struct NoMarker;
 /// to allow multiple registries in one bevy world, create some marker type and set it at [M]
 /// default is [NoMarker]
 ///
+/// changes of the entries can be tracked (see [Registry::track_changes]),
+/// [events::RegistryEventsPlugin] turns them into bevy events
 #[derive(Debug, Clone, Resource)]
 pub struct Registry<T, M = NoMarker> {
     inner: Arc<RegistryInner<T>>,
+    /// [None] while the changes are not tracked
+    changes: Option<Vec<RegistryChange>>,
     _marker: std::marker::PhantomData<M>,
 }
 
@@ -35,15 +58,31 @@ impl<T, M> Default for Registry<T, M> {
     fn default() -> Self {
         Self {
             inner: Arc::new(RegistryInner::default()),
+            changes: None,
             _marker: std::marker::PhantomData,
         }
     }
 }
 
+/// a change of a registry entry recorded by [Registry::track_changes]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RegistryChange {
+    Added(ResourceKey, usize),
+    /// the data of the entry was replaced with different data
+    Changed(ResourceKey, usize),
+    Removed(ResourceKey, usize),
+}
+
 #[derive(Debug, Clone)]
 struct RegistryInner<T> {
+    /// the slab index is the id - 1
     id_mapper: Slab<ResourceKey>,
     key_to_data: HashMap<ResourceKey, RegistryEntry<T>>,
+    /// restored ids of keys which are not registered (yet), their ids are not assigned to other keys
+    unknown: HashMap<ResourceKey, usize>,
+    tag_definitions: HashMap<ResourceKey, TagDefinition>,
+    tags: HashMap<ResourceKey, ResolvedTag>,
+    frozen: bool,
 }
 
 impl<T> Default for RegistryInner<T> {
@@ -51,29 +90,51 @@ impl<T> Default for RegistryInner<T> {
         Self {
             id_mapper: Slab::new(),
             key_to_data: HashMap::new(),
+            unknown: HashMap::new(),
+            tag_definitions: HashMap::new(),
+            tags: HashMap::new(),
+            frozen: false,
         }
     }
 }
 
 impl<T, M> Registry<T, M>
 where
-    T: Clone + Hash,
+    T: Clone + Hash + PartialEq,
 {
     fn edit(&mut self) -> &mut RegistryInner<T> {
+        assert!(!self.inner.frozen, "the registry is frozen");
         Arc::make_mut(&mut self.inner)
     }
 
+    /// registers or replaces the data of [key] and returns the id of the key.
+    /// keys with a [restored](Registry::restore_ids) id get it back.
+    /// panics when the registry is frozen
     pub fn register(&mut self, key: ResourceKey, data: T) -> usize {
         let RegistryInner {
             id_mapper,
             key_to_data,
+            unknown,
+            tags,
+            ..
         } = self.edit();
         let entry = key_to_data.get(&key);
+        let change_of = |id| match entry {
+            Some(entry) if entry.data == data => None,
+            Some(_) => Some(RegistryChange::Changed(key.clone(), id)),
+            None => Some(RegistryChange::Added(key.clone(), id)),
+        };
         let short_key = if let Some(entry) = entry {
             entry.id
+        } else if let Some(id) = unknown.remove(&key) {
+            id
         } else {
             id_mapper.insert(key.clone()) + 1
         };
+        let change = change_of(short_key);
+        for tag in tags.values_mut().filter(|tag| tag.keys.contains(&key)) {
+            tag.set(short_key, true);
+        }
         key_to_data.insert(
             key,
             RegistryEntry {
@@ -81,17 +142,46 @@ where
                 data,
             },
         );
+        if let Some(change) = change {
+            self.record(change);
+        }
         short_key
     }
 
+    /// Replaces the data of an already registered key, this is also allowed when the registry is frozen
+    /// because the ids stay the same (f.e. when data packs are reloaded).
+    ///
+    /// returns the id of the key or [None] if the key is not registered
+    pub fn update(&mut self, key: &ResourceKey, data: T) -> Option<usize> {
+        let entry = self.inner.key_to_data.get(key)?;
+        let id = entry.id;
+        if entry.data != data {
+            Arc::make_mut(&mut self.inner)
+                .key_to_data
+                .get_mut(key)
+                .expect("the key was checked before")
+                .data = data;
+            self.record(RegistryChange::Changed(key.clone(), id));
+        }
+        Some(id)
+    }
+
+    /// Removes the data of [key], its id stays reserved as an unknown placeholder,
+    /// so the id is not given to another key and registering [key] again gets it back.
     pub fn unregister_material(&mut self, key: &ResourceKey) {
         let RegistryInner {
-            id_mapper,
             key_to_data,
+            unknown,
+            tags,
+            ..
         } = self.edit();
 
         if let Some(entry) = key_to_data.remove(key) {
-            id_mapper.remove(entry.id - 1);
+            unknown.insert(key.clone(), entry.id);
+            for tag in tags.values_mut() {
+                tag.set(entry.id, false);
+            }
+            self.record(RegistryChange::Removed(key.clone(), entry.id));
         }
     }
 
@@ -99,46 +189,443 @@ where
         let RegistryInner {
             id_mapper,
             key_to_data,
-        } = self.edit();
+            ..
+        } = Arc::make_mut(&mut self.inner);
         id_mapper.shrink_to_fit();
         key_to_data.shrink_to_fit();
     }
 
+    /// panics when the registry is frozen
     pub fn clear(&mut self) {
+        assert!(!self.inner.frozen, "the registry is frozen");
+        if self.changes.is_some() {
+            let removed = self
+                .iter()
+                .map(|(key, entry)| RegistryChange::Removed(key.clone(), entry.id))
+                .collect::<Vec<_>>();
+            for change in removed {
+                self.record(change);
+            }
+        }
         let RegistryInner {
             id_mapper,
             key_to_data,
+            unknown,
+            tag_definitions,
+            tags,
+            ..
         } = self.edit();
         id_mapper.clear();
         key_to_data.clear();
+        unknown.clear();
+        tag_definitions.clear();
+        tags.clear();
     }
 
     pub fn reserve(&mut self, additional: usize) {
         let RegistryInner {
             id_mapper,
             key_to_data,
+            ..
         } = self.edit();
         id_mapper.reserve(additional);
         key_to_data.reserve(additional);
     }
 
+    /// prevents any further registration or removal of entries
+    pub fn freeze(&mut self) {
+        if !self.inner.frozen {
+            Arc::make_mut(&mut self.inner).frozen = true;
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.inner.frozen
+    }
+
+    /// Starts recording the changes of the entries until they are taken by [Registry::drain_changes].
+    /// The current entries are recorded as added, so a consumer can build its caches from the changes alone.
+    pub fn track_changes(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(
+                self.iter()
+                    .map(|(key, entry)| RegistryChange::Added(key.clone(), entry.id))
+                    .collect(),
+            );
+        }
+    }
+
+    pub fn is_tracking_changes(&self) -> bool {
+        self.changes.is_some()
+    }
+
+    /// takes the recorded changes in the order they happened
+    pub fn drain_changes(&mut self) -> impl Iterator<Item = RegistryChange> + '_ {
+        self.changes
+            .iter_mut()
+            .flat_map(|changes| changes.drain(..))
+    }
+
+    fn record(&mut self, change: RegistryChange) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(change);
+        }
+    }
+
     pub fn get_by_key(&self, key: &ResourceKey) -> Option<&RegistryEntry<T>> {
         self.inner.key_to_data.get(key)
     }
     pub fn get_by_id(&self, id: usize) -> Option<&RegistryEntry<T>> {
-        self.inner
-            .id_mapper
-            .get(id + 1)
+        self.key_of_id(id)
             .and_then(|key| self.inner.key_to_data.get(key))
     }
     pub fn key_of_id(&self, id: usize) -> Option<&ResourceKey> {
-        self.inner.id_mapper.get(id + 1)
+        self.inner.id_mapper.get(id.checked_sub(1)?)
     }
     pub fn id_of_key(&self, key: &ResourceKey) -> Option<usize> {
         self.inner.key_to_data.get(key).map(|entry| entry.id)
     }
+
+    pub fn len(&self) -> usize {
+        self.inner.key_to_data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.key_to_data.is_empty()
+    }
+
+    /// all registered entries ordered by their id
+    pub fn iter(&self) -> impl Iterator<Item = (&'_ ResourceKey, &'_ RegistryEntry<T>)> + '_ {
+        self.inner
+            .id_mapper
+            .iter()
+            .filter_map(|(_, key)| Some((key, self.inner.key_to_data.get(key)?)))
+    }
+
+    /// whether [id] belongs to a restored key which is not registered
+    pub fn is_unknown(&self, id: usize) -> bool {
+        self.key_of_id(id)
+            .is_some_and(|key| self.inner.unknown.contains_key(key))
+    }
+
+    /// the restored keys which are not registered, f.e. because their content was removed
+    pub fn unknown_keys(&self) -> impl Iterator<Item = (&'_ ResourceKey, usize)> + '_ {
+        self.inner.unknown.iter().map(|(key, id)| (key, *id))
+    }
+
+    /// the id of every registered key, which is sent to clients so they can use the same ids
+    pub fn snapshot(&self) -> RegistrySnapshot {
+        RegistrySnapshot {
+            entries: self
+                .iter()
+                .map(|(key, entry)| (entry.id, key.clone()))
+                .collect(),
+        }
+    }
+
+    /// the id of every key including the unknown ones, which is stored with the world (see [Registry::restore_ids])
+    pub fn persistent_ids(&self) -> RegistrySnapshot {
+        RegistrySnapshot {
+            entries: self
+                .inner
+                .id_mapper
+                .iter()
+                .map(|(index, key)| (index + 1, key.clone()))
+                .collect(),
+        }
+    }
+
+    /// Restores the ids stored with the world, it has to be called before any content is registered.
+    /// Registering a key of [ids] reuses its id, the keys which are never registered stay unknown placeholders
+    /// so their ids are not given to other keys and are stored again by [Registry::persistent_ids].
+    ///
+    /// panics when the registry is frozen or not empty
+    pub fn restore_ids(&mut self, ids: &RegistrySnapshot) -> Result<(), SnapshotError> {
+        assert!(
+            self.inner.id_mapper.is_empty(),
+            "the ids have to be restored before registering content"
+        );
+        Self::check_id_range(ids)?;
+        Self::validate_entries(ids)?;
+        let inner = self.edit();
+        inner.id_mapper = ids
+            .entries
+            .iter()
+            .map(|(id, key)| (id - 1, key.clone()))
+            .collect();
+        inner.unknown = ids
+            .entries
+            .iter()
+            .map(|(id, key)| (key.clone(), *id))
+            .collect();
+        inner.rebuild_tag_ids();
+        Ok(())
+    }
+
+    /// the ids must not be 0 and every id and key may only be used once
+    fn validate_entries(
+        snapshot: &RegistrySnapshot,
+    ) -> Result<HashSet<&ResourceKey>, SnapshotError> {
+        let mut ids = HashSet::with_capacity(snapshot.entries.len());
+        let mut keys = HashSet::with_capacity(snapshot.entries.len());
+        for (id, key) in &snapshot.entries {
+            if *id == 0 || !ids.insert(*id) || !keys.insert(key) {
+                return Err(SnapshotError::InvalidEntry(*id, key.clone()));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// the ids are indices of the id mapper, so they must not be far above the number of entries
+    fn check_id_range(snapshot: &RegistrySnapshot) -> Result<(), SnapshotError> {
+        let max_id = snapshot.entries.len() + MAX_ID_GAP;
+        match snapshot.entries.iter().find(|(id, _)| *id > max_id) {
+            Some((id, key)) => Err(SnapshotError::IdOutOfRange(*id, key.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Changes the ids of this registry to the ones of [snapshot].
+    /// Every key of the snapshot must be registered, keys which are only known locally get the free ids
+    /// and unknown placeholders are dropped.
+    /// This is also allowed when the registry is frozen.
+    ///
+    /// returns the changed ids (old id -> new id), on error the registry is unchanged
+    pub fn apply_snapshot(
+        &mut self,
+        snapshot: &RegistrySnapshot,
+    ) -> Result<HashMap<usize, usize>, SnapshotError> {
+        let missing = snapshot
+            .entries
+            .iter()
+            .filter(|(_, key)| !self.inner.key_to_data.contains_key(key))
+            .map(|(_, key)| key.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(SnapshotError::MissingKeys(missing));
+        }
+        Self::check_id_range(snapshot)?;
+        let keys = Self::validate_entries(snapshot)?;
+
+        let mut id_mapper = snapshot
+            .entries
+            .iter()
+            .map(|(id, key)| (id - 1, key.clone()))
+            .collect::<Slab<_>>();
+        let mut local_only = self
+            .inner
+            .key_to_data
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        local_only.sort_unstable();
+        for key in local_only {
+            id_mapper.insert(key);
+        }
+
+        let inner = Arc::make_mut(&mut self.inner);
+        let mut remapped = HashMap::new();
+        for (index, key) in id_mapper.iter() {
+            let entry = inner
+                .key_to_data
+                .get_mut(key)
+                .expect("all keys were checked before");
+            if entry.id != index + 1 {
+                remapped.insert(entry.id, index + 1);
+                entry.id = index + 1;
+            }
+        }
+        inner.id_mapper = id_mapper;
+        inner.unknown.clear();
+        inner.rebuild_tag_ids();
+        Ok(remapped)
+    }
+
+    /// Defines a tag or adds to its definition (see [TagDefinition::merge]).
+    /// The included tags must be defined before, keys which are not registered are ignored until they are registered.
+    ///
+    /// on error the registry is unchanged
+    pub fn define_tag(
+        &mut self,
+        tag: ResourceKey,
+        definition: TagDefinition,
+    ) -> Result<(), TagError> {
+        self.define_tags([(tag, definition)])
+    }
+
+    /// like [Registry::define_tag] but the tags may include each other in any order
+    ///
+    /// tags do not change the ids, so they can also be defined when the registry is frozen
+    pub fn define_tags(
+        &mut self,
+        tags: impl IntoIterator<Item = (ResourceKey, TagDefinition)>,
+    ) -> Result<(), TagError> {
+        let mut definitions = self.inner.tag_definitions.clone();
+        for (tag, definition) in tags {
+            definitions.entry(tag).or_default().merge(definition);
+        }
+        self.set_tag_definitions(definitions)
+    }
+
+    /// Removes the definitions of [tags] (f.e. before they are loaded again), unknown tags are ignored.
+    /// Like [Registry::define_tags] this is also allowed when the registry is frozen.
+    ///
+    /// on error (a remaining tag includes a removed one) the registry is unchanged
+    pub fn remove_tags<'a>(
+        &mut self,
+        tags: impl IntoIterator<Item = &'a ResourceKey>,
+    ) -> Result<(), TagError> {
+        let mut definitions = self.inner.tag_definitions.clone();
+        for tag in tags {
+            definitions.remove(tag);
+        }
+        self.set_tag_definitions(definitions)
+    }
+
+    fn set_tag_definitions(
+        &mut self,
+        definitions: HashMap<ResourceKey, TagDefinition>,
+    ) -> Result<(), TagError> {
+        let resolved = tag::resolve_keys(&definitions)?;
+
+        let inner = Arc::make_mut(&mut self.inner);
+        inner.tag_definitions = definitions;
+        inner.tags = resolved
+            .into_iter()
+            .map(|(tag, keys)| {
+                (
+                    tag,
+                    ResolvedTag {
+                        keys,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        inner.rebuild_tag_ids();
+        Ok(())
+    }
+
+    pub fn is_in_tag(&self, id: usize, tag: &ResourceKey) -> bool {
+        self.inner
+            .tags
+            .get(tag)
+            .is_some_and(|tag| tag.ids.get(id).is_some_and(|bit| *bit))
+    }
+
+    /// the ids of all registered entries of [tag]
+    pub fn tag_ids(&self, tag: &ResourceKey) -> impl Iterator<Item = usize> + '_ {
+        self.inner
+            .tags
+            .get(tag)
+            .into_iter()
+            .flat_map(|tag| tag.ids.iter_ones())
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &'_ ResourceKey> + '_ {
+        self.inner.tags.keys()
+    }
+
+    /// all tags containing [id]
+    pub fn tags_of(&self, id: usize) -> impl Iterator<Item = &'_ ResourceKey> + '_ {
+        self.inner
+            .tags
+            .iter()
+            .filter(move |(_, tag)| tag.ids.get(id).is_some_and(|bit| *bit))
+            .map(|(key, _)| key)
+    }
+}
+
+impl<T> RegistryInner<T> {
+    /// sets the bits of all registered keys of the tags, needed after the ids changed
+    fn rebuild_tag_ids(&mut self) {
+        for tag in self.tags.values_mut() {
+            let ids = tag
+                .keys
+                .iter()
+                .filter_map(|key| self.key_to_data.get(key).map(|entry| entry.id))
+                .collect::<Vec<_>>();
+            tag.ids.clear();
+            for id in ids {
+                tag.set(id, true);
+            }
+        }
+    }
+}
+
+/// The ids of all entries of a [Registry] (see [Registry::snapshot] and [Registry::persistent_ids])
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RegistrySnapshot {
+    pub entries: Vec<(usize, ResourceKey)>,
 }
 
+impl RegistrySnapshot {
+    /// writes the ids as json object (`"namespace:path": id`) so they can be inspected and fixed by hand
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let ids = self
+            .entries
+            .iter()
+            .map(|(id, key)| (key.to_string(), *id))
+            .collect::<BTreeMap<_, _>>();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, &ids)?;
+        Ok(())
+    }
+
+    /// reads the ids written by [RegistrySnapshot::save], a missing file results in [None] (f.e. for new worlds)
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let ids = serde_json::from_reader::<_, BTreeMap<String, usize>>(BufReader::new(file))?;
+        let mut entries = ids
+            .into_iter()
+            .map(|(key, id)| {
+                let key = key
+                    .parse()
+                    .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))?;
+                Ok((id, key))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_unstable();
+        Ok(Some(Self { entries }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// the keys are not registered locally, so the client can not join
+    MissingKeys(Vec<ResourceKey>),
+    /// the id is 0 or the id or the key is used more than once
+    InvalidEntry(usize, ResourceKey),
+    /// the id is far above the number of entries
+    IdOutOfRange(usize, ResourceKey),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::MissingKeys(keys) => {
+                write!(f, "missing registry entries: {}", keys.iter().join(", "))
+            }
+            SnapshotError::InvalidEntry(id, key) => {
+                write!(f, "invalid registry entry {} (id {})", key, id)
+            }
+            SnapshotError::IdOutOfRange(id, key) => {
+                write!(f, "the id {} of the registry entry {} is too large", id, key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct RegistryEntry<T> {
     pub id: usize,
@@ -227,8 +714,204 @@ impl ResourceMapperOverloadWorkaraoudBecauseRustDoensntWantTo<ResourceKey, usize
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::resource_key;
 
     fn create_registry() -> Registry<unstructured::Document> {
         Registry::default()
     }
+
+    #[test]
+    fn ids() {
+        let mut registry = create_registry();
+        let dirt = registry.register(resource_key!("dirt"), unstructured::Document::Null);
+        let stone = registry.register(resource_key!("stone"), unstructured::Document::Null);
+        assert_eq!((dirt, stone), (1, 2));
+        assert_eq!(registry.key_of_id(dirt), Some(&resource_key!("dirt")));
+        assert_eq!(registry.get_by_id(stone).map(|entry| entry.id), Some(stone));
+        assert_eq!(registry.key_of_id(0), None);
+        assert_eq!(registry.key_of_id(3), None);
+        assert_eq!(registry.id_of_key(&resource_key!("stone")), Some(stone));
+        assert_eq!(
+            registry.register(resource_key!("dirt"), unstructured::Document::Bool(true)),
+            dirt
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the registry is frozen")]
+    fn frozen() {
+        let mut registry = create_registry();
+        registry.register(resource_key!("dirt"), unstructured::Document::Null);
+        registry.freeze();
+        assert!(registry.is_frozen());
+        registry.register(resource_key!("stone"), unstructured::Document::Null);
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut server = create_registry();
+        for key in [resource_key!("a"), resource_key!("b"), resource_key!("c")] {
+            server.register(key, unstructured::Document::Null);
+        }
+        server.unregister_material(&resource_key!("a"));
+        let snapshot = server.snapshot();
+        assert_eq!(
+            snapshot.entries,
+            vec![(2, resource_key!("b")), (3, resource_key!("c"))]
+        );
+
+        let mut client = create_registry();
+        for key in [
+            resource_key!("c"),
+            resource_key!("local"),
+            resource_key!("b"),
+        ] {
+            client.register(key, unstructured::Document::Null);
+        }
+        client.freeze();
+        let remapped = client.apply_snapshot(&snapshot).unwrap();
+        assert_eq!(client.id_of_key(&resource_key!("b")), Some(2));
+        assert_eq!(client.id_of_key(&resource_key!("c")), Some(3));
+        assert_eq!(client.key_of_id(2), Some(&resource_key!("b")));
+        assert_eq!(client.id_of_key(&resource_key!("local")), Some(1));
+        assert_eq!(remapped, HashMap::from([(1, 3), (2, 1), (3, 2)]));
+
+        let mut incomplete = create_registry();
+        incomplete.register(resource_key!("b"), unstructured::Document::Null);
+        assert_eq!(
+            incomplete.apply_snapshot(&snapshot),
+            Err(SnapshotError::MissingKeys(vec![resource_key!("c")]))
+        );
+        assert_eq!(incomplete.id_of_key(&resource_key!("b")), Some(1));
+
+        //a huge id would make the id mapper allocate that many slots
+        let huge = RegistrySnapshot {
+            entries: vec![(usize::MAX / 2, resource_key!("b"))],
+        };
+        assert_eq!(
+            client.apply_snapshot(&huge),
+            Err(SnapshotError::IdOutOfRange(usize::MAX / 2, resource_key!("b")))
+        );
+        assert_eq!(client.id_of_key(&resource_key!("b")), Some(2));
+    }
+
+    #[test]
+    fn persistent_ids() {
+        let mut previous = create_registry();
+        for key in [
+            resource_key!("a"),
+            resource_key!("removed"),
+            resource_key!("c"),
+        ] {
+            previous.register(key, unstructured::Document::Null);
+        }
+        let path = std::env::temp_dir().join(format!("registry-ids-{}.json", uuid::Uuid::new_v4()));
+        previous.persistent_ids().save(&path).unwrap();
+        let ids = RegistrySnapshot::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ids, previous.persistent_ids());
+        assert_eq!(RegistrySnapshot::load(&path).unwrap(), None);
+
+        let mut registry = create_registry();
+        registry.restore_ids(&ids).unwrap();
+        assert_eq!(
+            registry.register(resource_key!("c"), unstructured::Document::Null),
+            3
+        );
+        assert_eq!(
+            registry.register(resource_key!("new"), unstructured::Document::Null),
+            4
+        );
+        assert_eq!(
+            registry.register(resource_key!("a"), unstructured::Document::Null),
+            1
+        );
+
+        assert!(registry.is_unknown(2));
+        assert!(!registry.is_unknown(1));
+        assert_eq!(registry.get_by_id(2), None);
+        assert_eq!(registry.key_of_id(2), Some(&resource_key!("removed")));
+        assert_eq!(registry.len(), 3);
+        assert_eq!(registry.iter().count(), 3);
+        assert!(!registry.snapshot().entries.iter().any(|(id, _)| *id == 2));
+        assert_eq!(registry.persistent_ids().entries.len(), 4);
+
+        //an unregistered key keeps its id
+        registry.unregister_material(&resource_key!("c"));
+        assert!(registry.is_unknown(3));
+        assert_eq!(
+            registry.register(resource_key!("other"), unstructured::Document::Null),
+            5
+        );
+        assert_eq!(
+            registry.register(resource_key!("c"), unstructured::Document::Null),
+            3
+        );
+
+        let huge = RegistrySnapshot {
+            entries: vec![(usize::MAX / 2, resource_key!("a"))],
+        };
+        assert_eq!(
+            create_registry().restore_ids(&huge),
+            Err(SnapshotError::IdOutOfRange(usize::MAX / 2, resource_key!("a")))
+        );
+    }
+
+    #[test]
+    fn tags() {
+        use crate::registry::tag::TagValue;
+
+        let mut registry = create_registry();
+        let oak = registry.register(resource_key!("oak_log"), unstructured::Document::Null);
+        let stone = registry.register(resource_key!("stone"), unstructured::Document::Null);
+
+        let definition =
+            serde_json::from_str::<TagDefinition>(r#"{"values": ["oak_log", "core:birch_log"]}"#)
+                .unwrap();
+        registry
+            .define_tag(resource_key!("logs"), definition)
+            .unwrap();
+        registry
+            .define_tag(
+                resource_key!("mineable"),
+                TagDefinition::new([
+                    TagValue::Tag(resource_key!("logs")),
+                    TagValue::Key(resource_key!("stone")),
+                ]),
+            )
+            .unwrap();
+
+        assert!(registry.is_in_tag(oak, &resource_key!("logs")));
+        assert!(!registry.is_in_tag(stone, &resource_key!("logs")));
+        assert!(registry.is_in_tag(stone, &resource_key!("mineable")));
+        assert!(!registry.is_in_tag(stone, &resource_key!("unknown")));
+
+        //entries registered after the tag definition are added to the tag
+        let birch = registry.register(resource_key!("birch_log"), unstructured::Document::Null);
+        assert!(registry.is_in_tag(birch, &resource_key!("mineable")));
+        assert_eq!(
+            registry.tag_ids(&resource_key!("logs")).collect::<Vec<_>>(),
+            vec![oak, birch]
+        );
+        assert_eq!(registry.tags_of(oak).count(), 2);
+
+        registry.unregister_material(&resource_key!("oak_log"));
+        assert!(!registry.is_in_tag(oak, &resource_key!("mineable")));
+
+        assert_eq!(
+            registry.define_tag(
+                resource_key!("logs"),
+                TagDefinition::new([TagValue::Tag(resource_key!("mineable"))])
+            ),
+            Err(TagError::Cycle(resource_key!("logs")))
+        );
+        assert_eq!(
+            registry.define_tag(
+                resource_key!("other"),
+                TagDefinition::new([TagValue::Tag(resource_key!("missing"))])
+            ),
+            Err(TagError::UnknownTag(resource_key!("missing")))
+        );
+        assert!(registry.is_in_tag(birch, &resource_key!("logs")));
+    }
 }
@@ -0,0 +1,185 @@
+//! Bevy events for the changes of a [Registry] resource.
+//!
+//! Add a [RegistryEventsPlugin] per registry, afterwards systems can react to
+//! [RegistryEntryAdded], [RegistryEntryChanged] and [RegistryEntryRemoved] to keep their caches
+//! (f.e. materials or meshes) up to date. The events are sent in [PreUpdate] in [RegistrySet::Events],
+//! so they are visible in [Update] of the same frame.
+
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use bevy::prelude::{
+    App, DetectChangesMut, Event, EventWriter, IntoSystemConfigs, IntoSystemSetConfigs, Plugin,
+    PreUpdate, ResMut, SystemSet,
+};
+
+use crate::registry::{NoMarker, Registry, RegistryChange};
+use crate::resource::ResourceKey;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub enum RegistrySet {
+    /// the registries are changed, f.e. by reloading data packs
+    Reload,
+    /// the changes are sent as events
+    Events,
+}
+
+macro_rules! registry_event {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name<T, M = NoMarker> {
+            pub key: ResourceKey,
+            pub id: usize,
+            _marker: PhantomData<fn() -> (T, M)>,
+        }
+
+        impl<T, M> $name<T, M> {
+            pub fn new(key: ResourceKey, id: usize) -> Self {
+                Self {
+                    key,
+                    id,
+                    _marker: PhantomData,
+                }
+            }
+        }
+
+        impl<T: 'static, M: 'static> Event for $name<T, M> {}
+    };
+}
+
+registry_event!(
+    /// a key was registered
+    RegistryEntryAdded
+);
+registry_event!(
+    /// the data of a registered key was replaced with different data, the id is unchanged
+    RegistryEntryChanged
+);
+registry_event!(
+    /// a key was unregistered, its id stays reserved for it
+    RegistryEntryRemoved
+);
+
+/// sends the change events of the [Registry] resource with the data [T] and the marker [M]
+pub struct RegistryEventsPlugin<T, M = NoMarker>(PhantomData<fn() -> (T, M)>);
+
+impl<T, M> Default for RegistryEventsPlugin<T, M> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T, M> Plugin for RegistryEventsPlugin<T, M>
+where
+    T: Clone + Hash + PartialEq + Send + Sync + 'static,
+    M: Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        app.add_event::<RegistryEntryAdded<T, M>>()
+            .add_event::<RegistryEntryChanged<T, M>>()
+            .add_event::<RegistryEntryRemoved<T, M>>()
+            .configure_sets(PreUpdate, RegistrySet::Reload.before(RegistrySet::Events))
+            .add_systems(
+                PreUpdate,
+                send_registry_events::<T, M>.in_set(RegistrySet::Events),
+            );
+    }
+}
+
+fn send_registry_events<T, M>(
+    registry: Option<ResMut<Registry<T, M>>>,
+    mut added: EventWriter<RegistryEntryAdded<T, M>>,
+    mut changed: EventWriter<RegistryEntryChanged<T, M>>,
+    mut removed: EventWriter<RegistryEntryRemoved<T, M>>,
+) where
+    T: Clone + Hash + PartialEq + Send + Sync + 'static,
+    M: Send + Sync + 'static,
+{
+    let Some(mut registry) = registry else {
+        return;
+    };
+    //draining the changes does not change the content
+    let registry = registry.bypass_change_detection();
+    registry.track_changes();
+    for change in registry.drain_changes() {
+        match change {
+            RegistryChange::Added(key, id) => {
+                added.send(RegistryEntryAdded::new(key, id));
+            }
+            RegistryChange::Changed(key, id) => {
+                changed.send(RegistryEntryChanged::new(key, id));
+            }
+            RegistryChange::Removed(key, id) => {
+                removed.send(RegistryEntryRemoved::new(key, id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{EventReader, Local, Update};
+
+    use super::*;
+    use crate::resource_key;
+
+    type Blocks = Registry<u32>;
+
+    #[derive(bevy::prelude::Resource, Default)]
+    struct Received(Vec<String>);
+
+    fn collect(
+        mut received: ResMut<Received>,
+        mut added: EventReader<RegistryEntryAdded<u32>>,
+        mut changed: EventReader<RegistryEntryChanged<u32>>,
+        mut removed: EventReader<RegistryEntryRemoved<u32>>,
+        mut frame: Local<usize>,
+    ) {
+        *frame += 1;
+        for event in added.read() {
+            received.0.push(format!("{} added {}", *frame, event.key));
+        }
+        for event in changed.read() {
+            received.0.push(format!("{} changed {}", *frame, event.key));
+        }
+        for event in removed.read() {
+            received.0.push(format!("{} removed {}", *frame, event.key));
+        }
+    }
+
+    #[test]
+    fn events() {
+        let mut app = App::new();
+        let mut blocks = Blocks::default();
+        blocks.register(resource_key!("dirt"), 1);
+        app.insert_resource(blocks)
+            .init_resource::<Received>()
+            .add_plugins(RegistryEventsPlugin::<u32>::default())
+            .add_systems(Update, collect);
+        app.update();
+
+        let mut blocks = app.world.resource_mut::<Blocks>();
+        blocks.register(resource_key!("stone"), 2);
+        blocks.register(resource_key!("dirt"), 1);
+        blocks.register(resource_key!("stone"), 3);
+        blocks.freeze();
+        blocks.update(&resource_key!("dirt"), 4);
+        app.update();
+
+        let mut blocks = app.world.resource_mut::<Blocks>();
+        assert!(blocks.is_tracking_changes());
+        assert_eq!(blocks.drain_changes().count(), 0);
+        app.update();
+
+        assert_eq!(
+            app.world.resource::<Received>().0,
+            vec![
+                "1 added core:dirt",
+                "2 added core:stone",
+                "2 changed core:stone",
+                "2 changed core:dirt",
+            ]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Stores the ids of a [Registry] with the world, so ids saved in chunks stay valid across restarts.
+//!
+//! The [RegistryPersistencePlugin] restores the saved ids in [PreStartup] (before the content is registered)
+//! and saves them again in [Last] whenever they changed (f.e. after the startup registration or a data pack reload).
+
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+use bevy::app::AppExit;
+use bevy::log::error;
+use bevy::prelude::{
+    resource_exists_and_changed, App, EventWriter, IntoSystemConfigs, Last, Local, Plugin,
+    PreStartup, Res, World,
+};
+
+use crate::registry::{NoMarker, Registry, RegistrySnapshot};
+
+/// restores and saves the ids of the [Registry] with the data [T] and the marker [M] at [RegistryPersistencePlugin::path]
+pub struct RegistryPersistencePlugin<T, M = NoMarker> {
+    /// the file of the ids inside the world directory, see [RegistrySnapshot::save]
+    pub path: PathBuf,
+    _marker: PhantomData<fn() -> (T, M)>,
+}
+
+impl<T, M> RegistryPersistencePlugin<T, M> {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, M> Plugin for RegistryPersistencePlugin<T, M>
+where
+    T: Clone + Hash + PartialEq + Send + Sync + 'static,
+    M: Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        let path = self.path.clone();
+        let restore_ids = move |world: &mut World| {
+            let ids = match RegistrySnapshot::load(&path) {
+                Ok(Some(ids)) => ids,
+                Ok(None) => return,
+                Err(err) => {
+                    error!("failed to read the registry ids {}: {}", path.display(), err);
+                    world.send_event(AppExit);
+                    return;
+                }
+            };
+            let mut registry = world.get_resource_or_insert_with(Registry::<T, M>::default);
+            if !registry.is_empty() {
+                error!(
+                    "the registry ids {} have to be restored before the content is registered",
+                    path.display()
+                );
+                world.send_event(AppExit);
+            } else if let Err(err) = registry.restore_ids(&ids) {
+                error!("invalid registry ids {}: {}", path.display(), err);
+                world.send_event(AppExit);
+            }
+        };
+
+        let path = self.path.clone();
+        let save_ids = move |registry: Res<Registry<T, M>>,
+                             mut saved: Local<Option<RegistrySnapshot>>,
+                             mut exit: EventWriter<AppExit>| {
+            let ids = registry.persistent_ids();
+            if saved.as_ref() == Some(&ids) {
+                return;
+            }
+            //a lost id would remap the saved chunks, so the world must not keep running without them
+            if let Err(err) = ids.save(&path) {
+                error!("failed to save the registry ids {}: {}", path.display(), err);
+                exit.send(AppExit);
+            }
+            *saved = Some(ids);
+        };
+
+        app.add_event::<AppExit>()
+            .add_systems(PreStartup, restore_ids)
+            .add_systems(
+                Last,
+                save_ids.run_if(resource_exists_and_changed::<Registry<T, M>>),
+            );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{ResMut, Startup};
+
+    use super::*;
+    use crate::resource_key;
+
+    type TestRegistry = Registry<unstructured::Document>;
+
+    fn run(path: &std::path::Path, keys: &'static [&'static str]) -> TestRegistry {
+        let mut app = App::new();
+        app.add_plugins(RegistryPersistencePlugin::<unstructured::Document>::new(path))
+            .add_systems(Startup, move |mut registry: ResMut<TestRegistry>| {
+                for key in keys {
+                    registry.register(key.parse().unwrap(), unstructured::Document::Null);
+                }
+            });
+        app.init_resource::<TestRegistry>();
+        app.update();
+        app.world.remove_resource::<TestRegistry>().unwrap()
+    }
+
+    #[test]
+    fn restart() {
+        let path = std::env::temp_dir()
+            .join(format!("world-{}", uuid::Uuid::new_v4()))
+            .join("registries/blocks.json");
+        let first = run(&path, &["dirt", "stone"]);
+        let second = run(&path, &["stone", "grass", "dirt"]);
+        std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+
+        assert_eq!(first.id_of_key(&resource_key!("dirt")), Some(1));
+        assert_eq!(second.id_of_key(&resource_key!("dirt")), Some(1));
+        assert_eq!(second.id_of_key(&resource_key!("stone")), Some(2));
+        assert_eq!(second.id_of_key(&resource_key!("grass")), Some(3));
+    }
+}
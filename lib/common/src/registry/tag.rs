@@ -0,0 +1,177 @@
+//! Tags are named sets of registry entries like "all logs" or "transparent blocks".
+//!
+//! A tag is identified by a [ResourceKey] and defined by a [TagDefinition], which lists keys and other tags.
+//! In data files values are written as strings, tags are prefixed with `#`:
+//!
+//! ```json
+//! { "replace": false, "values": ["core:oak_log", "#core:stripped_logs"] }
+//! ```
+//!
+//! The registry resolves the definitions into a bitset per tag, so [Registry::is_in_tag](super::Registry::is_in_tag)
+//! is a single lookup.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use bitvec::vec::BitVec;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+
+use crate::resource::{InvalidResourceKey, ResourceKey};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TagValue {
+    /// a single entry
+    Key(ResourceKey),
+    /// all entries of another tag
+    Tag(ResourceKey),
+}
+
+impl FromStr for TagValue {
+    type Err = InvalidResourceKey;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix('#') {
+            Some(tag) => Ok(TagValue::Tag(tag.parse()?)),
+            None => Ok(TagValue::Key(value.parse()?)),
+        }
+    }
+}
+
+impl TryFrom<String> for TagValue {
+    type Error = InvalidResourceKey;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for TagValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagValue::Key(key) => write!(f, "{}", key),
+            TagValue::Tag(tag) => write!(f, "#{}", tag),
+        }
+    }
+}
+
+impl From<TagValue> for String {
+    fn from(value: TagValue) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct TagDefinition {
+    /// replaces the values of an earlier definition of the tag instead of adding to them
+    #[serde(default)]
+    pub replace: bool,
+    pub values: Vec<TagValue>,
+}
+
+impl TagDefinition {
+    pub fn new(values: impl IntoIterator<Item = TagValue>) -> Self {
+        Self {
+            replace: false,
+            values: values.into_iter().collect(),
+        }
+    }
+
+    /// applies a later definition of the same tag
+    pub fn merge(&mut self, later: TagDefinition) {
+        if later.replace {
+            self.values.clear();
+        }
+        for value in later.values {
+            if !self.values.contains(&value) {
+                self.values.push(value);
+            }
+        }
+    }
+}
+
+/// a tag with all included tags resolved
+#[derive(Debug, Clone, Default)]
+pub(super) struct ResolvedTag {
+    pub(super) keys: HashSet<ResourceKey>,
+    /// indexed by the id of the entry
+    pub(super) ids: BitVec,
+}
+
+impl ResolvedTag {
+    pub(super) fn set(&mut self, id: usize, value: bool) {
+        if self.ids.len() <= id {
+            if !value {
+                return;
+            }
+            self.ids.resize(id + 1, false);
+        }
+        self.ids.set(id, value);
+    }
+}
+
+/// resolves the keys of every tag including the ones of the included tags
+pub(super) fn resolve_keys(
+    definitions: &HashMap<ResourceKey, TagDefinition>,
+) -> Result<HashMap<ResourceKey, HashSet<ResourceKey>>, TagError> {
+    fn resolve(
+        tag: &ResourceKey,
+        definitions: &HashMap<ResourceKey, TagDefinition>,
+        resolved: &mut HashMap<ResourceKey, HashSet<ResourceKey>>,
+        visiting: &mut Vec<ResourceKey>,
+    ) -> Result<(), TagError> {
+        if resolved.contains_key(tag) {
+            return Ok(());
+        }
+        if visiting.contains(tag) {
+            return Err(TagError::Cycle(tag.clone()));
+        }
+        let definition = definitions
+            .get(tag)
+            .ok_or_else(|| TagError::UnknownTag(tag.clone()))?;
+
+        visiting.push(tag.clone());
+        let mut keys = HashSet::new();
+        for value in &definition.values {
+            match value {
+                TagValue::Key(key) => {
+                    keys.insert(key.clone());
+                }
+                TagValue::Tag(included) => {
+                    resolve(included, definitions, resolved, visiting)?;
+                    keys.extend(resolved[included].iter().cloned());
+                }
+            }
+        }
+        visiting.pop();
+        resolved.insert(tag.clone(), keys);
+        Ok(())
+    }
+
+    let mut resolved = HashMap::with_capacity(definitions.len());
+    //sorted, so the same tag is reported for a cycle every time
+    for tag in definitions.keys().sorted_unstable() {
+        resolve(tag, definitions, &mut resolved, &mut Vec::new())?;
+    }
+    Ok(resolved)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagError {
+    /// a tag includes a tag which is not defined
+    UnknownTag(ResourceKey),
+    /// the tag includes itself (directly or through other tags)
+    Cycle(ResourceKey),
+}
+
+impl Display for TagError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagError::UnknownTag(tag) => write!(f, "the tag #{} is not defined", tag),
+            TagError::Cycle(tag) => write!(f, "the tag #{} includes itself", tag),
+        }
+    }
+}
+
+impl std::error::Error for TagError {}
@@ -0,0 +1,289 @@
+//! [ResourceKey]s and the interner of their strings.
+//!
+//! Keys are written as `namespace:path`, the namespace may be omitted and defaults to [NAMESPACE_CORE].
+//! Namespaces may contain `a-z`, `0-9`, `_`, `-` and `.`, paths additionally `/`.
+//!
+//! Both parts are interned, so cloning a key or storing it millions of times (f.e. in palettes)
+//! only copies two pointers. The interner only holds weak references, an interned string is dropped with the
+//! last key using it, so keys received from the network do not accumulate.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::hash::BuildHasher;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use crate::resource::NAMESPACE_CORE;
+
+/// A resource key represents an identifier for a resource in its scope.
+#[derive(
+    Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "SerializedResourceKey")]
+pub struct ResourceKey {
+    #[serde(rename = "n")]
+    namespace: Arc<str>,
+    #[serde(rename = "p")]
+    path: Arc<str>,
+}
+
+impl ResourceKey {
+    pub fn new(namespace: &str, path: &str) -> Result<Self, InvalidResourceKey> {
+        if !is_valid_namespace(namespace) || !is_valid_path(path) {
+            return Err(InvalidResourceKey(format!("{}:{}", namespace, path)));
+        }
+        Ok(Self {
+            namespace: intern(namespace),
+            path: intern(path),
+        })
+    }
+
+    /// a key in the [NAMESPACE_CORE] namespace
+    pub fn core(path: &str) -> Result<Self, InvalidResourceKey> {
+        Self::new(NAMESPACE_CORE, path)
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FromStr for ResourceKey {
+    type Err = InvalidResourceKey;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        match key.split_once(':') {
+            Some((namespace, path)) => Self::new(namespace, path),
+            None => Self::core(key),
+        }
+    }
+}
+
+impl Display for ResourceKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.path)
+    }
+}
+
+/// the interned strings are shared, so only the key itself is counted
+impl deepsize::DeepSizeOf for ResourceKey {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        0
+    }
+}
+
+/// the serialized form which is validated and interned when deserialized
+#[derive(serde::Deserialize)]
+struct SerializedResourceKey {
+    #[serde(rename = "n")]
+    namespace: String,
+    #[serde(rename = "p")]
+    path: String,
+}
+
+impl TryFrom<SerializedResourceKey> for ResourceKey {
+    type Error = InvalidResourceKey;
+
+    fn try_from(value: SerializedResourceKey) -> Result<Self, Self::Error> {
+        Self::new(&value.namespace, &value.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidResourceKey(pub String);
+
+impl Display for InvalidResourceKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid resource key `{}` (expected `namespace:path` with [a-z0-9_.-] in the namespace and [a-z0-9_.-/] in the path)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidResourceKey {}
+
+/// the dropped strings are pruned once the interner doubled its size since the last pruning
+const MIN_PRUNE_SIZE: usize = 1024;
+
+#[derive(Default)]
+struct Interner {
+    /// the strings by the hash of their text
+    strings: HashMap<u64, Vec<Weak<str>>>,
+    hasher: RandomState,
+    len: usize,
+    prune_at: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, text: &str) -> Arc<str> {
+        let hash = self.hasher.hash_one(text);
+        let strings = self.strings.entry(hash).or_default();
+        if let Some(interned) = strings
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|interned| &**interned == text)
+        {
+            return interned;
+        }
+        let interned = Arc::<str>::from(text);
+        strings.push(Arc::downgrade(&interned));
+        self.len += 1;
+        if self.len >= self.prune_at {
+            self.prune();
+        }
+        interned
+    }
+
+    fn prune(&mut self) {
+        self.strings.retain(|_, strings| {
+            strings.retain(|string| string.strong_count() > 0);
+            !strings.is_empty()
+        });
+        self.len = self.strings.values().map(Vec::len).sum();
+        self.prune_at = (self.len * 2).max(MIN_PRUNE_SIZE);
+    }
+}
+
+fn intern(text: &str) -> Arc<str> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .intern(text)
+}
+
+const fn is_valid_part(part: &[u8], allow_slash: bool) -> bool {
+    if part.is_empty() {
+        return false;
+    }
+    let mut i = 0;
+    while i < part.len() {
+        let valid = matches!(part[i], b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'.')
+            || (allow_slash && part[i] == b'/');
+        if !valid {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+pub const fn is_valid_namespace(namespace: &str) -> bool {
+    is_valid_part(namespace.as_bytes(), false)
+}
+
+pub const fn is_valid_path(path: &str) -> bool {
+    is_valid_part(path.as_bytes(), true)
+}
+
+/// checks the `namespace:path` (or only `path`) syntax without allocating, usable in const contexts
+pub const fn is_valid_key(key: &str) -> bool {
+    let bytes = key.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b':' {
+            let (namespace, path) = bytes.split_at(i);
+            let (_, path) = path.split_at(1);
+            return is_valid_part(namespace, false) && is_valid_part(path, true);
+        }
+        i += 1;
+    }
+    is_valid_part(bytes, true)
+}
+
+/// Creates a [ResourceKey] from a literal, the syntax is checked at compile time.
+/// The key is interned once per call site, so using the macro in hot paths is cheap.
+///
+/// ```
+/// # use common::resource_key;
+/// let dirt = resource_key!("core:dirt");
+/// assert_eq!(dirt, resource_key!("dirt"));
+/// ```
+#[macro_export]
+macro_rules! resource_key {
+    ($key:literal) => {{
+        const _: () = assert!(
+            $crate::resource::key::is_valid_key($key),
+            concat!("invalid resource key: ", $key)
+        );
+        static KEY: ::std::sync::OnceLock<$crate::resource::ResourceKey> =
+            ::std::sync::OnceLock::new();
+        KEY.get_or_init(|| {
+            <$crate::resource::ResourceKey as ::std::str::FromStr>::from_str($key)
+                .expect("the key was validated at compile time")
+        })
+        .clone()
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let key = "test:blocks/dirt".parse::<ResourceKey>().unwrap();
+        assert_eq!(key.namespace(), "test");
+        assert_eq!(key.path(), "blocks/dirt");
+        assert_eq!(key.to_string(), "test:blocks/dirt");
+
+        let core = "stone".parse::<ResourceKey>().unwrap();
+        assert_eq!(core, ResourceKey::core("stone").unwrap());
+        assert_eq!(core.to_string(), "core:stone");
+    }
+
+    #[test]
+    fn validation() {
+        for invalid in [
+            "",
+            ":",
+            "core:",
+            ":dirt",
+            "Core:dirt",
+            "core:di rt",
+            "a/b:c",
+            "a:b:c",
+        ] {
+            assert!(ResourceKey::from_str(invalid).is_err(), "{}", invalid);
+            assert!(!is_valid_key(invalid), "{}", invalid);
+        }
+        for valid in ["dirt", "core:dirt", "my-mod.v2:blocks/dirt_1"] {
+            assert!(ResourceKey::from_str(valid).is_ok(), "{}", valid);
+            assert!(is_valid_key(valid), "{}", valid);
+        }
+    }
+
+    #[test]
+    fn interned() {
+        let a = resource_key!("test:interned");
+        let b = ResourceKey::new("test", "interned").unwrap();
+        assert!(Arc::ptr_eq(&a.namespace, &b.namespace));
+        assert!(Arc::ptr_eq(&a.path, &b.path));
+    }
+
+    #[test]
+    fn interner_prunes() {
+        let mut interner = Interner::default();
+        let kept = interner.intern("kept");
+        for index in 0..MIN_PRUNE_SIZE * 4 {
+            interner.intern(&format!("dropped_{}", index));
+        }
+        //only the strings since the last pruning are left
+        assert!(interner.len <= MIN_PRUNE_SIZE);
+        assert!(Arc::ptr_eq(&interner.intern("kept"), &kept));
+    }
+
+    #[test]
+    fn deserialize_validates() {
+        assert!(serde_json::from_str::<ResourceKey>(r#"{"n":"core","p":"dirt"}"#).is_ok());
+        assert!(serde_json::from_str::<ResourceKey>(r#"{"n":"core","p":"Dirt"}"#).is_err());
+    }
+}
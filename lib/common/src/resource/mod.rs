@@ -1,48 +1,95 @@
 use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
+use itertools::Itertools;
 use unstructured::Document;
 
+use crate::resource::state::PropertyValue;
+
+pub use key::{InvalidResourceKey, ResourceKey};
+
+pub mod key;
+pub mod state;
+
 pub const NAMESPACE_CORE: &str = "core";
 
-//TODO document
-/// A resource key represents an identifier for a resource in its scope.
+/// A block state: the material and the values of its properties.
+/// The properties are declared in the [BlockSchema](state::BlockSchema) of the material,
+/// states created through the schema contain every property so equal states compare and serialize equally.
 #[derive(
-    Debug,
-    Eq,
-    PartialEq,
-    Clone,
-    Ord,
-    PartialOrd,
-    Hash,
-    serde::Serialize,
-    serde::Deserialize,
-    deepsize::DeepSizeOf,
+    Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
 )]
-pub struct ResourceKey {
-    #[serde(rename = "n")]
-    pub namespace: String,
-    #[serde(rename = "p")]
-    pub name: String,
+pub struct BlockData {
+    #[serde(rename = "m", alias = "material")]
+    pub material: ResourceKey,
+    #[serde(rename = "s", alias = "properties", default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    properties: BTreeMap<String, PropertyValue>,
 }
 
-impl ResourceKey {
-    fn new(namespace: &str, name: &str) -> Self {
+impl BlockData {
+    /// a state without any properties, use [BlockSchema](state::BlockSchema) for blocks with properties
+    pub fn new(material: ResourceKey) -> Self {
         Self {
-            namespace: namespace.to_string(),
-            name: name.to_string(),
+            material,
+            properties: BTreeMap::new(),
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&PropertyValue> {
+        self.properties.get(name)
+    }
+
+    /// the properties in their canonical order
+    pub fn properties(&self) -> impl Iterator<Item = (&'_ str, &'_ PropertyValue)> + '_ {
+        self.properties
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// the properties encoded as MessagePack like in the network and disk formats
+    pub fn encode_data(&self) -> Option<Vec<u8>> {
+        if self.properties.is_empty() {
+            return None;
         }
+        Some(
+            rmp_serde::to_vec(&self.properties)
+                .expect("properties can always be encoded as MessagePack"),
+        )
+    }
+
+    /// the inverse of [BlockData::encode_data].
+    /// the state is not validated against its schema
+    pub fn decode_data(
+        material: ResourceKey,
+        data: Option<&[u8]>,
+    ) -> Result<Self, rmp_serde::decode::Error> {
+        let properties = data
+            .map(rmp_serde::from_slice::<BTreeMap<String, PropertyValue>>)
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self {
+            material,
+            properties,
+        })
     }
 }
 
-#[derive(
-    Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
-)]
-pub struct BlockData {
-    #[serde(rename = "m", alias = "material")]
-    pub material: ResourceKey,
-    #[serde(rename = "d", alias = "data")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<Box<Document>>,
+/// the `namespace:path[property=value,...]` syntax
+impl Display for BlockData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.material)?;
+        if !self.properties.is_empty() {
+            let properties = self
+                .properties
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .join(",");
+            write!(f, "[{}]", properties)?;
+        }
+        Ok(())
+    }
 }
 
 pub trait Resource: Any {
@@ -55,18 +102,13 @@ mod test {
     use serde_json::json;
 
     use crate::humanize::humanize_memory;
+    use crate::resource_key;
 
     use super::*;
 
     #[test]
     pub fn test_msgpack() {
-        let id = BlockData {
-            material: ResourceKey {
-                namespace: NAMESPACE_CORE.to_string(),
-                name: "dirt".to_string(),
-            },
-            data: None,
-        };
+        let id = BlockData::new(resource_key!("core:dirt"));
         let bytes = rmp_serde::to_vec(&id).unwrap();
         let hex = hex::encode(&bytes);
 
@@ -83,10 +125,7 @@ mod test {
 
     #[test]
     pub fn test_de() {
-        let expected = ResourceKey {
-            namespace: "core".to_string(),
-            name: "dirt".to_string(),
-        };
+        let expected = resource_key!("core:dirt");
 
         let hex = "92a4636f7265a464697274";
         let bytes = hex::decode(hex).unwrap();
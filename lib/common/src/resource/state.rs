@@ -0,0 +1,521 @@
+//! The properties a block type declares and the validation of [BlockData] against them.
+//!
+//! Every block type registers a [BlockSchema] in the [BlockSchemas] registry under its material key.
+//! A schema lists the properties of the block (enums, bools and bounded ints) together with their defaults.
+//! Validated states always contain every declared property, so equal states are always equal values.
+//!
+//! The text syntax used by commands and data files is `namespace:path[property=value,...]`,
+//! the namespace defaults to [NAMESPACE_CORE](crate::resource::NAMESPACE_CORE) and the brackets may be omitted.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+
+use crate::registry::Registry;
+use crate::resource::{BlockData, ResourceKey};
+
+/// marker of the [BlockSchemas] registry
+#[derive(Debug, Default, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct BlockSchemaMarker;
+
+/// all block types with their properties
+pub type BlockSchemas = Registry<BlockSchema, BlockSchemaMarker>;
+
+/// the value of a single block property
+#[derive(
+    Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Enum(String),
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyValue::Bool(value) => write!(f, "{}", value),
+            PropertyValue::Int(value) => write!(f, "{}", value),
+            PropertyValue::Enum(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// the values a property can have
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PropertyKind {
+    Bool,
+    /// an integer in `min..=max`
+    Int {
+        min: i64,
+        max: i64,
+    },
+    Enum {
+        values: Vec<String>,
+    },
+}
+
+impl PropertyKind {
+    pub fn accepts(&self, value: &PropertyValue) -> bool {
+        match (self, value) {
+            (PropertyKind::Bool, PropertyValue::Bool(_)) => true,
+            (PropertyKind::Int { min, max }, PropertyValue::Int(value)) => {
+                (*min..=*max).contains(value)
+            }
+            (PropertyKind::Enum { values }, PropertyValue::Enum(value)) => values.contains(value),
+            _ => false,
+        }
+    }
+
+    /// parses the text representation of a value of this kind
+    pub fn parse(&self, text: &str) -> Option<PropertyValue> {
+        let value = match self {
+            PropertyKind::Bool => PropertyValue::Bool(text.parse().ok()?),
+            PropertyKind::Int { .. } => PropertyValue::Int(text.parse().ok()?),
+            PropertyKind::Enum { .. } => PropertyValue::Enum(text.to_string()),
+        };
+        self.accepts(&value).then_some(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PropertyDefinition {
+    #[serde(flatten)]
+    pub kind: PropertyKind,
+    pub default: PropertyValue,
+}
+
+impl PropertyDefinition {
+    /// checks that the kind can have values and the default is one of them
+    pub fn check(&self, name: &str) -> Result<(), BlockStateError> {
+        let invalid = |reason: &str| {
+            Err(BlockStateError::InvalidDefinition(
+                name.to_string(),
+                reason.to_string(),
+            ))
+        };
+        if !is_valid_property_name(name) {
+            return invalid("the name must be a non-empty lowercase identifier");
+        }
+        match &self.kind {
+            PropertyKind::Bool => {}
+            PropertyKind::Int { min, max } if min > max => {
+                return invalid("the minimum is larger than the maximum")
+            }
+            PropertyKind::Int { .. } => {}
+            PropertyKind::Enum { values } => {
+                if values.is_empty() {
+                    return invalid("an enum needs at least one value");
+                }
+                if let Some(value) = values.iter().find(|value| !is_valid_property_name(value)) {
+                    return invalid(&format!("the enum value `{}` is invalid", value));
+                }
+                if values.iter().duplicates().next().is_some() {
+                    return invalid("the enum values must be unique");
+                }
+            }
+        }
+        if !self.kind.accepts(&self.default) {
+            return invalid(&format!("the default {} is not a valid value", self.default));
+        }
+        Ok(())
+    }
+
+    pub fn bool(default: bool) -> Self {
+        Self {
+            kind: PropertyKind::Bool,
+            default: PropertyValue::Bool(default),
+        }
+    }
+
+    pub fn int(min: i64, max: i64, default: i64) -> Self {
+        Self {
+            kind: PropertyKind::Int { min, max },
+            default: PropertyValue::Int(default),
+        }
+    }
+
+    /// an enum property, the first value is the default
+    pub fn enumeration(values: &[&str]) -> Self {
+        Self {
+            kind: PropertyKind::Enum {
+                values: values.iter().map(|value| value.to_string()).collect(),
+            },
+            default: PropertyValue::Enum(
+                values
+                    .first()
+                    .expect("an enum property needs at least one value")
+                    .to_string(),
+            ),
+        }
+    }
+
+    pub fn with_default(mut self, default: PropertyValue) -> Self {
+        self.default = default;
+        self
+    }
+}
+
+/// the properties of a block type
+///
+/// deserialized schemas are checked like the ones built with [BlockSchema::with_property]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "SerializedBlockSchema")]
+pub struct BlockSchema {
+    properties: BTreeMap<String, PropertyDefinition>,
+}
+
+impl BlockSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a property to the schema.
+    /// panics when the definition is invalid (see [PropertyDefinition::check])
+    pub fn with_property(mut self, name: &str, definition: PropertyDefinition) -> Self {
+        if let Err(error) = definition.check(name) {
+            panic!("{} ({:?})", error, definition);
+        }
+        self.properties.insert(name.to_string(), definition);
+        self
+    }
+
+    pub fn property(&self, name: &str) -> Option<&PropertyDefinition> {
+        self.properties.get(name)
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = (&'_ str, &'_ PropertyDefinition)> + '_ {
+        self.properties
+            .iter()
+            .map(|(name, definition)| (name.as_str(), definition))
+    }
+
+    /// the state with every property set to its default
+    pub fn default_state(&self, material: ResourceKey) -> BlockData {
+        BlockData {
+            material,
+            properties: self
+                .properties
+                .iter()
+                .map(|(name, definition)| (name.clone(), definition.default.clone()))
+                .collect(),
+        }
+    }
+
+    /// creates a state, properties which are not set use their default
+    pub fn create_state(
+        &self,
+        material: ResourceKey,
+        properties: impl IntoIterator<Item = (String, PropertyValue)>,
+    ) -> Result<BlockData, BlockStateError> {
+        let mut state = self.default_state(material);
+        for (name, value) in properties {
+            let definition = self.properties.get(&name).ok_or_else(|| {
+                BlockStateError::UnknownProperty(state.material.clone(), name.clone())
+            })?;
+            if !definition.kind.accepts(&value) {
+                return Err(BlockStateError::InvalidValue(name, value.to_string()));
+            }
+            state.properties.insert(name, value);
+        }
+        Ok(state)
+    }
+
+    /// checks that [state] contains exactly the properties of the schema with valid values
+    pub fn validate(&self, state: &BlockData) -> Result<(), BlockStateError> {
+        for (name, value) in &state.properties {
+            let definition = self.properties.get(name).ok_or_else(|| {
+                BlockStateError::UnknownProperty(state.material.clone(), name.clone())
+            })?;
+            if !definition.kind.accepts(value) {
+                return Err(BlockStateError::InvalidValue(
+                    name.clone(),
+                    value.to_string(),
+                ));
+            }
+        }
+        if let Some(name) = self
+            .properties
+            .keys()
+            .find(|name| !state.properties.contains_key(*name))
+        {
+            return Err(BlockStateError::MissingProperty(name.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// the serialized form which is checked when deserialized
+#[derive(serde::Deserialize)]
+struct SerializedBlockSchema {
+    properties: BTreeMap<String, PropertyDefinition>,
+}
+
+impl TryFrom<SerializedBlockSchema> for BlockSchema {
+    type Error = BlockStateError;
+
+    fn try_from(value: SerializedBlockSchema) -> Result<Self, Self::Error> {
+        for (name, definition) in &value.properties {
+            definition.check(name)?;
+        }
+        Ok(Self {
+            properties: value.properties,
+        })
+    }
+}
+
+/// property names and enum values are written unquoted in the text syntax, so they are limited to `a-z`, `0-9` and `_`
+fn is_valid_property_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'_'))
+}
+
+impl<M> Registry<BlockSchema, M> {
+    pub fn default_state(&self, material: &ResourceKey) -> Result<BlockData, BlockStateError> {
+        Ok(self.schema_of(material)?.default_state(material.clone()))
+    }
+
+    pub fn validate_state(&self, state: &BlockData) -> Result<(), BlockStateError> {
+        self.schema_of(&state.material)?.validate(state)
+    }
+
+    /// parses a state in the `namespace:path[property=value,...]` syntax
+    pub fn parse_state(&self, text: &str) -> Result<BlockData, BlockStateError> {
+        let (material, properties) = split_state(text)?;
+        let schema = self.schema_of(&material)?;
+        let properties = properties
+            .into_iter()
+            .map(|(name, value)| {
+                let definition = schema.property(name).ok_or_else(|| {
+                    BlockStateError::UnknownProperty(material.clone(), name.to_string())
+                })?;
+                let value = definition.kind.parse(value).ok_or_else(|| {
+                    BlockStateError::InvalidValue(name.to_string(), value.to_string())
+                })?;
+                Ok((name.to_string(), value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        schema.create_state(material, properties)
+    }
+
+    fn schema_of(&self, material: &ResourceKey) -> Result<&BlockSchema, BlockStateError> {
+        self.get_by_key(material)
+            .map(|entry| &entry.data)
+            .ok_or_else(|| BlockStateError::UnknownBlock(material.clone()))
+    }
+}
+
+/// the name and the value of properties which are not typed yet
+type RawProperties<'a> = Vec<(&'a str, &'a str)>;
+
+/// splits the text syntax into the material and the raw properties
+fn split_state(text: &str) -> Result<(ResourceKey, RawProperties<'_>), BlockStateError> {
+    let syntax_error =
+        |reason: &str| BlockStateError::InvalidSyntax(text.to_string(), reason.to_string());
+    let text = text.trim();
+    let (key, properties) = match text.split_once('[') {
+        Some((key, properties)) => (
+            key,
+            properties
+                .strip_suffix(']')
+                .ok_or_else(|| syntax_error("missing closing bracket"))?,
+        ),
+        None => (text, ""),
+    };
+
+    let material = key
+        .parse::<ResourceKey>()
+        .map_err(|error| syntax_error(&error.to_string()))?;
+
+    let mut pairs = Vec::new();
+    for pair in properties
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (name, value) = pair
+            .split_once('=')
+            .ok_or_else(|| syntax_error("properties must be written as `name=value`"))?;
+        let (name, value) = (name.trim(), value.trim());
+        if pairs.iter().any(|(other, _)| *other == name) {
+            return Err(BlockStateError::DuplicateProperty(name.to_string()));
+        }
+        pairs.push((name, value));
+    }
+
+    Ok((material, pairs))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStateError {
+    /// the text and the reason why it could not be parsed
+    InvalidSyntax(String, String),
+    UnknownBlock(ResourceKey),
+    UnknownProperty(ResourceKey, String),
+    MissingProperty(String),
+    DuplicateProperty(String),
+    /// the property and the rejected value
+    InvalidValue(String, String),
+    /// the property and why its definition in a schema is invalid
+    InvalidDefinition(String, String),
+}
+
+impl Display for BlockStateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockStateError::InvalidSyntax(text, reason) => {
+                write!(f, "invalid block state `{}`: {}", text, reason)
+            }
+            BlockStateError::UnknownBlock(key) => {
+                write!(f, "unknown block {}", key)
+            }
+            BlockStateError::UnknownProperty(key, property) => {
+                write!(f, "the block {} has no property {}", key, property)
+            }
+            BlockStateError::MissingProperty(property) => {
+                write!(f, "the property {} is missing", property)
+            }
+            BlockStateError::DuplicateProperty(property) => {
+                write!(f, "the property {} is set more than once", property)
+            }
+            BlockStateError::InvalidValue(property, value) => {
+                write!(f, "invalid value {} for the property {}", value, property)
+            }
+            BlockStateError::InvalidDefinition(property, reason) => {
+                write!(f, "invalid definition of the property {}: {}", property, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlockStateError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(name: &str) -> ResourceKey {
+        ResourceKey::core(name).unwrap()
+    }
+
+    fn schemas() -> BlockSchemas {
+        let mut schemas = BlockSchemas::default();
+        schemas.register(key("dirt"), BlockSchema::new());
+        schemas.register(
+            key("stairs"),
+            BlockSchema::new()
+                .with_property(
+                    "facing",
+                    PropertyDefinition::enumeration(&["north", "east", "south", "west"]),
+                )
+                .with_property("waterlogged", PropertyDefinition::bool(false))
+                .with_property("level", PropertyDefinition::int(0, 15, 7)),
+        );
+        schemas
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let schemas = schemas();
+        let dirt = schemas.parse_state("dirt").unwrap();
+        assert_eq!(dirt, BlockData::new(key("dirt")));
+        assert_eq!(dirt.to_string(), "core:dirt");
+
+        let stairs = schemas
+            .parse_state("core:stairs[ level = 3, facing=west]")
+            .unwrap();
+        assert_eq!(
+            stairs.property("facing"),
+            Some(&PropertyValue::Enum("west".to_string()))
+        );
+        assert_eq!(
+            stairs.property("waterlogged"),
+            Some(&PropertyValue::Bool(false))
+        );
+        assert_eq!(
+            stairs.to_string(),
+            "core:stairs[facing=west,level=3,waterlogged=false]"
+        );
+        assert_eq!(schemas.parse_state(&stairs.to_string()), Ok(stairs));
+    }
+
+    #[test]
+    fn canonical() {
+        let schemas = schemas();
+        let a = schemas
+            .parse_state("stairs[waterlogged=true,facing=north]")
+            .unwrap();
+        let b = schemas
+            .parse_state("core:stairs[facing=north,level=7,waterlogged=true]")
+            .unwrap();
+        assert_eq!(a, b);
+        assert_eq!(
+            rmp_serde::to_vec(&a).unwrap(),
+            rmp_serde::to_vec(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let schemas = schemas();
+        let error = |text: &str| schemas.parse_state(text).unwrap_err();
+        assert_eq!(
+            error("core:stone"),
+            BlockStateError::UnknownBlock(key("stone"))
+        );
+        assert_eq!(
+            error("stairs[shape=straight]"),
+            BlockStateError::UnknownProperty(key("stairs"), "shape".to_string())
+        );
+        assert_eq!(
+            error("stairs[level=16]"),
+            BlockStateError::InvalidValue("level".to_string(), "16".to_string())
+        );
+        assert_eq!(
+            error("stairs[facing=up]"),
+            BlockStateError::InvalidValue("facing".to_string(), "up".to_string())
+        );
+        assert_eq!(
+            error("stairs[level=1,level=2]"),
+            BlockStateError::DuplicateProperty("level".to_string())
+        );
+        assert!(matches!(
+            error("stairs[level=1"),
+            BlockStateError::InvalidSyntax(..)
+        ));
+        assert!(matches!(error(":dirt"), BlockStateError::InvalidSyntax(..)));
+
+        assert_eq!(
+            schemas.validate_state(&BlockData::new(key("stairs"))),
+            Err(BlockStateError::MissingProperty("facing".to_string()))
+        );
+        assert_eq!(
+            schemas.validate_state(&schemas.default_state(&key("stairs")).unwrap()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn deserialize() {
+        let stairs = schemas().get_by_key(&key("stairs")).unwrap().data.clone();
+        let json = serde_json::to_string(&stairs).unwrap();
+        assert_eq!(serde_json::from_str::<BlockSchema>(&json).unwrap(), stairs);
+
+        let invalid = [
+            r#"{"properties":{"level":{"type":"int","min":5,"max":1,"default":3}}}"#,
+            r#"{"properties":{"level":{"type":"int","min":0,"max":7,"default":8}}}"#,
+            r#"{"properties":{"facing":{"type":"enum","values":[],"default":"north"}}}"#,
+            r#"{"properties":{"facing":{"type":"enum","values":["up","up"],"default":"up"}}}"#,
+            r#"{"properties":{"facing":{"type":"enum","values":["a=b"],"default":"a=b"}}}"#,
+            r#"{"properties":{"Lit":{"type":"bool","default":false}}}"#,
+        ];
+        for json in invalid {
+            assert!(serde_json::from_str::<BlockSchema>(json).is_err(), "{}", json);
+        }
+    }
+}
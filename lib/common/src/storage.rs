@@ -1,4 +1,5 @@
 use bevy::prelude::Component;
+use std::convert::Infallible;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::io::{Read, Write};
@@ -10,6 +11,7 @@ use packedvec::PackedVec;
 use rayon::prelude::*;
 
 use crate::lzw::lzw_decompress;
+use crate::{transform_local, transform_size, Direction, Positioning, CHUNK_SIZE, CHUNK_VOLUME};
 
 pub struct StorageCompressed;
 
@@ -211,6 +213,121 @@ where
     pub fn data(&self) -> &PackedVec<usize> {
         &self.data
     }
+    /// converts every item of the storage with [map], which is called once per palette entry.
+    /// entries which are mapped to the same value are merged
+    pub fn map_palette<T>(&self, mut map: impl FnMut(&ITEM) -> T) -> Storage<SIZE, T>
+    where
+        T: Debug + Clone + Ord + Eq + Hash + Send + Sync,
+    {
+        match self.try_map_palette(|item| Ok::<_, Infallible>(map(item))) {
+            Ok(storage) => storage,
+            Err(never) => match never {},
+        }
+    }
+
+    /// like [Storage::map_palette] but stops at the first error of [map]
+    pub fn try_map_palette<T, E>(
+        &self,
+        map: impl FnMut(&ITEM) -> Result<T, E>,
+    ) -> Result<Storage<SIZE, T>, E>
+    where
+        T: Debug + Clone + Ord + Eq + Hash + Send + Sync,
+    {
+        let mapped = self
+            .palette
+            .iter()
+            .map(map)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut palette = mapped.clone();
+        palette.sort_unstable();
+        palette.dedup();
+        if palette == mapped {
+            //the palette ids did not change so the grid can be reused
+            return Ok(Storage {
+                palette,
+                data: self.data.clone(),
+            });
+        }
+
+        let translation = mapped
+            .iter()
+            .map(|item| palette.binary_search(item).unwrap())
+            .collect::<Vec<_>>();
+        let grid = self
+            .data
+            .iter()
+            .map(|palette_id| translation[palette_id])
+            .collect::<Vec<_>>();
+        Ok(Storage {
+            palette,
+            data: PackedVec::new(grid),
+        })
+    }
+
+    /// exports the palette ids of the grid as lz4 compressed little endian [u16]s.
+    /// the palette is not included and has to be stored next to it (see [Storage::import_grid])
+    pub fn export_grid(&self) -> Vec<u8> {
+        assert!(
+            self.palette.len() <= u16::MAX as usize + 1,
+            "the palette is too large to be exported"
+        );
+        let bytes = self
+            .data
+            .iter()
+            .flat_map(|palette_id| (palette_id as u16).to_le_bytes())
+            .collect::<Vec<_>>();
+        lz4_flex::compress_prepend_size(&bytes)
+    }
+
+    /// the inverse of [Storage::export_grid].
+    /// [palette] may be in any order but must not contain duplicates
+    pub fn import_grid(palette: Vec<ITEM>, grid: &[u8]) -> Result<Self, InvalidGridError> {
+        //the size prefix comes from untrusted data, so it is only compared with the known size
+        let (prefix, compressed) = grid
+            .split_first_chunk::<4>()
+            .ok_or(InvalidGridError)?;
+        if palette.is_empty() || u32::from_le_bytes(*prefix) as usize != SIZE * 2 {
+            return Err(InvalidGridError);
+        }
+        let mut bytes = vec![0; SIZE * 2];
+        let length = lz4_flex::block::decompress_into(compressed, &mut bytes)
+            .map_err(|_| InvalidGridError)?;
+        if length != bytes.len() {
+            return Err(InvalidGridError);
+        }
+
+        let mut order = (0..palette.len()).collect::<Vec<_>>();
+        order.sort_unstable_by(|a, b| palette[*a].cmp(&palette[*b]));
+        if order
+            .windows(2)
+            .any(|pair| palette[pair[0]] == palette[pair[1]])
+        {
+            return Err(InvalidGridError);
+        }
+        let mut translation = vec![0; palette.len()];
+        for (palette_id, former_id) in order.iter().enumerate() {
+            translation[*former_id] = palette_id;
+        }
+
+        let grid = bytes
+            .chunks_exact(2)
+            .map(|id| {
+                translation
+                    .get(u16::from_le_bytes([id[0], id[1]]) as usize)
+                    .copied()
+                    .ok_or(InvalidGridError)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let palette = order
+            .into_iter()
+            .map(|former_id| palette[former_id].clone())
+            .collect();
+        Ok(Self {
+            palette,
+            data: PackedVec::new(grid),
+        })
+    }
+
     pub fn export_compressed_data(&self) -> Vec<u8> {
         if self.data.bwidth() == 0 {
             return Vec::new();
@@ -279,6 +396,355 @@ where
     }
 }
 
+crate::error_object!(
+    InvalidGridError,
+    "the grid is corrupted or does not match the palette"
+);
+
+/// Converts a chunk local position into the flat index used by [Storage].
+/// The x axis is the fastest changing one followed by y and z (`x + y * SIZE + z * SIZE^2`),
+/// this matches the positions the mesher passes to its surface callbacks.
+#[inline]
+pub fn local_index(x: usize, y: usize, z: usize) -> usize {
+    debug_assert!(
+        x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE,
+        "local position out of bounds ({}, {}, {})",
+        x,
+        y,
+        z
+    );
+    x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE
+}
+
+/// the inverse of [local_index]
+#[inline]
+pub fn local_position(index: usize) -> (usize, usize, usize) {
+    debug_assert!(index < CHUNK_VOLUME, "index out of bounds ({})", index);
+    (
+        index % CHUNK_SIZE,
+        (index / CHUNK_SIZE) % CHUNK_SIZE,
+        index / (CHUNK_SIZE * CHUNK_SIZE),
+    )
+}
+
+/// Axis aligned slices of a chunk (see [Storage::iter_slice])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SliceAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl<ITEM> Storage<CHUNK_VOLUME, ITEM>
+where
+    ITEM: Debug + Clone + Ord + Eq + Hash + Send + Sync,
+{
+    #[inline]
+    fn check_position(x: usize, y: usize, z: usize) {
+        if x >= CHUNK_SIZE || y >= CHUNK_SIZE || z >= CHUNK_SIZE {
+            panic!(
+                "local position out of bounds ({}, {}, {} of {})",
+                x, y, z, CHUNK_SIZE
+            );
+        }
+    }
+
+    #[inline]
+    fn check_box(min: (usize, usize, usize), max: (usize, usize, usize)) {
+        assert!(
+            min.0 <= max.0 && min.1 <= max.1 && min.2 <= max.2,
+            "box {:?}..{:?} is inverted",
+            min,
+            max
+        );
+        if max.0 > CHUNK_SIZE || max.1 > CHUNK_SIZE || max.2 > CHUNK_SIZE {
+            panic!("box {:?}..{:?} exceeds the chunk", min, max);
+        }
+    }
+
+    pub fn get_xyz(&self, x: usize, y: usize, z: usize) -> &ITEM {
+        Self::check_position(x, y, z);
+        self.get(local_index(x, y, z))
+    }
+
+    pub fn set_xyz(&mut self, x: usize, y: usize, z: usize, block: ITEM) {
+        Self::check_position(x, y, z);
+        self.set(local_index(x, y, z), block)
+    }
+
+    /// sets every block in the box from [min] (inclusive) to [max] (exclusive) to [block]
+    /// unlike calling [Storage::set_xyz] in a loop the grid is only unpacked and packed once
+    pub fn fill_box(
+        &mut self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        block: ITEM,
+    ) {
+        Self::check_box(min, max);
+        let mut unpacked = self.data.iter().collect::<Vec<_>>();
+        let palette_id = self.get_or_create_pallete_id(block, &mut unpacked);
+        for z in min.2..max.2 {
+            for y in min.1..max.1 {
+                let row = local_index(0, y, z);
+                unpacked[row + min.0..row + max.0].fill(palette_id);
+            }
+        }
+        self.compact_palette(&mut unpacked);
+        self.data = PackedVec::new(unpacked);
+    }
+
+    /// sets the box from [min] (inclusive) to [max] (exclusive) to [blocks] in x, y, z order
+    /// unlike calling [Storage::set_xyz] in a loop the grid is only unpacked and packed once
+    pub fn set_box(
+        &mut self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        blocks: impl IntoIterator<Item = ITEM>,
+    ) {
+        Self::check_box(min, max);
+        let mut unpacked = self.data.iter().collect::<Vec<_>>();
+        let mut blocks = blocks.into_iter();
+        for z in min.2..max.2 {
+            for y in min.1..max.1 {
+                for x in min.0..max.0 {
+                    let block = blocks.next().expect("fewer blocks than the box contains");
+                    let palette_id = self.get_or_create_pallete_id(block, &mut unpacked);
+                    unpacked[local_index(x, y, z)] = palette_id;
+                }
+            }
+        }
+        assert!(blocks.next().is_none(), "more blocks than the box contains");
+        self.compact_palette(&mut unpacked);
+        self.data = PackedVec::new(unpacked);
+    }
+
+    /// iterates over a single layer of the chunk
+    /// the items are `(u, v, item)` where u and v are the remaining axes in xyz order
+    /// (f.e. `(x, z)` for [SliceAxis::Y])
+    pub fn iter_slice(
+        &self,
+        axis: SliceAxis,
+        layer: usize,
+    ) -> impl Iterator<Item = (usize, usize, &'_ ITEM)> + '_ {
+        assert!(layer < CHUNK_SIZE, "layer {} is out of bounds", layer);
+        (0..CHUNK_SIZE * CHUNK_SIZE).map(move |i| {
+            let (u, v) = (i % CHUNK_SIZE, i / CHUNK_SIZE);
+            let index = match axis {
+                SliceAxis::X => local_index(layer, u, v),
+                SliceAxis::Y => local_index(u, layer, v),
+                SliceAxis::Z => local_index(u, v, layer),
+            };
+            (u, v, self.get(index))
+        })
+    }
+
+    /// iterates bottom to top over the column at x and z
+    pub fn iter_column(&self, x: usize, z: usize) -> impl Iterator<Item = (usize, &'_ ITEM)> + '_ {
+        Self::check_position(x, 0, z);
+        (0..CHUNK_SIZE).map(move |y| (y, self.get(local_index(x, y, z))))
+    }
+
+    /// copies the box at [source_min] with the dimensions of [size] from [source] into this storage at [target_min]
+    pub fn copy_box_from(
+        &mut self,
+        source: &Self,
+        source_min: (usize, usize, usize),
+        size: (usize, usize, usize),
+        target_min: (usize, usize, usize),
+    ) {
+        let source_max = (
+            source_min.0 + size.0,
+            source_min.1 + size.1,
+            source_min.2 + size.2,
+        );
+        let target_max = (
+            target_min.0 + size.0,
+            target_min.1 + size.1,
+            target_min.2 + size.2,
+        );
+        Self::check_box(source_min, source_max);
+        Self::check_box(target_min, target_max);
+
+        self.copy_box_with(source, source_min, size, |x, y, z| {
+            local_index(target_min.0 + x, target_min.1 + y, target_min.2 + z)
+        });
+    }
+
+    /// like [Storage::copy_box_from] but the box is transformed by [positioning] while copying
+    /// the box in this storage starts at [target_min] and has the size of [Positioning::transform_size]
+    pub fn copy_transformed_box_from(
+        &mut self,
+        source: &Self,
+        source_min: (usize, usize, usize),
+        size: (usize, usize, usize),
+        positioning: Positioning,
+        target_min: (usize, usize, usize),
+    ) {
+        let matrix = positioning.matrix();
+        let target_size = transform_size(&matrix, size);
+        Self::check_box(
+            source_min,
+            (
+                source_min.0 + size.0,
+                source_min.1 + size.1,
+                source_min.2 + size.2,
+            ),
+        );
+        Self::check_box(
+            target_min,
+            (
+                target_min.0 + target_size.0,
+                target_min.1 + target_size.1,
+                target_min.2 + target_size.2,
+            ),
+        );
+
+        self.copy_box_with(source, source_min, size, |x, y, z| {
+            let (tx, ty, tz) = transform_local(&matrix, (x, y, z), size, target_size);
+            local_index(target_min.0 + tx, target_min.1 + ty, target_min.2 + tz)
+        });
+    }
+
+    /// copies the box at [source_min] with the dimensions of [size] from [source],
+    /// [target_index] maps the position in the box to the index in this storage
+    fn copy_box_with(
+        &mut self,
+        source: &Self,
+        source_min: (usize, usize, usize),
+        size: (usize, usize, usize),
+        target_index: impl Fn(usize, usize, usize) -> usize,
+    ) {
+        let mut unpacked = self.data.iter().collect::<Vec<_>>();
+        //translate the palette of the source once instead of per block
+        let mut translated = vec![None; source.palette.len()];
+        for z in 0..size.2 {
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    let source_index =
+                        local_index(source_min.0 + x, source_min.1 + y, source_min.2 + z);
+                    let source_id = unsafe { source.data.get_unchecked(source_index) };
+                    let palette_id = match translated[source_id] {
+                        Some(palette_id) => palette_id,
+                        None => {
+                            let palette_len = self.palette.len();
+                            let palette_id = self.get_or_create_pallete_id(
+                                source.palette[source_id].clone(),
+                                &mut unpacked,
+                            );
+                            //ids of already translated entries are shifted by an insertion
+                            if self.palette.len() != palette_len {
+                                for id in translated.iter_mut().flatten() {
+                                    if *id >= palette_id {
+                                        *id += 1;
+                                    }
+                                }
+                            }
+                            translated[source_id] = Some(palette_id);
+                            palette_id
+                        }
+                    };
+                    unpacked[target_index(x, y, z)] = palette_id;
+                }
+            }
+        }
+        self.compact_palette(&mut unpacked);
+        self.data = PackedVec::new(unpacked);
+    }
+
+    /// the whole chunk transformed by [positioning] around its center
+    pub fn transformed(&self, positioning: Positioning) -> Self {
+        let mut transformed = self.clone();
+        let size = (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE);
+        transformed.copy_transformed_box_from(self, (0, 0, 0), size, positioning, (0, 0, 0));
+        transformed
+    }
+
+    /// copies the outermost layer of the chunk in [direction] (see [Direction::get_vector_values])
+    /// the items are in the order of [Storage::iter_slice]
+    pub fn extract_face(&self, direction: Direction) -> Vec<ITEM> {
+        (0..CHUNK_SIZE * CHUNK_SIZE)
+            .map(|index| self.get(Self::face_index(direction, index)).clone())
+            .collect()
+    }
+
+    /// overwrites the outermost layer of the chunk in [direction] with [face]
+    /// [face] must be in the order of [Storage::extract_face]
+    /// f.e. `chunk.apply_face(direction.opposite(), &neighbour.extract_face(direction))` copies the touching layer of a neighbour
+    pub fn apply_face(&mut self, direction: Direction, face: &[ITEM]) {
+        assert_eq!(
+            face.len(),
+            CHUNK_SIZE * CHUNK_SIZE,
+            "a face must contain exactly {} items",
+            CHUNK_SIZE * CHUNK_SIZE
+        );
+        let mut unpacked = self.data.iter().collect::<Vec<_>>();
+        for (index, block) in face.iter().enumerate() {
+            let palette_id = self.get_or_create_pallete_id(block.clone(), &mut unpacked);
+            unpacked[Self::face_index(direction, index)] = palette_id;
+        }
+        self.compact_palette(&mut unpacked);
+        self.data = PackedVec::new(unpacked);
+    }
+
+    /// the index of the block at [index] of the face in [direction], both sides of a border share the surface index
+    #[inline]
+    fn face_index(direction: Direction, index: usize) -> usize {
+        let (u, v) = (index % CHUNK_SIZE, index / CHUNK_SIZE);
+        let layer = if direction.is_negative() {
+            0
+        } else {
+            CHUNK_SIZE - 1
+        };
+        match direction.axis() {
+            SliceAxis::X => local_index(layer, u, v),
+            SliceAxis::Y => local_index(u, layer, v),
+            SliceAxis::Z => local_index(u, v, layer),
+        }
+    }
+
+    /// removes all palette entries which are not referenced by the grid anymore
+    fn compact_palette(&mut self, unpacked: &mut [usize]) {
+        let mut used = vec![false; self.palette.len()];
+        for palette_id in unpacked.iter() {
+            used[*palette_id] = true;
+        }
+        if used.iter().all(|used| *used) {
+            return;
+        }
+        let mut remapped = vec![0; self.palette.len()];
+        let mut next = 0;
+        for (palette_id, used) in used.iter().enumerate() {
+            remapped[palette_id] = next;
+            if *used {
+                next += 1;
+            }
+        }
+        let mut index = 0;
+        self.palette.retain(|_| {
+            index += 1;
+            used[index - 1]
+        });
+        unpacked
+            .par_iter_mut()
+            .for_each(|palette_id| *palette_id = remapped[*palette_id]);
+    }
+}
+
+impl<ITEM> Storage<CHUNK_VOLUME, ITEM>
+where
+    ITEM: Debug + Clone + Ord + Eq + Hash + Default + Send + Sync,
+{
+    /// iterates over all blocks which are not the [Default] value of [ITEM] with their local position
+    pub fn iter_non_default(&self) -> impl Iterator<Item = ((usize, usize, usize), &'_ ITEM)> + '_ {
+        let default_id = self.palette.binary_search(&ITEM::default()).ok();
+        self.data
+            .iter()
+            .enumerate()
+            .filter(move |(_, palette_id)| Some(*palette_id) != default_id)
+            .map(|(index, palette_id)| (local_position(index), &self.palette[palette_id]))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use packedvec::PackedVec;
@@ -334,4 +800,198 @@ mod test {
         println!("data: {}", humanize_memory(data.len()));
         println!("  {}", hex::encode(&data));
     }
+
+    #[test]
+    fn map_palette_and_grid() {
+        use super::Storage;
+
+        let mut storage = Storage::<64, u8>::new(&[0; 64]);
+        storage.set(1, 3);
+        storage.set(2, 5);
+
+        let reversed = storage.map_palette(|item| u8::MAX - *item);
+        assert_eq!(reversed.palette(), &[250, 252, 255]);
+        assert_eq!(*reversed.get(2), 250);
+        let merged = storage.map_palette(|item| *item > 0);
+        assert_eq!(merged.palette(), &[false, true]);
+        assert!(*merged.get(1) && *merged.get(2) && !*merged.get(3));
+
+        let grid = storage.export_grid();
+        assert_eq!(
+            Storage::<64, u8>::import_grid(storage.palette().to_vec(), &grid).unwrap(),
+            storage
+        );
+        //the palette may be in any order as long as it matches the grid
+        let shuffled = Storage::<64, u8>::import_grid(vec![5, 3, 0], &grid).unwrap();
+        assert_eq!(shuffled.palette(), &[0, 3, 5]);
+        assert_eq!(shuffled.iter().filter(|item| **item == 5).count(), 62);
+        assert!(Storage::<64, u8>::import_grid(vec![0, 3], &grid).is_err());
+        assert!(Storage::<64, u8>::import_grid(vec![0, 0, 3], &grid).is_err());
+        assert!(Storage::<32, u8>::import_grid(vec![0, 3, 5], &grid).is_err());
+        assert!(Storage::<64, u8>::import_grid(vec![0], &[1, 2, 3]).is_err());
+        //a forged size prefix is rejected before anything is allocated
+        let mut forged = grid.clone();
+        forged[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Storage::<64, u8>::import_grid(vec![0, 3, 5], &forged).is_err());
+    }
+
+    mod xyz {
+        use crate::storage::{local_index, local_position, SliceAxis, Storage};
+        use crate::{AxialRotation, Direction, Positioning, CHUNK_SIZE, CHUNK_VOLUME};
+
+        type TestStorage = Storage<CHUNK_VOLUME, u32>;
+
+        #[test]
+        fn index_roundtrip() {
+            for index in [0, 1, CHUNK_SIZE, CHUNK_SIZE * CHUNK_SIZE, CHUNK_VOLUME - 1] {
+                let (x, y, z) = local_position(index);
+                assert_eq!(local_index(x, y, z), index);
+            }
+            assert_eq!(
+                local_index(1, 2, 3),
+                1 + 2 * CHUNK_SIZE + 3 * CHUNK_SIZE * CHUNK_SIZE
+            );
+        }
+
+        #[test]
+        fn get_set_xyz() {
+            let mut storage = TestStorage::empty();
+            storage.set_xyz(1, 2, 3, 7);
+            assert_eq!(*storage.get_xyz(1, 2, 3), 7);
+            assert_eq!(*storage.get(local_index(1, 2, 3)), 7);
+            assert_eq!(*storage.get_xyz(3, 2, 1), 0);
+        }
+
+        #[test]
+        fn fill_box() {
+            let mut storage = TestStorage::empty();
+            storage.fill_box((0, 0, 0), (CHUNK_SIZE, 4, CHUNK_SIZE), 1);
+            storage.fill_box((2, 2, 2), (4, 6, 4), 2);
+            assert_eq!(*storage.get_xyz(0, 0, 0), 1);
+            assert_eq!(*storage.get_xyz(3, 5, 3), 2);
+            assert_eq!(*storage.get_xyz(3, 6, 3), 0);
+            assert_eq!(storage.palette(), &[0, 1, 2]);
+
+            storage.fill_box((0, 0, 0), (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE), 3);
+            assert_eq!(storage.palette(), &[3]);
+        }
+
+        #[test]
+        #[should_panic(expected = "is inverted")]
+        fn fill_inverted_box() {
+            let mut storage = TestStorage::empty();
+            storage.fill_box((4, 0, 0), (2, CHUNK_SIZE, CHUNK_SIZE), 1);
+        }
+
+        #[test]
+        fn set_box() {
+            let mut storage = TestStorage::empty();
+            storage.fill_box((0, 0, 0), (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE), 5);
+            storage.set_box((2, 3, 4), (4, 5, 6), 0..8);
+            assert_eq!(*storage.get_xyz(2, 3, 4), 0);
+            assert_eq!(*storage.get_xyz(3, 3, 4), 1);
+            assert_eq!(*storage.get_xyz(2, 4, 4), 2);
+            assert_eq!(*storage.get_xyz(3, 4, 5), 7);
+            assert_eq!(*storage.get_xyz(4, 4, 5), 5);
+            assert_eq!(storage.palette(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+
+            storage.set_box((2, 3, 4), (4, 5, 6), std::iter::repeat(5).take(8));
+            assert_eq!(storage.palette(), &[5]);
+        }
+
+        #[test]
+        fn slices_and_columns() {
+            let mut storage = TestStorage::empty();
+            storage.fill_box((0, 1, 0), (CHUNK_SIZE, 2, CHUNK_SIZE), 1);
+            assert!(storage
+                .iter_slice(SliceAxis::Y, 1)
+                .all(|(_, _, item)| *item == 1));
+            assert!(storage
+                .iter_slice(SliceAxis::Y, 0)
+                .all(|(_, _, item)| *item == 0));
+            let column = storage
+                .iter_column(5, 9)
+                .map(|(_, item)| *item)
+                .collect::<Vec<_>>();
+            assert_eq!(column[0], 0);
+            assert_eq!(column[1], 1);
+            assert_eq!(column[2], 0);
+            let slice_x = storage
+                .iter_slice(SliceAxis::X, 5)
+                .filter(|(_, _, item)| **item == 1)
+                .map(|(y, _, _)| y)
+                .collect::<Vec<_>>();
+            assert_eq!(slice_x, vec![1; CHUNK_SIZE]);
+        }
+
+        #[test]
+        fn copy_box() {
+            let mut source = TestStorage::empty();
+            source.set_xyz(0, 0, 0, 5);
+            source.set_xyz(1, 0, 0, 9);
+            let mut target = TestStorage::empty();
+            target.set_xyz(10, 10, 10, 7);
+            target.copy_box_from(&source, (0, 0, 0), (2, 1, 1), (10, 10, 10));
+            assert_eq!(*target.get_xyz(10, 10, 10), 5);
+            assert_eq!(*target.get_xyz(11, 10, 10), 9);
+            assert_eq!(target.palette(), &[0, 5, 9]);
+        }
+
+        #[test]
+        fn faces() {
+            let mut chunk = TestStorage::empty();
+            chunk.fill_box(
+                (CHUNK_SIZE - 1, 0, 0),
+                (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE),
+                1,
+            );
+            chunk.set_xyz(CHUNK_SIZE - 1, 3, 4, 2);
+            let face = chunk.extract_face(Direction::East);
+            assert_eq!(face[3 + 4 * CHUNK_SIZE], 2);
+
+            let mut neighbour = TestStorage::empty();
+            neighbour.apply_face(Direction::West, &face);
+            assert_eq!(*neighbour.get_xyz(0, 3, 4), 2);
+            assert_eq!(*neighbour.get_xyz(0, 0, 0), 1);
+            assert_eq!(*neighbour.get_xyz(1, 0, 0), 0);
+            assert_eq!(neighbour.extract_face(Direction::West), face);
+        }
+
+        #[test]
+        fn transformed() {
+            let mut chunk = TestStorage::empty();
+            chunk.set_xyz(0, 0, 0, 1);
+            chunk.set_xyz(CHUNK_SIZE - 1, 0, 0, 2);
+            let quarter = Positioning::new(Direction::Up, AxialRotation::By90);
+            let rotated = chunk.transformed(quarter);
+            assert_eq!(*rotated.get_xyz(CHUNK_SIZE - 1, 0, 0), 1);
+            assert_eq!(*rotated.get_xyz(CHUNK_SIZE - 1, 0, CHUNK_SIZE - 1), 2);
+            assert_eq!(*rotated.get_xyz(0, 0, 0), 0);
+            assert_eq!(rotated.transformed(quarter.inverse()), chunk);
+
+            let mut target = TestStorage::empty();
+            target.copy_transformed_box_from(
+                &chunk,
+                (CHUNK_SIZE - 2, 0, 0),
+                (2, 1, 1),
+                Positioning::IDENTITY.mirror(SliceAxis::X),
+                (4, 4, 4),
+            );
+            assert_eq!(*target.get_xyz(4, 4, 4), 2);
+            assert_eq!(*target.get_xyz(5, 4, 4), 0);
+            assert_eq!(target.palette(), &[0, 2]);
+        }
+
+        #[test]
+        fn non_default() {
+            let mut storage = TestStorage::empty();
+            storage.set_xyz(4, 5, 6, 2);
+            storage.set_xyz(31, 31, 31, 1);
+            let found = storage
+                .iter_non_default()
+                .map(|(position, item)| (position, *item))
+                .collect::<Vec<_>>();
+            assert_eq!(found, vec![((4, 5, 6), 2), ((31, 31, 31), 1)]);
+        }
+    }
 }
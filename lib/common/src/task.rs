@@ -0,0 +1,248 @@
+//! Polling of [TaskContainer]s, so systems only have to spawn the tasks.
+//!
+//! Add a [TaskPlugin] per result type. Every frame it completes the finished tasks ordered by their [TaskPriority],
+//! at most [TaskPlugin::budget] of them, and hands the results over as configured by [TaskOutput].
+//! The container is removed afterwards.
+//!
+//! Dropping a task cancels it, so tasks of despawned entities (or removed containers) are canceled automatically,
+//! they are counted in the [TaskStats].
+
+use std::marker::PhantomData;
+
+use bevy::prelude::{
+    App, Bundle, Commands, Component, Entity, Event, EventWriter, Local, Plugin, PreUpdate, Query,
+    RemovedComponents, ResMut, Resource,
+};
+use hashbrown::HashSet;
+
+use crate::{TaskContainer, TaskPoll};
+
+/// tasks with a higher priority are completed first when the budget is exceeded, the default is 0
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+pub struct TaskPriority(pub i32);
+
+/// sent by [TaskOutput::Event] when the task of [TaskCompleted::entity] is completed
+#[derive(Debug)]
+pub struct TaskCompleted<T> {
+    pub entity: Entity,
+    pub result: T,
+}
+
+impl<T: Send + Sync + 'static> Event for TaskCompleted<T> {}
+
+/// what happens with the result of a completed task
+pub enum TaskOutput<T> {
+    /// sends a [TaskCompleted] event
+    Event,
+    /// inserts the result into the entity of the task
+    Insert(fn(&mut Commands, Entity, T)),
+}
+
+impl<T> Clone for TaskOutput<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaskOutput<T> {}
+
+/// the tasks with the result [T] since the start
+#[derive(Debug)]
+pub struct TaskStats<T> {
+    /// the tasks which are currently running or wait for the budget
+    pub pending: usize,
+    pub completed: usize,
+    pub canceled: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for TaskStats<T> {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            completed: 0,
+            canceled: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: 'static> Resource for TaskStats<T> {}
+
+/// polls the [TaskContainer]s with the result [T]
+pub struct TaskPlugin<T> {
+    /// the maximum number of tasks completed per frame
+    pub budget: usize,
+    pub output: TaskOutput<T>,
+}
+
+impl<T> TaskPlugin<T> {
+    /// the results are sent as [TaskCompleted] events
+    pub fn events() -> Self {
+        Self {
+            budget: usize::MAX,
+            output: TaskOutput::Event,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+}
+
+impl<T: Bundle> TaskPlugin<T> {
+    /// the results are inserted into the entities of the tasks
+    pub fn insert() -> Self {
+        Self {
+            budget: usize::MAX,
+            output: TaskOutput::Insert(|commands, entity, result| {
+                //the entity may be despawned by a command which was queued before
+                commands.entity(entity).try_insert(result);
+            }),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Plugin for TaskPlugin<T> {
+    fn build(&self, app: &mut App) {
+        let budget = self.budget;
+        let output = self.output;
+        let poll_tasks =
+            move |mut commands: Commands,
+                  mut tasks: Query<(Entity, &mut TaskContainer<T>, Option<&TaskPriority>)>,
+                  mut removed: RemovedComponents<TaskContainer<T>>,
+                  mut completed: EventWriter<TaskCompleted<T>>,
+                  mut stats: ResMut<TaskStats<T>>,
+                  mut finished: Local<HashSet<Entity>>| {
+                //containers which were not removed by this system were dropped with their task
+                for entity in removed.read() {
+                    if !finished.remove(&entity) {
+                        stats.canceled += 1;
+                    }
+                }
+
+                let mut ready = Vec::new();
+                let mut pending = 0;
+                for (entity, container, priority) in &tasks {
+                    if !container.is_present() {
+                        commands.entity(entity).remove::<TaskContainer<T>>();
+                        finished.insert(entity);
+                        stats.canceled += 1;
+                    } else if container.is_done() {
+                        ready.push((priority.copied().unwrap_or_default(), entity));
+                    } else {
+                        pending += 1;
+                    }
+                }
+                ready.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+                pending += ready.len().saturating_sub(budget);
+
+                for (_, entity) in ready.into_iter().take(budget) {
+                    let (_, mut container, _) =
+                        tasks.get_mut(entity).expect("the entity was queried");
+                    let TaskPoll::Done(result) = container.poll_once() else {
+                        pending += 1;
+                        continue;
+                    };
+                    match output {
+                        TaskOutput::Event => {
+                            completed.send(TaskCompleted { entity, result });
+                        }
+                        TaskOutput::Insert(insert) => insert(&mut commands, entity, result),
+                    }
+                    commands.entity(entity).remove::<TaskContainer<T>>();
+                    finished.insert(entity);
+                    stats.completed += 1;
+                }
+                stats.pending = pending;
+            };
+
+        app.init_resource::<TaskStats<T>>()
+            .add_event::<TaskCompleted<T>>()
+            .add_systems(PreUpdate, poll_tasks);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bevy::prelude::{EventReader, ResMut, Update};
+    use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component)]
+    struct Generated(u32);
+
+    #[derive(Resource, Default)]
+    struct Received(Vec<u32>);
+
+    fn spawn_task<T: Send + Sync + 'static>(app: &mut App, value: T, priority: i32) -> Entity {
+        let task = AsyncComputeTaskPool::get_or_init(TaskPool::default).spawn(async move { value });
+        app.world
+            .spawn((TaskContainer::new(task), TaskPriority(priority)))
+            .id()
+    }
+
+    /// waits until the tasks are finished, so the budget decides what is completed
+    fn wait_for_tasks<T: Send + Sync + 'static>(app: &mut App) {
+        let mut tasks = app.world.query::<&TaskContainer<T>>();
+        while !tasks
+            .iter(&app.world)
+            .all(|task| task.is_done() || !task.is_present())
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn insert_with_budget() {
+        let mut app = App::new();
+        app.add_plugins(TaskPlugin::<Generated>::insert().with_budget(1));
+        let low = spawn_task(&mut app, Generated(1), 0);
+        let high = spawn_task(&mut app, Generated(2), 5);
+        let despawned = spawn_task(&mut app, Generated(3), 0);
+        app.world.despawn(despawned);
+        wait_for_tasks::<Generated>(&mut app);
+
+        app.update();
+        assert_eq!(app.world.get::<Generated>(high), Some(&Generated(2)));
+        assert_eq!(app.world.get::<Generated>(low), None);
+        let stats = app.world.resource::<TaskStats<Generated>>();
+        assert_eq!((stats.pending, stats.completed, stats.canceled), (1, 1, 1));
+
+        app.update();
+        assert_eq!(app.world.get::<Generated>(low), Some(&Generated(1)));
+        assert!(app.world.get::<TaskContainer<Generated>>(low).is_none());
+        app.update();
+        let stats = app.world.resource::<TaskStats<Generated>>();
+        assert_eq!((stats.pending, stats.completed, stats.canceled), (0, 2, 1));
+    }
+
+    #[test]
+    fn events() {
+        fn receive(mut events: EventReader<TaskCompleted<u32>>, mut received: ResMut<Received>) {
+            received.0.extend(events.read().map(|event| event.result));
+        }
+
+        let mut app = App::new();
+        app.add_plugins(TaskPlugin::<u32>::events())
+            .init_resource::<Received>()
+            .add_systems(Update, receive);
+        spawn_task(&mut app, 7u32, 0);
+        let canceled = spawn_task(&mut app, 8u32, 0);
+        app.world
+            .get_mut::<TaskContainer<u32>>(canceled)
+            .unwrap()
+            .cancel();
+        wait_for_tasks::<u32>(&mut app);
+
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<Received>().0, vec![7]);
+        let stats = app.world.resource::<TaskStats<u32>>();
+        assert_eq!((stats.completed, stats.canceled), (1, 1));
+    }
+}
@@ -0,0 +1,73 @@
+//! prints the packets of a capture file
+//!
+//! `capture-dump <file> [--clientbound|--serverbound]`
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+use protocol::capture::{CaptureReader, Direction};
+use protocol::proto::capture::captured_packet;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("usage: capture-dump <file> [--clientbound|--serverbound]");
+        return ExitCode::FAILURE;
+    };
+    let filter = match args.next().as_deref() {
+        None => None,
+        Some("--clientbound") => Some(Direction::Clientbound),
+        Some("--serverbound") => Some(Direction::Serverbound),
+        Some(arg) => {
+            eprintln!("unknown argument {arg}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!("could not open {path}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let reader = match CaptureReader::new(BufReader::new(file)) {
+        Ok(reader) => reader,
+        Err(error) => {
+            eprintln!("{error:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{:?}", reader.header());
+
+    for record in reader {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => {
+                eprintln!("{error:?}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if filter.is_some() && record.direction() != filter {
+            continue;
+        }
+
+        let kind = if record.datagram {
+            "datagram"
+        } else {
+            "packet"
+        };
+        let time = record.time().as_secs_f64();
+        match &record.packet {
+            Some(captured_packet::Packet::Serverbound(packet)) => {
+                println!("{time:>12.6} -> {kind:<8} {packet:?}")
+            }
+            Some(captured_packet::Packet::Clientbound(packet)) => {
+                println!("{time:>12.6} <- {kind:<8} {packet:?}")
+            }
+            None => println!("{time:>12.6} ?? {kind:<8}"),
+        }
+    }
+    ExitCode::SUCCESS
+}
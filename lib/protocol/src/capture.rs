@@ -0,0 +1,753 @@
+//! Capturing the play packets of a connection to reproduce desyncs.
+//!
+//! A capture file starts with a [CaptureHeader] followed by a [CapturedPacket] for every packet sent or received,
+//! every message is length-delimited.
+//! - [CaptureTransportLayer] wraps a [TransportLayer] and records everything passing through it
+//! - [ReplayTransportLayer] feeds the packets received in a capture back into a server or client app
+//!
+//! The login plugins wrap the connections reaching play into a [ServerCapture] or [ClientCapture], they are
+//! captured while a [CaptureDirectory] exists.
+//!
+//! `cargo run -p protocol --bin capture-dump <file>` prints the packets of a capture.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::log::warn;
+use bevy::prelude::Resource;
+use error_stack::{Report, ResultExt};
+use integer_encoding::VarIntReader;
+use prost::Message;
+use thiserror::Error;
+
+use crate::encoding::FrameConfig;
+use crate::handshake::{is_compatible, PROTOCOL_VERSION};
+use crate::proto::capture::{captured_packet, CaptureHeader, CapturedPacket};
+use crate::proto::common::DisconnectReason;
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::transport::simulated::SimulationClock;
+use crate::transport::{TransportContainer, TransportLayer};
+
+/// a capture larger than this is surely corrupted
+const MAX_RECORD_SIZE: u64 = crate::MAX_MESSAGE_SIZE as u64;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("could not write the capture")]
+    Write,
+    #[error("could not read the capture")]
+    Read,
+    #[error("the capture contains an invalid record")]
+    InvalidRecord,
+    #[error("the capture was made with an incompatible protocol version")]
+    IncompatibleVersion,
+    #[error("the replay speed has to be positive")]
+    InvalidSpeed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+/// a packet which can be captured, the type determines its [Direction]
+pub trait Capture: Sized {
+    fn capture(self) -> captured_packet::Packet;
+    fn from_capture(packet: captured_packet::Packet) -> Option<Self>;
+}
+
+impl Capture for PacketPlayClient {
+    fn capture(self) -> captured_packet::Packet {
+        captured_packet::Packet::Serverbound(self)
+    }
+
+    fn from_capture(packet: captured_packet::Packet) -> Option<Self> {
+        match packet {
+            captured_packet::Packet::Serverbound(packet) => Some(packet),
+            captured_packet::Packet::Clientbound(_) => None,
+        }
+    }
+}
+
+impl Capture for PacketPlayServer {
+    fn capture(self) -> captured_packet::Packet {
+        captured_packet::Packet::Clientbound(self)
+    }
+
+    fn from_capture(packet: captured_packet::Packet) -> Option<Self> {
+        match packet {
+            captured_packet::Packet::Clientbound(packet) => Some(packet),
+            captured_packet::Packet::Serverbound(_) => None,
+        }
+    }
+}
+
+impl CapturedPacket {
+    pub fn direction(&self) -> Option<Direction> {
+        match self.packet.as_ref()? {
+            captured_packet::Packet::Serverbound(_) => Some(Direction::Serverbound),
+            captured_packet::Packet::Clientbound(_) => Some(Direction::Clientbound),
+        }
+    }
+
+    pub fn time(&self) -> Duration {
+        Duration::from_micros(self.timestamp)
+    }
+}
+
+pub struct CaptureWriter<W> {
+    writer: W,
+    start: Instant,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// starts the capture by writing the header
+    pub fn new(mut writer: W) -> error_stack::Result<Self, CaptureError> {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let header = CaptureHeader {
+            protocol_version: Some(PROTOCOL_VERSION),
+            started_at,
+        };
+        writer
+            .write_all(&header.encode_length_delimited_to_vec())
+            .change_context(CaptureError::Write)?;
+
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// records the packet with the time since the start
+    pub fn write<P: Capture>(
+        &mut self,
+        packet: P,
+        datagram: bool,
+    ) -> error_stack::Result<(), CaptureError> {
+        let record = CapturedPacket {
+            timestamp: self.start.elapsed().as_micros() as u64,
+            datagram,
+            packet: Some(packet.capture()),
+        };
+        self.buffer.clear();
+        record
+            .encode_length_delimited(&mut self.buffer)
+            .change_context(CaptureError::Write)?;
+        self.writer
+            .write_all(&self.buffer)
+            .change_context(CaptureError::Write)
+    }
+
+    pub fn flush(&mut self) -> error_stack::Result<(), CaptureError> {
+        self.writer.flush().change_context(CaptureError::Write)
+    }
+}
+
+pub struct CaptureReader<R> {
+    reader: R,
+    header: CaptureHeader,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    /// reads the header and checks the protocol version
+    pub fn new(mut reader: R) -> error_stack::Result<Self, CaptureError> {
+        let mut buffer = Vec::new();
+        let header: CaptureHeader = read_record(&mut reader, &mut buffer)?
+            .ok_or_else(|| Report::new(CaptureError::Read))?;
+        let version = header
+            .protocol_version
+            .as_ref()
+            .ok_or_else(|| Report::new(CaptureError::InvalidRecord))?;
+        if !is_compatible(version) {
+            return Err(Report::new(CaptureError::IncompatibleVersion));
+        }
+
+        Ok(Self {
+            reader,
+            header,
+            buffer,
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+}
+
+impl<R: BufRead> Iterator for CaptureReader<R> {
+    type Item = error_stack::Result<CapturedPacket, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_record(&mut self.reader, &mut self.buffer).transpose()
+    }
+}
+
+/// reads a length-delimited message, [None] at the end of the reader
+fn read_record<M: Message + Default>(
+    reader: &mut impl BufRead,
+    buffer: &mut Vec<u8>,
+) -> error_stack::Result<Option<M>, CaptureError> {
+    if reader
+        .fill_buf()
+        .change_context(CaptureError::Read)?
+        .is_empty()
+    {
+        return Ok(None);
+    }
+
+    let length: u64 = reader.read_varint().change_context(CaptureError::Read)?;
+    if length > MAX_RECORD_SIZE {
+        return Err(Report::new(CaptureError::InvalidRecord));
+    }
+    buffer.resize(length as usize, 0);
+    reader
+        .read_exact(buffer)
+        .change_context(CaptureError::Read)?;
+    M::decode(buffer.as_slice())
+        .map(Some)
+        .change_context(CaptureError::InvalidRecord)
+}
+
+/// the directory the login plugins write the captures of their connections to
+#[derive(Debug, Clone, Resource)]
+pub struct CaptureDirectory(pub PathBuf);
+
+impl CaptureDirectory {
+    /// starts the capture `<name>-<start in milliseconds>.capture`
+    pub fn create(
+        &self,
+        name: &str,
+    ) -> error_stack::Result<CaptureWriter<Box<dyn Write + Send>>, CaptureError> {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        std::fs::create_dir_all(&self.0).change_context(CaptureError::Write)?;
+        let file = File::create(self.0.join(format!("{name}-{started_at}.capture")))
+            .change_context(CaptureError::Write)?;
+        CaptureWriter::new(Box::new(BufWriter::new(file)) as Box<dyn Write + Send>)
+    }
+}
+
+/// the server end of a play connection, see [CaptureTransportLayer]
+pub type ServerCapture<T, E> = CaptureTransportLayer<
+    T,
+    PacketPlayServer,
+    PacketPlayClient,
+    PacketPlayServer,
+    PacketPlayClient,
+    E,
+>;
+
+/// the client end of a play connection, see [CaptureTransportLayer]
+pub type ClientCapture<T, E> = CaptureTransportLayer<
+    T,
+    PacketPlayClient,
+    PacketPlayServer,
+    PacketPlayClient,
+    PacketPlayServer,
+    E,
+>;
+
+/// records every packet sent and received by the wrapped transport
+///
+/// a failing capture is stopped with a warning, the connection continues
+pub struct CaptureTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E> {
+    transport: T,
+    writer: Mutex<Option<CaptureWriter<Box<dyn Write + Send>>>>,
+    _sent: PhantomData<fn(PacketOut, DatagramOut)>,
+    _received: PhantomData<fn() -> (PacketIn, DatagramIn)>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    CaptureTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+{
+    pub fn new(transport: T, writer: CaptureWriter<Box<dyn Write + Send>>) -> Self {
+        Self::with_writer(transport, Some(writer))
+    }
+
+    /// captures into a new file of the [CaptureDirectory], without one or if the file cannot be created the
+    /// packets are passed through only
+    pub fn in_directory(transport: T, directory: Option<&CaptureDirectory>, name: &str) -> Self {
+        let writer = directory.and_then(|directory| match directory.create(name) {
+            Ok(writer) => Some(writer),
+            Err(error) => {
+                warn!("cannot capture the connection {name}: {error:?}");
+                None
+            }
+        });
+        Self::with_writer(transport, writer)
+    }
+
+    fn with_writer(transport: T, writer: Option<CaptureWriter<Box<dyn Write + Send>>>) -> Self {
+        Self {
+            transport,
+            writer: Mutex::new(writer),
+            _sent: PhantomData,
+            _received: PhantomData,
+            _error: PhantomData,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// whether the capture is still running
+    pub fn is_capturing(&self) -> bool {
+        self.writer
+            .lock()
+            .expect("cannot lock capture writer")
+            .is_some()
+    }
+
+    /// the packet is only cloned while the capture is running
+    fn capture<P: Capture + Clone>(&self, packet: &P, datagram: bool) {
+        let mut writer = self.writer.lock().expect("cannot lock capture writer");
+        let Some(capture) = writer.as_mut() else {
+            return;
+        };
+        if let Err(error) = capture.write(packet.clone(), datagram) {
+            warn!("stopping the capture: {error:?}");
+            *writer = None;
+        }
+    }
+
+    fn finish(&self) {
+        let mut writer = self.writer.lock().expect("cannot lock capture writer");
+        if let Some(mut capture) = writer.take() {
+            if let Err(error) = capture.flush() {
+                warn!("could not finish the capture: {error:?}");
+            }
+        }
+    }
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    for CaptureTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+where
+    T: TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, E>,
+    E: Error,
+    PacketOut: Capture + Clone,
+    PacketIn: Capture + Clone,
+    DatagramOut: Capture + Clone,
+    DatagramIn: Capture + Clone,
+{
+    fn send_packet(&self, packet: PacketOut) -> error_stack::Result<(), E> {
+        self.capture(&packet, false);
+        self.transport.send_packet(packet)
+    }
+
+    fn send_packets(
+        &self,
+        packets: impl IntoIterator<Item = PacketOut>,
+    ) -> error_stack::Result<(), E> {
+        self.transport.send_packets(
+            packets
+                .into_iter()
+                .inspect(|packet| self.capture(packet, false)),
+        )
+    }
+
+    fn send_datagram(&self, datagram: DatagramOut) -> error_stack::Result<(), E> {
+        self.capture(&datagram, true);
+        self.transport.send_datagram(datagram)
+    }
+
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketIn, DatagramIn>>,
+    ) -> error_stack::Result<(), E> {
+        let start = into.len();
+        let result = self.transport.drain(into);
+        for container in &into[start..] {
+            match container {
+                TransportContainer::Packet(packet) => self.capture(packet, false),
+                TransportContainer::Datagram(datagram) => self.capture(datagram, true),
+            }
+        }
+        result
+    }
+
+    fn close(&self) {
+        self.finish();
+        self.transport.close();
+    }
+
+    fn close_with(&self, reason: DisconnectReason) {
+        self.finish();
+        self.transport.close_with(reason);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.transport.is_closed()
+    }
+
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        self.transport.set_frame_config(config);
+    }
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E> Drop
+    for CaptureTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+{
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("the replay is finished")]
+pub struct ReplayFinished;
+
+/// delivers the packets a capture received at their original time divided by the speed
+///
+/// sent packets are dropped, the layer closes after the last packet was delivered and delivers nothing once closed
+pub struct ReplayTransportLayer<PacketOut, PacketIn> {
+    records: VecDeque<(Duration, TransportContainer<PacketIn, PacketIn>)>,
+    speed: f64,
+    clock: SimulationClock,
+    closed: Mutex<bool>,
+    _marker: PhantomData<fn(PacketOut)>,
+}
+
+impl<PacketOut, PacketIn: Capture> ReplayTransportLayer<PacketOut, PacketIn> {
+    /// replays the received packets of the records, packets of the other direction are skipped
+    ///
+    /// fails if the speed is not positive
+    pub fn new(
+        records: impl IntoIterator<Item = CapturedPacket>,
+        speed: f64,
+        clock: SimulationClock,
+    ) -> error_stack::Result<Self, CaptureError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Report::new(CaptureError::InvalidSpeed));
+        }
+        let records = records
+            .into_iter()
+            .filter_map(|record| {
+                let time = record.time().div_f64(speed);
+                let packet = PacketIn::from_capture(record.packet?)?;
+                let container = if record.datagram {
+                    TransportContainer::Datagram(packet)
+                } else {
+                    TransportContainer::Packet(packet)
+                };
+                Some((time, container))
+            })
+            .collect();
+
+        Ok(Self {
+            records,
+            speed,
+            clock,
+            closed: Mutex::new(false),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// the clock the packets are delivered by
+    pub fn clock(&self) -> &SimulationClock {
+        &self.clock
+    }
+
+    /// the number of packets still to be delivered
+    pub fn remaining(&self) -> usize {
+        self.records.len()
+    }
+}
+
+impl<PacketOut, PacketIn> TransportLayer<PacketOut, PacketIn, PacketOut, PacketIn, ReplayFinished>
+    for ReplayTransportLayer<PacketOut, PacketIn>
+{
+    fn send_packet(&self, _packet: PacketOut) -> error_stack::Result<(), ReplayFinished> {
+        if self.is_closed() {
+            return Err(Report::new(ReplayFinished));
+        }
+        Ok(())
+    }
+
+    fn send_datagram(&self, datagram: PacketOut) -> error_stack::Result<(), ReplayFinished> {
+        self.send_packet(datagram)
+    }
+
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketIn, PacketIn>>,
+    ) -> error_stack::Result<(), ReplayFinished> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        let now = self.clock.elapsed();
+        while self.records.front().is_some_and(|(time, _)| *time <= now) {
+            if let Some((_, container)) = self.records.pop_front() {
+                into.push(container);
+            }
+        }
+        if self.records.is_empty() {
+            self.close();
+        }
+        Ok(())
+    }
+
+    fn close(&self) {
+        *self.closed.lock().expect("cannot lock replay close") = true;
+    }
+
+    fn is_closed(&self) -> bool {
+        *self.closed.lock().expect("cannot lock replay close")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+    use std::sync::Arc;
+
+    use bevy::prelude::{App, Events};
+    use bevy::MinimalPlugins;
+    use bevy_replicon::prelude::RepliconClient;
+
+    use crate::client::{ClientConnection, ClientProtocolPlugin};
+    use crate::proto::play::client::packet::packet_play_client::Payload as ClientPayload;
+    use crate::proto::play::server::packet::packet_play_server::Payload as ServerPayload;
+    use crate::server::{
+        PlayerIdentity, PlayerJoined, PlayerProfile, ServerConnection, ServerProtocolPlugin,
+    };
+    use crate::transport::local::{
+        local_connection, ClientLocalTransport, LocalTransportError, ServerLocalTransport,
+    };
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client_packet(data: u8) -> PacketPlayClient {
+        PacketPlayClient {
+            payload: Some(ClientPayload::Sync(vec![data])),
+        }
+    }
+
+    fn server_packet(data: u8) -> PacketPlayServer {
+        PacketPlayServer {
+            payload: Some(ServerPayload::Sync(vec![data])),
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let buffer = SharedBuffer::default();
+        let (client, mut server) = local_connection();
+        let writer = CaptureWriter::new(Box::new(buffer.clone()) as Box<dyn Write + Send>).unwrap();
+        let mut client: CaptureTransportLayer<_, _, _, _, _, LocalTransportError> =
+            CaptureTransportLayer::new(client, writer);
+
+        client.send_packet(client_packet(1)).unwrap();
+        client.send_datagram(client_packet(2)).unwrap();
+        server.send_packet(server_packet(3)).unwrap();
+        let mut received = Vec::new();
+        server.drain(&mut received).unwrap();
+        assert_eq!(received.len(), 2);
+        client.drain(&mut Vec::new()).unwrap();
+        client.close();
+        assert!(!client.is_capturing());
+
+        let data = buffer.0.lock().unwrap().clone();
+        let reader = CaptureReader::new(data.as_slice()).unwrap();
+        assert_eq!(reader.header().protocol_version, Some(PROTOCOL_VERSION));
+        let records: Vec<_> = reader.map(Result::unwrap).collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|record| (record.direction().unwrap(), record.datagram))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Direction::Serverbound, false),
+                (Direction::Serverbound, true),
+                (Direction::Clientbound, false),
+            ]
+        );
+        assert!(records
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        assert_eq!(
+            records[2].packet,
+            Some(captured_packet::Packet::Clientbound(server_packet(3)))
+        );
+
+        //a truncated capture fails at the last record
+        let reader = CaptureReader::new(BufReader::new(&data[..data.len() - 1])).unwrap();
+        let records: Vec<_> = reader.collect();
+        assert_eq!(records.len(), 3);
+        assert!(records[2].is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let records = (0..3).map(|index| CapturedPacket {
+            timestamp: index * 1_000_000,
+            datagram: false,
+            packet: Some(server_packet(index as u8).capture()),
+        });
+        //the packets of the other direction are skipped
+        let sent = CapturedPacket {
+            timestamp: 0,
+            datagram: false,
+            packet: Some(client_packet(0).capture()),
+        };
+        let clock = SimulationClock::manual();
+        let mut replay: ReplayTransportLayer<PacketPlayClient, PacketPlayServer> =
+            ReplayTransportLayer::new(records.chain([sent]), 2.0, clock.clone()).unwrap();
+        assert_eq!(replay.remaining(), 3);
+
+        let mut received = Vec::new();
+        replay.drain(&mut received).unwrap();
+        assert_eq!(received.len(), 1);
+        clock.advance(Duration::from_millis(499));
+        replay.drain(&mut received).unwrap();
+        assert_eq!(received.len(), 1);
+        clock.advance(Duration::from_millis(1));
+        replay.drain(&mut received).unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(!replay.is_closed());
+        replay.send_packet(client_packet(1)).unwrap();
+
+        clock.advance(Duration::from_millis(500));
+        replay.drain(&mut received).unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(replay.is_closed());
+        assert!(replay.send_packet(client_packet(2)).is_err());
+    }
+
+    #[test]
+    fn invalid_speed() {
+        for speed in [0.0, -1.0, f64::NAN] {
+            let replay: error_stack::Result<
+                ReplayTransportLayer<PacketPlayClient, PacketPlayServer>,
+                _,
+            > = ReplayTransportLayer::new([], speed, SimulationClock::manual());
+            let Err(error) = replay else {
+                panic!("the speed {speed} was accepted");
+            };
+            assert!(matches!(
+                error.current_context(),
+                CaptureError::InvalidSpeed
+            ));
+        }
+    }
+
+    /// the player events a client app received
+    fn joined(app: &mut App) -> Vec<String> {
+        app.world
+            .resource_mut::<Events<PlayerJoined>>()
+            .drain()
+            .map(|joined| joined.display_name)
+            .collect()
+    }
+
+    #[test]
+    fn replay_app() {
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerProtocolPlugin::<ServerLocalTransport, LocalTransportError>::default(),
+        ));
+        server.finish();
+        server.cleanup();
+        let mut client = App::new();
+        client.add_plugins((
+            MinimalPlugins,
+            ClientProtocolPlugin::<
+                ClientCapture<ClientLocalTransport, LocalTransportError>,
+                LocalTransportError,
+            >::default(),
+        ));
+        client.finish();
+        client.cleanup();
+
+        //a session of the client is captured
+        let buffer = SharedBuffer::default();
+        let writer = CaptureWriter::new(Box::new(buffer.clone()) as Box<dyn Write + Send>).unwrap();
+        let (client_transport, server_transport) = local_connection();
+        let transport: ClientCapture<_, LocalTransportError> =
+            CaptureTransportLayer::new(client_transport, writer);
+        client.insert_resource(ClientConnection::new(transport));
+        //the connection of another player, which the captured client learns about
+        let (_bob_client, bob_transport) = local_connection();
+        for (name, transport) in [("alice", server_transport), ("bob", bob_transport)] {
+            server.world.spawn((
+                ServerConnection::new(transport),
+                PlayerProfile {
+                    display_name: name.to_string(),
+                },
+                PlayerIdentity::of_certificate(name.as_bytes()),
+            ));
+            server.update();
+            client.update();
+        }
+        assert_eq!(joined(&mut client), vec!["alice", "bob"]);
+
+        //the replay delivers the same packets to a new app
+        let data = buffer.0.lock().unwrap().clone();
+        let records: Vec<_> = CaptureReader::new(data.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let clock = SimulationClock::manual();
+        let replay: ReplayTransportLayer<PacketPlayClient, PacketPlayServer> =
+            ReplayTransportLayer::new(records, 1.0, clock.clone()).unwrap();
+        let mut replayed = App::new();
+        replayed.add_plugins((
+            MinimalPlugins,
+            ClientProtocolPlugin::<
+                ReplayTransportLayer<PacketPlayClient, PacketPlayServer>,
+                ReplayFinished,
+            >::default(),
+        ));
+        replayed.finish();
+        replayed.cleanup();
+        replayed.insert_resource(ClientConnection::new(replay));
+        clock.advance(Duration::from_secs(60));
+        replayed.update();
+        assert_eq!(joined(&mut replayed), vec!["alice", "bob"]);
+
+        //the replay ends the connection after the last packet
+        replayed.update();
+        assert!(replayed
+            .world
+            .resource::<RepliconClient>()
+            .is_disconnected());
+    }
+}
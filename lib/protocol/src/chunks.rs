@@ -0,0 +1,918 @@
+//! Streaming of chunks from the server to the client in play.
+//!
+//! The server keeps a [ChunkStreamer] per connection. It sends the chunks around the center of the view in [spiral]
+//! order, so the nearest chunks arrive first, and keeps at most [ChunkStreamConfig::max_in_flight] chunks which were
+//! not acknowledged yet, so a slow client does not fill up the send queue. Chunks which only contain one block state
+//! are sent as [ChunkLoad], all others as [ChunkFull]. Changes of loaded chunks are sent as [SectionUpdate]s and
+//! [BlockChange]s, changes of chunks which were not sent yet are part of their data anyway. Replaced chunks are sent
+//! again as a whole, removed ones are unloaded until they exist again.
+//!
+//! The client reassembles the chunks with a [ChunkAssembler] into [ChunkStorage]s and occlusion matrices for the
+//! mesher and acknowledges them with [ChunkAcknowledged].
+//!
+//! All chunk packets have the bulk priority of the [PacketScheduler](crate::transport::scheduler::PacketScheduler),
+//! so their order is kept. After a reconnect both sides are reset and every chunk in view is sent again.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::prelude::{Component, Event, Resource};
+use common::coordinate::{ChunkPos, LocalPos};
+use common::palette::{ChunkStorage, GlobalPalette, PaletteError, PaletteMode, StateId};
+use common::resource::BlockData;
+use common::storage::{local_position, Storage};
+use common::{CHUNK_SIZE, CHUNK_VOLUME};
+use hashbrown::{HashMap, HashSet};
+use mesher::b32::VoxelCubeOcclusionMatrix32;
+use thiserror::Error;
+
+use crate::convert::ConvertError;
+use crate::proto::chunk::{block_state, BlockState, ChunkData, GlobalPaletteUpdate};
+use crate::proto::play::client::packet::packet_play_client::Payload as ClientPayload;
+use crate::proto::play::client::packet::{ChunkAcknowledged, PacketPlayClient};
+use crate::proto::play::server::packet::packet_play_server::Payload as ServerPayload;
+use crate::proto::play::server::packet::{
+    BlockChange, ChunkFull, ChunkLoad, ChunkUnload, PacketPlayServer, SectionUpdate,
+};
+
+/// the edge length of a section, the part of a chunk replaced by a [SectionUpdate]
+pub const SECTION_SIZE: usize = 8;
+pub const SECTION_VOLUME: usize = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE;
+const SECTIONS_PER_AXIS: usize = CHUNK_SIZE / SECTION_SIZE;
+pub const SECTIONS_PER_CHUNK: usize = SECTIONS_PER_AXIS * SECTIONS_PER_AXIS * SECTIONS_PER_AXIS;
+
+/// the blocks of a section, the index order is the same as in [ChunkStorage]
+pub type SectionStorage = Storage<SECTION_VOLUME, StateId>;
+
+#[derive(Debug, Error)]
+pub enum ChunkStreamError {
+    #[error(transparent)]
+    InvalidPacket(#[from] ConvertError),
+    #[error(transparent)]
+    Palette(#[from] PaletteError),
+    #[error("the chunk {0} is not loaded")]
+    NotLoaded(ChunkPos),
+    #[error("the section {0} is out of bounds")]
+    InvalidSection(u32),
+    #[error("the block index {0} is out of bounds")]
+    InvalidIndex(u32),
+    #[error("the global palette update of the state {0} does not match the known states")]
+    PaletteMismatch(StateId),
+    #[error("the global palette update starting at the state {0} exceeds the state ids")]
+    PaletteOverflow(StateId),
+}
+
+/// the chunks around [center] ordered by their distance, rings of the same distance are walked as a spiral
+pub fn spiral(center: ChunkPos, view_distance: u32, vertical_view_distance: u32) -> Vec<ChunkPos> {
+    let view_distance = view_distance as i64;
+    let vertical_view_distance = vertical_view_distance as i64;
+    //0, 1, -1, 2, -2, ...
+    let vertical =
+        (0..=2 * vertical_view_distance).map(|i| if i % 2 == 0 { -i / 2 } else { i / 2 + 1 });
+
+    let mut positions = Vec::new();
+    for ring in 0..=view_distance {
+        let columns: Vec<(i64, i64)> = if ring == 0 {
+            vec![(0, 0)]
+        } else {
+            (-ring + 1..=ring)
+                .map(|z| (ring, z))
+                .chain((-ring..ring).rev().map(|x| (x, ring)))
+                .chain((-ring..ring).rev().map(|z| (-ring, z)))
+                .chain((-ring + 1..=ring).map(|x| (x, -ring)))
+                .collect()
+        };
+        for (x, z) in columns {
+            for y in vertical.clone() {
+                positions.push((
+                    ring.max(y.abs()),
+                    ChunkPos::new(center.x + x, center.y + y, center.z + z, center.dimension),
+                ));
+            }
+        }
+    }
+    //stable, so the spiral is kept within a distance
+    positions.sort_by_key(|(distance, _)| *distance);
+    positions
+        .into_iter()
+        .map(|(_, position)| position)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkStreamConfig {
+    /// the horizontal distance in chunks
+    pub view_distance: u32,
+    /// the vertical distance in chunks
+    pub vertical_view_distance: u32,
+    /// the maximum number of sent chunks which were not acknowledged yet
+    pub max_in_flight: usize,
+}
+
+impl Default for ChunkStreamConfig {
+    fn default() -> Self {
+        Self {
+            view_distance: 8,
+            vertical_view_distance: 4,
+            max_in_flight: 32,
+        }
+    }
+}
+
+/// sends the chunks in view of one client
+#[derive(Component)]
+pub struct ChunkStreamer {
+    config: ChunkStreamConfig,
+    mode: PaletteMode,
+    center: Option<ChunkPos>,
+    /// the chunks in view which were not sent yet, in spiral order
+    pending: VecDeque<ChunkPos>,
+    /// the chunks sent to the client including the ones in flight
+    loaded: HashSet<ChunkPos>,
+    in_flight: BTreeMap<u32, ChunkPos>,
+    next_sequence: u32,
+    /// the number of states of the global palette sent to the client
+    known_states: usize,
+}
+
+impl ChunkStreamer {
+    /// [mode] is the palette mode negotiated in the handshake
+    pub fn new(config: ChunkStreamConfig, mode: PaletteMode) -> Self {
+        Self {
+            config,
+            mode,
+            center: None,
+            pending: VecDeque::new(),
+            loaded: HashSet::new(),
+            in_flight: BTreeMap::new(),
+            next_sequence: 0,
+            known_states: 0,
+        }
+    }
+
+    pub fn config(&self) -> &ChunkStreamConfig {
+        &self.config
+    }
+
+    pub fn center(&self) -> Option<ChunkPos> {
+        self.center
+    }
+
+    /// whether the chunk was sent to the client, it may still be in flight
+    pub fn is_loaded(&self, position: &ChunkPos) -> bool {
+        self.loaded.contains(position)
+    }
+
+    /// the number of chunks in view which were not sent yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// the number of sent chunks which were not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn in_view(&self, center: &ChunkPos, position: &ChunkPos) -> bool {
+        position.dimension == center.dimension
+            && position.x.abs_diff(center.x) <= self.config.view_distance as u64
+            && position.z.abs_diff(center.z) <= self.config.view_distance as u64
+            && position.y.abs_diff(center.y) <= self.config.vertical_view_distance as u64
+    }
+
+    /// moves the view, the chunks which left it are unloaded
+    pub fn set_center(&mut self, center: ChunkPos, out: &mut Vec<PacketPlayServer>) {
+        if self.center == Some(center) {
+            return;
+        }
+        self.center = Some(center);
+
+        let mut unloaded: Vec<_> = self
+            .loaded
+            .iter()
+            .filter(|position| !self.in_view(&center, position))
+            .copied()
+            .collect();
+        unloaded.sort_unstable();
+        for position in unloaded {
+            self.loaded.remove(&position);
+            out.push(server_packet(ServerPayload::ChunkUnload(ChunkUnload {
+                position: Some(position.into()),
+            })));
+        }
+        self.refresh_pending();
+    }
+
+    fn refresh_pending(&mut self) {
+        let Some(center) = self.center else {
+            return;
+        };
+        self.pending = spiral(
+            center,
+            self.config.view_distance,
+            self.config.vertical_view_distance,
+        )
+        .into_iter()
+        .filter(|position| !self.loaded.contains(position))
+        .collect();
+    }
+
+    /// the client received every chunk up to [sequence]
+    pub fn acknowledge(&mut self, sequence: u32) {
+        self.in_flight.retain(|in_flight, _| *in_flight > sequence);
+    }
+
+    /// sends the next chunks while the in flight limit allows it
+    ///
+    /// [chunk] returns the chunks of the world, chunks which are not available yet (f.e. still generating) are
+    /// skipped and sent once they are
+    pub fn poll<'w>(
+        &mut self,
+        palette: &GlobalPalette,
+        mut chunk: impl FnMut(&ChunkPos) -> Option<&'w ChunkStorage>,
+        out: &mut Vec<PacketPlayServer>,
+    ) {
+        let mut skipped = Vec::new();
+        while self.in_flight.len() < self.config.max_in_flight {
+            let Some(position) = self.pending.pop_front() else {
+                break;
+            };
+            match chunk(&position) {
+                Some(storage) => self.send_chunk(position, storage, palette, out),
+                None => skipped.push(position),
+            }
+        }
+        for position in skipped.into_iter().rev() {
+            self.pending.push_front(position);
+        }
+    }
+
+    fn send_chunk(
+        &mut self,
+        position: ChunkPos,
+        storage: &ChunkStorage,
+        palette: &GlobalPalette,
+        out: &mut Vec<PacketPlayServer>,
+    ) {
+        self.sync_palette(palette, out);
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.in_flight.insert(sequence, position);
+        self.loaded.insert(position);
+
+        let payload = match uniform_state(storage) {
+            Some(state) => ServerPayload::ChunkLoad(ChunkLoad {
+                position: Some(position.into()),
+                sequence,
+                fill: Some(encode_state(palette, state, self.mode)),
+            }),
+            None => ServerPayload::ChunkFull(ChunkFull {
+                position: Some(position.into()),
+                sequence,
+                data: Some(ChunkData::from(&palette.encode(storage, self.mode))),
+            }),
+        };
+        out.push(server_packet(payload));
+    }
+
+    /// sends the new states of the global palette before they are used
+    fn sync_palette(&mut self, palette: &GlobalPalette, out: &mut Vec<PacketPlayServer>) {
+        if self.mode != PaletteMode::Global || palette.len() <= self.known_states {
+            return;
+        }
+        out.push(server_packet(ServerPayload::PaletteUpdate(
+            GlobalPaletteUpdate {
+                first_id: self.known_states as u32,
+                states: palette
+                    .iter()
+                    .skip(self.known_states)
+                    .map(|(_, state)| state.into())
+                    .collect(),
+            },
+        )));
+        self.known_states = palette.len();
+    }
+
+    /// sends the section of [storage] if the chunk is loaded by the client
+    pub fn section_update(
+        &mut self,
+        position: ChunkPos,
+        section: usize,
+        storage: &ChunkStorage,
+        palette: &GlobalPalette,
+        out: &mut Vec<PacketPlayServer>,
+    ) {
+        if !self.loaded.contains(&position) {
+            return;
+        }
+        assert!(section < SECTIONS_PER_CHUNK, "section out of bounds");
+        self.sync_palette(palette, out);
+        let states: Vec<_> = section_positions(section)
+            .map(|(x, y, z)| *storage.get_xyz(x, y, z))
+            .collect();
+        let blocks = SectionStorage::new(&states);
+        out.push(server_packet(ServerPayload::SectionUpdate(SectionUpdate {
+            position: Some(position.into()),
+            section: section as u32,
+            data: Some(ChunkData::from(&palette.encode(&blocks, self.mode))),
+        })));
+    }
+
+    /// sends the change if the chunk is loaded by the client
+    pub fn block_change(
+        &mut self,
+        position: ChunkPos,
+        local: LocalPos,
+        state: StateId,
+        palette: &GlobalPalette,
+        out: &mut Vec<PacketPlayServer>,
+    ) {
+        if !self.loaded.contains(&position) {
+            return;
+        }
+        self.sync_palette(palette, out);
+        out.push(server_packet(ServerPayload::BlockChange(BlockChange {
+            position: Some(position.into()),
+            index: local.index() as u32,
+            state: Some(encode_state(palette, state, self.mode)),
+        })));
+    }
+
+    /// sends the whole chunk again if the client loaded it, f.e. after it was replaced
+    pub fn resend(
+        &mut self,
+        position: ChunkPos,
+        storage: &ChunkStorage,
+        palette: &GlobalPalette,
+        out: &mut Vec<PacketPlayServer>,
+    ) {
+        if self.loaded.contains(&position) {
+            self.send_chunk(position, storage, palette, out);
+        }
+    }
+
+    /// unloads the chunk on the client, f.e. after it was removed
+    ///
+    /// a chunk in view is sent again once it exists again
+    pub fn unload(&mut self, position: ChunkPos, out: &mut Vec<PacketPlayServer>) {
+        if !self.loaded.remove(&position) {
+            return;
+        }
+        out.push(server_packet(ServerPayload::ChunkUnload(ChunkUnload {
+            position: Some(position.into()),
+        })));
+        if self
+            .center
+            .is_some_and(|center| self.in_view(&center, &position))
+        {
+            self.pending.push_front(position);
+        }
+    }
+
+    /// forgets what the client received, every chunk in view is sent again
+    pub fn reconnect(&mut self) {
+        self.loaded.clear();
+        self.in_flight.clear();
+        self.next_sequence = 0;
+        self.known_states = 0;
+        self.refresh_pending();
+    }
+}
+
+/// the state of a chunk which only contains one, the palette may still contain unused states
+fn uniform_state(storage: &ChunkStorage) -> Option<StateId> {
+    match storage.palette() {
+        [state] => Some(*state),
+        _ => {
+            let first = *storage.get(0);
+            storage.iter().all(|state| *state == first).then_some(first)
+        }
+    }
+}
+
+fn server_packet(payload: ServerPayload) -> PacketPlayServer {
+    PacketPlayServer {
+        payload: Some(payload),
+    }
+}
+
+fn encode_state(palette: &GlobalPalette, state: StateId, mode: PaletteMode) -> BlockState {
+    let state = match mode {
+        PaletteMode::Local => block_state::State::Local(
+            palette
+                .get(state)
+                .expect("the storage only contains interned states")
+                .into(),
+        ),
+        PaletteMode::Global => block_state::State::Global(state),
+    };
+    BlockState { state: Some(state) }
+}
+
+fn decode_state(
+    palette: &mut GlobalPalette,
+    state: Option<BlockState>,
+) -> Result<StateId, ChunkStreamError> {
+    match state.and_then(|state| state.state) {
+        Some(block_state::State::Local(entry)) => Ok(palette.intern(BlockData::try_from(entry)?)),
+        Some(block_state::State::Global(id)) => match palette.get(id) {
+            Some(_) => Ok(id),
+            None => Err(PaletteError::UnknownState(id).into()),
+        },
+        None => Err(ConvertError::MissingField("state").into()),
+    }
+}
+
+/// the position of the first block of the section in its chunk
+fn section_origin(section: usize) -> (usize, usize, usize) {
+    (
+        section % SECTIONS_PER_AXIS * SECTION_SIZE,
+        section / SECTIONS_PER_AXIS % SECTIONS_PER_AXIS * SECTION_SIZE,
+        section / (SECTIONS_PER_AXIS * SECTIONS_PER_AXIS) * SECTION_SIZE,
+    )
+}
+
+/// the positions of the blocks of the section in the chunk, in the index order of [SectionStorage]
+fn section_positions(section: usize) -> impl Iterator<Item = (usize, usize, usize)> {
+    let (x, y, z) = section_origin(section);
+    (0..SECTION_VOLUME).map(move |index| {
+        (
+            x + index % SECTION_SIZE,
+            y + index / SECTION_SIZE % SECTION_SIZE,
+            z + index / (SECTION_SIZE * SECTION_SIZE),
+        )
+    })
+}
+
+/// what a packet changed on the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub enum ChunkEvent {
+    Loaded(ChunkPos),
+    Unloaded(ChunkPos),
+    /// a section or block of a loaded chunk changed
+    Changed(ChunkPos),
+    PaletteUpdated,
+}
+
+/// a chunk received by the client
+#[derive(Debug, Clone)]
+pub struct AssembledChunk {
+    pub storage: ChunkStorage,
+    /// the solid blocks of the chunk, the neighbour faces are left to the mesher
+    pub occlusion: Box<VoxelCubeOcclusionMatrix32>,
+}
+
+/// reassembles the chunks sent by a [ChunkStreamer]
+#[derive(Resource)]
+pub struct ChunkAssembler {
+    chunks: HashMap<ChunkPos, AssembledChunk>,
+    solid: Box<dyn Fn(&BlockData) -> bool + Send + Sync>,
+    received: Option<u32>,
+    acknowledged: Option<u32>,
+}
+
+impl ChunkAssembler {
+    /// [solid] decides which blocks are part of the occlusion matrices
+    pub fn new(solid: impl Fn(&BlockData) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            chunks: HashMap::new(),
+            solid: Box::new(solid),
+            received: None,
+            acknowledged: None,
+        }
+    }
+
+    pub fn get(&self, position: &ChunkPos) -> Option<&AssembledChunk> {
+        self.chunks.get(position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// handles the chunk packets, [None] for all other packets
+    ///
+    /// [palette] is the palette of the client, block states of local palettes are interned
+    pub fn handle(
+        &mut self,
+        payload: ServerPayload,
+        palette: &mut GlobalPalette,
+    ) -> Result<Option<ChunkEvent>, ChunkStreamError> {
+        let event = match payload {
+            ServerPayload::ChunkLoad(load) => {
+                let position = required_position(load.position)?;
+                let state = decode_state(palette, load.fill)?;
+                let storage = ChunkStorage::new(&vec![state; CHUNK_VOLUME]);
+                self.load(position, load.sequence, storage, palette)
+            }
+            ServerPayload::ChunkFull(full) => {
+                let position = required_position(full.position)?;
+                let data = full.data.ok_or(ConvertError::MissingField("data"))?;
+                let storage = palette.decode(&data.try_into()?)?;
+                self.load(position, full.sequence, storage, palette)
+            }
+            ServerPayload::ChunkUnload(unload) => {
+                let position = required_position(unload.position)?;
+                self.chunks.remove(&position);
+                ChunkEvent::Unloaded(position)
+            }
+            ServerPayload::SectionUpdate(update) => {
+                let position = required_position(update.position)?;
+                let section = update.section as usize;
+                if section >= SECTIONS_PER_CHUNK {
+                    return Err(ChunkStreamError::InvalidSection(update.section));
+                }
+                let data = update.data.ok_or(ConvertError::MissingField("data"))?;
+                let blocks: SectionStorage = palette.decode_storage(&data.try_into()?)?;
+                let chunk = self
+                    .chunks
+                    .get_mut(&position)
+                    .ok_or(ChunkStreamError::NotLoaded(position))?;
+
+                for ((x, y, z), state) in section_positions(section).zip(blocks.iter()) {
+                    chunk
+                        .occlusion
+                        .set_voxel(x, y, z, is_solid(&self.solid, palette, *state));
+                }
+                let (x, y, z) = section_origin(section);
+                let end = (x + SECTION_SIZE, y + SECTION_SIZE, z + SECTION_SIZE);
+                chunk
+                    .storage
+                    .set_box((x, y, z), end, blocks.iter().copied());
+                ChunkEvent::Changed(position)
+            }
+            ServerPayload::BlockChange(change) => {
+                let position = required_position(change.position)?;
+                let local = LocalPos::from_index(change.index as usize)
+                    .ok_or(ChunkStreamError::InvalidIndex(change.index))?;
+                let state = decode_state(palette, change.state)?;
+                let chunk = self
+                    .chunks
+                    .get_mut(&position)
+                    .ok_or(ChunkStreamError::NotLoaded(position))?;
+                let (x, y, z) = local.xyz();
+                chunk.storage.set_xyz(x, y, z, state);
+                chunk
+                    .occlusion
+                    .set_voxel(x, y, z, is_solid(&self.solid, palette, state));
+                ChunkEvent::Changed(position)
+            }
+            ServerPayload::PaletteUpdate(update) => {
+                let end = StateId::try_from(update.states.len())
+                    .ok()
+                    .and_then(|len| update.first_id.checked_add(len))
+                    .ok_or(ChunkStreamError::PaletteOverflow(update.first_id))?;
+                for (id, entry) in (update.first_id..end).zip(update.states) {
+                    let state = BlockData::try_from(entry)?;
+                    let matches = match palette.get(id) {
+                        Some(known) => *known == state,
+                        None => palette.intern(state) == id,
+                    };
+                    if !matches {
+                        return Err(ChunkStreamError::PaletteMismatch(id));
+                    }
+                }
+                ChunkEvent::PaletteUpdated
+            }
+            ServerPayload::Sync(_) => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    fn load(
+        &mut self,
+        position: ChunkPos,
+        sequence: u32,
+        storage: ChunkStorage,
+        palette: &GlobalPalette,
+    ) -> ChunkEvent {
+        let solid: Vec<bool> = storage
+            .palette()
+            .iter()
+            .map(|state| is_solid(&self.solid, palette, *state))
+            .collect();
+        let mut occlusion = Box::new(VoxelCubeOcclusionMatrix32::new());
+        match solid[..] {
+            [false] => {}
+            [true] => occlusion.import(|_, _, _| true),
+            _ => {
+                for (index, state) in storage.iter().enumerate() {
+                    let palette_id = storage
+                        .palette()
+                        .binary_search(state)
+                        .expect("the state is in the palette");
+                    if solid[palette_id] {
+                        let (x, y, z) = local_position(index);
+                        occlusion.set_voxel(x, y, z, true);
+                    }
+                }
+            }
+        }
+        self.chunks
+            .insert(position, AssembledChunk { storage, occlusion });
+        self.received = Some(sequence);
+        ChunkEvent::Loaded(position)
+    }
+
+    /// acknowledges the chunks received since the last call
+    pub fn acknowledgement(&mut self) -> Option<PacketPlayClient> {
+        if self.received == self.acknowledged {
+            return None;
+        }
+        self.acknowledged = self.received;
+        Some(PacketPlayClient {
+            payload: Some(ClientPayload::ChunkAcknowledged(ChunkAcknowledged {
+                sequence: self.received?,
+            })),
+        })
+    }
+
+    /// forgets every chunk, the server sends them again after a reconnect
+    pub fn reset(&mut self) {
+        self.chunks.clear();
+        self.received = None;
+        self.acknowledged = None;
+    }
+}
+
+fn required_position(
+    position: Option<crate::proto::common::ChunkPosition>,
+) -> Result<ChunkPos, ConvertError> {
+    position
+        .map(ChunkPos::from)
+        .ok_or(ConvertError::MissingField("position"))
+}
+
+fn is_solid(
+    solid: &(dyn Fn(&BlockData) -> bool + Send + Sync),
+    palette: &GlobalPalette,
+    state: StateId,
+) -> bool {
+    palette.get(state).is_some_and(solid)
+}
+
+#[cfg(test)]
+mod test {
+    use common::resource::ResourceKey;
+
+    use super::*;
+
+    fn state(name: &str) -> BlockData {
+        BlockData::new(ResourceKey::core(name).unwrap())
+    }
+
+    fn solid(state: &BlockData) -> bool {
+        state.material.path() != "air"
+    }
+
+    #[test]
+    fn spiral_order() {
+        let center = ChunkPos::new(5, 0, -3, 1);
+        let flat = spiral(center, 2, 0);
+        assert_eq!(flat.len(), 25);
+        assert_eq!(flat[0], center);
+        assert_eq!(flat[1], ChunkPos::new(6, 0, -3, 1));
+        assert_eq!(flat[2], ChunkPos::new(6, 0, -2, 1));
+        //the walk around a ring moves by one chunk per step
+        for pair in flat[1..9].windows(2) {
+            assert_eq!(
+                pair[0].x.abs_diff(pair[1].x) + pair[0].z.abs_diff(pair[1].z),
+                1
+            );
+        }
+
+        let positions = spiral(center, 2, 1);
+        assert_eq!(positions.len(), 75);
+        assert_eq!(
+            positions.iter().copied().collect::<HashSet<_>>().len(),
+            positions.len()
+        );
+        let distance = |position: &ChunkPos| {
+            (position.x - center.x)
+                .abs()
+                .max((position.y - center.y).abs())
+                .max((position.z - center.z).abs())
+        };
+        assert!(positions
+            .windows(2)
+            .all(|pair| distance(&pair[0]) <= distance(&pair[1])));
+    }
+
+    /// sends everything pending and feeds it into the assembler until both sides are idle
+    fn stream(
+        streamer: &mut ChunkStreamer,
+        assembler: &mut ChunkAssembler,
+        world: &HashMap<ChunkPos, ChunkStorage>,
+        server_palette: &GlobalPalette,
+        client_palette: &mut GlobalPalette,
+    ) -> Vec<ChunkEvent> {
+        let mut events = Vec::new();
+        let mut out = Vec::new();
+        loop {
+            streamer.poll(server_palette, |position| world.get(position), &mut out);
+            if out.is_empty() {
+                return events;
+            }
+            for packet in out.drain(..) {
+                events.extend(
+                    assembler
+                        .handle(packet.payload.unwrap(), client_palette)
+                        .unwrap(),
+                );
+            }
+            let Some(ClientPayload::ChunkAcknowledged(acknowledged)) = assembler
+                .acknowledgement()
+                .and_then(|packet| packet.payload)
+            else {
+                panic!("the chunks were not acknowledged");
+            };
+            streamer.acknowledge(acknowledged.sequence);
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        for mode in [PaletteMode::Local, PaletteMode::Global] {
+            let mut server_palette = GlobalPalette::new(state("air"));
+            let stone = server_palette.intern(state("stone"));
+            let dirt = server_palette.intern(state("dirt"));
+
+            let center = ChunkPos::new(0, 0, 0, 0);
+            let mut world: HashMap<_, _> = spiral(center, 2, 0)
+                .into_iter()
+                .map(|position| (position, ChunkStorage::empty()))
+                .collect();
+            let mut mixed = ChunkStorage::empty();
+            mixed.set_xyz(1, 2, 3, stone);
+            world.insert(center, mixed.clone());
+            let mut filled = ChunkStorage::empty();
+            filled.set_many(0..CHUNK_VOLUME, stone);
+            world.insert(ChunkPos::new(1, 0, 0, 0), filled);
+            //still generating
+            let generating = ChunkPos::new(-1, 0, 0, 0);
+            world.remove(&generating);
+
+            let mut streamer = ChunkStreamer::new(
+                ChunkStreamConfig {
+                    view_distance: 1,
+                    vertical_view_distance: 0,
+                    max_in_flight: 3,
+                },
+                mode,
+            );
+            let mut client_palette = GlobalPalette::new(state("air"));
+            let mut assembler = ChunkAssembler::new(solid);
+            let mut out = Vec::new();
+            streamer.set_center(center, &mut out);
+            assert!(out.is_empty());
+
+            //the limit holds until the client acknowledges
+            streamer.poll(&server_palette, |position| world.get(position), &mut out);
+            streamer.poll(&server_palette, |position| world.get(position), &mut out);
+            assert_eq!(streamer.in_flight(), 3);
+            for packet in out.drain(..) {
+                assembler
+                    .handle(packet.payload.unwrap(), &mut client_palette)
+                    .unwrap();
+            }
+            assert!(assembler.acknowledgement().is_some());
+            assert!(assembler.acknowledgement().is_none());
+            streamer.acknowledge(2);
+
+            stream(
+                &mut streamer,
+                &mut assembler,
+                &world,
+                &server_palette,
+                &mut client_palette,
+            );
+            assert_eq!(assembler.len(), 8);
+            assert_eq!(streamer.pending(), 1);
+            assert!(assembler.get(&generating).is_none());
+            let chunk = assembler.get(&center).unwrap();
+            assert_eq!(
+                client_palette.get(*chunk.storage.get_xyz(1, 2, 3)),
+                Some(&state("stone"))
+            );
+            let mut expected = VoxelCubeOcclusionMatrix32::new();
+            expected.set_voxel(1, 2, 3, true);
+            assert_eq!(*chunk.occlusion, expected);
+            let chunk = assembler.get(&ChunkPos::new(1, 0, 0, 0)).unwrap();
+            assert_eq!(chunk.storage.palette().len(), 1);
+            let mut expected = VoxelCubeOcclusionMatrix32::new();
+            expected.import(|_, _, _| true);
+            assert_eq!(*chunk.occlusion, expected);
+
+            //the chunk is sent once it is generated
+            world.insert(generating, ChunkStorage::empty());
+            let events = stream(
+                &mut streamer,
+                &mut assembler,
+                &world,
+                &server_palette,
+                &mut client_palette,
+            );
+            assert_eq!(events, vec![ChunkEvent::Loaded(generating)]);
+
+            //changes of loaded chunks
+            let local = LocalPos::new(4, 5, 6).unwrap();
+            mixed.set_xyz(4, 5, 6, dirt);
+            mixed.set_xyz(9, 9, 17, dirt);
+            streamer.block_change(center, local, dirt, &server_palette, &mut out);
+            streamer.section_update(center, 37, &mixed, &server_palette, &mut out);
+            streamer.block_change(
+                ChunkPos::new(7, 0, 0, 0),
+                local,
+                dirt,
+                &server_palette,
+                &mut out,
+            );
+            assert_eq!(out.len(), 2);
+            for packet in out.drain(..) {
+                assert_eq!(
+                    assembler
+                        .handle(packet.payload.unwrap(), &mut client_palette)
+                        .unwrap(),
+                    Some(ChunkEvent::Changed(center))
+                );
+            }
+            let chunk = assembler.get(&center).unwrap();
+            assert!(chunk
+                .storage
+                .iter()
+                .map(|id| client_palette.get(*id))
+                .eq(mixed.iter().map(|id| server_palette.get(*id))));
+            assert_eq!(section_origin(37), (8, 8, 16));
+
+            //moving the view unloads the chunks behind it
+            streamer.set_center(ChunkPos::new(1, 0, 0, 0), &mut out);
+            assert_eq!(out.len(), 3);
+            for packet in out.drain(..) {
+                assert!(matches!(
+                    assembler.handle(packet.payload.unwrap(), &mut client_palette),
+                    Ok(Some(ChunkEvent::Unloaded(position))) if position.x == -1
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn reconnect() {
+        let palette = GlobalPalette::new(state("air"));
+        let world: HashMap<_, _> = spiral(ChunkPos::default(), 1, 0)
+            .into_iter()
+            .map(|position| (position, ChunkStorage::empty()))
+            .collect();
+        let mut streamer = ChunkStreamer::new(
+            ChunkStreamConfig {
+                view_distance: 1,
+                vertical_view_distance: 0,
+                max_in_flight: 4,
+            },
+            PaletteMode::Global,
+        );
+        let mut out = Vec::new();
+        streamer.set_center(ChunkPos::default(), &mut out);
+        streamer.poll(&palette, |position| world.get(position), &mut out);
+        assert_eq!(out.len(), 5);
+
+        //the connection is lost with chunks in flight
+        streamer.reconnect();
+        assert_eq!((streamer.pending(), streamer.in_flight()), (9, 0));
+        let mut assembler = ChunkAssembler::new(solid);
+        let mut client_palette = GlobalPalette::new(state("air"));
+        let events = stream(
+            &mut streamer,
+            &mut assembler,
+            &world,
+            &palette,
+            &mut client_palette,
+        );
+        assert_eq!(events[0], ChunkEvent::PaletteUpdated);
+        assert_eq!(assembler.len(), 9);
+
+        //a palette which does not match the one of the server
+        let mut other = GlobalPalette::new(state("stone"));
+        let update = ServerPayload::PaletteUpdate(GlobalPaletteUpdate {
+            first_id: 0,
+            states: vec![(&state("air")).into()],
+        });
+        assert!(matches!(
+            assembler.handle(update, &mut other),
+            Err(ChunkStreamError::PaletteMismatch(0))
+        ));
+
+        //ids past the last state id
+        let update = ServerPayload::PaletteUpdate(GlobalPaletteUpdate {
+            first_id: StateId::MAX,
+            states: vec![(&state("air")).into(), (&state("stone")).into()],
+        });
+        assert!(matches!(
+            assembler.handle(update, &mut other),
+            Err(ChunkStreamError::PaletteOverflow(StateId::MAX))
+        ));
+    }
+}
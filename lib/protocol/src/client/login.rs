@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+
+use crate::capture::{CaptureDirectory, CaptureTransportLayer, ClientCapture};
+use crate::client::{ClientConnection, ClientProtocolPlugin};
+use crate::handshake::{ClientHandshake, ConnectionState, HandshakeError};
+use crate::proto::connection::{PacketClient, PacketServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::transport::play::ClientPlayTransport;
+use crate::transport::TransportLayer;
+
+/// runs the [handshake](crate::handshake) of the [ClientLogin] resource
+///
+/// once play is reached the login is replaced by a [ClientConnection] of a [ClientPlayTransport] and the
+/// [ClientConfiguration](crate::handshake::ClientConfiguration) received from the server, the transport switches to
+/// its frame config. The connection is wrapped in a [ClientCapture], which is written to the [CaptureDirectory] if
+/// it exists. The [ClientProtocolPlugin] of this connection is added by this plugin.
+pub struct ClientLoginPlugin<T, E> {
+    _marker: PhantomData<fn() -> (T, E)>,
+}
+
+impl<T, E> Default for ClientLoginPlugin<T, E> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E> Plugin for ClientLoginPlugin<T, E>
+where
+    T: TransportLayer<PacketClient, PacketServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ClientProtocolPlugin::<
+            ClientCapture<ClientPlayTransport<T>, E>,
+            E,
+        >::default())
+            .add_systems(
+                PreUpdate,
+                login_to_server::<T, E>
+                    .run_if(resource_exists::<ClientLogin<T>>)
+                    .before(ClientSet::ReceivePackets),
+            );
+    }
+}
+
+/// the connection to the server before play
+#[derive(Resource)]
+pub struct ClientLogin<T> {
+    transport: T,
+    handshake: ClientHandshake,
+}
+
+impl<T> ClientLogin<T> {
+    /// sends the first packets of the handshake
+    pub fn start<E>(
+        transport: T,
+        handshake: ClientHandshake,
+    ) -> error_stack::Result<Self, HandshakeError>
+    where
+        T: TransportLayer<PacketClient, PacketServer, PacketPlayClient, PacketPlayServer, E>,
+        E: Error + Send + Sync + 'static,
+    {
+        handshake.start_transport(&transport)?;
+        Ok(Self {
+            transport,
+            handshake,
+        })
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.handshake.state()
+    }
+}
+
+fn login_to_server<T, E>(world: &mut World)
+where
+    T: TransportLayer<PacketClient, PacketServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    let Some(mut login) = world.remove_resource::<ClientLogin<T>>() else {
+        return;
+    };
+    let mut play = Vec::new();
+    if let Err(error) = login.handshake.poll(&mut login.transport, &mut play) {
+        warn!("the login to the server failed: {error:?}");
+        login.transport.close_with(error.current_context().reason());
+        return;
+    }
+    if login.transport.is_closed() {
+        warn!("the server closed the connection during the login");
+        return;
+    }
+    match login.handshake.configuration() {
+        Some(configuration) if login.handshake.state() == ConnectionState::Play => {
+            login
+                .transport
+                .set_frame_config(configuration.frame_config.clone());
+            world.insert_resource(configuration.clone());
+            let transport: ClientCapture<_, E> = CaptureTransportLayer::in_directory(
+                ClientPlayTransport::new(login.transport, play),
+                world.get_resource::<CaptureDirectory>(),
+                "client",
+            );
+            world.insert_resource(ClientConnection::new(transport));
+        }
+        _ => world.insert_resource(login),
+    }
+}
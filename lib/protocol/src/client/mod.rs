@@ -1,75 +1,317 @@
+use std::error::Error;
+use std::marker::PhantomData;
+
 use bevy::prelude::*;
 use bevy_replicon::prelude::*;
 
-use crate::local::ClientLocalConnectionAgent;
+use common::palette::GlobalPalette;
+
+use crate::chunks::{ChunkAssembler, ChunkEvent};
+use crate::proto::common::DisconnectReason;
 use crate::proto::play::client::packet::packet_play_client::Payload as ClientPayload;
 use crate::proto::play::client::packet::PacketPlayClient;
 use crate::proto::play::server::packet::packet_play_server::Payload as ServerPayload;
 use crate::proto::play::server::packet::PacketPlayServer;
+use crate::server::{PlayerJoined, PlayerLeft};
+use crate::transport::scheduler::{PacketScheduler, SchedulerConfig};
+use crate::transport::{TransportContainer, TransportLayer};
 
-pub struct ClientProtocolPlugin;
+pub use login::{ClientLogin, ClientLoginPlugin};
 
-impl Plugin for ClientProtocolPlugin {
+mod login;
+
+/// bridges bevy_replicon and the connection to the server
+///
+/// the client is connected while the [ClientConnection] resource exists, it is removed when the connection is closed.
+/// Reliable replicon channels are sent as packets and unreliable channels as datagrams.
+///
+/// The chunk packets are handled by the [ChunkAssembler] and [GlobalPalette] resources inserted by the game, every
+/// change is sent as a [ChunkEvent]. Without them the chunk packets are dropped.
+pub struct ClientProtocolPlugin<T, E> {
+    _marker: PhantomData<fn() -> (T, E)>,
+}
+
+impl<T, E> Default for ClientProtocolPlugin<T, E> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E> Plugin for ClientProtocolPlugin<T, E>
+where
+    T: TransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
     fn build(&self, app: &mut App) {
         app.add_plugins(RepliconPlugins.build().disable::<ServerPlugin>().build());
+        //registered in the same order as on the server
+        app.add_server_event::<PlayerJoined>(ChannelKind::Ordered)
+            .add_server_event::<PlayerLeft>(ChannelKind::Ordered)
+            .add_event::<ChunkEvent>();
         app.add_systems(
             PreUpdate,
             (
-                local_server_running.run_if(resource_added::<ClientLocalConnectionAgent>),
-                local_server_stopped.run_if(resource_removed::<ClientLocalConnectionAgent>()),
-            ),
-        );
-        app.add_systems(
-            PreUpdate,
-            receive_updates
-                .run_if(resource_exists::<ClientLocalConnectionAgent>)
-                .after(local_server_running)
-                .after(local_server_stopped),
+                server_connected.run_if(resource_added::<ClientConnection<T>>),
+                server_disconnected::<T, E>,
+                receive_updates::<T, E>.run_if(resource_exists::<ClientConnection<T>>),
+            )
+                .chain()
+                .in_set(ClientSet::ReceivePackets),
         );
         app.add_systems(
             PostUpdate,
-            send_updates.run_if(resource_exists::<ClientLocalConnectionAgent>),
+            send_updates::<T, E>
+                .run_if(resource_exists::<ClientConnection<T>>)
+                .in_set(ClientSet::SendPackets),
         );
     }
 }
 
-fn local_server_running(mut replicon_client: ResMut<RepliconClient>) {
-    replicon_client.set_status(RepliconClientStatus::Connected {
-        client_id: Some(ClientId::SERVER),
-    });
+/// the connection to the server in play
+///
+/// the packets are queued in its [PacketScheduler] and sent in [ClientSet::SendPackets], datagrams are sent at once
+#[derive(Resource)]
+pub struct ClientConnection<T> {
+    transport: T,
+    scheduler: PacketScheduler<PacketPlayClient>,
 }
 
-fn local_server_stopped(mut replicon_client: ResMut<RepliconClient>) {
-    replicon_client.set_status(RepliconClientStatus::Disconnected);
+impl<T> ClientConnection<T> {
+    pub fn new(transport: T) -> Self {
+        Self::with_scheduler(transport, SchedulerConfig::default())
+    }
+
+    pub fn with_scheduler(transport: T, config: SchedulerConfig) -> Self {
+        Self {
+            transport,
+            scheduler: PacketScheduler::new(config),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// the packets which were not sent yet
+    pub fn scheduler(&self) -> &PacketScheduler<PacketPlayClient> {
+        &self.scheduler
+    }
+
+    /// queues a packet, it is sent according to its priority
+    pub fn send_packet(&mut self, packet: PacketPlayClient) {
+        self.scheduler.push(packet);
+    }
 }
 
-fn send_updates(
-    local_connection_agent: Res<ClientLocalConnectionAgent>,
+fn server_connected(
     mut replicon_client: ResMut<RepliconClient>,
+    assembler: Option<ResMut<ChunkAssembler>>,
 ) {
+    //the client does not know its id
+    replicon_client.set_status(RepliconClientStatus::Connected { client_id: None });
+    //the server sends every chunk in view again
+    if let Some(mut assembler) = assembler {
+        assembler.reset();
+    }
+}
+
+fn server_disconnected<T, E>(
+    mut commands: Commands,
+    connection: Option<Res<ClientConnection<T>>>,
+    mut replicon_client: ResMut<RepliconClient>,
+) where
+    T: TransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    match connection {
+        Some(connection) if connection.transport.is_closed() => {
+            commands.remove_resource::<ClientConnection<T>>();
+        }
+        Some(_) => return,
+        None => {}
+    }
+    if !replicon_client.is_disconnected() {
+        replicon_client.set_status(RepliconClientStatus::Disconnected);
+    }
+}
+
+fn send_updates<T, E>(
+    mut connection: ResMut<ClientConnection<T>>,
+    channels: Res<RepliconChannels>,
+    mut replicon_client: ResMut<RepliconClient>,
+) where
+    T: TransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
     for (channel, message) in replicon_client.drain_sent() {
-        let mut sync_message = message.to_vec();
-        sync_message.splice(0..0, [channel]);
-        local_connection_agent.send(PacketPlayClient {
+        let mut sync_message = Vec::with_capacity(message.len() + 1);
+        sync_message.push(channel);
+        sync_message.extend_from_slice(&message);
+        let packet = PacketPlayClient {
             payload: Some(ClientPayload::Sync(sync_message)),
-        });
+        };
+
+        match channels.client_channels()[channel as usize].kind {
+            ChannelKind::Unreliable => {
+                if let Err(error) = connection.transport.send_datagram(packet) {
+                    warn!("cannot send to the server: {error:?}");
+                }
+            }
+            ChannelKind::Unordered | ChannelKind::Ordered => connection.send_packet(packet),
+        }
+    }
+
+    let connection = &mut *connection;
+    if let Err(error) = connection.scheduler.send(&connection.transport) {
+        warn!("cannot send to the server: {error:?}");
     }
 }
 
-fn receive_updates(
-    local_connection_agent: Res<ClientLocalConnectionAgent>,
+fn receive_updates<T, E>(
+    mut connection: ResMut<ClientConnection<T>>,
+    channels: Res<RepliconChannels>,
     mut replicon_client: ResMut<RepliconClient>,
-    mut buffer: Local<Vec<PacketPlayServer>>,
-) {
-    local_connection_agent.drain(&mut buffer);
-    for packet in buffer.drain(..) {
+    mut assembler: Option<ResMut<ChunkAssembler>>,
+    mut palette: Option<ResMut<GlobalPalette>>,
+    mut chunk_events: EventWriter<ChunkEvent>,
+    mut buffer: Local<Vec<TransportContainer<PacketPlayServer, PacketPlayServer>>>,
+) where
+    T: TransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    if let Err(error) = connection.transport.drain(&mut buffer) {
+        warn!("closing the connection to the server: {error:?}");
+        connection.transport.close();
+    }
+
+    for container in buffer.drain(..) {
+        let packet = match container {
+            TransportContainer::Packet(packet) => packet,
+            TransportContainer::Datagram(datagram) => datagram,
+        };
         match packet.payload {
             Some(ServerPayload::Sync(sync_message)) => {
-                let channel = sync_message[0];
-                let message = sync_message[1..].to_vec();
-                replicon_client.insert_received(channel, message);
+                let Some((&channel, message)) = sync_message.split_first() else {
+                    continue;
+                };
+                //replicon panics on unknown channels, the remaining packets are dropped
+                if channel as usize >= channels.server_channels().len() {
+                    warn!("disconnecting from the server: unknown channel {channel}");
+                    connection
+                        .transport
+                        .close_with(DisconnectReason::ProtocolViolation);
+                    break;
+                }
+                replicon_client.insert_received(channel, message.to_vec());
+            }
+            Some(payload) => {
+                let (Some(assembler), Some(palette)) = (assembler.as_mut(), palette.as_mut())
+                else {
+                    continue;
+                };
+                match assembler.handle(payload, palette) {
+                    Ok(Some(event)) => {
+                        chunk_events.send(event);
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        warn!("disconnecting from the server: invalid chunk packet: {error}");
+                        connection
+                            .transport
+                            .close_with(DisconnectReason::ProtocolViolation);
+                        break;
+                    }
+                }
             }
             None => continue,
         }
     }
+
+    if let Some(acknowledgement) = assembler.and_then(|mut assembler| assembler.acknowledgement()) {
+        connection.send_packet(acknowledgement);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::MinimalPlugins;
+
+    use crate::transport::local::{local_connection, ClientLocalTransport, LocalTransportError};
+
+    use super::*;
+
+    #[test]
+    fn connection() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            ClientProtocolPlugin::<ClientLocalTransport, LocalTransportError>::default(),
+        ));
+        let mut channels = app.world.resource_mut::<RepliconChannels>();
+        let reliable = channels.create_client_channel(ChannelKind::Ordered.into());
+        let unreliable = channels.create_client_channel(ChannelKind::Unreliable.into());
+        app.finish();
+        app.cleanup();
+        app.update();
+        assert!(app.world.resource::<RepliconClient>().is_disconnected());
+
+        let (transport, mut server) = local_connection();
+        app.insert_resource(ClientConnection::new(transport));
+        app.update();
+        assert!(app.world.resource::<RepliconClient>().is_connected());
+
+        //the reliable channel is sent as packets and the unreliable one as datagrams
+        let mut replicon_client = app.world.resource_mut::<RepliconClient>();
+        replicon_client.send(reliable, vec![1]);
+        replicon_client.send(unreliable, vec![2]);
+        app.update();
+        let mut received = Vec::new();
+        server.drain(&mut received).unwrap();
+        let received: Vec<_> = received
+            .into_iter()
+            .filter_map(|container| match container {
+                TransportContainer::Packet(PacketPlayClient {
+                    payload: Some(ClientPayload::Sync(message)),
+                }) => Some((true, message)),
+                TransportContainer::Datagram(PacketPlayClient {
+                    payload: Some(ClientPayload::Sync(message)),
+                }) => Some((false, message)),
+                _ => None,
+            })
+            .filter(|(_, message)| message[0] == reliable || message[0] == unreliable)
+            .collect();
+        //the packets are queued in the scheduler, they aren't ordered with the datagrams
+        assert_eq!(received.len(), 2);
+        assert!(received.contains(&(true, vec![reliable, 1])));
+        assert!(received.contains(&(false, vec![unreliable, 2])));
+
+        //a message on an unknown channel closes the connection
+        server
+            .send_packet(PacketPlayServer {
+                payload: Some(ServerPayload::Sync(vec![u8::MAX])),
+            })
+            .unwrap();
+        app.update();
+        assert!(server.is_closed());
+        app.update();
+        assert!(app.world.resource::<RepliconClient>().is_disconnected());
+        assert!(app
+            .world
+            .get_resource::<ClientConnection<ClientLocalTransport>>()
+            .is_none());
+    }
 }
@@ -0,0 +1,307 @@
+//! conversions between the protobuf messages and the types of [common]
+
+use common::coordinate::{BlockPos, ChunkPos};
+use common::palette::{EncodedChunk, EncodedPalette, PaletteMode};
+use common::registry::RegistrySnapshot;
+use common::resource::{BlockData, InvalidResourceKey, ResourceKey};
+use thiserror::Error;
+
+use crate::proto::chunk::{self, ChunkData, ChunkDataEntry};
+use crate::proto::common::{self as proto_common, ChunkPosition, Position};
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("the required field {0} is missing")]
+    MissingField(&'static str),
+    #[error("invalid block data: {0}")]
+    InvalidBlockData(String),
+    #[error(transparent)]
+    InvalidResourceKey(#[from] InvalidResourceKey),
+}
+
+impl From<ChunkPos> for ChunkPosition {
+    fn from(value: ChunkPos) -> Self {
+        ChunkPosition {
+            x: value.x,
+            y: value.y,
+            z: value.z,
+            dimension: value.dimension,
+        }
+    }
+}
+
+impl From<ChunkPosition> for ChunkPos {
+    fn from(value: ChunkPosition) -> Self {
+        ChunkPos::new(value.x, value.y, value.z, value.dimension)
+    }
+}
+
+/// only the block coordinates are set, the fine position and rotation are left empty
+impl From<BlockPos> for Position {
+    fn from(value: BlockPos) -> Self {
+        Position {
+            x: value.x,
+            y: value.y,
+            z: value.z,
+            ..Default::default()
+        }
+    }
+}
+
+/// the fine position and the rotation are dropped
+impl From<&Position> for BlockPos {
+    fn from(value: &Position) -> Self {
+        BlockPos::new(value.x, value.y, value.z)
+    }
+}
+
+impl From<Position> for BlockPos {
+    fn from(value: Position) -> Self {
+        BlockPos::from(&value)
+    }
+}
+
+impl From<&ResourceKey> for proto_common::ResourceKey {
+    fn from(value: &ResourceKey) -> Self {
+        proto_common::ResourceKey {
+            namespace: value.namespace().to_string(),
+            path: value.path().to_string(),
+        }
+    }
+}
+
+impl From<ResourceKey> for proto_common::ResourceKey {
+    fn from(value: ResourceKey) -> Self {
+        proto_common::ResourceKey::from(&value)
+    }
+}
+
+impl TryFrom<&proto_common::ResourceKey> for ResourceKey {
+    type Error = InvalidResourceKey;
+
+    fn try_from(value: &proto_common::ResourceKey) -> Result<Self, Self::Error> {
+        ResourceKey::new(&value.namespace, &value.path)
+    }
+}
+
+impl TryFrom<proto_common::ResourceKey> for ResourceKey {
+    type Error = InvalidResourceKey;
+
+    fn try_from(value: proto_common::ResourceKey) -> Result<Self, Self::Error> {
+        ResourceKey::try_from(&value)
+    }
+}
+
+/// the snapshot together with the key of its registry
+impl From<(&ResourceKey, &RegistrySnapshot)> for proto_common::RegistrySnapshot {
+    fn from((registry, snapshot): (&ResourceKey, &RegistrySnapshot)) -> Self {
+        proto_common::RegistrySnapshot {
+            registry: Some(registry.into()),
+            entries: snapshot
+                .entries
+                .iter()
+                .map(|(id, key)| proto_common::RegistryEntry {
+                    id: *id as u32,
+                    key: Some(key.into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto_common::RegistrySnapshot> for (ResourceKey, RegistrySnapshot) {
+    type Error = ConvertError;
+
+    fn try_from(value: proto_common::RegistrySnapshot) -> Result<Self, Self::Error> {
+        let registry = value
+            .registry
+            .ok_or(ConvertError::MissingField("registry"))?
+            .try_into()?;
+        let entries = value
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let key = entry.key.ok_or(ConvertError::MissingField("key"))?;
+                Ok((entry.id as usize, key.try_into()?))
+            })
+            .collect::<Result<_, ConvertError>>()?;
+        Ok((registry, RegistrySnapshot { entries }))
+    }
+}
+
+impl From<&BlockData> for ChunkDataEntry {
+    fn from(value: &BlockData) -> Self {
+        ChunkDataEntry {
+            resource: Some((&value.material).into()),
+            data: value.encode_data(),
+        }
+    }
+}
+
+impl TryFrom<ChunkDataEntry> for BlockData {
+    type Error = ConvertError;
+
+    fn try_from(value: ChunkDataEntry) -> Result<Self, Self::Error> {
+        let material = value
+            .resource
+            .ok_or(ConvertError::MissingField("resource"))?;
+        BlockData::decode_data(material.try_into()?, value.data.as_deref())
+            .map_err(|error| ConvertError::InvalidBlockData(error.to_string()))
+    }
+}
+
+impl From<PaletteMode> for chunk::PaletteMode {
+    fn from(value: PaletteMode) -> Self {
+        match value {
+            PaletteMode::Local => chunk::PaletteMode::Local,
+            PaletteMode::Global => chunk::PaletteMode::Global,
+        }
+    }
+}
+
+impl From<chunk::PaletteMode> for PaletteMode {
+    fn from(value: chunk::PaletteMode) -> Self {
+        match value {
+            chunk::PaletteMode::Local => PaletteMode::Local,
+            chunk::PaletteMode::Global => PaletteMode::Global,
+        }
+    }
+}
+
+impl From<&EncodedChunk> for ChunkData {
+    fn from(value: &EncodedChunk) -> Self {
+        let (pallete, global_pallete) = match &value.palette {
+            EncodedPalette::Local(states) => (states.iter().map(Into::into).collect(), Vec::new()),
+            EncodedPalette::Global(ids) => (Vec::new(), ids.clone()),
+        };
+        ChunkData {
+            pallete,
+            mask: value.grid.clone(),
+            global_pallete,
+        }
+    }
+}
+
+/// chunks with a global palette are preferred when both palettes are set
+impl TryFrom<ChunkData> for EncodedChunk {
+    type Error = ConvertError;
+
+    fn try_from(value: ChunkData) -> Result<Self, Self::Error> {
+        let palette = if value.global_pallete.is_empty() {
+            EncodedPalette::Local(
+                value
+                    .pallete
+                    .into_iter()
+                    .map(BlockData::try_from)
+                    .collect::<Result<_, _>>()?,
+            )
+        } else {
+            EncodedPalette::Global(value.global_pallete)
+        };
+        Ok(EncodedChunk {
+            palette,
+            grid: value.mask,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunk_position_roundtrip() {
+        let chunk = ChunkPos::new(-3, 7, i64::MIN, 2);
+        let message = ChunkPosition::from(chunk);
+        assert_eq!(ChunkPos::from(message), chunk);
+    }
+
+    #[test]
+    fn block_position_roundtrip() {
+        let block = BlockPos::new(-1, 2, i64::MAX);
+        let message = Position::from(block);
+        assert_eq!(message.dimension, None);
+        assert_eq!(BlockPos::from(message), block);
+    }
+
+    #[test]
+    fn chunk_data_roundtrip() {
+        use common::palette::{ChunkStorage, GlobalPalette};
+
+        let state = |name: &str| BlockData::new(ResourceKey::core(name).unwrap());
+        let mut palette = GlobalPalette::new(state("air"));
+        let mut storage = ChunkStorage::empty();
+        storage.set(5, palette.intern(state("dirt")));
+
+        for mode in [PaletteMode::Local, PaletteMode::Global] {
+            let encoded = palette.encode(&storage, mode);
+            let message = ChunkData::from(&encoded);
+            assert_eq!(
+                message.global_pallete.is_empty(),
+                mode == PaletteMode::Local
+            );
+            let decoded = EncodedChunk::try_from(message).unwrap();
+            assert_eq!(decoded, encoded);
+            assert_eq!(palette.decode(&decoded).unwrap(), storage);
+        }
+    }
+
+    #[test]
+    fn chunk_data_entry_roundtrip() {
+        use common::resource::state::{BlockSchema, PropertyDefinition, PropertyValue};
+
+        let schema = BlockSchema::new().with_property("level", PropertyDefinition::int(0, 7, 0));
+        let material = common::resource_key!("test:water");
+        let state = schema
+            .create_state(
+                material.clone(),
+                [("level".to_string(), PropertyValue::Int(3))],
+            )
+            .unwrap();
+
+        let entry = ChunkDataEntry::from(&state);
+        assert!(entry.data.is_some());
+        assert_eq!(BlockData::try_from(entry).unwrap(), state);
+
+        let entry = ChunkDataEntry::from(&BlockData::new(material));
+        assert_eq!(entry.data, None);
+        assert!(matches!(
+            BlockData::try_from(ChunkDataEntry {
+                resource: None,
+                data: None
+            }),
+            Err(ConvertError::MissingField("resource"))
+        ));
+    }
+
+    #[test]
+    fn resource_key_conversion() {
+        let key = common::resource_key!("test:blocks/dirt");
+        let message = proto_common::ResourceKey::from(&key);
+        assert_eq!(message.path, "blocks/dirt");
+        assert_eq!(ResourceKey::try_from(message).unwrap(), key);
+
+        let invalid = proto_common::ResourceKey {
+            namespace: "Test".to_string(),
+            path: "dirt".to_string(),
+        };
+        assert!(ResourceKey::try_from(invalid).is_err());
+    }
+
+    #[test]
+    fn registry_snapshot_roundtrip() {
+        let registry = common::resource_key!("blocks");
+        let snapshot = RegistrySnapshot {
+            entries: vec![
+                (1, common::resource_key!("dirt")),
+                (5, common::resource_key!("test:stone")),
+            ],
+        };
+        let message = proto_common::RegistrySnapshot::from((&registry, &snapshot));
+        assert_eq!(message.entries[1].id, 5);
+        let (decoded_registry, decoded) =
+            <(ResourceKey, RegistrySnapshot)>::try_from(message).unwrap();
+        assert_eq!(decoded_registry, registry);
+        assert_eq!(decoded, snapshot);
+    }
+}
@@ -1,115 +1,902 @@
-use integer_encoding::{VarIntAsyncReader, VarIntAsyncWriter, VarIntWriter};
+//! The framing of the messages on a reliable stream.
+//!
+//! Every frame starts with a header which describes the payload explicitly,
+//! so the reader never has to guess from sizes how the payload is encoded:
+//!
+//! ```text
+//! flags                u8      COMPRESSED | CHECKSUM, the other bits must be zero
+//! compression          varint  only if COMPRESSED, the CompressionAlgorithm of the payload
+//! length               varint  the length of the payload
+//! decompressed length  varint  only if COMPRESSED
+//! checksum             u32 le  only if CHECKSUM, the crc32 of the payload
+//! payload              [u8]    the encoded message, possibly compressed
+//! ```
+//!
+//! Both lengths are checked against the [FrameConfig] before anything is buffered or decompressed,
+//! so a peer cannot make the reader allocate more than the limits.
+//!
+//! The compression is chosen per message: small, latency sensitive messages use [FrameConfig::compression]
+//! (LZ4 by default), large ones like chunk data [FrameConfig::bulk_compression] (zstd with a dictionary trained on
+//! chunks, see [CompressionDictionary::train]). The algorithms and levels are negotiated in the handshake
+//! (see [negotiate] and [FrameConfig::negotiated]).
+
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use bitflags::bitflags;
+use bytes::{Buf, BufMut, BytesMut};
+use integer_encoding::VarInt;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
 
-use crate::proto::common::CompressionAlgorithm;
-use crate::COMPRESSION_THRESHOLD;
+use crate::proto::common::{CompressionAlgorithm, CompressionOption};
+use crate::{COMPRESSION_THRESHOLD, MAX_MESSAGE_SIZE};
 
-mod test {}
+/// the longest encoding of a u64 varint
+const MAX_VARINT_LENGTH: usize = 10;
+/// messages of at least this size use [FrameConfig::bulk_compression]
+const BULK_THRESHOLD: usize = 16 * 1024;
+/// the window size of brotli as log2
+const BROTLI_WINDOW: u32 = 22;
 
-#[derive(Debug, Error)]
-pub enum WriteMessageError {
-    #[error("io error: {0}")]
-    Io(std::io::Error),
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FrameFlags: u8 {
+        const COMPRESSED = 0b0000_0001;
+        const CHECKSUM = 0b0000_0010;
+    }
 }
 
-pub trait WriteMessageExt {
-    async fn write_message<M>(
-        &mut self,
-        message: M,
-        compression: CompressionAlgorithm,
-    ) -> Result<(), WriteMessageError>
-    where
-        M: prost::Message + Default;
+/// an algorithm with its level, the meaning of the level depends on the algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    pub level: i32,
+}
+
+impl Compression {
+    pub const NONE: Self = Self::new(CompressionAlgorithm::CompressionNone, 0);
+    pub const LZ4: Self = Self::new(CompressionAlgorithm::CompressionLz4, 0);
+    pub const ZSTD: Self = Self::new(CompressionAlgorithm::CompressionZstd, 3);
+    pub const BROTLI: Self = Self::new(CompressionAlgorithm::CompressionBrotli, 5);
+
+    pub const fn new(algorithm: CompressionAlgorithm, level: i32) -> Self {
+        Self { algorithm, level }
+    }
+
+    /// every algorithm of this crate with its default level
+    pub fn supported() -> Vec<Self> {
+        vec![Self::LZ4, Self::ZSTD, Self::BROTLI]
+    }
+
+    /// the valid levels of [algorithm], algorithms without levels only accept 0
+    pub fn levels(algorithm: CompressionAlgorithm) -> RangeInclusive<i32> {
+        match algorithm {
+            CompressionAlgorithm::CompressionNone | CompressionAlgorithm::CompressionLz4 => 0..=0,
+            CompressionAlgorithm::CompressionZstd => 1..=22,
+            CompressionAlgorithm::CompressionBrotli => 0..=11,
+        }
+    }
+
+    /// limits the level to the [valid levels](Compression::levels) of the algorithm
+    pub fn clamped(self) -> Self {
+        let levels = Self::levels(self.algorithm);
+        Self::new(
+            self.algorithm,
+            self.level.clamp(*levels.start(), *levels.end()),
+        )
+    }
+}
+
+impl From<Compression> for CompressionOption {
+    fn from(value: Compression) -> Self {
+        CompressionOption {
+            algorithm: value.algorithm as i32,
+            level: value.level,
+        }
+    }
+}
+
+/// unknown algorithms of newer peers are skipped
+fn known_options(options: &[CompressionOption]) -> impl Iterator<Item = Compression> + '_ {
+    options.iter().filter_map(|option| {
+        CompressionAlgorithm::try_from(option.algorithm)
+            .ok()
+            .map(|algorithm| Compression::new(algorithm, option.level))
+    })
+}
+
+/// the algorithms supported by both sides, ordered like [local], with the lower level of both sides
+/// limited to the [valid levels](Compression::levels) of the algorithm
+pub fn negotiate(local: &[Compression], remote: &[CompressionOption]) -> Vec<Compression> {
+    local
+        .iter()
+        .filter_map(|local| {
+            known_options(remote)
+                .find(|remote| remote.algorithm == local.algorithm)
+                .map(|remote| {
+                    Compression::new(local.algorithm, local.level.min(remote.level)).clamped()
+                })
+        })
+        .filter(|compression| compression.algorithm != CompressionAlgorithm::CompressionNone)
+        .collect()
+}
+
+/// a zstd dictionary shared by both sides, it is sent to the client in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionDictionary(Arc<[u8]>);
+
+impl CompressionDictionary {
+    pub fn new(dictionary: impl Into<Arc<[u8]>>) -> Self {
+        Self(dictionary.into())
+    }
+
+    /// trains a dictionary of at most [max_size] bytes on typical messages, f.e. encoded chunks
+    pub fn train(samples: &[impl AsRef<[u8]>], max_size: usize) -> Result<Self, FrameError> {
+        zstd::dict::from_samples(samples, max_size)
+            .map(Self::new)
+            .map_err(|error| FrameError::Compress(error.to_string()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameConfig {
+    /// the compression of written messages below [FrameConfig::bulk_threshold], every known algorithm is accepted when reading
+    pub compression: Compression,
+    /// the compression of written messages of at least [FrameConfig::bulk_threshold] bytes
+    pub bulk_compression: Compression,
+    /// messages smaller than this are never compressed
+    pub compression_threshold: usize,
+    pub bulk_threshold: usize,
+    /// the dictionary of zstd, frames compressed with a dictionary can only be read with the same one
+    pub dictionary: Option<CompressionDictionary>,
+    /// whether written frames contain a checksum, received checksums are always verified
+    pub checksum: bool,
+    /// the limit of the payload as transmitted
+    pub max_payload_size: usize,
+    /// the limit of the encoded message after decompression
+    pub max_message_size: usize,
+}
+
+/// the config before the negotiation, LZ4 is supported by every peer
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            compression: Compression::LZ4,
+            bulk_compression: Compression::LZ4,
+            compression_threshold: COMPRESSION_THRESHOLD,
+            bulk_threshold: BULK_THRESHOLD,
+            dictionary: None,
+            checksum: false,
+            max_payload_size: MAX_MESSAGE_SIZE,
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl FrameConfig {
+    /// prefers LZ4 for small messages and zstd, brotli or LZ4 (in this order) for large ones
+    pub fn negotiated(
+        negotiated: &[Compression],
+        dictionary: Option<CompressionDictionary>,
+    ) -> Self {
+        let find = |algorithm| {
+            negotiated
+                .iter()
+                .find(|compression| compression.algorithm == algorithm)
+                .copied()
+        };
+        let compression = find(CompressionAlgorithm::CompressionLz4).unwrap_or(Compression::NONE);
+        let bulk_compression = find(CompressionAlgorithm::CompressionZstd)
+            .or_else(|| find(CompressionAlgorithm::CompressionBrotli))
+            .unwrap_or(compression);
+        Self {
+            compression,
+            bulk_compression,
+            dictionary,
+            ..Default::default()
+        }
+    }
+
+    /// the compression of a message with the encoded size
+    pub fn compression_of(&self, size: usize) -> Compression {
+        if size < self.compression_threshold {
+            Compression::NONE
+        } else if size < self.bulk_threshold {
+            self.compression
+        } else {
+            self.bulk_compression
+        }
+    }
 }
 
 #[derive(Debug, Error)]
-pub enum ReadMessageError {
+pub enum FrameError {
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
-    #[error("decode error: {0}")]
-    Prost(#[from] prost::DecodeError),
+    #[error("unknown frame flags {0:#010b}")]
+    InvalidFlags(u8),
+    #[error("invalid varint in the frame header")]
+    InvalidVarint,
+    #[error("unknown compression algorithm {0}")]
+    UnknownCompression(u64),
+    #[error("payload size {0} exceeds limit {1}")]
+    PayloadTooLarge(usize, usize),
     #[error("message size {0} exceeds limit {1}")]
-    LimitExceeded(usize, usize),
-    #[error("lz4 decompression error: {0}")]
-    Lz4DecompressError(#[from] lz4_flex::block::DecompressError),
+    MessageTooLarge(usize, usize),
+    #[error("checksum mismatch, expected {expected:#010x} but got {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[error("compression error: {0}")]
+    Compress(String),
+    #[error("decompression error: {0}")]
+    Decompress(String),
+    #[error("decompressed {actual} bytes but the header announced {expected}")]
+    SizeMismatch { expected: usize, actual: usize },
+    #[error("decode error: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("the stream ended inside of a frame")]
+    Truncated,
+}
+
+/// the header of a frame, parsed before the payload is available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHeader {
+    flags: FrameFlags,
+    compression: CompressionAlgorithm,
+    payload_size: usize,
+    message_size: usize,
+    checksum: u32,
+    /// the size of the header itself
+    size: usize,
+}
+
+impl FrameHeader {
+    fn write(&self, into: &mut BytesMut) {
+        into.put_u8(self.flags.bits());
+        if self.flags.contains(FrameFlags::COMPRESSED) {
+            put_varint(into, self.compression as u64);
+        }
+        put_varint(into, self.payload_size as u64);
+        if self.flags.contains(FrameFlags::COMPRESSED) {
+            put_varint(into, self.message_size as u64);
+        }
+        if self.flags.contains(FrameFlags::CHECKSUM) {
+            into.put_u32_le(self.checksum);
+        }
+    }
+
+    /// [None] if the header is incomplete, the limits are checked as soon as the sizes are known
+    fn read(src: &[u8], config: &FrameConfig) -> Result<Option<Self>, FrameError> {
+        let Some(&flags) = src.first() else {
+            return Ok(None);
+        };
+        let flags = FrameFlags::from_bits(flags).ok_or(FrameError::InvalidFlags(flags))?;
+        let mut offset = 1;
+
+        let mut compression = CompressionAlgorithm::CompressionNone;
+        if flags.contains(FrameFlags::COMPRESSED) {
+            let Some(value) = read_varint(src, &mut offset)? else {
+                return Ok(None);
+            };
+            compression = i32::try_from(value)
+                .ok()
+                .and_then(|value| CompressionAlgorithm::try_from(value).ok())
+                .filter(|compression| *compression != CompressionAlgorithm::CompressionNone)
+                .ok_or(FrameError::UnknownCompression(value))?;
+        }
+
+        let Some(payload_size) = read_varint(src, &mut offset)? else {
+            return Ok(None);
+        };
+        let payload_size = checked_size(payload_size, config.max_payload_size)
+            .map_err(|size| FrameError::PayloadTooLarge(size, config.max_payload_size))?;
+
+        let mut message_size = payload_size;
+        if flags.contains(FrameFlags::COMPRESSED) {
+            let Some(size) = read_varint(src, &mut offset)? else {
+                return Ok(None);
+            };
+            message_size = checked_size(size, config.max_message_size)
+                .map_err(|size| FrameError::MessageTooLarge(size, config.max_message_size))?;
+        } else if payload_size > config.max_message_size {
+            return Err(FrameError::MessageTooLarge(
+                payload_size,
+                config.max_message_size,
+            ));
+        }
+
+        let mut checksum = 0;
+        if flags.contains(FrameFlags::CHECKSUM) {
+            let Some(bytes) = src.get(offset..offset + 4) else {
+                return Ok(None);
+            };
+            checksum = u32::from_le_bytes(bytes.try_into().expect("the slice has 4 bytes"));
+            offset += 4;
+        }
+
+        Ok(Some(Self {
+            flags,
+            compression,
+            payload_size,
+            message_size,
+            checksum,
+            size: offset,
+        }))
+    }
+}
+
+/// the size if it is within the limit, otherwise the size saturated to usize
+fn checked_size(size: u64, limit: usize) -> Result<usize, usize> {
+    match usize::try_from(size) {
+        Ok(size) if size <= limit => Ok(size),
+        Ok(size) => Err(size),
+        Err(_) => Err(usize::MAX),
+    }
+}
+
+fn put_varint(into: &mut BytesMut, value: u64) {
+    let mut buffer = [0u8; MAX_VARINT_LENGTH];
+    let length = value.encode_var(&mut buffer);
+    into.put_slice(&buffer[..length]);
+}
+
+/// [None] if the varint is incomplete
+fn read_varint(src: &[u8], offset: &mut usize) -> Result<Option<u64>, FrameError> {
+    let remaining = src.get(*offset..).unwrap_or_default();
+    match u64::decode_var(remaining) {
+        Some((value, length)) => {
+            *offset += length;
+            Ok(Some(value))
+        }
+        None if remaining.len() >= MAX_VARINT_LENGTH => Err(FrameError::InvalidVarint),
+        None => Ok(None),
+    }
+}
+
+fn compress(
+    compression: Compression,
+    dictionary: Option<&CompressionDictionary>,
+    message: &[u8],
+) -> Result<Vec<u8>, FrameError> {
+    let error = |error: std::io::Error| FrameError::Compress(error.to_string());
+    Ok(match compression.algorithm {
+        CompressionAlgorithm::CompressionNone => message.to_vec(),
+        CompressionAlgorithm::CompressionLz4 => lz4_flex::block::compress(message),
+        CompressionAlgorithm::CompressionZstd => {
+            let mut compressor = match dictionary {
+                Some(dictionary) => zstd::bulk::Compressor::with_dictionary(
+                    compression.level,
+                    dictionary.as_bytes(),
+                ),
+                None => zstd::bulk::Compressor::new(compression.level),
+            }
+            .map_err(error)?;
+            compressor.compress(message).map_err(error)?
+        }
+        CompressionAlgorithm::CompressionBrotli => {
+            let quality = compression.level.clamp(0, 11) as u32;
+            let mut writer =
+                brotli::CompressorWriter::new(Vec::new(), 4096, quality, BROTLI_WINDOW);
+            writer.write_all(message).map_err(error)?;
+            writer.into_inner()
+        }
+    })
 }
 
-pub trait ReadMessageExt {
-    async fn read_message<M>(&mut self, size_limit: Option<usize>) -> Result<M, ReadMessageError>
-    where
-        M: prost::Message + Default;
+/// decompresses at most [size] bytes, so the announced size cannot be exceeded
+fn decompress(
+    compression: CompressionAlgorithm,
+    dictionary: Option<&CompressionDictionary>,
+    payload: &[u8],
+    size: usize,
+) -> Result<Vec<u8>, FrameError> {
+    let error = |error: std::io::Error| FrameError::Decompress(error.to_string());
+    let message = match compression {
+        CompressionAlgorithm::CompressionNone => payload.to_vec(),
+        CompressionAlgorithm::CompressionLz4 => {
+            let mut message = vec![0u8; size];
+            let length = lz4_flex::block::decompress_into(payload, &mut message)
+                .map_err(|error| FrameError::Decompress(error.to_string()))?;
+            message.truncate(length);
+            message
+        }
+        CompressionAlgorithm::CompressionZstd => {
+            let mut decompressor = match dictionary {
+                Some(dictionary) => {
+                    zstd::bulk::Decompressor::with_dictionary(dictionary.as_bytes())
+                }
+                None => zstd::bulk::Decompressor::new(),
+            }
+            .map_err(error)?;
+            decompressor.decompress(payload, size).map_err(error)?
+        }
+        CompressionAlgorithm::CompressionBrotli => {
+            //one more byte than announced is read to detect larger messages
+            let mut message = Vec::with_capacity(size);
+            brotli::Decompressor::new(payload, 4096)
+                .take(size as u64 + 1)
+                .read_to_end(&mut message)
+                .map_err(error)?;
+            message
+        }
+    };
+    if message.len() != size {
+        return Err(FrameError::SizeMismatch {
+            expected: size,
+            actual: message.len(),
+        });
+    }
+    Ok(message)
+}
+
+/// encodes messages of the type [Out] and decodes messages of the type [In] as frames
+#[derive(Debug)]
+pub struct MessageCodec<Out, In> {
+    config: FrameConfig,
+    _marker: PhantomData<fn(Out) -> In>,
+}
+
+impl<Out, In> MessageCodec<Out, In> {
+    pub fn new(config: FrameConfig) -> Self {
+        Self {
+            config,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn config(&self) -> &FrameConfig {
+        &self.config
+    }
+}
+
+impl<Out, In> Default for MessageCodec<Out, In> {
+    fn default() -> Self {
+        Self::new(FrameConfig::default())
+    }
 }
 
-impl<W> WriteMessageExt for W
+impl<Out, In> Clone for MessageCodec<Out, In> {
+    fn clone(&self) -> Self {
+        Self::new(self.config.clone())
+    }
+}
+
+impl<Out, In> MessageCodec<Out, In>
 where
-    W: AsyncWrite + Unpin,
+    Out: prost::Message,
 {
-    async fn write_message<M>(
+    /// encodes the message with the compression instead of the one chosen by the size,
+    /// it still has to be supported by the peer
+    pub fn encode_with(
         &mut self,
-        message: M,
-        compression: CompressionAlgorithm,
-    ) -> Result<(), WriteMessageError>
-    where
-        M: prost::Message + Default,
-    {
+        message: Out,
+        compression: Compression,
+        dst: &mut BytesMut,
+    ) -> Result<(), FrameError> {
+        let message_size = message.encoded_len();
+        if message_size > self.config.max_message_size {
+            return Err(FrameError::MessageTooLarge(
+                message_size,
+                self.config.max_message_size,
+            ));
+        }
         let encoded = message.encode_to_vec();
-        let size = message.encoded_len();
-        if size < COMPRESSION_THRESHOLD {
-            self.write_varint_async(size as u64)
-                .await
-                .map_err(WriteMessageError::Io)?;
-            self.write_all(&encoded)
-                .await
-                .map_err(WriteMessageError::Io)?;
-        } else {
-            let payload = match compression {
-                CompressionAlgorithm::CompressionNone => encoded,
-                CompressionAlgorithm::CompressionLz4 => lz4_flex::compress_prepend_size(&encoded),
-            };
-            self.write_varint_async(payload.len() as u64)
-                .await
-                .map_err(WriteMessageError::Io)?;
-            self.write_varint_async((compression as i32) as u32)
-                .await
-                .map_err(WriteMessageError::Io)?;
-            self.write_all(&payload)
-                .await
-                .map_err(WriteMessageError::Io)?;
+
+        let mut flags = FrameFlags::empty();
+        let mut algorithm = CompressionAlgorithm::CompressionNone;
+        let mut payload = encoded;
+        if compression.algorithm != CompressionAlgorithm::CompressionNone {
+            let compressed = compress(compression, self.config.dictionary.as_ref(), &payload)?;
+            //incompressible messages are sent as they are
+            if compressed.len() < payload.len() {
+                flags |= FrameFlags::COMPRESSED;
+                algorithm = compression.algorithm;
+                payload = compressed;
+            }
+        }
+        if payload.len() > self.config.max_payload_size {
+            return Err(FrameError::PayloadTooLarge(
+                payload.len(),
+                self.config.max_payload_size,
+            ));
         }
+
+        let mut checksum = 0;
+        if self.config.checksum {
+            flags |= FrameFlags::CHECKSUM;
+            checksum = crc32fast::hash(&payload);
+        }
+
+        let header = FrameHeader {
+            flags,
+            compression: algorithm,
+            payload_size: payload.len(),
+            message_size,
+            checksum,
+            size: 0,
+        };
+        dst.reserve(3 * MAX_VARINT_LENGTH + 5 + payload.len());
+        header.write(dst);
+        dst.put_slice(&payload);
         Ok(())
     }
 }
 
-impl<R> ReadMessageExt for R
+impl<Out, In> Encoder<Out> for MessageCodec<Out, In>
+where
+    Out: prost::Message,
+{
+    type Error = FrameError;
+
+    fn encode(&mut self, message: Out, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let compression = self.config.compression_of(message.encoded_len());
+        self.encode_with(message, compression, dst)
+    }
+}
+
+impl<Out, In> Decoder for MessageCodec<Out, In>
 where
-    R: AsyncRead + Unpin,
+    In: prost::Message + Default,
 {
-    async fn read_message<M>(&mut self, size_limit: Option<usize>) -> Result<M, ReadMessageError>
-    where
-        M: prost::Message + Default,
-    {
-        let size = self.read_varint_async::<u64>().await?;
-        if let Some(size_limit) = size_limit {
-            if size as usize > size_limit {
-                return Err(ReadMessageError::LimitExceeded(size as usize, size_limit));
+    type Item = In;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(header) = FrameHeader::read(src, &self.config)? else {
+            return Ok(None);
+        };
+        let frame_size = header.size + header.payload_size;
+        if src.len() < frame_size {
+            src.reserve(frame_size - src.len());
+            return Ok(None);
+        }
+
+        src.advance(header.size);
+        let payload = src.split_to(header.payload_size);
+        if header.flags.contains(FrameFlags::CHECKSUM) {
+            let actual = crc32fast::hash(&payload);
+            if actual != header.checksum {
+                return Err(FrameError::ChecksumMismatch {
+                    expected: header.checksum,
+                    actual,
+                });
             }
         }
 
-        let compression = if size < COMPRESSION_THRESHOLD as u64 {
-            CompressionAlgorithm::CompressionNone
+        let message = if header.flags.contains(FrameFlags::COMPRESSED) {
+            In::decode(
+                &decompress(
+                    header.compression,
+                    self.config.dictionary.as_ref(),
+                    &payload,
+                    header.message_size,
+                )?[..],
+            )?
         } else {
-            let compression = self.read_varint_async::<u32>().await? as i32;
-            CompressionAlgorithm::try_from(compression)?
+            In::decode(payload.freeze())?
         };
+        Ok(Some(message))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() => Ok(None),
+            None => Err(FrameError::Truncated),
+        }
+    }
+}
 
-        let mut buffer = vec![0u8; size as usize];
-        self.read_exact(&mut buffer).await?;
-        let payload = match compression {
-            CompressionAlgorithm::CompressionNone => buffer,
-            CompressionAlgorithm::CompressionLz4 => lz4_flex::decompress_size_prepended(&buffer)
-                .map_err(ReadMessageError::Lz4DecompressError)?,
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use crate::proto::play::client::packet::packet_play_client::Payload;
+    use crate::proto::play::client::packet::PacketPlayClient;
+
+    use super::*;
+
+    type Codec = MessageCodec<PacketPlayClient, PacketPlayClient>;
+
+    fn packet(sync: Vec<u8>) -> PacketPlayClient {
+        PacketPlayClient {
+            payload: Some(Payload::Sync(sync)),
+        }
+    }
+
+    fn encode(codec: &mut Codec, message: PacketPlayClient) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer).unwrap();
+        buffer
+    }
+
+    fn pseudo_random(length: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        for checksum in [false, true] {
+            let mut codec = Codec::new(FrameConfig {
+                checksum,
+                ..Default::default()
+            });
+            let messages = [
+                packet(vec![]),
+                packet(vec![7; 10]),
+                //compresses below the threshold, which desynced the old framing
+                packet(vec![0; 4 * COMPRESSION_THRESHOLD]),
+                //incompressible, sent uncompressed
+                packet(pseudo_random(2 * COMPRESSION_THRESHOLD)),
+                PacketPlayClient { payload: None },
+            ];
+
+            let mut stream = BytesMut::new();
+            for message in messages.iter().cloned() {
+                codec.encode(message, &mut stream).unwrap();
+            }
+            assert!(stream.len() < 3 * COMPRESSION_THRESHOLD);
+
+            //the frames arrive byte by byte
+            let mut received = BytesMut::new();
+            let mut decoded = Vec::new();
+            for byte in stream {
+                received.put_u8(byte);
+                while let Some(message) = codec.decode(&mut received).unwrap() {
+                    decoded.push(message);
+                }
+            }
+            assert!(received.is_empty());
+            assert_eq!(decoded, messages);
+        }
+    }
+
+    #[test]
+    fn flags() {
+        let mut codec = Codec::default();
+        let frame = encode(&mut codec, packet(vec![0; 4 * COMPRESSION_THRESHOLD]));
+        assert_eq!(frame[0], FrameFlags::COMPRESSED.bits());
+        assert_eq!(frame[1], CompressionAlgorithm::CompressionLz4 as u8);
+
+        let mut codec = Codec::new(FrameConfig {
+            compression: Compression::NONE,
+            checksum: true,
+            ..Default::default()
+        });
+        let frame = encode(&mut codec, packet(vec![0; 4 * COMPRESSION_THRESHOLD]));
+        assert_eq!(frame[0], FrameFlags::CHECKSUM.bits());
+    }
+
+    #[test]
+    fn limits() {
+        let limited = FrameConfig {
+            max_payload_size: 64,
+            max_message_size: 256,
+            ..Default::default()
         };
-        M::decode(&payload[..]).map_err(ReadMessageError::Prost)
+        let mut codec = Codec::new(limited);
+        assert!(matches!(
+            codec.encode(packet(vec![1; 300]), &mut BytesMut::new()),
+            Err(FrameError::MessageTooLarge(_, 256))
+        ));
+
+        //the payload limit is checked from the header alone
+        let mut unlimited = Codec::default();
+        let mut frame = encode(&mut unlimited, packet(pseudo_random(100)));
+        frame.truncate(4);
+        assert!(matches!(
+            codec.decode(&mut frame),
+            Err(FrameError::PayloadTooLarge(_, 64))
+        ));
+
+        //a zip bomb: a small payload which decompresses far beyond the limit
+        let mut unlimited = Codec::new(FrameConfig {
+            compression_threshold: 0,
+            ..Default::default()
+        });
+        let mut frame = encode(&mut unlimited, packet(vec![0; 1024 * 1024]));
+        assert!(frame.len() < 64 * 1024);
+        let mut codec = Codec::new(FrameConfig {
+            max_message_size: 256,
+            ..Default::default()
+        });
+        assert!(matches!(
+            codec.decode(&mut frame),
+            Err(FrameError::MessageTooLarge(_, 256))
+        ));
+    }
+
+    #[test]
+    fn adversarial() {
+        let mut codec = Codec::default();
+        let decode = |bytes: &[u8]| Codec::default().decode(&mut BytesMut::from(bytes));
+
+        assert!(matches!(
+            decode(&[0b1000_0000]),
+            Err(FrameError::InvalidFlags(_))
+        ));
+        assert!(matches!(
+            decode(&[FrameFlags::COMPRESSED.bits(), 0x7F]),
+            Err(FrameError::UnknownCompression(0x7F))
+        ));
+        assert!(matches!(
+            decode(&[FrameFlags::COMPRESSED.bits(), 0]),
+            Err(FrameError::UnknownCompression(0))
+        ));
+        assert!(matches!(decode(&[0; 1]), Ok(None)));
+        assert!(matches!(decode(&[0, 0xFF, 0xFF]), Ok(None)));
+        assert!(matches!(
+            decode(&[0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            Err(FrameError::InvalidVarint)
+        ));
+        assert!(matches!(
+            decode(&[0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+            Err(FrameError::PayloadTooLarge(_, _))
+        ));
+
+        //the announced size differs from the real one
+        let message = packet(vec![0; 4 * COMPRESSION_THRESHOLD]).encode_to_vec();
+        let payload = lz4_flex::block::compress(&message);
+        for message_size in [message.len() - 1, message.len() + 1] {
+            let mut frame = BytesMut::new();
+            FrameHeader {
+                flags: FrameFlags::COMPRESSED,
+                compression: CompressionAlgorithm::CompressionLz4,
+                payload_size: payload.len(),
+                message_size,
+                checksum: 0,
+                size: 0,
+            }
+            .write(&mut frame);
+            frame.put_slice(&payload);
+            assert!(matches!(
+                codec.decode(&mut frame),
+                Err(FrameError::Decompress(_) | FrameError::SizeMismatch { .. })
+            ));
+        }
+
+        //a corrupted payload is detected by the checksum
+        let mut codec = Codec::new(FrameConfig {
+            checksum: true,
+            ..Default::default()
+        });
+        let mut frame = encode(&mut codec, packet(vec![1, 2, 3]));
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        assert!(matches!(
+            codec.decode(&mut frame),
+            Err(FrameError::ChecksumMismatch { .. })
+        ));
+
+        //the stream ends inside of a frame
+        let mut frame = encode(&mut codec, packet(vec![1, 2, 3]));
+        frame.truncate(frame.len() - 1);
+        assert!(matches!(
+            codec.decode_eof(&mut frame),
+            Err(FrameError::Truncated)
+        ));
+        assert!(matches!(codec.decode_eof(&mut BytesMut::new()), Ok(None)));
+    }
+
+    #[test]
+    fn algorithms() {
+        let samples: Vec<_> = (0..256u32)
+            .map(|i| {
+                packet(format!("chunk {} at {} {}", i % 7, i, i * 31).into_bytes()).encode_to_vec()
+            })
+            .collect();
+        let dictionary = CompressionDictionary::train(&samples, 1024).unwrap();
+        let message = packet(format!("chunk 3 at 17 527 {:?}", vec![5u8; 512]).into_bytes());
+
+        for (compression, dictionary) in [
+            (Compression::ZSTD, None),
+            (Compression::ZSTD, Some(dictionary.clone())),
+            (Compression::BROTLI, None),
+        ] {
+            let mut codec = Codec::new(FrameConfig {
+                dictionary,
+                ..Default::default()
+            });
+            let mut frame = BytesMut::new();
+            codec
+                .encode_with(message.clone(), compression, &mut frame)
+                .unwrap();
+            assert_eq!(frame[1], compression.algorithm as u8);
+            assert!(frame.len() < message.encoded_len());
+            assert_eq!(codec.decode(&mut frame).unwrap(), Some(message.clone()));
+        }
+
+        //frames compressed with a dictionary cannot be read without it
+        let mut codec = Codec::new(FrameConfig {
+            dictionary: Some(dictionary),
+            ..Default::default()
+        });
+        let mut frame = BytesMut::new();
+        codec
+            .encode_with(message, Compression::ZSTD, &mut frame)
+            .unwrap();
+        assert!(matches!(
+            Codec::default().decode(&mut frame),
+            Err(FrameError::Decompress(_))
+        ));
+
+        //zip bombs are stopped at the limit by every algorithm
+        for compression in [Compression::ZSTD, Compression::BROTLI] {
+            let mut frame = BytesMut::new();
+            Codec::default()
+                .encode_with(packet(vec![0; 1024 * 1024]), compression, &mut frame)
+                .unwrap();
+            let mut codec = Codec::new(FrameConfig {
+                max_message_size: 256,
+                ..Default::default()
+            });
+            assert!(matches!(
+                codec.decode(&mut frame),
+                Err(FrameError::MessageTooLarge(_, 256))
+            ));
+        }
+    }
+
+    #[test]
+    fn negotiation() {
+        let remote = [
+            CompressionOption::from(Compression::new(CompressionAlgorithm::CompressionZstd, 9)),
+            CompressionOption::from(Compression::LZ4),
+            CompressionOption {
+                algorithm: 0x7F,
+                level: 0,
+            },
+        ];
+        let negotiated = negotiate(&Compression::supported(), &remote);
+        assert_eq!(negotiated, vec![Compression::LZ4, Compression::ZSTD]);
+
+        //levels outside of the valid range of the algorithm are limited to it
+        let remote = [
+            CompressionOption::from(Compression::new(CompressionAlgorithm::CompressionZstd, -7)),
+            CompressionOption::from(Compression::new(
+                CompressionAlgorithm::CompressionBrotli,
+                99,
+            )),
+        ];
+        let local = [
+            Compression::new(CompressionAlgorithm::CompressionZstd, 3),
+            Compression::new(CompressionAlgorithm::CompressionBrotli, 40),
+        ];
+        assert_eq!(
+            negotiate(&local, &remote),
+            vec![
+                Compression::new(CompressionAlgorithm::CompressionZstd, 1),
+                Compression::new(CompressionAlgorithm::CompressionBrotli, 11),
+            ]
+        );
+
+        let config = FrameConfig::negotiated(&negotiated, None);
+        assert_eq!(config.compression_of(1), Compression::NONE);
+        assert_eq!(
+            config.compression_of(COMPRESSION_THRESHOLD),
+            Compression::LZ4
+        );
+        assert_eq!(config.compression_of(BULK_THRESHOLD), Compression::ZSTD);
+
+        let config = FrameConfig::negotiated(&[Compression::BROTLI], None);
+        assert_eq!(
+            config.compression_of(COMPRESSION_THRESHOLD),
+            Compression::NONE
+        );
+        assert_eq!(config.compression_of(BULK_THRESHOLD), Compression::BROTLI);
     }
 }
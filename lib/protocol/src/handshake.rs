@@ -0,0 +1,999 @@
+//! The states of a connection before play.
+//!
+//! 1. handshake: the client sends its [PROTOCOL_VERSION] and palette modes, the server checks the compatibility
+//! 2. login: the client sends its profile and session certificate with the signature of the login challenge of the
+//!    server, the server admits or refuses it
+//! 3. configuration: the server sends its registry snapshots and the palette mode, the client acknowledges them
+//! 4. play: the packets are wrapped into [PacketClient::play](packet_client::State::Play)
+//!
+//! The compression is negotiated in the handshake, the zstd dictionary of the server is sent in the configuration.
+//! Both sides switch to the negotiated [FrameConfig] once play is reached.
+//!
+//! [ClientHandshake] and [ServerHandshake] only produce and consume packets, so they work with every
+//! [TransportLayer]. A refused client receives a disconnect packet of the current state and the connection is
+//! closed with the same [DisconnectReason].
+//! The [ServerLoginPlugin](crate::server::ServerLoginPlugin) and [ClientLoginPlugin](crate::client::ClientLoginPlugin)
+//! run them for the connections of an app and hand the connections over to the play plugins.
+
+use std::sync::Arc;
+
+use bevy::prelude::Resource;
+use common::palette::PaletteMode;
+use common::registry::RegistrySnapshot;
+use common::resource::ResourceKey;
+use error_stack::{Report, ResultExt};
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use thiserror::Error;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::oid_registry::OID_SIG_ED25519;
+use x509_parser::prelude::FromDer;
+
+use crate::convert::ConvertError;
+use crate::encoding::{negotiate, Compression, CompressionDictionary, FrameConfig};
+use crate::proto::chunk;
+use crate::proto::common::{
+    self as proto_common, CompressionAlgorithm, DisconnectReason, SemanticVersion,
+};
+use crate::proto::configuration::client::packet::{
+    packet_configuration_client, ConfigurationAcknowledged, PacketConfigurationClient,
+};
+use crate::proto::configuration::server::packet::{
+    packet_configuration_server, FinishConfiguration, PacketConfigurationServer,
+};
+use crate::proto::connection::{packet_client, packet_server, PacketClient, PacketServer};
+use crate::proto::handshake::client::packet::{
+    packet_handshake_client, Hello, PacketHandshakeClient,
+};
+use crate::proto::handshake::server::packet::{
+    packet_handshake_server, HelloAccepted, PacketHandshakeServer,
+};
+use crate::proto::login::client::packet::{packet_login_client, LoginStart, PacketLoginClient};
+use crate::proto::login::server::packet::{packet_login_server, LoginSuccess, PacketLoginServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::server::PlayerProfile;
+use crate::transport::{TransportContainer, TransportLayer};
+
+/// the version of the protocol implemented by this crate
+pub const PROTOCOL_VERSION: SemanticVersion = SemanticVersion {
+    major: 0,
+    minor: 1,
+    patch: 0,
+    pre_release: None,
+};
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+/// prepended to the login challenge before it is signed, so the signature is not valid in another context
+const LOGIN_CHALLENGE_CONTEXT: &[u8] = b"zenith login challenge";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    Handshake,
+    Login,
+    Configuration,
+    Play,
+    Closed(DisconnectReason),
+}
+
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    /// the connection was closed by the server or the client was refused
+    #[error("disconnected ({}): {}", .reason.as_str_name(), .message.as_deref().unwrap_or("no message"))]
+    Disconnected {
+        reason: DisconnectReason,
+        message: Option<String>,
+    },
+    #[error("unexpected packet in the state {0:?}")]
+    UnexpectedPacket(ConnectionState),
+    #[error("invalid packet: {0}")]
+    InvalidPacket(#[from] ConvertError),
+    #[error("the transport failed")]
+    Transport,
+}
+
+impl HandshakeError {
+    fn refuse(reason: DisconnectReason, message: impl Into<String>) -> Self {
+        HandshakeError::Disconnected {
+            reason,
+            message: Some(message.into()),
+        }
+    }
+
+    /// the reason the connection is closed with
+    pub fn reason(&self) -> DisconnectReason {
+        match self {
+            HandshakeError::Disconnected { reason, .. } => *reason,
+            HandshakeError::UnexpectedPacket(_) | HandshakeError::InvalidPacket(_) => {
+                DisconnectReason::ProtocolViolation
+            }
+            HandshakeError::Transport => DisconnectReason::Timeout,
+        }
+    }
+}
+
+/// whether a peer with the version [other] can talk to this crate, versions before 1.0 must have the same minor version
+pub fn is_compatible(other: &SemanticVersion) -> bool {
+    other.major == PROTOCOL_VERSION.major
+        && (other.major != 0 || other.minor == PROTOCOL_VERSION.minor)
+        && other.pre_release == PROTOCOL_VERSION.pre_release
+}
+
+/// the login of a client, passed to [ServerHandshakeConfig::admission]
+#[derive(Debug, Clone)]
+pub struct LoginRequest {
+    pub display_name: String,
+    /// the DER encoded session certificate, it was parsed, is currently valid and the client owns its key
+    ///
+    /// neither its issuer nor its subject were checked, so it does not prove the identity of the client yet
+    pub session_certificate: Vec<u8>,
+}
+
+pub type Admission = dyn Fn(&LoginRequest) -> Result<PlayerProfile, DisconnectReason> + Send + Sync;
+
+#[derive(Clone)]
+pub struct ServerHandshakeConfig {
+    /// the palette modes supported by the server, the first one supported by the client is chosen
+    pub palette_modes: Vec<PaletteMode>,
+    /// the snapshots of the registries whose ids are shared with the client
+    pub registries: Vec<(ResourceKey, RegistrySnapshot)>,
+    /// decides whether a client is admitted, f.e. by checking a whitelist or the player count
+    ///
+    /// servers which authenticate their players have to verify the issuer of the session certificate here
+    pub admission: Arc<Admission>,
+    /// the compressions supported by the server ordered by preference
+    pub compression: Vec<Compression>,
+    /// sent to clients which support zstd, the server transports have to read with it from the start
+    pub compression_dictionary: Option<CompressionDictionary>,
+}
+
+impl Default for ServerHandshakeConfig {
+    /// admits everyone with the requested name
+    fn default() -> Self {
+        Self {
+            palette_modes: vec![PaletteMode::Global, PaletteMode::Local],
+            registries: Vec::new(),
+            admission: Arc::new(|request| {
+                Ok(PlayerProfile {
+                    display_name: request.display_name.clone(),
+                })
+            }),
+            compression: Compression::supported(),
+            compression_dictionary: None,
+        }
+    }
+}
+
+/// a client which completed the handshake
+#[derive(Debug, Clone)]
+pub struct AdmittedClient {
+    pub profile: PlayerProfile,
+    pub session_certificate: Vec<u8>,
+    pub palette_mode: PaletteMode,
+    /// the config of the transport after the handshake
+    pub frame_config: FrameConfig,
+}
+
+/// the server side of a connection before play
+pub struct ServerHandshake {
+    config: Arc<ServerHandshakeConfig>,
+    state: ConnectionState,
+    palette_mode: PaletteMode,
+    compression: Vec<Compression>,
+    /// signed by the client with the key of its session certificate
+    login_challenge: [u8; 32],
+    request: Option<LoginRequest>,
+    admitted: Option<AdmittedClient>,
+}
+
+impl ServerHandshake {
+    pub fn new(config: Arc<ServerHandshakeConfig>) -> Self {
+        Self {
+            config,
+            state: ConnectionState::Handshake,
+            palette_mode: PaletteMode::Local,
+            compression: Vec::new(),
+            login_challenge: rand::random(),
+            request: None,
+            admitted: None,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// [Some] once the client was admitted and its configuration was sent, the connection reaches play when the client
+    /// acknowledges the configuration
+    pub fn admitted(&self) -> Option<&AdmittedClient> {
+        self.admitted.as_ref()
+    }
+
+    /// handles a packet of the client, the responses are pushed to [out]
+    ///
+    /// on an error a disconnect packet is pushed and the connection has to be closed with [HandshakeError::reason]
+    pub fn handle(
+        &mut self,
+        packet: PacketClient,
+        out: &mut Vec<PacketServer>,
+    ) -> Result<(), HandshakeError> {
+        let result = self.handle_state(packet, out);
+        if let Err(error) = &result {
+            let message = match error {
+                HandshakeError::Disconnected { message, .. } => message.clone(),
+                error => Some(error.to_string()),
+            };
+            if let Some(packet) = disconnect_packet(self.state, error.reason(), message) {
+                out.push(packet);
+            }
+            self.state = ConnectionState::Closed(error.reason());
+        }
+        result
+    }
+
+    fn handle_state(
+        &mut self,
+        packet: PacketClient,
+        out: &mut Vec<PacketServer>,
+    ) -> Result<(), HandshakeError> {
+        use packet_client::State;
+
+        match (self.state, packet.state) {
+            (
+                ConnectionState::Handshake,
+                Some(State::Handshake(PacketHandshakeClient {
+                    payload: Some(packet_handshake_client::Payload::Hello(hello)),
+                })),
+            ) => {
+                self.hello(hello)?;
+                out.push(handshake_packet(
+                    packet_handshake_server::Payload::Accepted(HelloAccepted {
+                        protocol_version: Some(PROTOCOL_VERSION),
+                        compression: self.compression.iter().copied().map(Into::into).collect(),
+                        login_challenge: self.login_challenge.to_vec(),
+                    }),
+                ));
+                self.state = ConnectionState::Login;
+            }
+            (
+                ConnectionState::Login,
+                Some(State::Login(PacketLoginClient {
+                    payload: Some(packet_login_client::Payload::Start(start)),
+                })),
+            ) => {
+                let profile = self.login(start)?;
+                out.push(login_packet(packet_login_server::Payload::Success(
+                    LoginSuccess {
+                        display_name: profile.display_name.clone(),
+                    },
+                )));
+                self.state = ConnectionState::Configuration;
+                self.configure(out);
+                let request = self.request.take().expect("the login was handled");
+                self.admitted = Some(AdmittedClient {
+                    profile,
+                    session_certificate: request.session_certificate,
+                    palette_mode: self.palette_mode,
+                    frame_config: FrameConfig::negotiated(&self.compression, self.dictionary()),
+                });
+            }
+            (
+                ConnectionState::Configuration,
+                Some(State::Configuration(PacketConfigurationClient {
+                    payload: Some(packet_configuration_client::Payload::Acknowledged(_)),
+                })),
+            ) => {
+                self.state = ConnectionState::Play;
+            }
+            (state, _) => return Err(HandshakeError::UnexpectedPacket(state)),
+        }
+        Ok(())
+    }
+
+    fn hello(&mut self, hello: Hello) -> Result<(), HandshakeError> {
+        let version = hello.protocol_version.clone().unwrap_or_default();
+        if !is_compatible(&version) {
+            return Err(HandshakeError::refuse(
+                DisconnectReason::IncompatibleVersion,
+                format!(
+                    "the client version {}.{}.{} is not compatible with {}.{}.{}",
+                    version.major,
+                    version.minor,
+                    version.patch,
+                    PROTOCOL_VERSION.major,
+                    PROTOCOL_VERSION.minor,
+                    PROTOCOL_VERSION.patch
+                ),
+            ));
+        }
+        self.palette_mode = hello
+            .palette_modes()
+            .map(PaletteMode::from)
+            .find(|mode| self.config.palette_modes.contains(mode))
+            .ok_or_else(|| {
+                HandshakeError::refuse(
+                    DisconnectReason::IncompatibleVersion,
+                    "no palette mode is supported by both sides",
+                )
+            })?;
+        self.compression = negotiate(&self.config.compression, &hello.compression);
+        Ok(())
+    }
+
+    /// the dictionary is only used if zstd was negotiated
+    fn dictionary(&self) -> Option<CompressionDictionary> {
+        self.compression
+            .iter()
+            .any(|compression| compression.algorithm == CompressionAlgorithm::CompressionZstd)
+            .then(|| self.config.compression_dictionary.clone())
+            .flatten()
+    }
+
+    fn login(&mut self, start: LoginStart) -> Result<PlayerProfile, HandshakeError> {
+        let name_length = start.display_name.chars().count();
+        if name_length == 0
+            || name_length > MAX_DISPLAY_NAME_LENGTH
+            || start.display_name.chars().any(char::is_control)
+        {
+            return Err(HandshakeError::refuse(
+                DisconnectReason::InvalidSession,
+                "invalid display name",
+            ));
+        }
+        validate_certificate(
+            &start.session_certificate,
+            &self.login_challenge,
+            &start.challenge_signature,
+        )?;
+
+        let request = LoginRequest {
+            display_name: start.display_name,
+            session_certificate: start.session_certificate,
+        };
+        let profile =
+            (self.config.admission)(&request).map_err(|reason| HandshakeError::Disconnected {
+                reason,
+                message: None,
+            })?;
+        self.request = Some(request);
+        Ok(profile)
+    }
+
+    fn configure(&self, out: &mut Vec<PacketServer>) {
+        for (key, snapshot) in &self.config.registries {
+            out.push(configuration_packet(
+                packet_configuration_server::Payload::Registry((key, snapshot).into()),
+            ));
+        }
+        out.push(configuration_packet(
+            packet_configuration_server::Payload::Finish(FinishConfiguration {
+                palette_mode: chunk::PaletteMode::from(self.palette_mode) as i32,
+                compression_dictionary: self
+                    .dictionary()
+                    .map(|dictionary| dictionary.as_bytes().to_vec()),
+            }),
+        ));
+    }
+
+    /// handles the packets of the transport until play is reached, the play packets received afterwards are pushed to [play]
+    ///
+    /// the transport is closed on errors
+    pub fn poll<T, DatagramOut, DatagramIn, E>(
+        &mut self,
+        transport: &mut T,
+        play: &mut Vec<PacketPlayClient>,
+    ) -> error_stack::Result<(), HandshakeError>
+    where
+        T: TransportLayer<PacketServer, PacketClient, DatagramOut, DatagramIn, E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut received = Vec::new();
+        transport
+            .drain(&mut received)
+            .change_context(HandshakeError::Transport)?;
+        let mut out = Vec::new();
+        for container in received {
+            //datagrams are not used before play
+            let TransportContainer::Packet(packet) = container else {
+                continue;
+            };
+            if self.state == ConnectionState::Play {
+                match packet.state {
+                    Some(packet_client::State::Play(packet)) => play.push(packet),
+                    _ => {
+                        transport.close_with(DisconnectReason::ProtocolViolation);
+                        return Err(Report::new(HandshakeError::UnexpectedPacket(self.state)));
+                    }
+                }
+                continue;
+            }
+            let result = self.handle(packet, &mut out);
+            for packet in out.drain(..) {
+                transport
+                    .send_packet(packet)
+                    .change_context(HandshakeError::Transport)?;
+            }
+            if let Err(error) = result {
+                transport.close_with(error.reason());
+                return Err(Report::new(error));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// what the client received during the handshake
+#[derive(Debug, Clone, Resource)]
+pub struct ClientConfiguration {
+    pub profile: PlayerProfile,
+    pub registries: Vec<(ResourceKey, RegistrySnapshot)>,
+    pub palette_mode: PaletteMode,
+    /// the config of the transport after the handshake
+    pub frame_config: FrameConfig,
+}
+
+/// the client side of a connection before play
+pub struct ClientHandshake {
+    state: ConnectionState,
+    palette_modes: Vec<PaletteMode>,
+    compression: Vec<Compression>,
+    request: LoginRequest,
+    session_key: PrivatePkcs8KeyDer<'static>,
+    profile: Option<PlayerProfile>,
+    registries: Vec<(ResourceKey, RegistrySnapshot)>,
+    configuration: Option<ClientConfiguration>,
+}
+
+impl ClientHandshake {
+    /// [session_key] is the ed25519 key of the session certificate, [palette_modes] are the supported modes ordered
+    /// by preference
+    pub fn new(
+        display_name: impl Into<String>,
+        session_certificate: CertificateDer<'_>,
+        session_key: PrivatePkcs8KeyDer<'_>,
+        palette_modes: Vec<PaletteMode>,
+    ) -> Self {
+        Self {
+            state: ConnectionState::Handshake,
+            palette_modes,
+            compression: Compression::supported(),
+            request: LoginRequest {
+                display_name: display_name.into(),
+                session_certificate: session_certificate.to_vec(),
+            },
+            session_key: session_key.clone_key(),
+            profile: None,
+            registries: Vec::new(),
+            configuration: None,
+        }
+    }
+
+    /// the supported compressions ordered by preference, [Compression::supported] by default
+    pub fn with_compression(mut self, compression: Vec<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// [Some] once the connection reached play
+    pub fn configuration(&self) -> Option<&ClientConfiguration> {
+        self.configuration.as_ref()
+    }
+
+    /// the first packets of the connection
+    pub fn start(&self, out: &mut Vec<PacketClient>) {
+        out.push(PacketClient {
+            state: Some(packet_client::State::Handshake(PacketHandshakeClient {
+                payload: Some(packet_handshake_client::Payload::Hello(Hello {
+                    protocol_version: Some(PROTOCOL_VERSION),
+                    palette_modes: self
+                        .palette_modes
+                        .iter()
+                        .map(|mode| chunk::PaletteMode::from(*mode) as i32)
+                        .collect(),
+                    compression: self.compression.iter().copied().map(Into::into).collect(),
+                })),
+            })),
+        });
+    }
+
+    /// handles a packet of the server, the responses are pushed to [out]
+    ///
+    /// after an error the connection has to be closed with [HandshakeError::reason]
+    pub fn handle(
+        &mut self,
+        packet: PacketServer,
+        out: &mut Vec<PacketClient>,
+    ) -> Result<(), HandshakeError> {
+        let result = self.handle_state(packet, out);
+        if let Err(error) = &result {
+            self.state = ConnectionState::Closed(error.reason());
+        }
+        result
+    }
+
+    fn handle_state(
+        &mut self,
+        packet: PacketServer,
+        out: &mut Vec<PacketClient>,
+    ) -> Result<(), HandshakeError> {
+        use packet_server::State;
+
+        let disconnect = match (self.state, packet.state) {
+            (
+                ConnectionState::Handshake,
+                Some(State::Handshake(PacketHandshakeServer { payload })),
+            ) => match payload {
+                Some(packet_handshake_server::Payload::Accepted(accepted)) => {
+                    if !is_compatible(&accepted.protocol_version.unwrap_or_default()) {
+                        return Err(HandshakeError::refuse(
+                            DisconnectReason::IncompatibleVersion,
+                            "the server version is not compatible",
+                        ));
+                    }
+                    let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(
+                        self.session_key.secret_pkcs8_der(),
+                    )
+                    .map_err(|_| {
+                        HandshakeError::refuse(
+                            DisconnectReason::InvalidSession,
+                            "the session key is not an ed25519 key",
+                        )
+                    })?;
+                    let signature = key.sign(&challenge_message(&accepted.login_challenge));
+                    out.push(PacketClient {
+                        state: Some(packet_client::State::Login(PacketLoginClient {
+                            payload: Some(packet_login_client::Payload::Start(LoginStart {
+                                display_name: self.request.display_name.clone(),
+                                session_certificate: self.request.session_certificate.clone(),
+                                challenge_signature: signature.as_ref().to_vec(),
+                            })),
+                        })),
+                    });
+                    self.compression = negotiate(&self.compression, &accepted.compression);
+                    self.state = ConnectionState::Login;
+                    return Ok(());
+                }
+                Some(packet_handshake_server::Payload::Disconnect(disconnect)) => disconnect,
+                None => return Err(HandshakeError::UnexpectedPacket(self.state)),
+            },
+            (ConnectionState::Login, Some(State::Login(PacketLoginServer { payload }))) => {
+                match payload {
+                    Some(packet_login_server::Payload::Success(success)) => {
+                        self.profile = Some(PlayerProfile {
+                            display_name: success.display_name,
+                        });
+                        self.state = ConnectionState::Configuration;
+                        return Ok(());
+                    }
+                    Some(packet_login_server::Payload::Disconnect(disconnect)) => disconnect,
+                    None => return Err(HandshakeError::UnexpectedPacket(self.state)),
+                }
+            }
+            (
+                ConnectionState::Configuration,
+                Some(State::Configuration(PacketConfigurationServer { payload })),
+            ) => match payload {
+                Some(packet_configuration_server::Payload::Registry(snapshot)) => {
+                    self.registries.push(snapshot.try_into()?);
+                    return Ok(());
+                }
+                Some(packet_configuration_server::Payload::Finish(finish)) => {
+                    let palette_mode = PaletteMode::from(finish.palette_mode());
+                    if !self.palette_modes.contains(&palette_mode) {
+                        return Err(HandshakeError::UnexpectedPacket(self.state));
+                    }
+                    out.push(PacketClient {
+                        state: Some(packet_client::State::Configuration(
+                            PacketConfigurationClient {
+                                payload: Some(packet_configuration_client::Payload::Acknowledged(
+                                    ConfigurationAcknowledged {},
+                                )),
+                            },
+                        )),
+                    });
+                    self.configuration = Some(ClientConfiguration {
+                        profile: self.profile.take().expect("the login succeeded"),
+                        registries: std::mem::take(&mut self.registries),
+                        palette_mode,
+                        frame_config: FrameConfig::negotiated(
+                            &self.compression,
+                            finish
+                                .compression_dictionary
+                                .map(CompressionDictionary::new),
+                        ),
+                    });
+                    self.state = ConnectionState::Play;
+                    return Ok(());
+                }
+                Some(packet_configuration_server::Payload::Disconnect(disconnect)) => disconnect,
+                None => return Err(HandshakeError::UnexpectedPacket(self.state)),
+            },
+            (state, _) => return Err(HandshakeError::UnexpectedPacket(state)),
+        };
+        Err(HandshakeError::Disconnected {
+            reason: disconnect.reason(),
+            message: disconnect.message,
+        })
+    }
+
+    /// sends the first packets, call it once before [ClientHandshake::poll]
+    pub fn start_transport<T, DatagramOut, DatagramIn, E>(
+        &self,
+        transport: &T,
+    ) -> error_stack::Result<(), HandshakeError>
+    where
+        T: TransportLayer<PacketClient, PacketServer, DatagramOut, DatagramIn, E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut out = Vec::new();
+        self.start(&mut out);
+        for packet in out {
+            transport
+                .send_packet(packet)
+                .change_context(HandshakeError::Transport)?;
+        }
+        Ok(())
+    }
+
+    /// handles the packets of the transport until play is reached, the play packets received afterwards are pushed to [play]
+    ///
+    /// the transport is closed on errors
+    pub fn poll<T, DatagramOut, DatagramIn, E>(
+        &mut self,
+        transport: &mut T,
+        play: &mut Vec<PacketPlayServer>,
+    ) -> error_stack::Result<(), HandshakeError>
+    where
+        T: TransportLayer<PacketClient, PacketServer, DatagramOut, DatagramIn, E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut received = Vec::new();
+        transport
+            .drain(&mut received)
+            .change_context(HandshakeError::Transport)?;
+        let mut out = Vec::new();
+        for container in received {
+            let TransportContainer::Packet(packet) = container else {
+                continue;
+            };
+            if self.state == ConnectionState::Play {
+                match packet.state {
+                    Some(packet_server::State::Play(packet)) => play.push(packet),
+                    _ => {
+                        transport.close_with(DisconnectReason::ProtocolViolation);
+                        return Err(Report::new(HandshakeError::UnexpectedPacket(self.state)));
+                    }
+                }
+                continue;
+            }
+            let result = self.handle(packet, &mut out);
+            for packet in out.drain(..) {
+                transport
+                    .send_packet(packet)
+                    .change_context(HandshakeError::Transport)?;
+            }
+            if let Err(error) = result {
+                transport.close_with(error.reason());
+                return Err(Report::new(error));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the session certificate has to be parseable, currently valid and owned by the client, which proves it by
+/// signing the login challenge with the ed25519 key of the certificate
+///
+/// the signature and the issuer are not verified and the subject is not compared with the display name, the
+/// handshake has no trusted session authority to check them against. Servers which authenticate their players
+/// do that in [ServerHandshakeConfig::admission].
+fn validate_certificate(
+    certificate: &[u8],
+    challenge: &[u8],
+    signature: &[u8],
+) -> Result<(), HandshakeError> {
+    let refuse = |message| HandshakeError::refuse(DisconnectReason::InvalidSession, message);
+    let (_, certificate) = X509Certificate::from_der(certificate)
+        .map_err(|_| refuse("invalid session certificate"))?;
+    if !certificate.validity().is_valid() {
+        return Err(refuse("the session certificate is expired"));
+    }
+    let key = certificate.public_key();
+    if key.algorithm.algorithm != OID_SIG_ED25519 {
+        return Err(refuse("the session certificate has no ed25519 key"));
+    }
+    UnparsedPublicKey::new(&ED25519, &key.subject_public_key.data)
+        .verify(&challenge_message(challenge), signature)
+        .map_err(|_| refuse("the login challenge was not signed with the session key"))
+}
+
+/// the message signed to answer the login [challenge]
+fn challenge_message(challenge: &[u8]) -> Vec<u8> {
+    [LOGIN_CHALLENGE_CONTEXT, challenge].concat()
+}
+
+/// the disconnect packet of the state, [None] if the state has none
+fn disconnect_packet(
+    state: ConnectionState,
+    reason: DisconnectReason,
+    message: Option<String>,
+) -> Option<PacketServer> {
+    let disconnect = proto_common::Disconnect {
+        reason: reason as i32,
+        message,
+    };
+    match state {
+        ConnectionState::Handshake => Some(handshake_packet(
+            packet_handshake_server::Payload::Disconnect(disconnect),
+        )),
+        ConnectionState::Login => Some(login_packet(packet_login_server::Payload::Disconnect(
+            disconnect,
+        ))),
+        ConnectionState::Configuration => Some(configuration_packet(
+            packet_configuration_server::Payload::Disconnect(disconnect),
+        )),
+        ConnectionState::Play | ConnectionState::Closed(_) => None,
+    }
+}
+
+fn handshake_packet(payload: packet_handshake_server::Payload) -> PacketServer {
+    PacketServer {
+        state: Some(packet_server::State::Handshake(PacketHandshakeServer {
+            payload: Some(payload),
+        })),
+    }
+}
+
+fn login_packet(payload: packet_login_server::Payload) -> PacketServer {
+    PacketServer {
+        state: Some(packet_server::State::Login(PacketLoginServer {
+            payload: Some(payload),
+        })),
+    }
+}
+
+fn configuration_packet(payload: packet_configuration_server::Payload) -> PacketServer {
+    PacketServer {
+        state: Some(packet_server::State::Configuration(
+            PacketConfigurationServer {
+                payload: Some(payload),
+            },
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rcgen::{CertificateParams, KeyPair, PKCS_ED25519};
+
+    use crate::transport::local::LocalTransportLayer;
+
+    use super::*;
+
+    type ClientTransport = LocalTransportLayer<PacketClient, PacketServer, (), ()>;
+    type ServerTransport = LocalTransportLayer<PacketServer, PacketClient, (), ()>;
+
+    fn certificate() -> (CertificateDer<'static>, PrivatePkcs8KeyDer<'static>) {
+        let key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let certificate = CertificateParams::new(vec![])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        (certificate.der().clone(), key.serialize_der().into())
+    }
+
+    /// runs both sides until neither makes progress, returns the errors
+    fn run(
+        client: &mut ClientHandshake,
+        server: &mut ServerHandshake,
+    ) -> (
+        Option<Report<HandshakeError>>,
+        Option<Report<HandshakeError>>,
+        ClientTransport,
+    ) {
+        let (mut client_transport, mut server_transport): (ClientTransport, ServerTransport) =
+            LocalTransportLayer::pair();
+        client.start_transport(&client_transport).unwrap();
+        let (mut client_error, mut server_error) = (None, None);
+        for _ in 0..8 {
+            if server_error.is_none() {
+                server_error = server.poll(&mut server_transport, &mut Vec::new()).err();
+            }
+            if client_error.is_none() {
+                client_error = client.poll(&mut client_transport, &mut Vec::new()).err();
+            }
+        }
+        (client_error, server_error, client_transport)
+    }
+
+    #[test]
+    fn admitted() {
+        let registry = common::resource_key!("blocks");
+        let snapshot = RegistrySnapshot {
+            entries: vec![(1, common::resource_key!("dirt"))],
+        };
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig {
+            palette_modes: vec![PaletteMode::Local],
+            registries: vec![(registry.clone(), snapshot.clone())],
+            admission: Arc::new(|request| {
+                Ok(PlayerProfile {
+                    display_name: format!("{}_1", request.display_name),
+                })
+            }),
+            compression: vec![Compression::LZ4, Compression::ZSTD],
+            compression_dictionary: Some(CompressionDictionary::new(vec![1, 2, 3])),
+        }));
+        let (certificate, key) = certificate();
+        let mut client = ClientHandshake::new(
+            "steve",
+            certificate.clone(),
+            key,
+            vec![PaletteMode::Global, PaletteMode::Local],
+        )
+        .with_compression(vec![
+            Compression::BROTLI,
+            Compression::new(CompressionAlgorithm::CompressionZstd, 1),
+            Compression::LZ4,
+        ]);
+
+        let (client_error, server_error, transport) = run(&mut client, &mut server);
+        assert!(client_error.is_none() && server_error.is_none());
+        assert!(!transport.is_closed());
+        assert_eq!(server.state(), ConnectionState::Play);
+        assert_eq!(client.state(), ConnectionState::Play);
+
+        let configuration = client.configuration().unwrap();
+        assert_eq!(configuration.profile.display_name, "steve_1");
+        assert_eq!(configuration.palette_mode, PaletteMode::Local);
+        assert_eq!(configuration.registries, vec![(registry, snapshot)]);
+        let admitted = server.admitted().unwrap();
+        assert_eq!(admitted.profile.display_name, "steve_1");
+        assert_eq!(admitted.session_certificate, certificate.to_vec());
+
+        //brotli is only supported by the client and the lower zstd level wins
+        assert_eq!(configuration.frame_config, admitted.frame_config);
+        assert_eq!(configuration.frame_config.compression, Compression::LZ4);
+        assert_eq!(
+            configuration.frame_config.bulk_compression,
+            Compression::new(CompressionAlgorithm::CompressionZstd, 1)
+        );
+        assert_eq!(
+            configuration.frame_config.dictionary,
+            Some(CompressionDictionary::new(vec![1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn refused() {
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig {
+            admission: Arc::new(|_| Err(DisconnectReason::ServerFull)),
+            ..Default::default()
+        }));
+        let (certificate, key) = certificate();
+        let mut client = ClientHandshake::new("steve", certificate, key, vec![PaletteMode::Local]);
+
+        let (client_error, server_error, transport) = run(&mut client, &mut server);
+        assert_eq!(
+            server_error.unwrap().current_context().reason(),
+            DisconnectReason::ServerFull
+        );
+        assert!(matches!(
+            client_error.unwrap().current_context(),
+            HandshakeError::Disconnected {
+                reason: DisconnectReason::ServerFull,
+                ..
+            }
+        ));
+        assert!(transport.is_closed());
+        assert_eq!(
+            client.state(),
+            ConnectionState::Closed(DisconnectReason::ServerFull)
+        );
+        assert!(server.admitted().is_none());
+    }
+
+    #[test]
+    fn incompatible() {
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        let mut out = Vec::new();
+        let hello = |major| PacketClient {
+            state: Some(packet_client::State::Handshake(PacketHandshakeClient {
+                payload: Some(packet_handshake_client::Payload::Hello(Hello {
+                    protocol_version: Some(SemanticVersion {
+                        major,
+                        ..PROTOCOL_VERSION
+                    }),
+                    palette_modes: vec![chunk::PaletteMode::Local as i32],
+                    compression: vec![],
+                })),
+            })),
+        };
+        let error = server.handle(hello(7), &mut out).unwrap_err();
+        assert_eq!(error.reason(), DisconnectReason::IncompatibleVersion);
+        assert!(matches!(
+            &out[..],
+            [PacketServer {
+                state: Some(packet_server::State::Handshake(PacketHandshakeServer {
+                    payload: Some(packet_handshake_server::Payload::Disconnect(_))
+                }))
+            }]
+        ));
+
+        //packets of a later state are not allowed
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        let login = PacketClient {
+            state: Some(packet_client::State::Login(PacketLoginClient {
+                payload: Some(packet_login_client::Payload::Start(LoginStart {
+                    display_name: "steve".to_string(),
+                    session_certificate: certificate().0.to_vec(),
+                    challenge_signature: vec![],
+                })),
+            })),
+        };
+        let error = server.handle(login, &mut out).unwrap_err();
+        assert_eq!(error.reason(), DisconnectReason::ProtocolViolation);
+
+        //invalid certificates are refused
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        server
+            .handle(hello(PROTOCOL_VERSION.major), &mut out)
+            .unwrap();
+        let login = PacketClient {
+            state: Some(packet_client::State::Login(PacketLoginClient {
+                payload: Some(packet_login_client::Payload::Start(LoginStart {
+                    display_name: "steve".to_string(),
+                    session_certificate: vec![1, 2, 3],
+                    challenge_signature: vec![],
+                })),
+            })),
+        };
+        let error = server.handle(login, &mut out).unwrap_err();
+        assert_eq!(error.reason(), DisconnectReason::InvalidSession);
+    }
+
+    #[test]
+    fn forged_certificate() {
+        //the certificate of another player without its key
+        let (certificate, _) = certificate();
+        let (_, key) = self::certificate();
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        let mut client = ClientHandshake::new("steve", certificate, key, vec![PaletteMode::Local]);
+        let (_, server_error, transport) = run(&mut client, &mut server);
+        assert_eq!(
+            server_error.unwrap().current_context().reason(),
+            DisconnectReason::InvalidSession
+        );
+        assert!(transport.is_closed());
+        assert!(server.admitted().is_none());
+
+        //a login replayed on another connection, whose challenge differs
+        let (certificate, key) = self::certificate();
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        let mut client = ClientHandshake::new("steve", certificate, key, vec![PaletteMode::Local]);
+        let mut client_out = Vec::new();
+        let mut server_out = Vec::new();
+        client.start(&mut client_out);
+        server
+            .handle(client_out.remove(0), &mut server_out)
+            .unwrap();
+        client
+            .handle(server_out.remove(0), &mut client_out)
+            .unwrap();
+        let login = client_out.remove(0);
+        server.handle(login.clone(), &mut server_out).unwrap();
+        assert!(server.admitted().is_some());
+
+        let mut server = ServerHandshake::new(Arc::new(ServerHandshakeConfig::default()));
+        client.start(&mut client_out);
+        server
+            .handle(client_out.remove(0), &mut server_out)
+            .unwrap();
+        let error = server.handle(login, &mut server_out).unwrap_err();
+        assert_eq!(error.reason(), DisconnectReason::InvalidSession);
+        assert!(server.admitted().is_none());
+    }
+}
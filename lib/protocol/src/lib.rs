@@ -5,12 +5,16 @@ use bytes::Bytes;
 use rustls::client::danger::ServerCertVerifier;
 use tonic::codegen::Body;
 
-mod client;
+pub mod capture;
+pub mod chunks;
+pub mod client;
+mod convert;
 mod crypto;
-mod encoding;
+pub mod encoding;
+pub mod handshake;
 pub mod proto;
-mod server;
-mod transport;
+pub mod server;
+pub mod transport;
 
 const DEFAULT_PORT: u16 = 26381;
 const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
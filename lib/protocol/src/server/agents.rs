@@ -1,14 +1,190 @@
-use bevy::prelude::{Entity, Resource};
+use std::fmt::{Display, Formatter};
+
+use bevy::ecs::system::Command;
+use bevy::prelude::{Component, Entity, Event, Resource, World};
 use bevy_replicon::prelude::ClientId;
+use common::palette::PaletteMode;
 use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::chunks::ChunkStreamer;
+use crate::server::{PlayerProfile, ServerChunks, ServerConnection};
+
+/// what happens to the agent of a client when it disconnects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub enum AgentPersistence {
+    #[default]
+    Despawn,
+    /// the entity keeps its components without the connection and is reused when a client with the same
+    /// [PlayerIdentity] logs in again
+    Persist,
+}
+
+/// identifies the player of a client across logins, the display name is chosen by the player and not unique
+///
+/// it is the SHA-256 fingerprint of the session certificate, so a player keeps it as long as the certificate is
+/// valid. The handshake only admits clients which own the key of their certificate, so the identity of a persisted
+/// agent can not be taken over with a copy of the certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct PlayerIdentity([u8; 32]);
+
+impl PlayerIdentity {
+    /// the identity of the DER encoded session certificate
+    pub fn of_certificate(certificate: &[u8]) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, certificate);
+        Self(
+            digest
+                .as_ref()
+                .try_into()
+                .expect("a SHA-256 digest has 32 bytes"),
+        )
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
 
+impl Display for PlayerIdentity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+/// a client finished the login, broadcast to every client
+#[derive(Debug, Clone, PartialEq, Eq, Event, Serialize, Deserialize)]
+pub struct PlayerJoined {
+    pub client_id: ClientId,
+    pub display_name: String,
+}
+
+/// a client disconnected, broadcast to every client
+#[derive(Debug, Clone, PartialEq, Eq, Event, Serialize, Deserialize)]
+pub struct PlayerLeft {
+    pub client_id: ClientId,
+    pub display_name: String,
+}
+
+struct Agent {
+    entity: Entity,
+    display_name: String,
+    identity: PlayerIdentity,
+}
+
+/// maps the connected clients to their agent entities and back
 #[derive(Default, Resource)]
 pub struct ClientAgentMap {
-    agents: HashMap<ClientId, Entity>,
+    agents: HashMap<ClientId, Agent>,
+    clients: HashMap<Entity, ClientId>,
+    /// the agents of disconnected clients by their identity
+    persisted: HashMap<PlayerIdentity, Entity>,
+    /// ids are never reused, [ClientId::SERVER] is 0
+    next_id: u64,
 }
 
 impl ClientAgentMap {
     pub fn get(&self, client_id: ClientId) -> Option<Entity> {
-        self.agents.get(&client_id).copied()
+        self.agents.get(&client_id).map(|agent| agent.entity)
+    }
+
+    pub fn client_id(&self, entity: Entity) -> Option<ClientId> {
+        self.clients.get(&entity).copied()
+    }
+
+    /// the agent of a disconnected client, see [AgentPersistence::Persist]
+    pub fn persisted(&self, identity: &PlayerIdentity) -> Option<Entity> {
+        self.persisted.get(identity).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, Entity)> + '_ {
+        self.agents
+            .iter()
+            .map(|(&client_id, agent)| (client_id, agent.entity))
+    }
+
+    /// assigns a new id to the agent
+    pub(crate) fn connect(
+        &mut self,
+        entity: Entity,
+        profile: &PlayerProfile,
+        identity: PlayerIdentity,
+    ) -> ClientId {
+        if let Some(client_id) = self.client_id(entity) {
+            return client_id;
+        }
+        self.next_id += 1;
+        let client_id = ClientId::new(self.next_id);
+        self.agents.insert(
+            client_id,
+            Agent {
+                entity,
+                display_name: profile.display_name.clone(),
+                identity,
+            },
+        );
+        self.clients.insert(entity, client_id);
+        client_id
+    }
+
+    /// unregisters the agent, it is kept for the next login if `persist` is set
+    pub(crate) fn disconnect(&mut self, entity: Entity, persist: bool) -> Option<PlayerLeft> {
+        let client_id = self.clients.remove(&entity)?;
+        let agent = self.agents.remove(&client_id)?;
+        if persist {
+            self.persisted.insert(agent.identity, entity);
+        }
+        Some(PlayerLeft {
+            client_id,
+            display_name: agent.display_name,
+        })
+    }
+}
+
+/// spawns the agent of a client which finished the login, or reuses its persisted agent
+///
+/// the [ServerProtocolPlugin](crate::server::ServerProtocolPlugin) assigns the [ClientId] in the next update.
+/// The agent gets a new [ChunkStreamer], so a client logging in again receives every chunk in view.
+pub struct SpawnAgent<T> {
+    pub transport: T,
+    pub profile: PlayerProfile,
+    pub identity: PlayerIdentity,
+    /// the palette mode negotiated in the handshake
+    pub palette_mode: PaletteMode,
+}
+
+impl<T: Send + Sync + 'static> Command for SpawnAgent<T> {
+    fn apply(self, world: &mut World) {
+        let persisted = world
+            .resource_mut::<ClientAgentMap>()
+            .persisted
+            .remove(&self.identity)
+            .filter(|&entity| world.get_entity(entity).is_some());
+
+        let config = world
+            .get_resource::<ServerChunks>()
+            .map(|chunks| *chunks.config())
+            .unwrap_or_default();
+        let components = (
+            ServerConnection::new(self.transport),
+            self.profile,
+            self.identity,
+            ChunkStreamer::new(config, self.palette_mode),
+        );
+        match persisted {
+            Some(entity) => {
+                world.entity_mut(entity).insert(components);
+            }
+            None => {
+                world.spawn(components);
+            }
+        }
     }
 }
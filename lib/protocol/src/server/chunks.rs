@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use common::coordinate::{ChunkPos, LocalPos};
+use common::palette::{ChunkStorage, GlobalPalette, StateId};
+use hashbrown::HashMap;
+
+use crate::chunks::{ChunkStreamConfig, ChunkStreamer};
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::server::ServerConnection;
+
+/// the chunk the view of an agent is centered on, set by the game
+///
+/// the [ChunkStreamer] of an agent only sends chunks once it has a view center
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct ViewCenter(pub ChunkPos);
+
+/// the chunks streamed to the clients
+///
+/// the changes made with [ServerChunks::insert], [ServerChunks::remove] and [ServerChunks::set_block] are sent to
+/// every client which loaded the chunk already, the others receive them with the chunk
+#[derive(Default, Resource)]
+pub struct ServerChunks {
+    config: ChunkStreamConfig,
+    chunks: HashMap<ChunkPos, ChunkStorage>,
+    changes: Vec<ChunkChange>,
+}
+
+enum ChunkChange {
+    /// the whole chunk is sent again
+    Replaced(ChunkPos),
+    /// the chunk is unloaded until it is inserted again
+    Removed(ChunkPos),
+    Block(ChunkPos, LocalPos, StateId),
+}
+
+impl ServerChunks {
+    pub fn new(config: ChunkStreamConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &ChunkStreamConfig {
+        &self.config
+    }
+
+    /// used for the agents joining afterwards
+    pub fn set_config(&mut self, config: ChunkStreamConfig) {
+        self.config = config;
+    }
+
+    pub fn get(&self, position: &ChunkPos) -> Option<&ChunkStorage> {
+        self.chunks.get(position)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ChunkPos, &ChunkStorage)> {
+        self.chunks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// adds a chunk, the states have to be interned in the [GlobalPalette]
+    pub fn insert(&mut self, position: ChunkPos, storage: ChunkStorage) {
+        self.chunks.insert(position, storage);
+        self.changes.push(ChunkChange::Replaced(position));
+    }
+
+    /// the clients which loaded the chunk unload it
+    pub fn remove(&mut self, position: &ChunkPos) -> Option<ChunkStorage> {
+        let storage = self.chunks.remove(position)?;
+        self.changes.push(ChunkChange::Removed(*position));
+        Some(storage)
+    }
+
+    /// sets a block of a chunk, false if the chunk does not exist
+    pub fn set_block(&mut self, position: ChunkPos, local: LocalPos, state: StateId) -> bool {
+        let Some(storage) = self.chunks.get_mut(&position) else {
+            return false;
+        };
+        let (x, y, z) = local.xyz();
+        storage.set_xyz(x, y, z, state);
+        self.changes
+            .push(ChunkChange::Block(position, local, state));
+        true
+    }
+}
+
+/// queues the chunks in view and the changes of the [ServerChunks] for every agent with a [ViewCenter]
+pub(crate) fn stream_chunks<T: Send + Sync + 'static>(
+    mut chunks: ResMut<ServerChunks>,
+    palette: Option<Res<GlobalPalette>>,
+    mut agents: Query<(&mut ServerConnection<T>, &mut ChunkStreamer, &ViewCenter)>,
+    mut out: Local<Vec<PacketPlayServer>>,
+) {
+    let changes = std::mem::take(&mut chunks.changes);
+    //nothing can be encoded without the palette
+    let Some(palette) = palette else {
+        return;
+    };
+    let chunks = &chunks.chunks;
+    for (mut connection, mut streamer, view) in &mut agents {
+        streamer.set_center(view.0, &mut out);
+        for change in &changes {
+            match *change {
+                ChunkChange::Replaced(position) => {
+                    let Some(storage) = chunks.get(&position) else {
+                        continue;
+                    };
+                    streamer.resend(position, storage, &palette, &mut out);
+                }
+                ChunkChange::Removed(position) => streamer.unload(position, &mut out),
+                ChunkChange::Block(position, local, state) => {
+                    streamer.block_change(position, local, state, &palette, &mut out)
+                }
+            }
+        }
+        streamer.poll(&palette, |position| chunks.get(position), &mut out);
+        for packet in out.drain(..) {
+            connection.send_packet(packet);
+        }
+    }
+}
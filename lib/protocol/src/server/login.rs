@@ -0,0 +1,391 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy_replicon::prelude::*;
+
+use common::palette::{GlobalPalette, PaletteMode};
+use common::{TaskContainer, TaskPoll};
+use prost::Message;
+
+use crate::capture::{CaptureDirectory, CaptureTransportLayer, ServerCapture};
+use crate::encoding::{CompressionDictionary, FrameConfig, FrameError};
+use crate::handshake::{ConnectionState, ServerHandshake, ServerHandshakeConfig};
+use crate::proto::chunk::ChunkData;
+use crate::proto::connection::{PacketClient, PacketServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::server::{PlayerIdentity, ServerChunks, ServerProtocolPlugin, SpawnAgent};
+use crate::transport::play::ServerPlayTransport;
+use crate::transport::TransportLayer;
+
+/// runs the [handshake](crate::handshake) of the connections added with [PendingConnections::accept]
+///
+/// an admitted client joins with a [SpawnAgent] once it reached play, its connection is a [ServerPlayTransport] of the
+/// transport [T] in a [ServerCapture], which is written to the [CaptureDirectory] if it exists.
+/// The [ServerProtocolPlugin] of these connections is added by this plugin.
+///
+/// Without a dictionary in the config one is trained on the first [DICTIONARY_SAMPLES] chunks of the [ServerChunks]
+/// in the background, the connections accepted after the training finished use it.
+pub struct ServerLoginPlugin<T, E> {
+    config: Arc<ServerHandshakeConfig>,
+    _marker: PhantomData<fn() -> (T, E)>,
+}
+
+impl<T, E> ServerLoginPlugin<T, E> {
+    pub fn new(config: ServerHandshakeConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E> Default for ServerLoginPlugin<T, E> {
+    fn default() -> Self {
+        Self::new(ServerHandshakeConfig::default())
+    }
+}
+
+impl<T, E> Plugin for ServerLoginPlugin<T, E>
+where
+    T: TransportLayer<PacketServer, PacketClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ServerProtocolPlugin::<
+            ServerCapture<ServerPlayTransport<T>, E>,
+            E,
+        >::default())
+            .insert_resource(PendingConnections::<T>::new(self.config.clone()))
+            .add_systems(
+                PreUpdate,
+                login_clients::<T, E>.before(ServerSet::ReceivePackets),
+            )
+            .add_systems(Update, train_dictionary::<T>);
+    }
+}
+
+/// the number of chunks the compression dictionary is trained on
+pub const DICTIONARY_SAMPLES: usize = 64;
+/// the maximum size of a trained compression dictionary
+pub const DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// the connections of the clients before play
+#[derive(Resource)]
+pub struct PendingConnections<T> {
+    config: Arc<ServerHandshakeConfig>,
+    /// the connections whose handshake starts in the next update
+    accepted: Vec<T>,
+    connections: Vec<(T, ServerHandshake)>,
+}
+
+impl<T> PendingConnections<T> {
+    pub fn new(config: Arc<ServerHandshakeConfig>) -> Self {
+        Self {
+            config,
+            accepted: Vec::new(),
+            connections: Vec::new(),
+        }
+    }
+
+    /// starts the handshake of a new connection in the next update
+    pub fn accept(&mut self, transport: T) {
+        self.accepted.push(transport);
+    }
+
+    pub fn config(&self) -> &Arc<ServerHandshakeConfig> {
+        &self.config
+    }
+
+    /// used for the connections accepted afterwards, f.e. with new registry snapshots
+    pub fn set_config(&mut self, config: Arc<ServerHandshakeConfig>) {
+        self.config = config;
+    }
+
+    pub fn len(&self) -> usize {
+        self.accepted.len() + self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accepted.is_empty() && self.connections.is_empty()
+    }
+}
+
+fn login_clients<T, E>(
+    mut commands: Commands,
+    mut pending: ResMut<PendingConnections<T>>,
+    capture: Option<Res<CaptureDirectory>>,
+) where
+    T: TransportLayer<PacketServer, PacketClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    let pending = &mut *pending;
+    for mut transport in pending.accepted.drain(..) {
+        //the client may use the dictionary as soon as it received the configuration
+        transport.set_frame_config(FrameConfig {
+            dictionary: pending.config.compression_dictionary.clone(),
+            ..FrameConfig::default()
+        });
+        let handshake = ServerHandshake::new(pending.config.clone());
+        pending.connections.push((transport, handshake));
+    }
+
+    for (mut transport, mut handshake) in std::mem::take(&mut pending.connections) {
+        let admitted = handshake.admitted().is_some();
+        let mut play = Vec::new();
+        if let Err(error) = handshake.poll(&mut transport, &mut play) {
+            debug!("closing a connection before play: {error:?}");
+            transport.close_with(error.current_context().reason());
+            continue;
+        }
+        if transport.is_closed() {
+            continue;
+        }
+        //the configuration was sent with the old framing
+        if let Some(admitted) = handshake.admitted().filter(|_| !admitted) {
+            transport.set_frame_config(admitted.frame_config.clone());
+        }
+        match handshake.admitted() {
+            Some(admitted) if handshake.state() == ConnectionState::Play => {
+                let identity = PlayerIdentity::of_certificate(&admitted.session_certificate);
+                let transport: ServerCapture<_, E> = CaptureTransportLayer::in_directory(
+                    ServerPlayTransport::new(transport, play),
+                    capture.as_deref(),
+                    &identity.to_string(),
+                );
+                commands.add(SpawnAgent {
+                    transport,
+                    profile: admitted.profile.clone(),
+                    identity,
+                    palette_mode: admitted.palette_mode,
+                });
+            }
+            _ => pending.connections.push((transport, handshake)),
+        }
+    }
+}
+
+/// the training of the compression dictionary, it runs on the [AsyncComputeTaskPool]
+#[derive(Default)]
+enum DictionaryTraining {
+    #[default]
+    Waiting,
+    Running(TaskContainer<Result<CompressionDictionary, FrameError>>),
+    /// a failed training is not repeated
+    Done,
+}
+
+/// starts training the compression dictionary once enough chunks exist and applies it in a later update
+fn train_dictionary<T: Send + Sync + 'static>(
+    mut pending: ResMut<PendingConnections<T>>,
+    chunks: Res<ServerChunks>,
+    palette: Option<Res<GlobalPalette>>,
+    mut training: Local<DictionaryTraining>,
+) {
+    match &mut *training {
+        DictionaryTraining::Waiting => {
+            if pending.config.compression_dictionary.is_some() || chunks.len() < DICTIONARY_SAMPLES
+            {
+                return;
+            }
+            let Some(palette) = palette else {
+                return;
+            };
+            let samples: Vec<_> = chunks
+                .iter()
+                .take(DICTIONARY_SAMPLES)
+                .map(|(_, storage)| {
+                    ChunkData::from(&palette.encode(storage, PaletteMode::Global)).encode_to_vec()
+                })
+                .collect();
+            let task = AsyncComputeTaskPool::get()
+                .spawn(async move { CompressionDictionary::train(&samples, DICTIONARY_SIZE) });
+            *training = DictionaryTraining::Running(TaskContainer::new(task));
+        }
+        DictionaryTraining::Running(task) => match task.poll_once() {
+            TaskPoll::Pending => {}
+            TaskPoll::Done(result) => {
+                *training = DictionaryTraining::Done;
+                match result {
+                    Ok(dictionary) => {
+                        let mut config = ServerHandshakeConfig::clone(&pending.config);
+                        config.compression_dictionary = Some(dictionary);
+                        pending.set_config(Arc::new(config));
+                    }
+                    Err(error) => warn!("cannot train the compression dictionary: {error}"),
+                }
+            }
+            TaskPoll::Canceled => *training = DictionaryTraining::Done,
+        },
+        DictionaryTraining::Done => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::MinimalPlugins;
+    use common::coordinate::ChunkPos;
+    use common::palette::ChunkStorage;
+    use common::resource::{BlockData, ResourceKey};
+    use common::CHUNK_SIZE;
+    use rcgen::{CertificateParams, KeyPair, PKCS_ED25519};
+
+    use crate::capture::ClientCapture;
+    use crate::client::{ClientConnection, ClientLogin, ClientLoginPlugin};
+    use crate::handshake::ClientHandshake;
+    use crate::server::{ClientAgentMap, PlayerJoined, PlayerProfile, DICTIONARY_SIZE};
+    use crate::transport::local::{
+        ClientLocalConnection, LocalTransportError, ServerLocalConnection,
+    };
+    use crate::transport::play::ClientPlayTransport;
+
+    use super::*;
+
+    #[test]
+    fn login() {
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerLoginPlugin::<ServerLocalConnection, LocalTransportError>::default(),
+        ));
+        server.finish();
+        server.cleanup();
+        let mut client = App::new();
+        client.add_plugins((
+            MinimalPlugins,
+            ClientLoginPlugin::<ClientLocalConnection, LocalTransportError>::default(),
+        ));
+        client.finish();
+        client.cleanup();
+
+        let key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let certificate = CertificateParams::new(vec![])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let (client_transport, server_transport) = ClientLocalConnection::pair();
+        let handshake = ClientHandshake::new(
+            "steve",
+            certificate.der().clone(),
+            key.serialize_der().into(),
+            vec![PaletteMode::Local],
+        );
+        client.insert_resource(ClientLogin::start(client_transport, handshake).unwrap());
+        server
+            .world
+            .resource_mut::<PendingConnections<ServerLocalConnection>>()
+            .accept(server_transport);
+
+        for _ in 0..4 {
+            server.update();
+            client.update();
+        }
+        assert!(server
+            .world
+            .resource::<PendingConnections<ServerLocalConnection>>()
+            .is_empty());
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        assert_eq!(agent_map.len(), 1);
+        let (_, agent) = agent_map.iter().next().unwrap();
+        assert_eq!(
+            server
+                .world
+                .get::<PlayerProfile>(agent)
+                .unwrap()
+                .display_name,
+            "steve"
+        );
+        assert_eq!(
+            server.world.get::<PlayerIdentity>(agent),
+            Some(&PlayerIdentity::of_certificate(certificate.der()))
+        );
+
+        //the client is connected in play and receives the broadcasts of the server
+        assert!(client
+            .world
+            .get_resource::<ClientLogin<ClientLocalConnection>>()
+            .is_none());
+        assert!(client
+            .world
+            .get_resource::<ClientConnection<
+                ClientCapture<ClientPlayTransport<ClientLocalConnection>, LocalTransportError>,
+            >>()
+            .is_some());
+        assert!(client.world.resource::<RepliconClient>().is_connected());
+        let joined: Vec<_> = client
+            .world
+            .resource_mut::<Events<PlayerJoined>>()
+            .drain()
+            .collect();
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].display_name, "steve");
+    }
+
+    #[test]
+    fn dictionary() {
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerLoginPlugin::<ServerLocalConnection, LocalTransportError>::default(),
+        ));
+        let mut palette = GlobalPalette::new(BlockData::new(ResourceKey::core("air").unwrap()));
+        let states: Vec<_> = ["stone", "dirt", "grass", "sand"]
+            .into_iter()
+            .map(|name| palette.intern(BlockData::new(ResourceKey::core(name).unwrap())))
+            .collect();
+        server.insert_resource(palette);
+        server.finish();
+        server.cleanup();
+
+        //the chunks are layered like a terrain with a few differences
+        let chunk = |index: usize| {
+            let mut storage = ChunkStorage::empty();
+            for (layer, state) in states.iter().enumerate() {
+                let height = layer * 4 + index % 4;
+                storage.fill_box((0, height, 0), (CHUNK_SIZE, height + 4, CHUNK_SIZE), *state);
+            }
+            storage.set_xyz(
+                index % CHUNK_SIZE,
+                CHUNK_SIZE - 1,
+                0,
+                states[index % states.len()],
+            );
+            storage
+        };
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        for index in 0..DICTIONARY_SAMPLES - 1 {
+            chunks.insert(ChunkPos::new(index as i64, 0, 0, 0), chunk(index));
+        }
+        server.update();
+        let pending = server
+            .world
+            .resource::<PendingConnections<ServerLocalConnection>>();
+        assert!(pending.config().compression_dictionary.is_none());
+
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        chunks.insert(ChunkPos::new(-1, 0, 0, 0), chunk(DICTIONARY_SAMPLES));
+        //the training runs in the background and is picked up by a later update
+        let mut dictionary = None;
+        for _ in 0..1000 {
+            server.update();
+            let pending = server
+                .world
+                .resource::<PendingConnections<ServerLocalConnection>>();
+            dictionary = pending.config().compression_dictionary.clone();
+            if dictionary.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(dictionary.unwrap().as_bytes().len() <= DICTIONARY_SIZE);
+    }
+}
@@ -1,21 +1,58 @@
+use std::error::Error;
+use std::marker::PhantomData;
 use std::time::Duration;
 
-use bevy::app::App;
-use bevy::asset::AsyncWriteExt;
 use bevy::prelude::*;
 use bevy_replicon::prelude::*;
 
-use crate::local::ServerLocalConnectionAgent;
+use crate::chunks::ChunkStreamer;
+use crate::proto::common::DisconnectReason;
 use crate::proto::play::client::packet::packet_play_client::Payload as ClientPayload;
 use crate::proto::play::client::packet::PacketPlayClient;
 use crate::proto::play::server::packet::packet_play_server::Payload as ServerPayload;
 use crate::proto::play::server::packet::PacketPlayServer;
+use crate::transport::scheduler::{PacketScheduler, SchedulerConfig};
+use crate::transport::{TransportContainer, TransportLayer};
+
+pub use agents::{
+    AgentPersistence, ClientAgentMap, PlayerIdentity, PlayerJoined, PlayerLeft, SpawnAgent,
+};
+pub use chunks::{ServerChunks, ViewCenter};
+pub use login::{PendingConnections, ServerLoginPlugin, DICTIONARY_SAMPLES, DICTIONARY_SIZE};
 
 mod agents;
+mod chunks;
+mod login;
+
+/// bridges bevy_replicon and the connections of the clients
+///
+/// every entity with a [ServerConnection], a [PlayerProfile] and a [PlayerIdentity] is the agent of a client, it gets a [ClientId] when
+/// the connection is added, see [SpawnAgent]. When the connection is closed the agent is despawned or persisted
+/// according to [AgentPersistence]. [PlayerJoined] and [PlayerLeft] are sent to the server and every client.
+/// Reliable replicon channels are sent as packets and unreliable channels as datagrams.
+///
+/// The [ServerChunks] are streamed to every agent with a [ViewCenter] by its [ChunkStreamer] once a
+/// [GlobalPalette](common::palette::GlobalPalette) exists.
+pub struct ServerProtocolPlugin<T, E> {
+    _marker: PhantomData<fn() -> (T, E)>,
+}
 
-pub struct ServerProtocolPlugin;
+impl<T, E> Default for ServerProtocolPlugin<T, E> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
 
-impl Plugin for ServerProtocolPlugin {
+impl<T, E> Plugin for ServerProtocolPlugin<T, E>
+where
+    T: TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
     fn build(&self, app: &mut App) {
         app.add_plugins(
             RepliconPlugins
@@ -28,21 +65,29 @@ impl Plugin for ServerProtocolPlugin {
                 })
                 .build(),
         );
+        app.init_resource::<ClientAgentMap>()
+            .init_resource::<AgentPersistence>()
+            .init_resource::<ServerChunks>()
+            .add_server_event::<PlayerJoined>(ChannelKind::Ordered)
+            .add_server_event::<PlayerLeft>(ChannelKind::Ordered);
 
-        app.add_systems(
-            Update,
-            (
-                start_server.run_if(resource_added::<ServerLocalConnectionAgent>),
-                stop_server.run_if(resource_removed::<ServerLocalConnectionAgent>()),
-            ),
-        );
+        app.add_systems(Startup, start_server);
         app.add_systems(
             PreUpdate,
-            receive_updates.run_if(resource_exists::<ServerLocalConnectionAgent>),
+            (
+                connect_clients::<T, E>,
+                receive_updates::<T, E>,
+                disconnect_clients::<T, E>,
+            )
+                .chain()
+                .in_set(ServerSet::ReceivePackets),
         );
         app.add_systems(
             PostUpdate,
-            send_updates.run_if(resource_exists::<ServerLocalConnectionAgent>),
+            (
+                chunks::stream_chunks::<T>.before(ServerSet::SendPackets),
+                send_updates::<T, E>.in_set(ServerSet::SendPackets),
+            ),
         );
     }
 }
@@ -52,55 +97,672 @@ pub struct PlayerProfile {
     pub display_name: String,
 }
 
-fn start_server(
-    mut replicon_server: ResMut<RepliconServer>,
-    mut event_writer: EventWriter<ServerEvent>,
-) {
-    event_writer.send(ServerEvent::ClientConnected {
-        client_id: ClientId::SERVER,
-    });
+/// the connection of a client in play
+///
+/// the packets are queued in its [PacketScheduler] and sent in [ServerSet::SendPackets], datagrams are sent at once
+#[derive(Component)]
+pub struct ServerConnection<T> {
+    transport: T,
+    scheduler: PacketScheduler<PacketPlayServer>,
+}
+
+impl<T> ServerConnection<T> {
+    pub fn new(transport: T) -> Self {
+        Self::with_scheduler(transport, SchedulerConfig::default())
+    }
+
+    pub fn with_scheduler(transport: T, config: SchedulerConfig) -> Self {
+        Self {
+            transport,
+            scheduler: PacketScheduler::new(config),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// the packets which were not sent yet
+    pub fn scheduler(&self) -> &PacketScheduler<PacketPlayServer> {
+        &self.scheduler
+    }
+
+    /// queues a packet, it is sent according to its priority
+    pub fn send_packet(&mut self, packet: PacketPlayServer) {
+        self.scheduler.push(packet);
+    }
+}
+
+fn start_server(mut replicon_server: ResMut<RepliconServer>) {
     replicon_server.set_running(true);
 }
 
-fn stop_server(
-    mut replicon_server: ResMut<RepliconServer>,
+fn connect_clients<T, E>(
+    agents: Query<(Entity, &PlayerProfile, &PlayerIdentity), Added<ServerConnection<T>>>,
+    profiles: Query<&PlayerProfile>,
+    mut agent_map: ResMut<ClientAgentMap>,
     mut event_writer: EventWriter<ServerEvent>,
-) {
-    event_writer.send(ServerEvent::ClientDisconnected {
-        client_id: ClientId::SERVER,
-        reason: "shutdown".to_string(),
-    });
-    replicon_server.set_running(false);
+    mut broadcast_writer: EventWriter<ToClients<PlayerJoined>>,
+) where
+    T: TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    if agents.is_empty() {
+        return;
+    }
+    //the players which joined before, the ones joining now receive the broadcasts of each other
+    let online: Vec<_> = agent_map
+        .iter()
+        .filter_map(|(client_id, entity)| {
+            let profile = profiles.get(entity).ok()?;
+            Some(PlayerJoined {
+                client_id,
+                display_name: profile.display_name.clone(),
+            })
+        })
+        .collect();
+
+    for (entity, profile, &identity) in &agents {
+        let client_id = agent_map.connect(entity, profile, identity);
+        event_writer.send(ServerEvent::ClientConnected { client_id });
+
+        //the new client learns about the players already online
+        for joined in &online {
+            broadcast_writer.send(ToClients {
+                mode: SendMode::Direct(client_id),
+                event: joined.clone(),
+            });
+        }
+
+        //replicon also emits broadcasts on the server
+        broadcast_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: PlayerJoined {
+                client_id,
+                display_name: profile.display_name.clone(),
+            },
+        });
+    }
 }
 
-fn send_updates(
-    local_connection_agent: Res<ServerLocalConnectionAgent>,
+fn receive_updates<T, E>(
+    mut connections: Query<(Entity, &mut ServerConnection<T>, Option<&mut ChunkStreamer>)>,
+    agent_map: Res<ClientAgentMap>,
+    channels: Res<RepliconChannels>,
     mut replicon_server: ResMut<RepliconServer>,
-) {
-    for (client_id, channel, message) in replicon_server.drain_sent() {
-        assert_eq!(client_id, ClientId::SERVER);
-        let mut message = message.to_vec();
-        message.splice(0..0, [channel]);
-        local_connection_agent.send(PacketPlayServer {
-            payload: Some(ServerPayload::Sync(message)),
+    mut buffer: Local<Vec<TransportContainer<PacketPlayClient, PacketPlayClient>>>,
+) where
+    T: TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    for (entity, mut connection, mut streamer) in &mut connections {
+        let Some(client_id) = agent_map.client_id(entity) else {
+            continue;
+        };
+        if let Err(error) = connection.transport.drain(&mut buffer) {
+            warn!("closing the connection of client {client_id:?}: {error:?}");
+            connection.transport.close();
+        }
+
+        for container in buffer.drain(..) {
+            let packet = match container {
+                TransportContainer::Packet(packet) => packet,
+                TransportContainer::Datagram(datagram) => datagram,
+            };
+            match packet.payload {
+                Some(ClientPayload::Sync(sync_message)) => {
+                    let Some((&channel, message)) = sync_message.split_first() else {
+                        continue;
+                    };
+                    //replicon panics on unknown channels, the remaining packets are dropped with the client
+                    if channel as usize >= channels.client_channels().len() {
+                        warn!("disconnecting client {client_id:?}: unknown channel {channel}");
+                        connection
+                            .transport
+                            .close_with(DisconnectReason::ProtocolViolation);
+                        break;
+                    }
+                    replicon_server.insert_received(client_id, channel, message.to_vec());
+                }
+                Some(ClientPayload::ChunkAcknowledged(acknowledged)) => {
+                    if let Some(streamer) = streamer.as_mut() {
+                        streamer.acknowledge(acknowledged.sequence);
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+fn disconnect_clients<T, E>(
+    mut commands: Commands,
+    connections: Query<(Entity, &ServerConnection<T>)>,
+    mut removed: RemovedComponents<ServerConnection<T>>,
+    persistence: Res<AgentPersistence>,
+    mut agent_map: ResMut<ClientAgentMap>,
+    mut event_writer: EventWriter<ServerEvent>,
+    mut broadcast_writer: EventWriter<ToClients<PlayerLeft>>,
+) where
+    T: TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    let persist = *persistence == AgentPersistence::Persist;
+    let closed = connections
+        .iter()
+        .filter(|(_, connection)| connection.transport.is_closed())
+        .map(|(entity, _)| (entity, "connection closed"));
+    //the connection was removed by the game, f.e. on a kick, persisted agents may have logged in again since
+    let removed = removed
+        .read()
+        .filter(|&entity| !connections.contains(entity))
+        .map(|entity| (entity, "connection removed"));
+
+    for (entity, reason) in closed.chain(removed) {
+        let Some(left) = agent_map.disconnect(entity, persist) else {
+            continue;
+        };
+        if let Some(mut agent) = commands.get_entity(entity) {
+            if persist {
+                agent.remove::<ServerConnection<T>>();
+            } else {
+                agent.despawn_recursive();
+            }
+        }
+
+        event_writer.send(ServerEvent::ClientDisconnected {
+            client_id: left.client_id,
+            reason: reason.to_string(),
+        });
+        broadcast_writer.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: left,
         });
     }
 }
 
-fn receive_updates(
-    local_connection_agent: Res<ServerLocalConnectionAgent>,
+fn send_updates<T, E>(
+    mut connections: Query<(Entity, &mut ServerConnection<T>)>,
+    agent_map: Res<ClientAgentMap>,
+    channels: Res<RepliconChannels>,
     mut replicon_server: ResMut<RepliconServer>,
-    mut buffer: Local<Vec<PacketPlayClient>>,
-) {
-    local_connection_agent.drain(&mut buffer);
-    for packet in buffer.drain(..) {
-        match packet.payload {
-            Some(ClientPayload::Sync(sync_message)) => {
-                let channel = sync_message[0];
-                let message = sync_message[1..].to_vec();
-                replicon_server.insert_received(ClientId::SERVER, channel, message);
+) where
+    T: TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+        + Send
+        + Sync
+        + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    for (client_id, channel, message) in replicon_server.drain_sent() {
+        let Some((_, mut connection)) = agent_map
+            .get(client_id)
+            .and_then(|entity| connections.get_mut(entity).ok())
+        else {
+            continue;
+        };
+
+        let mut sync_message = Vec::with_capacity(message.len() + 1);
+        sync_message.push(channel);
+        sync_message.extend_from_slice(&message);
+        let packet = PacketPlayServer {
+            payload: Some(ServerPayload::Sync(sync_message)),
+        };
+
+        match channels.server_channels()[channel as usize].kind {
+            ChannelKind::Unreliable => {
+                if let Err(error) = connection.transport.send_datagram(packet) {
+                    warn!("cannot send to client {client_id:?}: {error:?}");
+                }
+            }
+            ChannelKind::Unordered | ChannelKind::Ordered => connection.send_packet(packet),
+        }
+    }
+
+    for (entity, mut connection) in &mut connections {
+        let connection = &mut *connection;
+        if let Err(error) = connection.scheduler.send(&connection.transport) {
+            warn!(
+                "cannot send to client {:?}: {error:?}",
+                agent_map.client_id(entity)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::Command;
+    use bevy::MinimalPlugins;
+    use common::coordinate::{ChunkPos, LocalPos};
+    use common::palette::{ChunkStorage, GlobalPalette, PaletteMode};
+    use common::resource::{BlockData, ResourceKey};
+    use common::CHUNK_SIZE;
+    use mesher::b32::VoxelCubeOcclusionMatrix32;
+
+    use crate::chunks::{spiral, ChunkAssembler, ChunkEvent, ChunkStreamConfig};
+    use crate::client::{ClientConnection, ClientProtocolPlugin};
+    use crate::transport::local::{
+        local_connection, ClientLocalTransport, LocalTransportError, ServerLocalTransport,
+    };
+
+    use super::*;
+
+    #[test]
+    fn multiple_clients() {
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerProtocolPlugin::<ServerLocalTransport, LocalTransportError>::default(),
+        ));
+        let mut channels = server.world.resource_mut::<RepliconChannels>();
+        let reliable = channels.create_server_channel(ChannelKind::Ordered.into());
+        let unreliable = channels.create_server_channel(ChannelKind::Unreliable.into());
+        let incoming = channels.create_client_channel(ChannelKind::Ordered.into());
+        server.finish();
+        server.cleanup();
+
+        fn profile(index: usize) -> PlayerProfile {
+            PlayerProfile {
+                display_name: format!("player{index}"),
             }
-            None => continue,
         }
+
+        fn identity(index: usize) -> PlayerIdentity {
+            PlayerIdentity::of_certificate(&[index as u8])
+        }
+
+        fn sync(channel: u8, message: &[u8]) -> Vec<u8> {
+            [&[channel], message].concat()
+        }
+
+        let mut clients = Vec::new();
+        let mut entities = Vec::new();
+        for index in 0..3 {
+            let (client, transport) = local_connection();
+            let entity = server
+                .world
+                .spawn((
+                    ServerConnection::new(transport),
+                    profile(index),
+                    identity(index),
+                ))
+                .id();
+            entities.push(entity);
+            clients.push(client);
+        }
+        server.update();
+
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        let ids: Vec<_> = entities
+            .iter()
+            .map(|&entity| agent_map.client_id(entity).unwrap())
+            .collect();
+        let connected = server.world.resource::<ConnectedClients>();
+        assert_eq!(connected.len(), 3);
+        for &client_id in &ids {
+            assert_ne!(client_id, ClientId::SERVER);
+            assert!(connected.get_client(client_id).is_some());
+        }
+
+        //every client receives only its own messages, the unreliable channel is sent as datagrams
+        for (index, &client_id) in ids.iter().enumerate() {
+            let mut replicon_server = server.world.resource_mut::<RepliconServer>();
+            replicon_server.send(client_id, reliable, vec![index as u8]);
+            replicon_server.send(client_id, unreliable, vec![index as u8]);
+        }
+        server.update();
+        for (index, client) in clients.iter_mut().enumerate() {
+            let mut received = Vec::new();
+            client.drain(&mut received).unwrap();
+            let received: Vec<_> = received
+                .into_iter()
+                .filter_map(|container| match container {
+                    TransportContainer::Packet(PacketPlayServer {
+                        payload: Some(ServerPayload::Sync(message)),
+                    }) => Some((true, message)),
+                    TransportContainer::Datagram(PacketPlayServer {
+                        payload: Some(ServerPayload::Sync(message)),
+                    }) => Some((false, message)),
+                    _ => None,
+                })
+                .collect();
+            let index = index as u8;
+            assert!(received.contains(&(true, sync(reliable, &[index]))));
+            assert!(received.contains(&(false, sync(unreliable, &[index]))));
+        }
+
+        //the messages go through the scheduler, so they are sent before the queued bulk packets
+        let unload = PacketPlayServer {
+            payload: Some(ServerPayload::ChunkUnload(Default::default())),
+        };
+        server
+            .world
+            .get_mut::<ServerConnection<ServerLocalTransport>>(entities[0])
+            .unwrap()
+            .send_packet(unload.clone());
+        let mut replicon_server = server.world.resource_mut::<RepliconServer>();
+        replicon_server.send(ids[0], reliable, vec![9]);
+        server.update();
+        let mut received = Vec::new();
+        clients[0].drain(&mut received).unwrap();
+        let packets: Vec<_> = received
+            .into_iter()
+            .filter_map(|container| match container {
+                TransportContainer::Packet(packet) => Some(packet),
+                TransportContainer::Datagram(_) => None,
+            })
+            .collect();
+        let message = PacketPlayServer {
+            payload: Some(ServerPayload::Sync(sync(reliable, &[9]))),
+        };
+        assert!(packets.contains(&message));
+        assert_eq!(packets.last(), Some(&unload));
+
+        //messages of the clients are received with their id
+        clients[2]
+            .send_packet(PacketPlayClient {
+                payload: Some(ClientPayload::Sync(sync(incoming, &[7]))),
+            })
+            .unwrap();
+        clients[2]
+            .send_packet(PacketPlayClient {
+                payload: Some(ClientPayload::Sync(Vec::new())),
+            })
+            .unwrap();
+        server.update();
+        let mut replicon_server = server.world.resource_mut::<RepliconServer>();
+        let received: Vec<_> = replicon_server.receive(incoming).collect();
+        assert_eq!(received, vec![(ids[2], vec![7].into())]);
+
+        //closing a connection disconnects only that client
+        clients[1].close();
+        server.update();
+        let connected = server.world.resource::<ConnectedClients>();
+        assert_eq!(connected.len(), 2);
+        assert!(connected.get_client(ids[1]).is_none());
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        assert_eq!(agent_map.get(ids[1]), None);
+        assert!(agent_map.get(ids[0]).is_some());
+
+        //a message on an unknown channel disconnects the client, its later messages are dropped
+        for channel in [incoming + 1, incoming] {
+            clients[0]
+                .send_packet(PacketPlayClient {
+                    payload: Some(ClientPayload::Sync(sync(channel, &[8]))),
+                })
+                .unwrap();
+        }
+        server.update();
+        server.update();
+        assert!(clients[0].is_closed());
+        let connected = server.world.resource::<ConnectedClients>();
+        assert!(connected.get_client(ids[0]).is_none());
+        let mut replicon_server = server.world.resource_mut::<RepliconServer>();
+        assert_eq!(replicon_server.receive(incoming).count(), 0);
+    }
+
+    fn client(transport: ClientLocalTransport) -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            ClientProtocolPlugin::<ClientLocalTransport, LocalTransportError>::default(),
+        ));
+        app.finish();
+        app.cleanup();
+        app.insert_resource(ClientConnection::new(transport));
+        app
+    }
+
+    /// the identity of the players in the tests, the certificate is a stand-in
+    fn identity(certificate: &str) -> PlayerIdentity {
+        PlayerIdentity::of_certificate(certificate.as_bytes())
+    }
+
+    fn join(server: &mut App, name: &str, certificate: &str) -> App {
+        let (client_transport, transport) = local_connection();
+        SpawnAgent {
+            transport,
+            profile: PlayerProfile {
+                display_name: name.to_string(),
+            },
+            identity: identity(certificate),
+            palette_mode: PaletteMode::Global,
+        }
+        .apply(&mut server.world);
+        server.update();
+        client(client_transport)
+    }
+
+    fn events<E: Event>(app: &mut App) -> Vec<E> {
+        app.world.resource_mut::<Events<E>>().drain().collect()
+    }
+
+    fn names(events: &[PlayerJoined]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| event.display_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn agent_lifecycle() {
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerProtocolPlugin::<ServerLocalTransport, LocalTransportError>::default(),
+        ));
+        server.insert_resource(AgentPersistence::Persist);
+        server.finish();
+        server.cleanup();
+
+        let mut alice = join(&mut server, "alice", "alice");
+        assert_eq!(names(&events::<PlayerJoined>(&mut server)), vec!["alice"]);
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        let (alice_id, alice_entity) = agent_map.iter().next().unwrap();
+        assert_eq!(agent_map.client_id(alice_entity), Some(alice_id));
+        assert_eq!(
+            server
+                .world
+                .get::<PlayerProfile>(alice_entity)
+                .unwrap()
+                .display_name,
+            "alice"
+        );
+        alice.update();
+        assert_eq!(names(&events::<PlayerJoined>(&mut alice)), vec!["alice"]);
+
+        //the new player learns about the players online
+        let mut bob = join(&mut server, "bob", "bob");
+        bob.update();
+        assert_eq!(
+            names(&events::<PlayerJoined>(&mut bob)),
+            vec!["alice", "bob"]
+        );
+        alice.update();
+        assert_eq!(names(&events::<PlayerJoined>(&mut alice)), vec!["bob"]);
+
+        //the agent is persisted without the connection
+        alice
+            .world
+            .resource::<ClientConnection<ClientLocalTransport>>()
+            .transport()
+            .close();
+        server.update();
+        let left = PlayerLeft {
+            client_id: alice_id,
+            display_name: "alice".to_string(),
+        };
+        assert_eq!(events::<PlayerLeft>(&mut server), vec![left.clone()]);
+        assert_eq!(
+            server.world.resource::<ClientAgentMap>().get(alice_id),
+            None
+        );
+        assert!(server
+            .world
+            .get::<ServerConnection<ServerLocalTransport>>(alice_entity)
+            .is_none());
+        bob.update();
+        assert_eq!(events::<PlayerLeft>(&mut bob), vec![left]);
+
+        //another player with the same display name gets a new agent
+        join(&mut server, "alice", "mallory");
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        assert_eq!(agent_map.persisted(&identity("alice")), Some(alice_entity));
+        assert_eq!(agent_map.client_id(alice_entity), None);
+
+        //and the agent is reused on the next login of its player with a new id
+        join(&mut server, "alice", "alice");
+        let agent_map = server.world.resource::<ClientAgentMap>();
+        let alice_id = agent_map.client_id(alice_entity).unwrap();
+        assert_eq!(agent_map.get(alice_id), Some(alice_entity));
+        assert_eq!(agent_map.persisted(&identity("alice")), None);
+        assert_eq!(agent_map.len(), 3);
+
+        //without persistence the agent is despawned
+        let bob_entity = agent_map
+            .iter()
+            .map(|(_, entity)| entity)
+            .find(|&entity| {
+                server
+                    .world
+                    .get::<PlayerProfile>(entity)
+                    .unwrap()
+                    .display_name
+                    == "bob"
+            })
+            .unwrap();
+        server.insert_resource(AgentPersistence::Despawn);
+        server
+            .world
+            .entity_mut(bob_entity)
+            .remove::<ServerConnection<ServerLocalTransport>>();
+        server.update();
+        assert!(server.world.get_entity(bob_entity).is_none());
+        let left = events::<PlayerLeft>(&mut server);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].display_name, "bob");
+    }
+
+    #[test]
+    fn chunk_streaming() {
+        let air = BlockData::new(ResourceKey::core("air").unwrap());
+        let stone = BlockData::new(ResourceKey::core("stone").unwrap());
+        let mut server = App::new();
+        server.add_plugins((
+            MinimalPlugins,
+            ServerProtocolPlugin::<ServerLocalTransport, LocalTransportError>::default(),
+        ));
+        let mut palette = GlobalPalette::new(air.clone());
+        let stone_id = palette.intern(stone.clone());
+        server.insert_resource(palette);
+        server.insert_resource(ServerChunks::new(ChunkStreamConfig {
+            view_distance: 1,
+            vertical_view_distance: 0,
+            max_in_flight: 4,
+        }));
+        server.finish();
+        server.cleanup();
+
+        let center = ChunkPos::default();
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        for position in spiral(center, 1, 0) {
+            chunks.insert(position, ChunkStorage::empty());
+        }
+        let mut client = join(&mut server, "alice", "alice");
+        client.insert_resource(GlobalPalette::new(air));
+        client.insert_resource(ChunkAssembler::new(move |state| *state == stone));
+        let (_, agent) = server
+            .world
+            .resource::<ClientAgentMap>()
+            .iter()
+            .next()
+            .unwrap();
+        server.world.entity_mut(agent).insert(ViewCenter(center));
+
+        //the chunks arrive in view order and the acknowledgements release the in flight limit
+        for _ in 0..4 {
+            server.update();
+            client.update();
+        }
+        let loaded: Vec<_> = events::<ChunkEvent>(&mut client)
+            .into_iter()
+            .filter_map(|event| match event {
+                ChunkEvent::Loaded(position) => Some(position),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(loaded, spiral(center, 1, 0));
+        assert_eq!(client.world.resource::<ChunkAssembler>().len(), 9);
+        let streamer = server.world.get::<ChunkStreamer>(agent).unwrap();
+        assert_eq!((streamer.pending(), streamer.in_flight()), (0, 0));
+
+        //changes are sent to the clients which loaded the chunk
+        let local = LocalPos::new(1, 2, 3).unwrap();
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        assert!(chunks.set_block(center, local, stone_id));
+        server.update();
+        client.update();
+        assert_eq!(
+            events::<ChunkEvent>(&mut client),
+            vec![ChunkEvent::Changed(center)]
+        );
+        let assembler = client.world.resource::<ChunkAssembler>();
+        let chunk = assembler.get(&center).unwrap();
+        let palette = client.world.resource::<GlobalPalette>();
+        assert_eq!(
+            palette.get(*chunk.storage.get_xyz(1, 2, 3)),
+            Some(&BlockData::new(ResourceKey::core("stone").unwrap()))
+        );
+        let mut occlusion = VoxelCubeOcclusionMatrix32::new();
+        occlusion.set_voxel(1, 2, 3, true);
+        assert_eq!(*chunk.occlusion, occlusion);
+
+        //a removed chunk is unloaded and sent again once it is inserted again
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        chunks.remove(&center);
+        server.update();
+        client.update();
+        assert_eq!(
+            events::<ChunkEvent>(&mut client),
+            vec![ChunkEvent::Unloaded(center)]
+        );
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        chunks.insert(center, ChunkStorage::empty());
+        server.update();
+        client.update();
+        assert_eq!(
+            events::<ChunkEvent>(&mut client),
+            vec![ChunkEvent::Loaded(center)]
+        );
+        let assembler = client.world.resource::<ChunkAssembler>();
+        let chunk = assembler.get(&center).unwrap();
+        assert_eq!(chunk.storage.palette().len(), 1);
+        assert_eq!(*chunk.occlusion, VoxelCubeOcclusionMatrix32::new());
+
+        //a replaced chunk is sent again as a whole
+        let mut filled = ChunkStorage::empty();
+        filled.fill_box((0, 0, 0), (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE), stone_id);
+        let mut chunks = server.world.resource_mut::<ServerChunks>();
+        chunks.insert(center, filled);
+        server.update();
+        client.update();
+        assert_eq!(
+            events::<ChunkEvent>(&mut client),
+            vec![ChunkEvent::Loaded(center)]
+        );
+        let assembler = client.world.resource::<ChunkAssembler>();
+        let chunk = assembler.get(&center).unwrap();
+        let mut occlusion = VoxelCubeOcclusionMatrix32::new();
+        occlusion.import(|_, _, _| true);
+        assert_eq!(*chunk.occlusion, occlusion);
     }
 }
@@ -3,6 +3,9 @@ use std::sync::{Arc, RwLock};
 use error_stack::Report;
 use thiserror::Error;
 
+use crate::proto::connection::{PacketClient, PacketServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
 use crate::transport::{TransportContainer, TransportLayer};
 
 pub struct LocalTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn> {
@@ -11,6 +14,33 @@ pub struct LocalTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn> {
     is_closed: Arc<RwLock<bool>>,
 }
 
+impl<PacketOut, PacketIn, DatagramOut, DatagramIn>
+    LocalTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn>
+{
+    /// creates two connected ends of an in-process connection
+    /// closing one end also closes the other one
+    pub fn pair() -> (
+        Self,
+        LocalTransportLayer<PacketIn, PacketOut, DatagramIn, DatagramOut>,
+    ) {
+        let (egress, remote_ingress) = crossbeam::channel::unbounded();
+        let (remote_egress, ingress) = crossbeam::channel::unbounded();
+        let is_closed = Arc::new(RwLock::new(false));
+        (
+            Self {
+                egress,
+                ingress,
+                is_closed: is_closed.clone(),
+            },
+            LocalTransportLayer {
+                egress: remote_egress,
+                ingress: remote_ingress,
+                is_closed,
+            },
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("Local transport error")]
 pub struct LocalTransportError;
@@ -58,3 +88,26 @@ where
         *self.is_closed.read().expect("cannot read lock local agent close")
     }
 }
+
+/// The client end of a connection to a server running in the same process (singleplayer)
+pub type ClientLocalTransport =
+    LocalTransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer>;
+
+/// The server end of a connection to a client running in the same process (singleplayer)
+pub type ServerLocalTransport =
+    LocalTransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient>;
+
+/// The client end of a connection to a server running in the same process which starts with the
+/// [handshake](crate::handshake), it carries the packets of every state
+pub type ClientLocalConnection =
+    LocalTransportLayer<PacketClient, PacketServer, PacketPlayClient, PacketPlayServer>;
+
+/// The server end of a connection to a client running in the same process which starts with the
+/// [handshake](crate::handshake), it carries the packets of every state
+pub type ServerLocalConnection =
+    LocalTransportLayer<PacketServer, PacketClient, PacketPlayServer, PacketPlayClient>;
+
+/// creates both ends of a local connection in play
+pub fn local_connection() -> (ClientLocalTransport, ServerLocalTransport) {
+    LocalTransportLayer::pair()
+}
@@ -1,6 +1,13 @@
 use std::error::Error;
 
-mod local;
+use crate::encoding::FrameConfig;
+use crate::proto::common::DisconnectReason;
+
+pub mod local;
+pub mod play;
+pub mod quic;
+pub mod scheduler;
+pub mod simulated;
 
 #[derive(Debug)]
 pub enum TransportContainer<Packet, Datagram> {
@@ -13,13 +20,35 @@ where
     TransportError: Error,
 {
     fn send_packet(&self, packet: PacketOut) -> error_stack::Result<(), TransportError>;
+
+    /// sends the packets in order, layers which can write them at once coalesce them
+    fn send_packets(
+        &self,
+        packets: impl IntoIterator<Item = PacketOut>,
+    ) -> error_stack::Result<(), TransportError> {
+        packets
+            .into_iter()
+            .try_for_each(|packet| self.send_packet(packet))
+    }
+
     fn send_datagram(&self, datagram: DatagramOut) -> error_stack::Result<(), TransportError>;
     fn drain(
         &mut self,
         into: &mut Vec<TransportContainer<PacketIn, DatagramIn>>,
     ) -> error_stack::Result<(), TransportError>;
-    
+
     fn close(&self);
-    
+
+    /// closes the connection and tells the peer why, layers without a way to transmit the reason just close
+    fn close_with(&self, reason: DisconnectReason) {
+        let _ = reason;
+        self.close();
+    }
+
     fn is_closed(&self) -> bool;
+
+    /// changes the framing of the packets sent and received afterwards, layers without framing ignore it
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        let _ = config;
+    }
 }
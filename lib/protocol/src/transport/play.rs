@@ -0,0 +1,184 @@
+//! The play packets of a connection whose transport carries every state.
+//!
+//! After the [handshake](crate::handshake) the packets of a connection are wrapped into
+//! [PacketServer::play](packet_server::State::Play) and [PacketClient::play](packet_client::State::Play).
+//! A [PlayTransport] wraps and unwraps them, so the play plugins only see the play packets.
+//! Packets of another state close the connection with [DisconnectReason::ProtocolViolation].
+
+use std::error::Error;
+
+use crate::encoding::FrameConfig;
+use crate::proto::common::DisconnectReason;
+use crate::proto::connection::{packet_client, packet_server, PacketClient, PacketServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::transport::{TransportContainer, TransportLayer};
+
+/// the server end of a connection in play, see [PlayTransport]
+pub type ServerPlayTransport<T> = PlayTransport<T, PacketClient, PacketPlayClient>;
+
+/// the client end of a connection in play, see [PlayTransport]
+pub type ClientPlayTransport<T> = PlayTransport<T, PacketServer, PacketPlayServer>;
+
+/// the play packets of the transport [T], [PacketIn] are the packets received by [T] and [PlayIn] their play packets
+pub struct PlayTransport<T, PacketIn, PlayIn> {
+    transport: T,
+    /// the play packets received together with the last packets of the handshake
+    received: Vec<PlayIn>,
+    buffer: Vec<TransportContainer<PacketIn, PlayIn>>,
+}
+
+impl<T, PacketIn, PlayIn> PlayTransport<T, PacketIn, PlayIn> {
+    /// [received] are the play packets the handshake received, they are drained first
+    pub fn new(transport: T, received: Vec<PlayIn>) -> Self {
+        Self {
+            transport,
+            received,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// drains the transport, the packets of another state than play close the connection
+    fn drain_play<PacketOut, PlayOut, E>(
+        &mut self,
+        into: &mut Vec<TransportContainer<PlayIn, PlayIn>>,
+        play: impl Fn(PacketIn) -> Option<PlayIn>,
+    ) -> error_stack::Result<(), E>
+    where
+        T: TransportLayer<PacketOut, PacketIn, PlayOut, PlayIn, E>,
+        E: Error,
+    {
+        into.extend(self.received.drain(..).map(TransportContainer::Packet));
+        self.transport.drain(&mut self.buffer)?;
+        for container in self.buffer.drain(..) {
+            match container {
+                TransportContainer::Packet(packet) => match play(packet) {
+                    Some(packet) => into.push(TransportContainer::Packet(packet)),
+                    None => {
+                        self.transport
+                            .close_with(DisconnectReason::ProtocolViolation);
+                        break;
+                    }
+                },
+                TransportContainer::Datagram(datagram) => {
+                    into.push(TransportContainer::Datagram(datagram))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, E> TransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient, E>
+    for ServerPlayTransport<T>
+where
+    T: TransportLayer<PacketServer, PacketClient, PacketPlayServer, PacketPlayClient, E>,
+    E: Error,
+{
+    fn send_packet(&self, packet: PacketPlayServer) -> error_stack::Result<(), E> {
+        self.transport.send_packet(PacketServer {
+            state: Some(packet_server::State::Play(packet)),
+        })
+    }
+
+    fn send_packets(
+        &self,
+        packets: impl IntoIterator<Item = PacketPlayServer>,
+    ) -> error_stack::Result<(), E> {
+        self.transport
+            .send_packets(packets.into_iter().map(|packet| PacketServer {
+                state: Some(packet_server::State::Play(packet)),
+            }))
+    }
+
+    fn send_datagram(&self, datagram: PacketPlayServer) -> error_stack::Result<(), E> {
+        self.transport.send_datagram(datagram)
+    }
+
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketPlayClient, PacketPlayClient>>,
+    ) -> error_stack::Result<(), E> {
+        self.drain_play::<PacketServer, PacketPlayServer, E>(into, |packet| match packet.state {
+            Some(packet_client::State::Play(packet)) => Some(packet),
+            _ => None,
+        })
+    }
+
+    fn close(&self) {
+        self.transport.close();
+    }
+
+    fn close_with(&self, reason: DisconnectReason) {
+        self.transport.close_with(reason);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.transport.is_closed()
+    }
+
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        self.transport.set_frame_config(config);
+    }
+}
+
+impl<T, E> TransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer, E>
+    for ClientPlayTransport<T>
+where
+    T: TransportLayer<PacketClient, PacketServer, PacketPlayClient, PacketPlayServer, E>,
+    E: Error,
+{
+    fn send_packet(&self, packet: PacketPlayClient) -> error_stack::Result<(), E> {
+        self.transport.send_packet(PacketClient {
+            state: Some(packet_client::State::Play(packet)),
+        })
+    }
+
+    fn send_packets(
+        &self,
+        packets: impl IntoIterator<Item = PacketPlayClient>,
+    ) -> error_stack::Result<(), E> {
+        self.transport
+            .send_packets(packets.into_iter().map(|packet| PacketClient {
+                state: Some(packet_client::State::Play(packet)),
+            }))
+    }
+
+    fn send_datagram(&self, datagram: PacketPlayClient) -> error_stack::Result<(), E> {
+        self.transport.send_datagram(datagram)
+    }
+
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketPlayServer, PacketPlayServer>>,
+    ) -> error_stack::Result<(), E> {
+        self.drain_play::<PacketClient, PacketPlayClient, E>(into, |packet| match packet.state {
+            Some(packet_server::State::Play(packet)) => Some(packet),
+            _ => None,
+        })
+    }
+
+    fn close(&self) {
+        self.transport.close();
+    }
+
+    fn close_with(&self, reason: DisconnectReason) {
+        self.transport.close_with(reason);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.transport.is_closed()
+    }
+
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        self.transport.set_frame_config(config);
+    }
+}
@@ -0,0 +1,520 @@
+//! A [TransportLayer] over a QUIC connection, used when client and server run in different processes.
+//!
+//! Every side opens one unidirectional stream for its packets, so they are reliable and ordered.
+//! Datagrams are sent as QUIC datagrams, they can be lost or reordered and must fit into
+//! [QuicTransportLayer::max_datagram_size].
+//!
+//! Closing the connection finishes the packet stream first, so every packet sent before is delivered.
+//! The [DisconnectReason] is transmitted as the application error code of the QUIC close frame.
+
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use error_stack::{Report, ResultExt};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, ConnectionError, Endpoint, ServerConfig, VarInt};
+use rustls::client::danger::ServerCertVerifier;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, watch};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::encoding::{FrameConfig, MessageCodec};
+use crate::proto::common::DisconnectReason;
+use crate::transport::{TransportContainer, TransportLayer};
+
+/// how long a close waits for the peer to receive the remaining packets
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum QuicTransportError {
+    #[error("invalid endpoint configuration")]
+    Configuration,
+    #[error("could not establish the connection")]
+    Connect,
+    #[error("the connection is closed")]
+    Closed,
+    #[error("could not encode the message")]
+    Encode,
+    #[error("could not send the datagram")]
+    SendDatagram,
+    #[error("received an invalid message")]
+    InvalidMessage,
+}
+
+/// creates the config of a server endpoint which authenticates with the certificate chain
+pub fn server_config(
+    certificate_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> error_stack::Result<ServerConfig, QuicTransportError> {
+    let crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .change_context(QuicTransportError::Configuration)?
+    .with_no_client_auth()
+    .with_single_cert(certificate_chain, key)
+    .change_context(QuicTransportError::Configuration)?;
+    let crypto =
+        QuicServerConfig::try_from(crypto).change_context(QuicTransportError::Configuration)?;
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// creates the config of a client endpoint which verifies the server certificates with the verifier
+pub fn client_config(
+    verifier: Arc<dyn ServerCertVerifier>,
+) -> error_stack::Result<ClientConfig, QuicTransportError> {
+    let crypto = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .change_context(QuicTransportError::Configuration)?
+    .dangerous()
+    .with_custom_certificate_verifier(verifier)
+    .with_no_client_auth();
+    let crypto =
+        QuicClientConfig::try_from(crypto).change_context(QuicTransportError::Configuration)?;
+    Ok(ClientConfig::new(Arc::new(crypto)))
+}
+
+type Ingress<PacketIn, DatagramIn> =
+    Result<TransportContainer<PacketIn, DatagramIn>, Report<QuicTransportError>>;
+
+pub struct QuicTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn> {
+    connection: Connection,
+    /// the framed packets for the writer task, [None] finishes the stream and closes the connection
+    egress: mpsc::UnboundedSender<Option<Bytes>>,
+    codec: MessageCodec<PacketOut, PacketIn>,
+    /// the config of the reader task
+    frame_config: watch::Sender<FrameConfig>,
+    ingress: crossbeam::channel::Receiver<Ingress<PacketIn, DatagramIn>>,
+    /// the reason of a close which was requested by this side
+    close_reason: Arc<Mutex<Option<DisconnectReason>>>,
+    _marker: PhantomData<fn(DatagramOut)>,
+}
+
+impl<PacketOut, PacketIn, DatagramOut, DatagramIn>
+    QuicTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn>
+where
+    PacketOut: prost::Message,
+    PacketIn: prost::Message + Default + Send + 'static,
+    DatagramOut: prost::Message,
+    DatagramIn: prost::Message + Default + Send + 'static,
+{
+    /// connects to a server, the endpoint needs a default client config (see [client_config])
+    pub async fn connect(
+        endpoint: &Endpoint,
+        address: SocketAddr,
+        server_name: &str,
+    ) -> error_stack::Result<Self, QuicTransportError> {
+        let connection = endpoint
+            .connect(address, server_name)
+            .change_context(QuicTransportError::Connect)?
+            .await
+            .change_context(QuicTransportError::Connect)?;
+        Self::new(connection, FrameConfig::default()).await
+    }
+
+    /// accepts the next client of a server endpoint, [None] if the endpoint is closed
+    pub async fn accept(
+        endpoint: &Endpoint,
+    ) -> Option<error_stack::Result<Self, QuicTransportError>> {
+        let incoming = endpoint.accept().await?;
+        Some(match incoming.await {
+            Ok(connection) => Self::new(connection, FrameConfig::default()).await,
+            Err(error) => Err(Report::new(error).change_context(QuicTransportError::Connect)),
+        })
+    }
+
+    /// spawns the tasks of an established connection, must be called inside of a tokio runtime
+    pub async fn new(
+        connection: Connection,
+        frame_config: FrameConfig,
+    ) -> error_stack::Result<Self, QuicTransportError> {
+        let send = connection
+            .open_uni()
+            .await
+            .change_context(QuicTransportError::Connect)?;
+        let (egress, egress_receiver) = mpsc::unbounded_channel();
+        let (ingress_sender, ingress) = crossbeam::channel::unbounded();
+        let close_reason = Arc::new(Mutex::new(None));
+        let (frame_config_sender, frame_config_receiver) = watch::channel(frame_config.clone());
+
+        tokio::spawn(write_packets(
+            connection.clone(),
+            send,
+            egress_receiver,
+            close_reason.clone(),
+        ));
+        tokio::spawn(read_packets(
+            connection.clone(),
+            frame_config_receiver,
+            ingress_sender.clone(),
+        ));
+        tokio::spawn(read_datagrams(connection.clone(), ingress_sender));
+
+        Ok(Self {
+            connection,
+            egress,
+            codec: MessageCodec::new(frame_config),
+            frame_config: frame_config_sender,
+            ingress,
+            close_reason,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<PacketOut, PacketIn, DatagramOut, DatagramIn>
+    QuicTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn>
+{
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    /// the largest datagram the peer accepts, [None] if datagrams are not supported
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+
+    pub fn frame_config(&self) -> &FrameConfig {
+        self.codec.config()
+    }
+
+    /// changes the framing of the packets sent and received afterwards, f.e. to the negotiated compression
+    pub fn set_frame_config(&mut self, config: FrameConfig) {
+        self.codec = MessageCodec::new(config.clone());
+        //the reader task is gone if the connection is closed
+        let _ = self.frame_config.send(config);
+    }
+
+    /// why the connection was closed, [None] while it is open
+    ///
+    /// closes by the peer without a known reason and connection errors are reported as [DisconnectReason::Timeout]
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        if let Some(reason) = *self
+            .close_reason
+            .lock()
+            .expect("cannot lock quic close reason")
+        {
+            return Some(reason);
+        }
+        match self.connection.close_reason()? {
+            ConnectionError::ApplicationClosed(close) => Some(
+                i32::try_from(close.error_code.into_inner())
+                    .ok()
+                    .and_then(|code| DisconnectReason::try_from(code).ok())
+                    .unwrap_or(DisconnectReason::Timeout),
+            ),
+            _ => Some(DisconnectReason::Timeout),
+        }
+    }
+}
+
+impl<PacketOut, PacketIn, DatagramOut, DatagramIn>
+    TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, QuicTransportError>
+    for QuicTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn>
+where
+    PacketOut: prost::Message,
+    DatagramOut: prost::Message,
+{
+    fn send_packet(&self, packet: PacketOut) -> error_stack::Result<(), QuicTransportError> {
+        if self.is_closed() {
+            return Err(Report::new(QuicTransportError::Closed));
+        }
+        let mut frame = BytesMut::new();
+        self.codec
+            .clone()
+            .encode(packet, &mut frame)
+            .change_context(QuicTransportError::Encode)?;
+        self.egress
+            .send(Some(frame.freeze()))
+            .map_err(|_| Report::new(QuicTransportError::Closed))
+    }
+
+    /// the frames of the packets are written to the stream at once
+    fn send_packets(
+        &self,
+        packets: impl IntoIterator<Item = PacketOut>,
+    ) -> error_stack::Result<(), QuicTransportError> {
+        if self.is_closed() {
+            return Err(Report::new(QuicTransportError::Closed));
+        }
+        let mut codec = self.codec.clone();
+        let mut frames = BytesMut::new();
+        for packet in packets {
+            codec
+                .encode(packet, &mut frames)
+                .change_context(QuicTransportError::Encode)?;
+        }
+        if frames.is_empty() {
+            return Ok(());
+        }
+        self.egress
+            .send(Some(frames.freeze()))
+            .map_err(|_| Report::new(QuicTransportError::Closed))
+    }
+
+    fn send_datagram(&self, datagram: DatagramOut) -> error_stack::Result<(), QuicTransportError> {
+        if self.is_closed() {
+            return Err(Report::new(QuicTransportError::Closed));
+        }
+        self.connection
+            .send_datagram(datagram.encode_to_vec().into())
+            .change_context(QuicTransportError::SendDatagram)
+    }
+
+    /// returns the first error of the received messages, the messages before it are drained
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketIn, DatagramIn>>,
+    ) -> error_stack::Result<(), QuicTransportError> {
+        while let Ok(container) = self.ingress.try_recv() {
+            into.push(container?);
+        }
+        Ok(())
+    }
+
+    fn close(&self) {
+        self.close_with(DisconnectReason::Goodbye);
+    }
+
+    /// closes the connection after the packets sent before are delivered, later packets are discarded
+    fn close_with(&self, reason: DisconnectReason) {
+        let mut close_reason = self
+            .close_reason
+            .lock()
+            .expect("cannot lock quic close reason");
+        if close_reason.is_none() && self.connection.close_reason().is_none() {
+            *close_reason = Some(reason);
+            //the writer task is gone if the connection was lost already
+            let _ = self.egress.send(None);
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.close_reason
+            .lock()
+            .expect("cannot lock quic close reason")
+            .is_some()
+            || self.connection.close_reason().is_some()
+    }
+
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        QuicTransportLayer::set_frame_config(self, config);
+    }
+}
+
+async fn write_packets(
+    connection: Connection,
+    mut send: quinn::SendStream,
+    mut egress: mpsc::UnboundedReceiver<Option<Bytes>>,
+    close_reason: Arc<Mutex<Option<DisconnectReason>>>,
+) {
+    while let Some(Some(frame)) = egress.recv().await {
+        if send.write_all(&frame).await.is_err() {
+            //the connection is lost, the reason is available from the connection
+            return;
+        }
+    }
+
+    //the layer was closed or dropped
+    let reason = close_reason
+        .lock()
+        .expect("cannot lock quic close reason")
+        .unwrap_or(DisconnectReason::Goodbye);
+    if send.finish().is_ok() {
+        //the peer stops the stream after reading everything or closes the connection itself
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, send.stopped()).await;
+    }
+    connection.close(
+        VarInt::from_u32(reason as u32),
+        reason.as_str_name().as_bytes(),
+    );
+}
+
+async fn read_packets<PacketIn, DatagramIn>(
+    connection: Connection,
+    mut frame_config: watch::Receiver<FrameConfig>,
+    ingress: crossbeam::channel::Sender<Ingress<PacketIn, DatagramIn>>,
+) where
+    PacketIn: prost::Message + Default,
+{
+    let Ok(mut receive) = connection.accept_uni().await else {
+        return;
+    };
+    let mut codec = MessageCodec::<(), PacketIn>::new(frame_config.borrow_and_update().clone());
+    let mut buffer = BytesMut::new();
+    loop {
+        if frame_config.has_changed().unwrap_or(false) {
+            codec = MessageCodec::new(frame_config.borrow_and_update().clone());
+        }
+        let container = match codec.decode(&mut buffer) {
+            Ok(Some(packet)) => Ok(TransportContainer::Packet(packet)),
+            Ok(None) => match receive.read_buf(&mut buffer).await {
+                Ok(0) | Err(_) => {
+                    //the peer finished the stream or the connection is lost, the reason is available from the connection
+                    return;
+                }
+                Ok(_) => continue,
+            },
+            Err(error) => {
+                Err(Report::new(error).change_context(QuicTransportError::InvalidMessage))
+            }
+        };
+        let invalid = container.is_err();
+        if ingress.send(container).is_err() || invalid {
+            //the stream cannot be resynchronized after an invalid message
+            return;
+        }
+    }
+}
+
+async fn read_datagrams<PacketIn, DatagramIn>(
+    connection: Connection,
+    ingress: crossbeam::channel::Sender<Ingress<PacketIn, DatagramIn>>,
+) where
+    DatagramIn: prost::Message + Default,
+{
+    while let Ok(datagram) = connection.read_datagram().await {
+        let container = DatagramIn::decode(datagram)
+            .map(TransportContainer::Datagram)
+            .map_err(|error| Report::new(error).change_context(QuicTransportError::InvalidMessage));
+        if ingress.send(container).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rcgen::{CertificateParams, KeyPair, PKCS_ED25519};
+    use rustls::pki_types::PrivatePkcs8KeyDer;
+
+    use super::*;
+    use crate::crypto::UnsafeServerCertVerifier;
+    use crate::proto::play::client::packet::packet_play_client::Payload as ClientPayload;
+    use crate::proto::play::client::packet::PacketPlayClient;
+    use crate::proto::play::server::packet::packet_play_server::Payload as ServerPayload;
+    use crate::proto::play::server::packet::PacketPlayServer;
+
+    type ClientLayer =
+        QuicTransportLayer<PacketPlayClient, PacketPlayServer, PacketPlayClient, PacketPlayServer>;
+    type ServerLayer =
+        QuicTransportLayer<PacketPlayServer, PacketPlayClient, PacketPlayServer, PacketPlayClient>;
+
+    fn endpoints() -> (Endpoint, Endpoint) {
+        let key = KeyPair::generate_for(&PKCS_ED25519).expect("could not generate key pair");
+        let certificate = CertificateParams::new(vec!["localhost".into()])
+            .expect("could not create certificate params")
+            .self_signed(&key)
+            .expect("could not sign certificate");
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+        let server_config = server_config(vec![certificate.der().clone()], key)
+            .expect("could not create server config");
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap())
+            .expect("could not create server endpoint");
+
+        let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap())
+            .expect("could not create client endpoint");
+        client.set_default_client_config(
+            client_config(Arc::new(UnsafeServerCertVerifier))
+                .expect("could not create client config"),
+        );
+        (client, server)
+    }
+
+    async fn receive<PacketOut, PacketIn, DatagramOut, DatagramIn>(
+        layer: &mut QuicTransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn>,
+        count: usize,
+    ) -> Vec<TransportContainer<PacketIn, DatagramIn>>
+    where
+        PacketOut: prost::Message,
+        DatagramOut: prost::Message,
+    {
+        let mut received = Vec::new();
+        while received.len() < count {
+            layer.drain(&mut received).expect("could not drain");
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn loopback() {
+        let (client, server) = endpoints();
+        let address = server.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let layer = ServerLayer::accept(&server)
+                .await
+                .expect("the endpoint is closed")
+                .expect("could not accept");
+            (server, layer)
+        });
+        let mut client_layer = ClientLayer::connect(&client, address, "localhost")
+            .await
+            .expect("could not connect");
+        let (server, mut server_layer) = accept.await.unwrap();
+
+        for i in 0..100u8 {
+            client_layer
+                .send_packet(PacketPlayClient {
+                    payload: Some(ClientPayload::Sync(vec![i; 16 * i as usize])),
+                })
+                .unwrap();
+        }
+        let received = receive(&mut server_layer, 100).await;
+        for (i, container) in received.into_iter().enumerate() {
+            let TransportContainer::Packet(packet) = container else {
+                panic!("expected a packet");
+            };
+            assert_eq!(
+                packet.payload,
+                Some(ClientPayload::Sync(vec![i as u8; 16 * i]))
+            );
+        }
+
+        server_layer
+            .send_datagram(PacketPlayServer {
+                payload: Some(ServerPayload::Sync(vec![1, 2, 3])),
+            })
+            .unwrap();
+        let received = receive(&mut client_layer, 1).await;
+        assert!(matches!(
+            &received[..],
+            [TransportContainer::Datagram(PacketPlayServer {
+                payload: Some(ServerPayload::Sync(sync))
+            })] if sync == &[1, 2, 3]
+        ));
+
+        //the packet before the close is still delivered
+        server_layer
+            .send_packet(PacketPlayServer {
+                payload: Some(ServerPayload::Sync(vec![4])),
+            })
+            .unwrap();
+        server_layer.close_with(DisconnectReason::Kick);
+        assert!(server_layer.is_closed());
+        assert!(server_layer
+            .send_packet(PacketPlayServer { payload: None })
+            .is_err());
+        let received = receive(&mut client_layer, 1).await;
+        assert!(matches!(&received[..], [TransportContainer::Packet(_)]));
+
+        client.wait_idle().await;
+        assert!(client_layer.is_closed());
+        assert_eq!(
+            client_layer.disconnect_reason(),
+            Some(DisconnectReason::Kick)
+        );
+        assert_eq!(
+            server_layer.disconnect_reason(),
+            Some(DisconnectReason::Kick)
+        );
+        server.wait_idle().await;
+    }
+}
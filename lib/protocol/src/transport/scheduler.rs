@@ -0,0 +1,422 @@
+//! Scheduling of the outgoing packets of a connection.
+//!
+//! The packets are queued by their [Prioritized::priority] and sent with [PacketScheduler::send] once per tick,
+//! the packets of a priority keep their order.
+//!
+//! - [BULK_PRIORITY] packets (f.e. chunks) are only sent while the [SchedulerConfig::bandwidth] of the tick is left,
+//!   the last one may exceed it and the excess is taken from the next ticks
+//! - the other packets are sent every tick before the bulk packets, they count towards the bandwidth but are never
+//!   delayed by it, so bulk traffic cannot starve movement or chat
+//! - [Prioritized::attachment] packets are sent together with the packet queued before them
+//! - consecutive small packets are coalesced into one write of the transport
+
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+
+use crate::proto::connection::{packet_client, packet_server, PacketClient, PacketServer};
+use crate::proto::play::client::packet::PacketPlayClient;
+use crate::proto::play::server::packet::PacketPlayServer;
+use crate::transport::TransportLayer;
+
+/// the priority of packets without one, they are limited by the bandwidth
+pub const BULK_PRIORITY: u8 = 0;
+
+/// 256 KiB per tick are about 5 MiB/s at 20 ticks per second
+const DEFAULT_BANDWIDTH: usize = 256 * 1024;
+const DEFAULT_COALESCE_THRESHOLD: usize = 1024;
+const DEFAULT_MAX_BATCH_SIZE: usize = 16 * 1024;
+
+pub trait Prioritized {
+    /// packets with a higher priority are sent first
+    fn priority(&self) -> u8;
+    /// whether the packet belongs to the packet queued before it
+    fn attachment(&self) -> bool;
+}
+
+impl Prioritized for PacketPlayClient {
+    fn priority(&self) -> u8 {
+        self.payload
+            .as_ref()
+            .map_or(BULK_PRIORITY, |payload| payload.priority())
+    }
+
+    fn attachment(&self) -> bool {
+        self.payload
+            .as_ref()
+            .is_some_and(|payload| payload.attachment())
+    }
+}
+
+impl Prioritized for PacketPlayServer {
+    fn priority(&self) -> u8 {
+        self.payload
+            .as_ref()
+            .map_or(BULK_PRIORITY, |payload| payload.priority())
+    }
+
+    fn attachment(&self) -> bool {
+        self.payload
+            .as_ref()
+            .is_some_and(|payload| payload.attachment())
+    }
+}
+
+/// the packets before play are sent before everything else
+impl Prioritized for PacketClient {
+    fn priority(&self) -> u8 {
+        match &self.state {
+            Some(packet_client::State::Play(packet)) => packet.priority(),
+            _ => u8::MAX,
+        }
+    }
+
+    fn attachment(&self) -> bool {
+        match &self.state {
+            Some(packet_client::State::Play(packet)) => packet.attachment(),
+            _ => false,
+        }
+    }
+}
+
+/// the packets before play are sent before everything else
+impl Prioritized for PacketServer {
+    fn priority(&self) -> u8 {
+        match &self.state {
+            Some(packet_server::State::Play(packet)) => packet.priority(),
+            _ => u8::MAX,
+        }
+    }
+
+    fn attachment(&self) -> bool {
+        match &self.state {
+            Some(packet_server::State::Play(packet)) => packet.attachment(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// the encoded bytes sent per tick
+    pub bandwidth: usize,
+    /// packets smaller than this are coalesced
+    pub coalesce_threshold: usize,
+    /// the maximum size of a coalesced write
+    pub max_batch_size: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            bandwidth: DEFAULT_BANDWIDTH,
+            coalesce_threshold: DEFAULT_COALESCE_THRESHOLD,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+}
+
+/// a packet with its attachments
+struct Entry<P> {
+    packets: Vec<(P, usize)>,
+    size: usize,
+}
+
+/// queues the outgoing packets of a connection, see the [module](self) for the rules
+pub struct PacketScheduler<P> {
+    config: SchedulerConfig,
+    queues: BTreeMap<u8, VecDeque<Entry<P>>>,
+    /// the priority of the last queued entry, it is the back of its queue until the queue is empty
+    last: Option<u8>,
+    /// the bandwidth left in this tick, negative if the last bulk packet exceeded it
+    credit: i64,
+    queued: usize,
+    queued_bytes: usize,
+}
+
+impl<P: Prioritized + prost::Message> PacketScheduler<P> {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            queues: BTreeMap::new(),
+            last: None,
+            credit: 0,
+            queued: 0,
+            queued_bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SchedulerConfig {
+        &self.config
+    }
+
+    /// the number of queued packets
+    pub fn len(&self) -> usize {
+        self.queued
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued == 0
+    }
+
+    /// the encoded size of the queued packets
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    pub fn push(&mut self, packet: P) {
+        let size = packet.encoded_len();
+        self.queued += 1;
+        self.queued_bytes += size;
+
+        if packet.attachment() {
+            let previous = self
+                .last
+                .and_then(|priority| self.queues.get_mut(&priority))
+                .and_then(VecDeque::back_mut);
+            if let Some(previous) = previous {
+                previous.packets.push((packet, size));
+                previous.size += size;
+                return;
+            }
+        }
+
+        let priority = packet.priority();
+        self.queues.entry(priority).or_default().push_back(Entry {
+            packets: vec![(packet, size)],
+            size,
+        });
+        self.last = Some(priority);
+    }
+
+    /// takes the packets of this tick ordered by priority, every batch should be written at once
+    pub fn tick(&mut self) -> Vec<Vec<P>> {
+        let bandwidth = self.config.bandwidth as i64;
+        self.credit = (self.credit + bandwidth).min(bandwidth);
+
+        let mut batches = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for (priority, queue) in self.queues.iter_mut().rev() {
+            while *priority != BULK_PRIORITY || self.credit > 0 {
+                let Some(entry) = queue.pop_front() else {
+                    break;
+                };
+                self.credit -= entry.size as i64;
+                self.queued -= entry.packets.len();
+                self.queued_bytes -= entry.size;
+
+                for (packet, size) in entry.packets {
+                    if size >= self.config.coalesce_threshold
+                        || batch_size + size > self.config.max_batch_size
+                    {
+                        if !batch.is_empty() {
+                            batches.push(std::mem::take(&mut batch));
+                        }
+                        batch_size = 0;
+                    }
+                    batch.push(packet);
+                    batch_size += size;
+                    if size >= self.config.coalesce_threshold {
+                        batches.push(std::mem::take(&mut batch));
+                        batch_size = 0;
+                    }
+                }
+            }
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+
+        self.queues.retain(|_, queue| !queue.is_empty());
+        if self
+            .last
+            .is_some_and(|priority| !self.queues.contains_key(&priority))
+        {
+            self.last = None;
+        }
+        batches
+    }
+
+    /// sends the packets of this tick, the packets after an error are dropped
+    pub fn send<T, DatagramOut, DatagramIn, PacketIn, E>(
+        &mut self,
+        transport: &T,
+    ) -> error_stack::Result<(), E>
+    where
+        T: TransportLayer<P, PacketIn, DatagramOut, DatagramIn, E>,
+        E: Error,
+    {
+        for batch in self.tick() {
+            transport.send_packets(batch)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: Prioritized + prost::Message> Default for PacketScheduler<P> {
+    fn default() -> Self {
+        Self::new(SchedulerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Packet {
+        #[prost(uint32, tag = "1")]
+        priority: u32,
+        #[prost(bool, tag = "2")]
+        attachment: bool,
+        #[prost(bytes = "vec", tag = "3")]
+        data: Vec<u8>,
+    }
+
+    impl Prioritized for Packet {
+        fn priority(&self) -> u8 {
+            self.priority as u8
+        }
+
+        fn attachment(&self) -> bool {
+            self.attachment
+        }
+    }
+
+    fn packet(priority: u8, size: usize) -> Packet {
+        Packet {
+            priority: priority as u32,
+            attachment: false,
+            data: vec![priority; size],
+        }
+    }
+
+    fn scheduler(bandwidth: usize) -> PacketScheduler<Packet> {
+        PacketScheduler::new(SchedulerConfig {
+            bandwidth,
+            ..Default::default()
+        })
+    }
+
+    fn priorities(batches: &[Vec<Packet>]) -> Vec<u32> {
+        batches
+            .iter()
+            .flatten()
+            .map(|packet| packet.priority)
+            .collect()
+    }
+
+    #[test]
+    fn bulk_does_not_starve() {
+        let mut scheduler = scheduler(10_000);
+        for _ in 0..10 {
+            scheduler.push(packet(BULK_PRIORITY, 4_000));
+        }
+        scheduler.push(packet(10, 10));
+
+        //the bulk packet exceeding the bandwidth is still sent
+        let batches = scheduler.tick();
+        assert_eq!(priorities(&batches), vec![10, 0, 0, 0]);
+        assert_eq!(scheduler.len(), 7);
+
+        //the excess is taken from the next tick, movement and chat are sent anyway
+        scheduler.push(packet(5, 10));
+        scheduler.push(packet(10, 10));
+        let batches = scheduler.tick();
+        assert_eq!(priorities(&batches), vec![10, 5, 0, 0]);
+
+        let mut ticks = 0;
+        while !scheduler.is_empty() {
+            scheduler.tick();
+            ticks += 1;
+        }
+        assert_eq!(ticks, 2);
+        assert_eq!(scheduler.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn acknowledgements_are_not_bulk() {
+        use crate::proto::play::client::packet::{packet_play_client, ChunkAcknowledged};
+
+        //no bandwidth is left for bulk packets
+        let mut scheduler = PacketScheduler::new(SchedulerConfig {
+            bandwidth: 0,
+            ..Default::default()
+        });
+        let bulk = PacketPlayClient { payload: None };
+        let acknowledged = PacketPlayClient {
+            payload: Some(packet_play_client::Payload::ChunkAcknowledged(
+                ChunkAcknowledged { sequence: 7 },
+            )),
+        };
+        scheduler.push(bulk.clone());
+        scheduler.push(bulk);
+        scheduler.push(acknowledged.clone());
+
+        assert!(acknowledged.priority() > BULK_PRIORITY);
+        let sent: Vec<_> = scheduler.tick().into_iter().flatten().collect();
+        assert_eq!(sent, vec![acknowledged]);
+        assert_eq!(scheduler.len(), 2);
+    }
+
+    #[test]
+    fn attachments() {
+        let mut scheduler = scheduler(20);
+        let attachment = Packet {
+            attachment: true,
+            ..packet(10, 1)
+        };
+        //nothing to attach to
+        scheduler.push(attachment.clone());
+        scheduler.push(packet(BULK_PRIORITY, 10));
+        scheduler.push(attachment.clone());
+        scheduler.push(packet(BULK_PRIORITY, 10));
+        scheduler.push(attachment.clone());
+
+        let batches = scheduler.tick();
+        let sent: Vec<_> = batches.iter().flatten().collect();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[1].priority, 0);
+        assert!(sent[2].attachment);
+
+        //the attachment stays with its bulk packet although its priority is higher
+        let batches = scheduler.tick();
+        let sent: Vec<_> = batches.iter().flatten().collect();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1].attachment);
+        assert!(scheduler.is_empty());
+
+        //the previous packet was sent
+        scheduler.push(attachment);
+        assert_eq!(priorities(&scheduler.tick()), vec![10]);
+    }
+
+    #[test]
+    fn coalescing() {
+        let mut scheduler = PacketScheduler::new(SchedulerConfig {
+            bandwidth: usize::MAX / 2,
+            coalesce_threshold: 100,
+            max_batch_size: 250,
+        });
+        for _ in 0..5 {
+            scheduler.push(packet(1, 60));
+        }
+        scheduler.push(packet(1, 200));
+        scheduler.push(packet(1, 10));
+
+        let sizes: Vec<_> = scheduler
+            .tick()
+            .iter()
+            .map(|batch| {
+                batch
+                    .iter()
+                    .map(|packet| packet.data.len())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![vec![60, 60, 60], vec![60, 60], vec![200], vec![10]]
+        );
+    }
+}
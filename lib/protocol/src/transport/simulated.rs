@@ -0,0 +1,611 @@
+//! A [TransportLayer] wrapping another one to simulate bad network conditions, f.e. to test the lag handling
+//! in-process over a [LocalTransportLayer](crate::transport::local::LocalTransportLayer).
+//!
+//! The conditions apply to both directions of the wrapped end, so the round trip time is twice the latency.
+//! - packets are delayed but stay reliable and ordered like on a stream
+//! - datagrams can additionally be lost and reordered
+//! - the bandwidth delays the messages behind the ones which are still being transmitted
+//!
+//! The delayed messages are forwarded whenever the layer is used, so [TransportLayer::drain] should be called
+//! regularly. Closing the layer forwards the delayed packets first, only the datagrams on their way are lost.
+//! All randomness comes from [SimulationConfig::seed], with a [SimulationClock::manual] the
+//! simulation is reproducible.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use error_stack::{Report, ResultExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+use crate::encoding::FrameConfig;
+use crate::proto::common::DisconnectReason;
+use crate::transport::{TransportContainer, TransportLayer};
+
+#[derive(Debug, Error)]
+pub enum SimulatedTransportError {
+    #[error("the simulation disconnected")]
+    Disconnected,
+    #[error("the wrapped transport failed")]
+    Transport,
+    #[error("the simulation config is invalid: {0}")]
+    InvalidConfig(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+    /// the delay of every message in one direction
+    pub latency: Duration,
+    /// a random delay up to this is added to the latency
+    pub jitter: Duration,
+    /// the probability of a datagram to be lost
+    pub datagram_loss: f64,
+    /// the probability of a datagram to be delayed by [Self::reorder_delay], so later ones overtake it
+    pub reordering: f64,
+    pub reorder_delay: Duration,
+    /// the encoded bytes per second in one direction
+    pub bandwidth: Option<u64>,
+    /// the connection is closed after this time
+    pub disconnect_after: Option<Duration>,
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            datagram_loss: 0.0,
+            reordering: 0.0,
+            reorder_delay: Duration::from_millis(50),
+            bandwidth: None,
+            disconnect_after: None,
+            seed: 0,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// the probabilities have to be within 0 and 1 and the bandwidth must not be 0
+    pub fn validate(&self) -> error_stack::Result<(), SimulatedTransportError> {
+        let invalid = |reason| Err(Report::new(SimulatedTransportError::InvalidConfig(reason)));
+        //NaN is never contained
+        if !(0.0..=1.0).contains(&self.datagram_loss) {
+            return invalid("the datagram loss is not a probability");
+        }
+        if !(0.0..=1.0).contains(&self.reordering) {
+            return invalid("the reordering is not a probability");
+        }
+        if self.bandwidth == Some(0) {
+            return invalid("the bandwidth is 0");
+        }
+        Ok(())
+    }
+}
+
+/// the time of a simulation
+#[derive(Debug, Clone)]
+pub enum SimulationClock {
+    System(Instant),
+    /// only advances with [SimulationClock::advance]
+    Manual(Arc<Mutex<Duration>>),
+}
+
+impl SimulationClock {
+    pub fn system() -> Self {
+        Self::System(Instant::now())
+    }
+
+    pub fn manual() -> Self {
+        Self::Manual(Arc::default())
+    }
+
+    /// advances a manual clock, the system clock advances on its own
+    pub fn advance(&self, duration: Duration) {
+        if let Self::Manual(elapsed) = self {
+            *elapsed.lock().expect("cannot lock simulation clock") += duration;
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            Self::System(start) => start.elapsed(),
+            Self::Manual(elapsed) => *elapsed.lock().expect("cannot lock simulation clock"),
+        }
+    }
+}
+
+/// the messages of one direction which are still on their way
+struct Link<Packet, Datagram> {
+    /// ordered by the time they arrive and the order they were sent in
+    queue: BTreeMap<(Duration, u64), TransportContainer<Packet, Datagram>>,
+    sequence: u64,
+    /// packets must not overtake each other
+    last_packet: Duration,
+    /// the end of the transmission of the previous message
+    busy_until: Duration,
+}
+
+impl<Packet, Datagram> Link<Packet, Datagram> {
+    fn new() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            sequence: 0,
+            last_packet: Duration::ZERO,
+            busy_until: Duration::ZERO,
+        }
+    }
+
+    fn push(
+        &mut self,
+        container: TransportContainer<Packet, Datagram>,
+        size: usize,
+        now: Duration,
+        config: &SimulationConfig,
+        rng: &mut StdRng,
+    ) {
+        let is_datagram = matches!(container, TransportContainer::Datagram(_));
+        if is_datagram && rng.gen_bool(config.datagram_loss) {
+            return;
+        }
+
+        let mut sent = now;
+        if let Some(bandwidth) = config.bandwidth {
+            let transmission = Duration::from_secs_f64(size as f64 / bandwidth as f64);
+            sent = self.busy_until.max(now) + transmission;
+            self.busy_until = sent;
+        }
+
+        let mut arrival = sent + config.latency;
+        if !config.jitter.is_zero() {
+            arrival += rng.gen_range(Duration::ZERO..=config.jitter);
+        }
+        if is_datagram {
+            if rng.gen_bool(config.reordering) {
+                arrival += config.reorder_delay;
+            }
+        } else {
+            arrival = arrival.max(self.last_packet);
+            self.last_packet = arrival;
+        }
+
+        self.queue.insert((arrival, self.sequence), container);
+        self.sequence += 1;
+    }
+
+    /// the messages which arrived until now
+    fn arrived(
+        &mut self,
+        now: Duration,
+    ) -> impl Iterator<Item = TransportContainer<Packet, Datagram>> {
+        let pending = self.queue.split_off(&(now, u64::MAX));
+        std::mem::replace(&mut self.queue, pending).into_values()
+    }
+}
+
+struct Simulation<PacketOut, PacketIn, DatagramOut, DatagramIn> {
+    rng: StdRng,
+    egress: Link<PacketOut, DatagramOut>,
+    ingress: Link<PacketIn, DatagramIn>,
+    disconnected: bool,
+}
+
+pub struct SimulatedTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E> {
+    transport: T,
+    config: SimulationConfig,
+    clock: SimulationClock,
+    simulation: Mutex<Simulation<PacketOut, PacketIn, DatagramOut, DatagramIn>>,
+    buffer: Vec<TransportContainer<PacketIn, DatagramIn>>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    SimulatedTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+{
+    /// fails if the config is invalid, see [SimulationConfig::validate]
+    pub fn new(
+        transport: T,
+        config: SimulationConfig,
+        clock: SimulationClock,
+    ) -> error_stack::Result<Self, SimulatedTransportError> {
+        config.validate()?;
+        Ok(Self {
+            transport,
+            simulation: Mutex::new(Simulation {
+                rng: StdRng::seed_from_u64(config.seed),
+                egress: Link::new(),
+                ingress: Link::new(),
+                disconnected: false,
+            }),
+            config,
+            clock,
+            buffer: Vec::new(),
+            _error: PhantomData,
+        })
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    pub fn clock(&self) -> &SimulationClock {
+        &self.clock
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// the number of messages on their way in both directions
+    pub fn in_flight(&self) -> usize {
+        let simulation = self.lock();
+        simulation.egress.queue.len() + simulation.ingress.queue.len()
+    }
+
+    fn lock(
+        &self,
+    ) -> std::sync::MutexGuard<'_, Simulation<PacketOut, PacketIn, DatagramOut, DatagramIn>> {
+        self.simulation
+            .lock()
+            .expect("cannot lock simulated transport")
+    }
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    SimulatedTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+where
+    T: TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, E>,
+    E: Error + Send + Sync + 'static,
+    PacketOut: prost::Message,
+    DatagramOut: prost::Message,
+{
+    /// closes the connection like a network failure, the messages on their way are lost
+    pub fn disconnect(&self) {
+        let mut simulation = self.lock();
+        simulation.disconnected = true;
+        simulation.egress.queue.clear();
+        simulation.ingress.queue.clear();
+        self.transport.close();
+    }
+
+    /// sends the outgoing packets which are still on their way before the wrapped transport is closed, the
+    /// datagrams are dropped
+    fn flush_packets(&self) {
+        let mut simulation = self.lock();
+        if simulation.disconnected {
+            return;
+        }
+        for container in std::mem::take(&mut simulation.egress.queue).into_values() {
+            if let TransportContainer::Packet(packet) = container {
+                if self.transport.send_packet(packet).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// sends the outgoing messages which arrived at the peer by now
+    fn forward(&self, now: Duration) -> error_stack::Result<(), SimulatedTransportError> {
+        if self
+            .config
+            .disconnect_after
+            .is_some_and(|disconnect_after| now >= disconnect_after)
+        {
+            self.disconnect();
+        }
+
+        let mut simulation = self.lock();
+        if simulation.disconnected {
+            return Err(Report::new(SimulatedTransportError::Disconnected));
+        }
+        for container in simulation.egress.arrived(now) {
+            match container {
+                TransportContainer::Packet(packet) => self.transport.send_packet(packet),
+                TransportContainer::Datagram(datagram) => self.transport.send_datagram(datagram),
+            }
+            .change_context(SimulatedTransportError::Transport)?;
+        }
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        container: TransportContainer<PacketOut, DatagramOut>,
+    ) -> error_stack::Result<(), SimulatedTransportError> {
+        let now = self.clock.elapsed();
+        self.forward(now)?;
+
+        let size = match &container {
+            TransportContainer::Packet(packet) => packet.encoded_len(),
+            TransportContainer::Datagram(datagram) => datagram.encoded_len(),
+        };
+        let mut simulation = self.lock();
+        let Simulation { rng, egress, .. } = &mut *simulation;
+        egress.push(container, size, now, &self.config, rng);
+        drop(simulation);
+
+        //messages without delay are forwarded immediately
+        self.forward(now)
+    }
+}
+
+impl<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+    TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, SimulatedTransportError>
+    for SimulatedTransportLayer<T, PacketOut, PacketIn, DatagramOut, DatagramIn, E>
+where
+    T: TransportLayer<PacketOut, PacketIn, DatagramOut, DatagramIn, E>,
+    E: Error + Send + Sync + 'static,
+    PacketOut: prost::Message,
+    PacketIn: prost::Message,
+    DatagramOut: prost::Message,
+    DatagramIn: prost::Message,
+{
+    fn send_packet(&self, packet: PacketOut) -> error_stack::Result<(), SimulatedTransportError> {
+        self.send(TransportContainer::Packet(packet))
+    }
+
+    fn send_datagram(
+        &self,
+        datagram: DatagramOut,
+    ) -> error_stack::Result<(), SimulatedTransportError> {
+        self.send(TransportContainer::Datagram(datagram))
+    }
+
+    fn drain(
+        &mut self,
+        into: &mut Vec<TransportContainer<PacketIn, DatagramIn>>,
+    ) -> error_stack::Result<(), SimulatedTransportError> {
+        let now = self.clock.elapsed();
+        self.forward(now)?;
+
+        self.transport
+            .drain(&mut self.buffer)
+            .change_context(SimulatedTransportError::Transport)?;
+        let simulation = self
+            .simulation
+            .get_mut()
+            .expect("cannot lock simulated transport");
+        let Simulation { rng, ingress, .. } = simulation;
+        for container in self.buffer.drain(..) {
+            let size = match &container {
+                TransportContainer::Packet(packet) => packet.encoded_len(),
+                TransportContainer::Datagram(datagram) => datagram.encoded_len(),
+            };
+            ingress.push(container, size, now, &self.config, rng);
+        }
+        into.extend(ingress.arrived(now));
+        Ok(())
+    }
+
+    fn close(&self) {
+        self.flush_packets();
+        self.transport.close();
+    }
+
+    fn close_with(&self, reason: DisconnectReason) {
+        self.flush_packets();
+        self.transport.close_with(reason);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.lock().disconnected || self.transport.is_closed()
+    }
+
+    /// the delayed messages are framed with the new config as well
+    fn set_frame_config(&mut self, config: FrameConfig) {
+        self.transport.set_frame_config(config);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use crate::proto::play::client::packet::packet_play_client::Payload;
+    use crate::proto::play::client::packet::PacketPlayClient;
+    use crate::proto::play::server::packet::PacketPlayServer;
+    use crate::transport::local::{
+        local_connection, ClientLocalTransport, LocalTransportError, ServerLocalTransport,
+    };
+
+    use super::*;
+
+    type Simulated = SimulatedTransportLayer<
+        ClientLocalTransport,
+        PacketPlayClient,
+        PacketPlayServer,
+        PacketPlayClient,
+        PacketPlayServer,
+        LocalTransportError,
+    >;
+
+    fn simulated(config: SimulationConfig) -> (Simulated, ServerLocalTransport) {
+        let (client, server) = local_connection();
+        let simulated =
+            SimulatedTransportLayer::new(client, config, SimulationClock::manual()).unwrap();
+        (simulated, server)
+    }
+
+    fn message(index: usize, size: usize) -> PacketPlayClient {
+        let mut data = vec![0; size.max(1)];
+        data[0] = index as u8;
+        PacketPlayClient {
+            payload: Some(Payload::Sync(data)),
+        }
+    }
+
+    /// the indices of the received packets and datagrams
+    fn received(server: &mut ServerLocalTransport) -> Vec<(bool, u8)> {
+        let mut received = Vec::new();
+        server.drain(&mut received).unwrap();
+        received
+            .into_iter()
+            .map(|container| match container {
+                TransportContainer::Packet(packet) => (true, packet),
+                TransportContainer::Datagram(datagram) => (false, datagram),
+            })
+            .map(|(is_packet, packet)| match packet.payload {
+                Some(Payload::Sync(data)) => (is_packet, data[0]),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn advance(simulated: &mut Simulated, duration: Duration) {
+        simulated.clock().advance(duration);
+        simulated.drain(&mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn latency() {
+        let (mut simulated, mut server) = simulated(SimulationConfig {
+            latency: Duration::from_millis(100),
+            jitter: Duration::from_millis(50),
+            ..Default::default()
+        });
+        for index in 0..20 {
+            simulated.send_packet(message(index, 1)).unwrap();
+        }
+        advance(&mut simulated, Duration::from_millis(99));
+        assert!(received(&mut server).is_empty());
+
+        //the packets stay in order despite the jitter
+        advance(&mut simulated, Duration::from_millis(51));
+        let expected: Vec<_> = (0..20).map(|index| (true, index)).collect();
+        assert_eq!(received(&mut server), expected);
+        assert_eq!(simulated.in_flight(), 0);
+
+        //the incoming direction is delayed too
+        server
+            .send_packet(PacketPlayServer { payload: None })
+            .unwrap();
+        let mut incoming = Vec::new();
+        simulated.drain(&mut incoming).unwrap();
+        assert!(incoming.is_empty());
+        simulated.clock().advance(Duration::from_millis(150));
+        simulated.drain(&mut incoming).unwrap();
+        assert_eq!(incoming.len(), 1);
+    }
+
+    #[test]
+    fn datagrams() {
+        let config = SimulationConfig {
+            datagram_loss: 0.3,
+            reordering: 0.2,
+            reorder_delay: Duration::from_millis(20),
+            seed: 7,
+            ..Default::default()
+        };
+        let run = || {
+            let (mut simulated, mut server) = simulated(config.clone());
+            for index in 0..100 {
+                simulated.send_datagram(message(index, 1)).unwrap();
+                //packets are never lost
+                simulated.send_packet(message(index, 1)).unwrap();
+                advance(&mut simulated, Duration::from_millis(1));
+            }
+            advance(&mut simulated, Duration::from_millis(20));
+            received(&mut server)
+        };
+
+        let received = run();
+        let (packets, datagrams): (Vec<_>, Vec<_>) =
+            received.iter().partition(|(is_packet, _)| *is_packet);
+        assert_eq!(packets.len(), 100);
+        assert!(datagrams.len() > 50 && datagrams.len() < 90);
+        let indices: Vec<_> = datagrams.iter().map(|(_, index)| *index).collect();
+        let mut sorted = indices.clone();
+        sorted.sort();
+        assert_ne!(indices, sorted);
+
+        //the same seed produces the same conditions
+        assert_eq!(run(), received);
+    }
+
+    #[test]
+    fn bandwidth() {
+        let (mut simulated, mut server) = simulated(SimulationConfig {
+            bandwidth: Some(1000),
+            ..Default::default()
+        });
+        let size = message(0, 98).encoded_len();
+        assert_eq!(size, 100);
+        for index in 0..10 {
+            simulated.send_packet(message(index, 98)).unwrap();
+        }
+        advance(&mut simulated, Duration::from_millis(500));
+        assert_eq!(received(&mut server).len(), 5);
+        advance(&mut simulated, Duration::from_millis(500));
+        assert_eq!(received(&mut server).len(), 5);
+    }
+
+    #[test]
+    fn disconnect() {
+        let (simulated, server) = simulated(SimulationConfig {
+            latency: Duration::from_millis(100),
+            disconnect_after: Some(Duration::from_secs(1)),
+            ..Default::default()
+        });
+        simulated.send_packet(message(0, 1)).unwrap();
+        simulated.clock().advance(Duration::from_secs(1));
+        assert!(simulated.send_packet(message(1, 1)).is_err());
+        assert!(simulated.is_closed());
+        assert!(server.is_closed());
+        assert_eq!(simulated.in_flight(), 0);
+    }
+
+    #[test]
+    fn close() {
+        let (simulated, mut server) = simulated(SimulationConfig {
+            latency: Duration::from_millis(100),
+            ..Default::default()
+        });
+        simulated.send_packet(message(0, 1)).unwrap();
+        simulated.send_datagram(message(1, 1)).unwrap();
+        simulated.send_packet(message(2, 1)).unwrap();
+        simulated.close();
+        assert!(simulated.is_closed());
+        assert!(server.is_closed());
+        assert_eq!(simulated.in_flight(), 0);
+        //the packets arrive although they were still on their way
+        assert_eq!(received(&mut server), vec![(true, 0), (true, 2)]);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let invalid = [
+            SimulationConfig {
+                datagram_loss: 1.5,
+                ..Default::default()
+            },
+            SimulationConfig {
+                reordering: -0.1,
+                ..Default::default()
+            },
+            SimulationConfig {
+                datagram_loss: f64::NAN,
+                ..Default::default()
+            },
+            SimulationConfig {
+                bandwidth: Some(0),
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            let (client, _server) = local_connection();
+            let result: error_stack::Result<Simulated, _> =
+                SimulatedTransportLayer::new(client, config, SimulationClock::manual());
+            let Err(error) = result else {
+                panic!("the config was accepted");
+            };
+            assert!(matches!(
+                error.current_context(),
+                SimulatedTransportError::InvalidConfig(_)
+            ));
+        }
+    }
+}
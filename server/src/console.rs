@@ -0,0 +1,52 @@
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+
+use common::diagnostics::WorldMemoryReport;
+
+/// reads commands from the standard input, they are executed in [Update]
+///
+/// commands:
+///  - `memory`: prints the [WorldMemoryReport]
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+        std::thread::Builder::new()
+            .name("console".to_string())
+            .spawn(move || {
+                for line in std::io::stdin().lock().lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn the console thread");
+
+        app.insert_resource(ConsoleInput(Mutex::new(receiver)))
+            .add_systems(Update, execute_commands);
+    }
+}
+
+#[derive(Resource)]
+struct ConsoleInput(Mutex<Receiver<String>>);
+
+fn execute_commands(input: Res<ConsoleInput>, report: Option<Res<WorldMemoryReport>>) {
+    let input = input.0.lock().expect("the console input is only used here");
+    for line in input.try_iter() {
+        match line.trim() {
+            "" => {}
+            "memory" => match &report {
+                Some(report) => info!("world memory:\n{}", **report),
+                None => warn!("the world diagnostics are not enabled"),
+            },
+            command => warn!("unknown command: {}", command),
+        }
+    }
+}
@@ -1,14 +1,29 @@
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use bevy::app::{App, ScheduleRunnerPlugin};
 use bevy::prelude::*;
 
+use common::compressible::LZ4;
+use common::datapack::watch::{DataPackWatchPlugin, ReloadRegistryPlugin};
+use common::diagnostics::{WorldDiagnosticsAppExt, WorldDiagnosticsPlugin};
+use common::palette::{ChunkStorage, GlobalPalette, StateId};
+use common::registry::events::RegistryEventsPlugin;
+use common::registry::persistence::RegistryPersistencePlugin;
+use common::resource::state::{BlockSchema, BlockSchemaMarker, BlockSchemas};
+use common::resource::{BlockData, ResourceKey};
+use common::CHUNK_VOLUME;
+
+use crate::console::ConsolePlugin;
 use crate::transport::local::LocalTransportPlugin;
 
+mod console;
 mod transport;
 mod core;
 
 pub const TARGET_TPS: u16 = 40;
+/// the world is stored here when no other directory is given
+pub const DEFAULT_WORLD_DIRECTORY: &str = "world";
 
 
 #[derive(Debug, Component)]
@@ -19,10 +34,19 @@ impl WorldController {}
 #[derive(Debug)]
 pub struct WorldServer {
     pub app: App,
+    directory: PathBuf,
 }
 
 impl Default for WorldServer {
     fn default() -> Self {
+        Self::new(DEFAULT_WORLD_DIRECTORY)
+    }
+}
+
+impl WorldServer {
+    /// creates the server of the world stored in [directory]
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
         let mut app = App::new();
         app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin {
             run_mode: bevy::app::RunMode::Loop {
@@ -30,11 +54,34 @@ impl Default for WorldServer {
             },
         })).add_plugins((LocalTransportPlugin));
 
-        WorldServer { app }
+        //the saved ids are restored before the content of the data packs is registered
+        app.init_resource::<BlockSchemas>()
+            .add_plugins(RegistryPersistencePlugin::<BlockSchema, BlockSchemaMarker>::new(
+                directory.join("registries").join("blocks.json"),
+            ))
+            .add_plugins((
+                DataPackWatchPlugin::new(directory.join("datapacks"))
+                    .watch(cfg!(feature = "datapack-watch")),
+                ReloadRegistryPlugin::<BlockSchema, BlockSchemaMarker>::new("blocks"),
+                RegistryEventsPlugin::<BlockSchema, BlockSchemaMarker>::default(),
+            ));
+
+        //the states of the streamed chunks, the clients start with the same default state
+        let air = BlockData::new(ResourceKey::core("air").expect("air is a valid key"));
+        app.insert_resource(GlobalPalette::new(air));
+
+        //the memory report can be printed with the `memory` command of the console
+        app.add_plugins((WorldDiagnosticsPlugin::default(), ConsolePlugin))
+            .add_storage_source::<CHUNK_VOLUME, StateId>("chunk_storages")
+            .add_compressed_source::<ChunkStorage, LZ4>("compressed_chunks");
+
+        WorldServer { app, directory }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
     }
-}
 
-impl WorldServer {
     pub async fn run(&mut self) {
         loop {
             let tick_time = self.tick().await;
@@ -1,12 +1,14 @@
 use bevy::prelude::*;
-use bevy_replicon::prelude::*;
-
-pub struct LocalTransportPlugin;
 
+use protocol::server::ServerLoginPlugin;
+use protocol::transport::local::{LocalTransportError, ServerLocalConnection};
 
+/// serves the clients running in the same process (singleplayer), their connections are accepted with
+/// [protocol::server::PendingConnections] and join once they finished the handshake
+pub struct LocalTransportPlugin;
 
 impl Plugin for LocalTransportPlugin {
     fn build(&self, app: &mut App) {
-
+        app.add_plugins(ServerLoginPlugin::<ServerLocalConnection, LocalTransportError>::default());
     }
 }